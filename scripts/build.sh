@@ -1,7 +1,13 @@
 set -e
+# The candid files are generated first, as the token embeds its interface into the wasm.
+mkdir -p src/candid
+cargo run -p token-factory > src/candid/token-factory.did
+cargo run -p is20-token-canister > src/candid/token.did
+cargo run -p is20-token-test-canister > src/candid/token-test.did
 cargo build --target wasm32-unknown-unknown --package is20-token-canister --release
 ic-cdk-optimizer target/wasm32-unknown-unknown/release/is20-token-canister.wasm -o src/factory/src/token.wasm
+ic-wasm src/factory/src/token.wasm -o src/factory/src/token.wasm metadata candid:service -f src/candid/token.did -v public
+cargo build --target wasm32-unknown-unknown --package is20-token-test-canister --release --features "${TOKEN_TEST_FEATURES:-}"
+ic-cdk-optimizer target/wasm32-unknown-unknown/release/is20-token-test-canister.wasm -o target/wasm32-unknown-unknown/release/token-test.wasm
 cargo build --target wasm32-unknown-unknown --package token-factory --release
 ic-cdk-optimizer target/wasm32-unknown-unknown/release/token-factory.wasm -o target/wasm32-unknown-unknown/release/factory.wasm
-cargo run -p token-factory > src/candid/token-factory.did
-cargo run -p is20-token-canister > src/candid/token.did
@@ -5,16 +5,31 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::state::StableState;
+use crate::icrc2::{self, TransferFromError};
+use crate::management;
+use crate::stable;
+use crate::state::{
+    CreationEvent, CreationFee, DeployedToken, FactoryStats, StableState, TokenStatus, TokenVersion,
+};
 use crate::{error::TokenFactoryError, state::State};
 use candid::Principal;
-use ic_canister::{init, post_upgrade, pre_upgrade, query, update, Canister, PreUpdate};
-use ic_factory::{api::FactoryCanister, error::FactoryError, FactoryConfiguration, FactoryState};
+use ic_canister::{
+    init, post_upgrade, pre_upgrade, query, update, virtual_canister_call, Canister, PreUpdate,
+};
+use ic_factory::api::{FactoryCanister, UpgradeResult};
+use ic_factory::{error::FactoryError, FactoryConfiguration, FactoryState};
 use ic_helpers::candid_header::{candid_header, CandidHeader};
-use token::types::Metadata;
+use ic_helpers::tokens::Tokens128;
+use sha2::{Digest, Sha256};
+use token::types::{valid_metadata_key, Metadata};
 
 const DEFAULT_LEDGER_PRINCIPAL: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
 const DEFAULT_ICP_FEE: u64 = 10u64.pow(8); // 1 ICP
+const MAX_TOKENS_PAGE_LEN: usize = 1000;
+const MAX_SEARCH_RESULTS: usize = 100;
+const E8S_PER_ICP: u128 = 10u128.pow(8);
+const MAX_SYMBOL_LEN: usize = 10;
+const MAX_DECIMALS: u8 = 18;
 
 #[cfg(not(feature = "no_api"))]
 mod inspect_message;
@@ -45,22 +60,41 @@ impl TokenFactoryCanister {
             .expect("Someone has the base factory state borrowed. This is a program bug because state lock was bypassed.")
             .into_inner();
 
-        ic_storage::stable::write(&StableState {
+        // The token registry and the configuration are kept in the stable structures, so only the
+        // rest of the state is serialized here.
+        let data = candid::encode_one(&StableState {
             token_factory_state,
             base_factory_state,
         })
         .expect("failed to serialize state to the stable storage");
+        stable::write_upgrade_data(&data);
     }
 
     #[post_upgrade]
     fn post_upgrade(&self) {
-        let stable_state = ic_storage::stable::read::<StableState>()
-            .expect("failed to read stable state from the stable storage");
+        let stable_state = if stable::has_legacy_layout() {
+            ic_storage::stable::read::<StableState>()
+                .expect("failed to read stable state from the stable storage")
+        } else {
+            let data = stable::read_upgrade_data();
+            if data.is_empty() {
+                StableState::default()
+            } else {
+                candid::decode_one::<StableState>(&data)
+                    .expect("failed to read stable state from the stable storage")
+            }
+        };
         let StableState {
-            token_factory_state,
-            base_factory_state,
+            mut token_factory_state,
+            mut base_factory_state,
         } = stable_state;
 
+        token_factory_state.migrate_registry();
+        match stable::read_configuration() {
+            Some(configuration) => base_factory_state.configuration = configuration,
+            None => stable::write_configuration(&base_factory_state.configuration),
+        }
+
         self.state.replace(token_factory_state);
         self.factory_state().replace(base_factory_state);
     }
@@ -72,6 +106,7 @@ impl TokenFactoryCanister {
 
         let factory_configuration =
             FactoryConfiguration::new(ledger, DEFAULT_ICP_FEE, controller, controller);
+        stable::write_configuration(&factory_configuration);
 
         self.factory_state()
             .replace(FactoryState::new(factory_configuration));
@@ -80,16 +115,226 @@ impl TokenFactoryCanister {
     /// Returns the token, or None if it does not exist.
     #[query]
     pub async fn get_token(&self, name: String) -> Option<Principal> {
-        self.state.borrow().tokens.get(&name).copied()
+        self.state.borrow().token(&name)
     }
 
+    /// Returns up to `limit` tokens deployed by the factory, starting from the `offset` in the
+    /// creation order. No more than 1000 tokens are returned at once. The tokens forgotten with
+    /// `forget_token` are not listed.
+    #[query]
+    pub fn get_tokens(&self, offset: u64, limit: u64) -> Vec<DeployedToken> {
+        let limit = (limit as usize).min(MAX_TOKENS_PAGE_LEN);
+        self.state
+            .borrow()
+            .deployed_tokens
+            .iter()
+            .skip(offset as usize)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns up to `limit` deployed tokens whose name or symbol starts with the `query`,
+    /// ignoring the case. No more than 100 tokens are returned at once.
+    #[query]
+    pub fn find_tokens(&self, query: String, limit: u64) -> Vec<DeployedToken> {
+        let limit = (limit as usize).min(MAX_SEARCH_RESULTS);
+        self.state.borrow().find_tokens(&query, limit)
+    }
+
+    /// Returns up to `limit` token creation events, starting from the `offset`. The events are
+    /// never removed, so they can be used to verify that a token was created by the factory. No
+    /// more than 1000 events are returned at once.
+    #[query]
+    pub fn get_creation_events(&self, offset: u64, limit: u64) -> Vec<CreationEvent> {
+        let limit = (limit as usize).min(MAX_TOKENS_PAGE_LEN);
+        self.state
+            .borrow()
+            .creation_events
+            .iter()
+            .skip(offset as usize)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Sets the wasm of the new tokens and returns its version. The deployed tokens are updated to
+    /// it with the `upgrade` call.
     #[update]
     pub async fn set_token_bytecode(
         &self,
         bytecode: Vec<u8>,
         state_header: CandidHeader,
     ) -> Result<u32, FactoryError> {
-        self.set_canister_code::<token::state::CanisterState>(bytecode, state_header)
+        self.install_token_wasm(bytecode, state_header)
+    }
+
+    /// Uploads a chunk of the token wasm, for the wasm that doesn't fit into a single message.
+    /// The chunks are assembled in the index order by `commit_wasm`. Uploading the chunk with
+    /// index 0 discards the previously uploaded chunks. Only the factory controller is allowed to
+    /// call this method.
+    #[update]
+    pub fn upload_wasm(&self, chunk: Vec<u8>, index: u32) -> Result<(), TokenFactoryError> {
+        if self.factory_state().borrow().controller() != ic_canister::ic_kit::ic::caller() {
+            return Err(TokenFactoryError::NotController);
+        }
+
+        let mut state = self.state.borrow_mut();
+        if index == 0 {
+            state.clear_wasm_chunks();
+        }
+        state.insert_wasm_chunk(index, &chunk);
+
+        Ok(())
+    }
+
+    /// Assembles the chunks uploaded with `upload_wasm` and sets the result as the wasm of the new
+    /// tokens, the same way `set_token_bytecode` does. The SHA-256 hash of the assembled wasm must
+    /// be equal to `hash`. Only the factory controller is allowed to call this method.
+    #[update]
+    pub fn commit_wasm(
+        &self,
+        hash: Vec<u8>,
+        state_header: CandidHeader,
+    ) -> Result<u32, TokenFactoryError> {
+        if self.factory_state().borrow().controller() != ic_canister::ic_kit::ic::caller() {
+            return Err(TokenFactoryError::NotController);
+        }
+
+        let bytecode = self
+            .state
+            .borrow()
+            .assembled_wasm()
+            .ok_or(TokenFactoryError::MissingWasmChunks)?;
+        if Sha256::digest(&bytecode).as_slice() != hash.as_slice() {
+            return Err(TokenFactoryError::WasmHashMismatch);
+        }
+
+        let version = self.install_token_wasm(bytecode, state_header)?;
+        self.state.borrow_mut().clear_wasm_chunks();
+
+        Ok(version)
+    }
+
+    fn install_token_wasm(
+        &self,
+        bytecode: Vec<u8>,
+        state_header: CandidHeader,
+    ) -> Result<u32, FactoryError> {
+        let wasm_hash = Sha256::digest(&bytecode).to_vec();
+        let version =
+            self.set_canister_code::<token::state::CanisterState>(bytecode, state_header)?;
+        self.state.borrow_mut().current_version = Some(TokenVersion { version, wasm_hash });
+
+        Ok(version)
+    }
+
+    /// Returns the version of the wasm the token is running, or None if it's unknown.
+    #[query]
+    pub fn get_token_version(&self, principal: Principal) -> Option<TokenVersion> {
+        self.state.borrow().token_versions.get(&principal).cloned()
+    }
+
+    /// Returns the tokens that are not running the current wasm version, including the ones with
+    /// unknown version.
+    #[query]
+    pub fn get_outdated_tokens(&self) -> Vec<Principal> {
+        self.state.borrow().outdated_tokens()
+    }
+
+    /// Returns the factory configuration: the ICP ledger, the creation fee, the fee recipient and
+    /// the controller.
+    #[query]
+    pub fn get_configuration(&self) -> FactoryConfiguration {
+        self.factory_state().borrow().configuration.clone()
+    }
+
+    /// Sets the creation fee in ICP e8s. Only the factory controller is allowed to call this
+    /// method.
+    #[update]
+    pub fn set_creation_fee(&self, fee: u64) -> Result<(), TokenFactoryError> {
+        self.update_configuration(|configuration| configuration.icp_fee = fee)
+    }
+
+    /// Sets the principal the creation fees are transferred to. Only the factory controller is
+    /// allowed to call this method.
+    #[update]
+    pub fn set_fee_recipient(&self, recipient: Principal) -> Result<(), TokenFactoryError> {
+        self.update_configuration(|configuration| configuration.icp_to = recipient)
+    }
+
+    /// Sets the ICP ledger the creation fees are paid with. Only the factory controller is allowed
+    /// to call this method.
+    #[update]
+    pub fn set_ledger_principal(&self, ledger: Principal) -> Result<(), TokenFactoryError> {
+        self.update_configuration(|configuration| configuration.ledger_principal = ledger)
+    }
+
+    fn update_configuration(
+        &self,
+        update: impl FnOnce(&mut FactoryConfiguration),
+    ) -> Result<(), TokenFactoryError> {
+        let factory_state = self.factory_state();
+        let mut factory_state = factory_state.borrow_mut();
+        if factory_state.controller() != ic_canister::ic_kit::ic::caller() {
+            return Err(TokenFactoryError::NotController);
+        }
+
+        update(&mut factory_state.configuration);
+        stable::write_configuration(&factory_state.configuration);
+        Ok(())
+    }
+
+    /// Sets the number of cycles equivalent to 1 ICP, used to charge the creation fee from the
+    /// cycles attached to `create_token`. `None` disables the fee for the tokens created with
+    /// cycles. Only the factory controller is allowed to call this method.
+    #[update]
+    pub fn set_cycles_per_icp(&self, rate: Option<u64>) -> Result<(), TokenFactoryError> {
+        if self.factory_state().borrow().controller() != ic_canister::ic_kit::ic::caller() {
+            return Err(TokenFactoryError::NotController);
+        }
+
+        self.state.borrow_mut().cycles_per_icp = rate;
+        Ok(())
+    }
+
+    /// Returns the creation fee charged from the attached cycles, or None if the tokens created
+    /// with cycles are not charged a fee.
+    #[query]
+    pub fn get_cycles_fee(&self) -> Option<u64> {
+        let rate = self.state.borrow().cycles_per_icp?;
+        let fee = self.get_icp_fee() as u128 * rate as u128 / E8S_PER_ICP;
+        Some(fee.try_into().unwrap_or(u64::MAX))
+    }
+
+    /// Returns true if no active token created by the factory or a creation in progress has the
+    /// symbol, ignoring the case.
+    #[query]
+    pub fn is_symbol_available(&self, symbol: String) -> bool {
+        self.state.borrow().is_symbol_available(&symbol)
+    }
+
+    /// Enables or disables the symbol uniqueness check. When it's enabled, a token cannot be
+    /// created with the symbol of another active token. Only the factory controller is allowed to
+    /// call this method.
+    #[update]
+    pub fn set_unique_symbols(&self, enabled: bool) -> Result<(), TokenFactoryError> {
+        if self.factory_state().borrow().controller() != ic_canister::ic_kit::ic::caller() {
+            return Err(TokenFactoryError::NotController);
+        }
+
+        self.state.borrow_mut().unique_symbols = enabled;
+        Ok(())
+    }
+
+    /// Returns the aggregate statistics of the token creation and the current cycles balance of
+    /// the factory.
+    #[query]
+    pub fn get_factory_stats(&self) -> FactoryStats {
+        FactoryStats {
+            creation: self.state.borrow().stats.clone(),
+            cycles_balance: ic_canister::ic_kit::ic::balance(),
+        }
     }
 
     /// Creates a new token.
@@ -98,7 +343,9 @@ impl TokenFactoryCanister {
     /// * the call must be made through a cycles wallet with enough cycles to cover the canister
     ///   expenses. The amount of provided cycles must be greater than `10^12`. Most of the cycles
     ///   will be added to the newly created canister balance, while some will be consumed by the
-    ///   factory
+    ///   factory. If the cycles exchange rate is set by the controller, the creation fee
+    ///   equivalent to `icp_fee` is charged from the attached cycles first, see `get_cycles_fee`.
+    ///   The fee is kept by the factory
     /// * the caller must transfer some amount of ICP to their subaccount into the ICP ledger factory account.
     ///   The subaccount id can be calculated like this:
     ///
@@ -117,38 +364,178 @@ impl TokenFactoryCanister {
     /// If the provided ICP amount is greater than required by the factory, extra ICP will not be
     /// consumed and can be used to create more canisters, or can be reclaimed by calling `refund_icp`
     /// method.
+    ///
+    /// If the creation fails after the fee was paid, the caller is credited with a free creation,
+    /// which is used by their next `create_token` call instead of charging the fee, see
+    /// `get_creation_credits`.
+    ///
+    /// Alternatively, if the ledger supports ICRC-2, the caller can approve the factory to spend
+    /// `icp_fee` (plus the ledger fee) from their account with `icrc2_approve`. The fee is then
+    /// transferred with `icrc2_transfer_from` by this call, so no prior transfer is needed. The
+    /// subaccount flow is used if the allowance is not sufficient.
     #[update]
     pub async fn create_token(
+        &self,
+        mut info: Metadata,
+        controller: Option<Principal>,
+    ) -> Result<Principal, TokenFactoryError> {
+        validate_metadata(&info)?;
+        info.registry = Some(ic_canister::ic_kit::ic::id());
+
+        let name = info.name.clone();
+        let symbol = info.symbol.clone();
+        {
+            let mut state = self.state.borrow_mut();
+            if state.is_name_taken(&name) {
+                return Err(TokenFactoryError::AlreadyExists);
+            }
+
+            if state.unique_symbols && !state.is_symbol_available(&symbol) {
+                return Err(TokenFactoryError::SymbolTaken);
+            }
+
+            // The name and the symbol are reserved before the first await, so a concurrent
+            // creation cannot pass the checks above with the same ones.
+            state.reserve_token(&name, &symbol);
+        }
+
+        let result = self.create_reserved_token(info, controller).await;
+        self.state.borrow_mut().release_token(&name, &symbol);
+
+        result
+    }
+
+    /// Creates the token whose name and symbol were reserved by `create_token`.
+    async fn create_reserved_token(
         &self,
         info: Metadata,
         controller: Option<Principal>,
     ) -> Result<Principal, TokenFactoryError> {
-        if info.name.is_empty() {
-            return Err(TokenFactoryError::InvalidConfiguration(
-                "name",
-                "cannot be `None`",
-            ));
+        let caller = ic_canister::ic_kit::ic::caller();
+        let fee = self.collect_creation_fee(caller).await?;
+        let (icp_fee, cycles_fee) = match fee {
+            CreationFee::Cycles(fee) => (0, fee),
+            CreationFee::ApprovedIcp(fee) | CreationFee::DepositedIcp(fee) => (fee, 0),
+            CreationFee::Free | CreationFee::Credit => (0, 0),
+        };
+
+        let name = info.name.clone();
+        let symbol = info.symbol.clone();
+        let owner = info.owner;
+        let balance_before = ic_canister::ic_kit::ic::balance();
+        // The fee is already collected, so the caller is not charged by the base factory.
+        let result = self.create_canister((info,), controller, None).await;
+        let cycles_spent = balance_before.saturating_sub(ic_canister::ic_kit::ic::balance());
+
+        let mut state = self.state.borrow_mut();
+        state.stats.icp_fees_collected += icp_fee;
+        state.stats.cycles_fees_collected += cycles_fee;
+        state.stats.cycles_spent += cycles_spent;
+        let principal = match result {
+            Ok(principal) => principal,
+            Err(e) => {
+                state.record_failed_creation(caller, fee);
+                return Err(e.into());
+            }
+        };
+
+        state.stats.tokens_created += 1;
+        state.creation_events.push(CreationEvent {
+            creator: caller,
+            principal,
+            icp_fee,
+            cycles_fee,
+            timestamp: ic_canister::ic_kit::ic::time(),
+        });
+        state.register_token(name.clone(), principal);
+        if let Some(version) = state.current_version.clone() {
+            state.token_versions.insert(principal, version);
         }
+        state.add_deployed_token(DeployedToken {
+            name,
+            symbol,
+            principal,
+            owner,
+            created_at: ic_canister::ic_kit::ic::time(),
+            retired_at: None,
+        });
 
-        if info.symbol.is_empty() {
-            return Err(TokenFactoryError::InvalidConfiguration(
-                "symbol",
-                "cannot be `None`",
-            ));
+        Ok(principal)
+    }
+
+    /// Returns the number of tokens the principal can create without paying the creation fee, as
+    /// the fee was paid for a failed creation.
+    #[query]
+    pub fn get_creation_credits(&self, principal: Principal) -> u32 {
+        self.state
+            .borrow()
+            .creation_credits
+            .get(&principal)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Collects the creation fee from the `caller`, see `create_token`. A creation credit of the
+    /// caller is used first, so the caller is never charged while they have one.
+    async fn collect_creation_fee(
+        &self,
+        caller: Principal,
+    ) -> Result<CreationFee, TokenFactoryError> {
+        if self.state.borrow_mut().take_creation_credit(caller) {
+            return Ok(CreationFee::Credit);
         }
 
-        let key = info.name.clone();
-        if self.state.borrow().tokens.contains_key(&key) {
-            return Err(TokenFactoryError::AlreadyExists);
+        let cycles = ic_canister::ic_kit::ic::msg_cycles_available();
+        if cycles > 0 {
+            if let Some(fee) = self.get_cycles_fee() {
+                if cycles < fee {
+                    return Err(TokenFactoryError::InsufficientCycles(fee));
+                }
+
+                ic_canister::ic_kit::ic::msg_cycles_accept(fee);
+                return Ok(CreationFee::Cycles(fee));
+            }
         }
 
-        let caller = ic_canister::ic_kit::ic::caller();
-        let principal = self
-            .create_canister((info,), controller, Some(caller))
-            .await?;
-        self.state.borrow_mut().tokens.insert(key, principal);
+        let fee = self.get_icp_fee();
+        if fee == 0 {
+            return Ok(CreationFee::Free);
+        }
 
-        Ok(principal)
+        if self.collect_approved_fee(caller, fee).await? {
+            return Ok(CreationFee::ApprovedIcp(fee));
+        }
+
+        self.consume_provided_icp(caller).await?;
+        Ok(CreationFee::DepositedIcp(fee))
+    }
+
+    /// Transfers the creation fee from the caller account with `icrc2_transfer_from`. Returns
+    /// `false` if the fee was not collected because the allowance is not sufficient, or the ledger
+    /// doesn't support ICRC-2.
+    async fn collect_approved_fee(
+        &self,
+        caller: Principal,
+        fee: u64,
+    ) -> Result<bool, TokenFactoryError> {
+        if fee == 0 {
+            return Ok(false);
+        }
+
+        let (ledger, recipient) = {
+            let factory_state = self.factory_state();
+            let factory_state = factory_state.borrow();
+            (
+                factory_state.configuration.ledger_principal,
+                factory_state.configuration.icp_to,
+            )
+        };
+
+        match icrc2::transfer_from(ledger, caller, recipient, fee).await {
+            Ok(Ok(_)) => Ok(true),
+            Ok(Err(TransferFromError::InsufficientAllowance { .. })) | Err(_) => Ok(false),
+            Ok(Err(e)) => Err(TokenFactoryError::FeeTransferFailed(format!("{e:?}"))),
+        }
     }
 
     #[update]
@@ -159,17 +546,168 @@ impl TokenFactoryCanister {
             .ok_or(TokenFactoryError::FactoryError(FactoryError::NotFound))?;
 
         self.drop_canister(canister_id, None).await?;
-        self.state.borrow_mut().tokens.remove(&name);
+        let mut state = self.state.borrow_mut();
+        state.unregister_token(&name);
+        state.token_versions.remove(&canister_id);
+        state.remove_deployed_token(canister_id);
+
+        Ok(())
+    }
+
+    /// Retires the token, e.g. an abandoned test token. The token canister is stopped, or, if
+    /// `withdraw_cycles` is set, deleted with its remaining cycles sent to the factory. The token
+    /// name is released, and the token is listed by `get_tokens` as retired. Only the factory
+    /// controller is allowed to call this method.
+    #[update]
+    pub async fn decommission_token(
+        &self,
+        principal: Principal,
+        withdraw_cycles: bool,
+    ) -> Result<(), TokenFactoryError> {
+        if self.factory_state().borrow().controller() != ic_canister::ic_kit::ic::caller() {
+            return Err(TokenFactoryError::NotController);
+        }
+
+        let name = self
+            .state
+            .borrow()
+            .registered_tokens()
+            .into_iter()
+            .find(|(_, token)| *token == principal)
+            .map(|(name, _)| name)
+            .ok_or(TokenFactoryError::TokenNotFound)?;
+
+        if withdraw_cycles {
+            self.drop_canister(principal, Some(ic_canister::ic_kit::ic::id()))
+                .await?;
+        } else {
+            management::stop_canister(principal).await?;
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.unregister_token(&name);
+        state.token_versions.remove(&principal);
+        state.retire_token(principal, ic_canister::ic_kit::ic::time());
+
+        Ok(())
+    }
+
+    /// Sets the controllers of the token canister, e.g. to add the owner principal or a blackhole
+    /// canister. The factory is always kept as a controller, so it can upgrade the token. Only the
+    /// token owner or the factory controller is allowed to call this method.
+    #[update]
+    pub async fn set_token_controllers(
+        &self,
+        token: Principal,
+        mut controllers: Vec<Principal>,
+    ) -> Result<(), TokenFactoryError> {
+        self.check_token_owner(token)?;
+
+        let factory = ic_canister::ic_kit::ic::id();
+        if !controllers.contains(&factory) {
+            controllers.push(factory);
+        }
+
+        management::set_controllers(token, controllers).await
+    }
+
+    /// Returns the controllers of the token canister.
+    #[update]
+    pub async fn get_token_controllers(
+        &self,
+        token: Principal,
+    ) -> Result<Vec<Principal>, TokenFactoryError> {
+        if self.state.borrow().deployed_token(token).is_none() {
+            return Err(TokenFactoryError::TokenNotFound);
+        }
+
+        let status = management::canister_status(token).await?;
+        Ok(status.settings.controllers)
+    }
+
+    /// Updates the owner of the token in the registry. Called by the token canister when its owner
+    /// change is applied.
+    #[update]
+    pub fn notify_owner_changed(&self, owner: Principal) -> Result<(), TokenFactoryError> {
+        let token = ic_canister::ic_kit::ic::caller();
+        if !self.state.borrow_mut().set_token_owner(token, owner) {
+            return Err(TokenFactoryError::TokenNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the owner from the token canister and updates it in the registry, for the owner
+    /// changes the factory was not notified about. Returns the current owner.
+    #[update]
+    pub async fn sync_token_owner(&self, token: Principal) -> Result<Principal, TokenFactoryError> {
+        if self.state.borrow().deployed_token(token).is_none() {
+            return Err(TokenFactoryError::TokenNotFound);
+        }
+
+        let owner = virtual_canister_call!(token, "owner", (), Principal)
+            .await
+            .map_err(|(_, message)| TokenFactoryError::TokenCallFailed(message))?;
+        if !self.state.borrow_mut().set_token_owner(token, owner) {
+            return Err(TokenFactoryError::TokenNotFound);
+        }
+
+        Ok(owner)
+    }
+
+    /// Returns the memory size, the cycles balance and the module hash of the token canister, for
+    /// the token owners that are not its controllers. Only the token owner or the factory
+    /// controller is allowed to call this method.
+    #[update]
+    pub async fn get_token_status(
+        &self,
+        principal: Principal,
+    ) -> Result<TokenStatus, TokenFactoryError> {
+        self.check_token_owner(principal)?;
+
+        let status = management::canister_status(principal).await?;
+        Ok(TokenStatus {
+            memory_size: status.memory_size,
+            cycles: status.cycles,
+            module_hash: status.module_hash,
+        })
+    }
+
+    fn check_token_owner(&self, token: Principal) -> Result<(), TokenFactoryError> {
+        let caller = ic_canister::ic_kit::ic::caller();
+        let owner = self
+            .state
+            .borrow()
+            .deployed_token(token)
+            .ok_or(TokenFactoryError::TokenNotFound)?
+            .owner;
+        if caller != owner && caller != self.factory_state().borrow().controller() {
+            return Err(TokenFactoryError::NotTokenOwner);
+        }
 
         Ok(())
     }
 
+    /// Upgrades the deployed tokens to the current wasm. The tokens that were upgraded or already
+    /// run the current wasm are recorded as running the current version.
     #[update]
     pub async fn upgrade(
         &mut self,
-    ) -> Result<std::collections::HashMap<Principal, ic_factory::api::UpgradeResult>, FactoryError>
-    {
-        self.upgrade_canister::<token::state::CanisterState>().await
+    ) -> Result<std::collections::HashMap<Principal, UpgradeResult>, FactoryError> {
+        let results = self
+            .upgrade_canister::<token::state::CanisterState>()
+            .await?;
+
+        let mut state = self.state.borrow_mut();
+        if let Some(version) = state.current_version.clone() {
+            for (principal, result) in &results {
+                if matches!(result, UpgradeResult::Upgraded | UpgradeResult::Noop) {
+                    state.token_versions.insert(*principal, version.clone());
+                }
+            }
+        }
+
+        Ok(results)
     }
 
     #[query]
@@ -178,6 +716,91 @@ impl TokenFactoryCanister {
     }
 }
 
+/// Checks that the token can be created with the metadata, so that the creation fee is not spent
+/// on a token that fails to initialize or cannot be used.
+fn validate_metadata(info: &Metadata) -> Result<(), TokenFactoryError> {
+    if info.name.is_empty() {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "name",
+            "cannot be `None`",
+        ));
+    }
+
+    if info.name.len() > stable::MAX_TOKEN_NAME_LEN {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "name",
+            "cannot be longer than 256 bytes",
+        ));
+    }
+
+    if info.symbol.is_empty() {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "symbol",
+            "cannot be `None`",
+        ));
+    }
+
+    if info.symbol.len() > MAX_SYMBOL_LEN {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "symbol",
+            "cannot be longer than 10 characters",
+        ));
+    }
+
+    if !info.symbol.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "symbol",
+            "can only contain latin letters and digits",
+        ));
+    }
+
+    if info.decimals > MAX_DECIMALS {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "decimals",
+            "cannot be greater than 18",
+        ));
+    }
+
+    if info.fee > Tokens128::ZERO && info.fee >= info.totalSupply {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "fee",
+            "must be less than the total supply",
+        ));
+    }
+
+    if info.owner == Principal::anonymous() {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "owner",
+            "cannot be anonymous",
+        ));
+    }
+
+    if matches!(info.maxSupply, Some(max_supply) if max_supply < info.totalSupply) {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "maxSupply",
+            "cannot be less than the total supply",
+        ));
+    }
+
+    if let Some(entries) = &info.customMetadata {
+        if !entries.iter().all(|(key, _)| valid_metadata_key(key)) {
+            return Err(TokenFactoryError::InvalidConfiguration(
+                "customMetadata",
+                "keys must be non-empty, at most 64 bytes long and not in the icrc1 namespace",
+            ));
+        }
+    }
+
+    if info.initial_balances().is_none() {
+        return Err(TokenFactoryError::InvalidConfiguration(
+            "genesisAllocation",
+            "must sum up to the total supply",
+        ));
+    }
+
+    Ok(())
+}
+
 impl PreUpdate for TokenFactoryCanister {}
 impl FactoryCanister for TokenFactoryCanister {
     fn factory_state(&self) -> Rc<RefCell<FactoryState>> {
@@ -185,3 +808,82 @@ impl FactoryCanister for TokenFactoryCanister {
         FactoryState::get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use candid::Nat;
+    use ic_canister::ic_kit::mock_principals::{alice, john};
+    use ic_canister::ic_kit::{ic, MockContext};
+    use ic_canister::register_virtual_responder;
+
+    use crate::icrc2::TransferFromArgs;
+
+    use super::*;
+
+    const CYCLES_PER_ICP: u64 = 10u64.pow(12);
+
+    /// Factory controlled by `alice` with `john` as the ledger, called by `alice`.
+    fn test_factory() -> (&'static mut MockContext, TokenFactoryCanister) {
+        let context = MockContext::new().with_caller(alice()).inject();
+        let factory = TokenFactoryCanister::init_instance();
+        factory.init(alice(), Some(john()));
+
+        (context, factory)
+    }
+
+    #[tokio::test]
+    async fn credit_used_instead_of_fee() {
+        let (context, factory) = test_factory();
+        factory.set_cycles_per_icp(Some(CYCLES_PER_ICP)).unwrap();
+        factory.state.borrow_mut().add_creation_credit(alice());
+        context.update_msg_cycles(2 * CYCLES_PER_ICP);
+
+        let fee = factory.collect_creation_fee(alice()).await.unwrap();
+        assert_eq!(fee, CreationFee::Credit);
+        assert_eq!(ic::msg_cycles_available(), 2 * CYCLES_PER_ICP);
+        assert_eq!(factory.get_creation_credits(alice()), 0);
+    }
+
+    #[tokio::test]
+    async fn fee_paid_with_cycles() {
+        let (context, factory) = test_factory();
+        factory.set_cycles_per_icp(Some(CYCLES_PER_ICP)).unwrap();
+        context.update_msg_cycles(2 * CYCLES_PER_ICP);
+
+        let fee = factory.collect_creation_fee(alice()).await.unwrap();
+        assert_eq!(fee, CreationFee::Cycles(CYCLES_PER_ICP));
+        assert_eq!(ic::msg_cycles_available(), CYCLES_PER_ICP);
+
+        context.update_msg_cycles(CYCLES_PER_ICP - 1);
+        assert!(matches!(
+            factory.collect_creation_fee(alice()).await,
+            Err(TokenFactoryError::InsufficientCycles(CYCLES_PER_ICP))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fee_paid_with_approved_icp() {
+        register_virtual_responder(
+            john(),
+            "icrc2_transfer_from",
+            |(args,): (TransferFromArgs,)| {
+                assert_eq!(args.from.owner, alice());
+                assert_eq!(args.amount, Nat::from(DEFAULT_ICP_FEE));
+                Ok::<Nat, TransferFromError>(Nat::from(1))
+            },
+        );
+        let (_, factory) = test_factory();
+
+        let fee = factory.collect_creation_fee(alice()).await.unwrap();
+        assert_eq!(fee, CreationFee::ApprovedIcp(DEFAULT_ICP_FEE));
+    }
+
+    #[tokio::test]
+    async fn no_fee_when_icp_fee_is_zero() {
+        let (_, factory) = test_factory();
+        factory.set_creation_fee(0).unwrap();
+
+        let fee = factory.collect_creation_fee(alice()).await.unwrap();
+        assert_eq!(fee, CreationFee::Free);
+    }
+}
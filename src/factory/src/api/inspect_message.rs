@@ -9,7 +9,8 @@ fn inspect_message() {
     let factory = ic_factory::FactoryState::get();
     let factory = factory.borrow();
 
-    if ic_cdk::api::call::method_name() == "set_token_bytecode"
+    let method = ic_cdk::api::call::method_name();
+    if ["set_token_bytecode", "upload_wasm", "commit_wasm"].contains(&method.as_str())
         && factory.controller() == ic_canister::ic_kit::ic::caller()
     {
         return ic_cdk::api::call::accept_message();
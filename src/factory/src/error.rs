@@ -10,6 +10,36 @@ pub enum TokenFactoryError {
     #[error("a token with the same name is already registered")]
     AlreadyExists,
 
+    #[error("a token with the same symbol is already registered")]
+    SymbolTaken,
+
+    #[error("the method can only be called by the factory controller")]
+    NotController,
+
+    #[error("the method can only be called by the token owner or the factory controller")]
+    NotTokenOwner,
+
+    #[error("the token is not registered in the factory")]
+    TokenNotFound,
+
+    #[error("management canister call failed: {0}")]
+    ManagementCallFailed(String),
+
+    #[error("token canister call failed: {0}")]
+    TokenCallFailed(String),
+
+    #[error("the uploaded wasm chunks are missing or not contiguous")]
+    MissingWasmChunks,
+
+    #[error("the hash of the uploaded wasm doesn't match the expected one")]
+    WasmHashMismatch,
+
+    #[error("failed to collect the creation fee: {0}")]
+    FeeTransferFailed(String),
+
+    #[error("not enough cycles attached to pay the creation fee of {0} cycles")]
+    InsufficientCycles(u64),
+
     #[error(transparent)]
     FactoryError(#[from] FactoryError),
 }
@@ -0,0 +1,70 @@
+//! ICRC-2 ledger calls used to collect the creation fee from an approved allowance.
+
+use candid::{CandidType, Nat, Principal};
+use ic_canister::virtual_canister_call;
+use ic_cdk::api::call::RejectionCode;
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+pub async fn transfer_from(
+    ledger: Principal,
+    from: Principal,
+    to: Principal,
+    amount: u64,
+) -> Result<Result<Nat, TransferFromError>, (RejectionCode, String)> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: from.into(),
+        to: to.into(),
+        amount: amount.into(),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    virtual_canister_call!(
+        ledger,
+        "icrc2_transfer_from",
+        (args,),
+        Result<Nat, TransferFromError>
+    )
+    .await
+}
@@ -1,5 +1,8 @@
 pub mod api;
 mod error;
+mod icrc2;
+mod management;
+mod stable;
 pub mod state;
 
 pub use self::api::*;
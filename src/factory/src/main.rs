@@ -1,5 +1,8 @@
 mod api;
 mod error;
+mod icrc2;
+mod management;
+mod stable;
 mod state;
 
 #[cfg(any(target_arch = "wasm32", test))]
@@ -0,0 +1,84 @@
+//! Calls to the management canister made by the factory on behalf of the token canisters.
+
+use candid::{CandidType, Nat, Principal};
+use ic_canister::virtual_canister_call;
+use serde::Deserialize;
+
+use crate::error::TokenFactoryError;
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct CanisterIdRecord {
+    pub canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize, Debug, Default)]
+pub struct CanisterSettings {
+    pub controllers: Option<Vec<Principal>>,
+    pub compute_allocation: Option<Nat>,
+    pub memory_allocation: Option<Nat>,
+    pub freezing_threshold: Option<Nat>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct UpdateSettingsArgument {
+    pub canister_id: Principal,
+    pub settings: CanisterSettings,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct DefiniteCanisterSettings {
+    pub controllers: Vec<Principal>,
+}
+
+/// Part of the `canister_status` response used by the factory.
+#[derive(CandidType, Deserialize, Debug)]
+pub struct CanisterStatus {
+    pub settings: DefiniteCanisterSettings,
+    pub module_hash: Option<Vec<u8>>,
+    pub memory_size: Nat,
+    pub cycles: Nat,
+}
+
+pub async fn stop_canister(canister_id: Principal) -> Result<(), TokenFactoryError> {
+    virtual_canister_call!(
+        Principal::management_canister(),
+        "stop_canister",
+        (CanisterIdRecord { canister_id },),
+        ()
+    )
+    .await
+    .map_err(|(_, message)| TokenFactoryError::ManagementCallFailed(message))
+}
+
+pub async fn canister_status(canister_id: Principal) -> Result<CanisterStatus, TokenFactoryError> {
+    virtual_canister_call!(
+        Principal::management_canister(),
+        "canister_status",
+        (CanisterIdRecord { canister_id },),
+        CanisterStatus
+    )
+    .await
+    .map_err(|(_, message)| TokenFactoryError::ManagementCallFailed(message))
+}
+
+pub async fn set_controllers(
+    canister_id: Principal,
+    controllers: Vec<Principal>,
+) -> Result<(), TokenFactoryError> {
+    let argument = UpdateSettingsArgument {
+        canister_id,
+        settings: CanisterSettings {
+            controllers: Some(controllers),
+            ..Default::default()
+        },
+    };
+
+    virtual_canister_call!(
+        Principal::management_canister(),
+        "update_settings",
+        (argument,),
+        ()
+    )
+    .await
+    .map_err(|(_, message)| TokenFactoryError::ManagementCallFailed(message))
+}
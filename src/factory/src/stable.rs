@@ -0,0 +1,204 @@
+//! Layout of the factory stable memory.
+//!
+//! The stable memory is split into virtual memories by the [MemoryManager]. The token registry
+//! and the factory configuration are updated on every change, so they are kept even if the state
+//! serialized in the `pre_upgrade` hook is lost. The uploaded wasm chunks are kept in the stable
+//! memory too, so a large upload doesn't grow the serialized state. The rest of the factory state
+//! is written into the [UPGRADE_MEMORY_ID] memory on upgrade.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::Principal;
+use ic_factory::FactoryConfiguration;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory, StableBTreeMap, Storable};
+
+pub type StableMemory = VirtualMemory<DefaultMemoryImpl>;
+
+pub const UPGRADE_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const TOKENS_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const CONFIGURATION_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const WASM_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(3);
+
+/// Max length of the token name in bytes.
+pub const MAX_TOKEN_NAME_LEN: usize = 256;
+
+/// The uploaded wasm chunks are stored in pieces of at most this length, as the stable map
+/// reserves the max value size for every entry.
+pub const WASM_PIECE_LEN: usize = 64 * 1024;
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const MAX_PRINCIPAL_LEN: usize = 29;
+const MEMORY_MANAGER_MAGIC: &[u8; 3] = b"MGR";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    /// Principals of the registered tokens by their names.
+    pub(crate) static TOKENS: RefCell<StableBTreeMap<StableMemory, TokenName, StablePrincipal>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(TOKENS_MEMORY_ID),
+            MAX_TOKEN_NAME_LEN as u32,
+            StablePrincipal::SIZE as u32,
+        ));
+
+    /// Pieces of the wasm chunks uploaded with `upload_wasm`, waiting for `commit_wasm`.
+    pub(crate) static WASM_CHUNKS: RefCell<StableBTreeMap<StableMemory, WasmPieceKey, WasmPiece>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(WASM_CHUNKS_MEMORY_ID),
+            WasmPieceKey::SIZE as u32,
+            WASM_PIECE_LEN as u32,
+        ));
+}
+
+pub fn get_memory(id: MemoryId) -> StableMemory {
+    MEMORY_MANAGER.with(|mm| mm.borrow().get(id))
+}
+
+/// Whether the stable memory contains the state written with `ic_storage` before the memory
+/// manager was used. Must be checked before any stable structure is accessed, as the memory
+/// manager overwrites the beginning of the stable memory on initialization.
+pub fn has_legacy_layout() -> bool {
+    let memory = DefaultMemoryImpl::default();
+    if memory.size() == 0 {
+        return false;
+    }
+
+    let mut magic = [0u8; 3];
+    memory.read(0, &mut magic);
+    &magic != MEMORY_MANAGER_MAGIC
+}
+
+/// Writes the serialized heap state into the upgrade memory. Must be called in `pre_upgrade`.
+pub fn write_upgrade_data(data: &[u8]) {
+    write_data(UPGRADE_MEMORY_ID, data);
+}
+
+/// Reads the heap state written by [write_upgrade_data]. Returns an empty vec if nothing was
+/// written.
+pub fn read_upgrade_data() -> Vec<u8> {
+    read_data(UPGRADE_MEMORY_ID)
+}
+
+pub fn write_configuration(configuration: &FactoryConfiguration) {
+    let data = candid::encode_one(configuration).expect("failed to encode factory configuration");
+    write_data(CONFIGURATION_MEMORY_ID, &data);
+}
+
+/// Reads the configuration written by [write_configuration], or None if nothing was written.
+pub fn read_configuration() -> Option<FactoryConfiguration> {
+    let data = read_data(CONFIGURATION_MEMORY_ID);
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(candid::decode_one(&data).expect("failed to decode factory configuration"))
+}
+
+fn write_data(id: MemoryId, data: &[u8]) {
+    let memory = get_memory(id);
+    let required_pages = (8 + data.len() as u64 + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+    let current_pages = memory.size();
+    if current_pages < required_pages && memory.grow(required_pages - current_pages) < 0 {
+        ic_cdk::trap("failed to grow stable memory");
+    }
+
+    memory.write(0, &(data.len() as u64).to_le_bytes());
+    memory.write(8, data);
+}
+
+fn read_data(id: MemoryId) -> Vec<u8> {
+    let memory = get_memory(id);
+    if memory.size() == 0 {
+        return Vec::new();
+    }
+
+    let mut len = [0u8; 8];
+    memory.read(0, &mut len);
+    let mut data = vec![0u8; u64::from_le_bytes(len) as usize];
+    memory.read(8, &mut data);
+
+    data
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TokenName(pub String);
+
+impl Storable for TokenName {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(String::from_utf8(bytes).expect("token name is a valid utf-8 string"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StablePrincipal(pub Principal);
+
+impl StablePrincipal {
+    const SIZE: usize = 1 + MAX_PRINCIPAL_LEN;
+}
+
+impl Storable for StablePrincipal {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let slice = self.0.as_slice();
+        let mut bytes = vec![0u8; Self::SIZE];
+        bytes[0] = slice.len() as u8;
+        bytes[1..1 + slice.len()].copy_from_slice(slice);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let len = bytes[0] as usize;
+        Self(Principal::from_slice(&bytes[1..1 + len]))
+    }
+}
+
+/// Key of a piece of an uploaded wasm chunk. The keys are ordered by the chunk index and then by
+/// the piece index, so the pieces are iterated in the wasm order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WasmPieceKey {
+    pub chunk: u32,
+    pub piece: u32,
+}
+
+impl WasmPieceKey {
+    const SIZE: usize = 8;
+
+    /// Key prefix shared by all the pieces of the chunk.
+    pub fn prefix(chunk: u32) -> Vec<u8> {
+        chunk.to_be_bytes().to_vec()
+    }
+}
+
+impl Storable for WasmPieceKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = self.chunk.to_be_bytes().to_vec();
+        bytes.extend(self.piece.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            chunk: u32::from_be_bytes(bytes[..4].try_into().expect("chunk index is 4 bytes")),
+            piece: u32::from_be_bytes(bytes[4..8].try_into().expect("piece index is 4 bytes")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmPiece(pub Vec<u8>);
+
+impl Storable for WasmPiece {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
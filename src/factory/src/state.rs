@@ -1,15 +1,392 @@
-use candid::Principal;
+use candid::{Nat, Principal};
 use ic_cdk::export::candid::CandidType;
 use ic_factory::FactoryState;
 use ic_storage::{stable::Versioned, IcStorage};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::stable::{
+    StablePrincipal, TokenName, WasmPiece, WasmPieceKey, TOKENS, WASM_CHUNKS, WASM_PIECE_LEN,
+};
 
 #[derive(CandidType, Deserialize, IcStorage, Default, Debug)]
 pub struct State {
     pub token_wasm: Option<Vec<u8>>,
-    /// Associated list of token name and its principal
+    /// Associated list of token name and its principal. Not used since the registry was moved
+    /// into the stable memory, kept to migrate the state written by the previous versions.
     pub tokens: HashMap<String, Principal>,
+    /// Tokens deployed by the factory in the creation order, see `get_tokens`.
+    #[serde(default)]
+    pub deployed_tokens: Vec<DeployedToken>,
+    /// Version of the token wasm set by the last `set_token_bytecode` call.
+    #[serde(default)]
+    pub current_version: Option<TokenVersion>,
+    /// Versions of the wasm the deployed tokens are running. The tokens deployed before the
+    /// versions were tracked are not listed.
+    #[serde(default)]
+    pub token_versions: HashMap<Principal, TokenVersion>,
+    /// Number of cycles equivalent to 1 ICP, used to charge the creation fee in cycles. If it's
+    /// not set, the tokens created with cycles are not charged a fee.
+    #[serde(default)]
+    pub cycles_per_icp: Option<u64>,
+    /// If set, a token cannot be created with the symbol of another active token, see
+    /// `is_symbol_available`.
+    #[serde(default)]
+    pub unique_symbols: bool,
+    /// Number of free creations of the principals whose paid creation failed, see
+    /// `get_creation_credits`.
+    #[serde(default)]
+    pub creation_credits: HashMap<Principal, u32>,
+    /// Append-only log of the token creations, see `get_creation_events`.
+    #[serde(default)]
+    pub creation_events: Vec<CreationEvent>,
+    /// Aggregate statistics of the token creation, see `get_factory_stats`.
+    #[serde(default)]
+    pub stats: CreationStats,
+    /// Lowercase names and symbols of the deployed tokens, see `find_tokens`.
+    #[serde(default)]
+    pub search_index: BTreeMap<String, BTreeSet<Principal>>,
+    /// Names of the tokens being created, see `reserve_token`.
+    #[serde(default)]
+    pub reserved_names: HashSet<String>,
+    /// Lowercase symbols of the tokens being created, see `reserve_token`.
+    #[serde(default)]
+    pub reserved_symbols: HashSet<String>,
+}
+
+impl State {
+    /// Principal of the registered token with the name.
+    pub fn token(&self, name: &str) -> Option<Principal> {
+        TOKENS.with(|tokens| {
+            tokens
+                .borrow()
+                .get(&TokenName(name.to_string()))
+                .map(|principal| principal.0)
+        })
+    }
+
+    /// Registered token names and principals, ordered by name.
+    pub fn registered_tokens(&self) -> Vec<(String, Principal)> {
+        TOKENS.with(|tokens| {
+            tokens
+                .borrow()
+                .iter()
+                .map(|(name, principal)| (name.0, principal.0))
+                .collect()
+        })
+    }
+
+    pub fn register_token(&mut self, name: String, principal: Principal) {
+        TOKENS.with(|tokens| {
+            tokens
+                .borrow_mut()
+                .insert(TokenName(name), StablePrincipal(principal))
+                .expect("token name length is checked on creation");
+        });
+    }
+
+    pub fn unregister_token(&mut self, name: &str) {
+        TOKENS.with(|tokens| tokens.borrow_mut().remove(&TokenName(name.to_string())));
+    }
+
+    /// Whether the name is registered or reserved by a creation in progress.
+    pub fn is_name_taken(&self, name: &str) -> bool {
+        self.reserved_names.contains(name) || self.token(name).is_some()
+    }
+
+    /// Reserves the name and the symbol of the token being created, so the concurrent creations
+    /// don't pass the uniqueness checks while this one waits for the fee and the canister. The
+    /// reservation must be released with [Self::release_token] when the creation is finished.
+    pub fn reserve_token(&mut self, name: &str, symbol: &str) {
+        self.reserved_names.insert(name.to_string());
+        self.reserved_symbols.insert(symbol.to_lowercase());
+    }
+
+    pub fn release_token(&mut self, name: &str, symbol: &str) {
+        self.reserved_names.remove(name);
+        self.reserved_symbols.remove(&symbol.to_lowercase());
+    }
+
+    /// Replaces the uploaded wasm chunk with the index.
+    pub fn insert_wasm_chunk(&mut self, index: u32, chunk: &[u8]) {
+        WASM_CHUNKS.with(|chunks| {
+            let mut chunks = chunks.borrow_mut();
+            let previous = chunks
+                .range(WasmPieceKey::prefix(index), None)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+            for key in previous {
+                chunks.remove(&key);
+            }
+
+            // An empty chunk is stored as a single empty piece, so it's not treated as missing.
+            let pieces = chunk.chunks(WASM_PIECE_LEN).map(<[u8]>::to_vec);
+            let pieces = if chunk.is_empty() {
+                vec![Vec::new()]
+            } else {
+                pieces.collect()
+            };
+            for (piece, bytes) in pieces.into_iter().enumerate() {
+                let key = WasmPieceKey {
+                    chunk: index,
+                    piece: piece as u32,
+                };
+                chunks
+                    .insert(key, WasmPiece(bytes))
+                    .expect("wasm piece length is limited");
+            }
+        });
+    }
+
+    pub fn clear_wasm_chunks(&mut self) {
+        WASM_CHUNKS.with(|chunks| {
+            let mut chunks = chunks.borrow_mut();
+            let keys = chunks.iter().map(|(key, _)| key).collect::<Vec<_>>();
+            for key in keys {
+                chunks.remove(&key);
+            }
+        });
+    }
+
+    /// The uploaded wasm chunks assembled in the index order, or None if no chunks are uploaded
+    /// or the indices are not contiguous.
+    pub fn assembled_wasm(&self) -> Option<Vec<u8>> {
+        WASM_CHUNKS.with(|chunks| {
+            let mut wasm = Vec::new();
+            let mut last_chunk = None;
+            for (key, piece) in chunks.borrow().iter() {
+                if last_chunk != Some(key.chunk) {
+                    if key.chunk != last_chunk.map_or(0, |last| last + 1) {
+                        return None;
+                    }
+                    last_chunk = Some(key.chunk);
+                }
+                wasm.extend(piece.0);
+            }
+
+            last_chunk.map(|_| wasm)
+        })
+    }
+
+    /// Uses one of the free creations of the principal. Returns false if it has none.
+    pub fn take_creation_credit(&mut self, principal: Principal) -> bool {
+        match self.creation_credits.get_mut(&principal) {
+            Some(credits) if *credits > 1 => *credits -= 1,
+            Some(_) => {
+                self.creation_credits.remove(&principal);
+            }
+            None => return false,
+        }
+
+        true
+    }
+
+    pub fn add_creation_credit(&mut self, principal: Principal) {
+        *self.creation_credits.entry(principal).or_default() += 1;
+    }
+
+    /// Records a failed creation of the `creator`. If the creation `fee` was paid, the creator is
+    /// credited with a free creation.
+    pub fn record_failed_creation(&mut self, creator: Principal, fee: CreationFee) {
+        self.stats.failed_creations += 1;
+        if fee != CreationFee::Free {
+            self.add_creation_credit(creator);
+        }
+    }
+
+    /// Moves the registry from the heap state into the stable memory.
+    pub fn migrate_registry(&mut self) {
+        for (name, principal) in std::mem::take(&mut self.tokens) {
+            self.register_token(name, principal);
+        }
+    }
+
+    pub fn add_deployed_token(&mut self, token: DeployedToken) {
+        for key in [&token.name, &token.symbol] {
+            self.search_index
+                .entry(key.to_lowercase())
+                .or_default()
+                .insert(token.principal);
+        }
+        self.deployed_tokens.push(token);
+    }
+
+    /// Marks the token as retired. The retired tokens are still listed by `get_tokens`, but they
+    /// are not found by `find_tokens`.
+    pub fn retire_token(&mut self, principal: Principal, retired_at: u64) {
+        if let Some(token) = self
+            .deployed_tokens
+            .iter_mut()
+            .find(|token| token.principal == principal)
+        {
+            token.retired_at = Some(retired_at);
+        }
+        self.search_index.retain(|_, tokens| {
+            tokens.remove(&principal);
+            !tokens.is_empty()
+        });
+    }
+
+    /// Whether no active token or a creation in progress has the symbol, ignoring the case.
+    pub fn is_symbol_available(&self, symbol: &str) -> bool {
+        let symbol = symbol.to_lowercase();
+        !self.reserved_symbols.contains(&symbol)
+            && !self
+                .deployed_tokens
+                .iter()
+                .any(|token| token.retired_at.is_none() && token.symbol.to_lowercase() == symbol)
+    }
+
+    pub fn deployed_token(&self, principal: Principal) -> Option<&DeployedToken> {
+        self.deployed_tokens
+            .iter()
+            .find(|token| token.principal == principal && token.retired_at.is_none())
+    }
+
+    /// Updates the owner of the active token. Returns false if there's no such token.
+    pub fn set_token_owner(&mut self, principal: Principal, owner: Principal) -> bool {
+        match self
+            .deployed_tokens
+            .iter_mut()
+            .find(|token| token.principal == principal && token.retired_at.is_none())
+        {
+            Some(token) => {
+                token.owner = owner;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_deployed_token(&mut self, principal: Principal) {
+        self.deployed_tokens
+            .retain(|token| token.principal != principal);
+        self.search_index.retain(|_, tokens| {
+            tokens.remove(&principal);
+            !tokens.is_empty()
+        });
+    }
+
+    /// Up to `limit` deployed tokens whose name or symbol starts with the `query`, ignoring the
+    /// case. The tokens are ordered by the matching name or symbol.
+    pub fn find_tokens(&self, query: &str, limit: usize) -> Vec<DeployedToken> {
+        let query = query.to_lowercase();
+        let mut found = Vec::new();
+        let matching = self
+            .search_index
+            .range(query.clone()..)
+            .take_while(|(key, _)| key.starts_with(&query))
+            .flat_map(|(_, tokens)| tokens);
+        for principal in matching {
+            if found.len() >= limit {
+                break;
+            }
+            if found
+                .iter()
+                .any(|token: &DeployedToken| token.principal == *principal)
+            {
+                continue;
+            }
+            if let Some(token) = self
+                .deployed_tokens
+                .iter()
+                .find(|token| token.principal == *principal)
+            {
+                found.push(token.clone());
+            }
+        }
+
+        found
+    }
+
+    /// Tokens that are not running the current wasm version, including the ones with unknown
+    /// version.
+    pub fn outdated_tokens(&self) -> Vec<Principal> {
+        self.registered_tokens()
+            .into_iter()
+            .map(|(_, principal)| principal)
+            .filter(|principal| {
+                self.current_version.is_some()
+                    && self.token_versions.get(principal) != self.current_version.as_ref()
+            })
+            .collect()
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenVersion {
+    /// Version number returned by `set_token_bytecode`.
+    pub version: u32,
+    /// SHA-256 hash of the wasm.
+    pub wasm_hash: Vec<u8>,
+}
+
+/// How the creation fee of a token was paid, see `create_token`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreationFee {
+    /// No fee is charged.
+    Free,
+    /// A creation credit of a failed creation was used instead of the fee.
+    Credit,
+    /// The fee was accepted from the attached cycles.
+    Cycles(u64),
+    /// The fee was transferred from the approved ICP allowance with `icrc2_transfer_from`.
+    ApprovedIcp(u64),
+    /// The fee was transferred from the ICP deposited to the caller subaccount.
+    DepositedIcp(u64),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CreationStats {
+    /// Number of tokens created by the factory, including the forgotten ones.
+    pub tokens_created: u64,
+    /// Creation fees paid in ICP, in e8s.
+    pub icp_fees_collected: u64,
+    /// Creation fees paid in cycles.
+    pub cycles_fees_collected: u64,
+    /// Cycles spent from the factory balance on creating the token canisters.
+    pub cycles_spent: u64,
+    /// Number of token creations that failed to create or install the canister.
+    #[serde(default)]
+    pub failed_creations: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FactoryStats {
+    pub creation: CreationStats,
+    /// Current cycles balance of the factory.
+    pub cycles_balance: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CreationEvent {
+    pub creator: Principal,
+    pub principal: Principal,
+    /// Creation fee paid in ICP, in e8s.
+    pub icp_fee: u64,
+    /// Creation fee paid in cycles.
+    pub cycles_fee: u64,
+    pub timestamp: u64,
+}
+
+/// Health of a token canister, see `get_token_status`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenStatus {
+    pub memory_size: Nat,
+    pub cycles: Nat,
+    /// SHA-256 hash of the installed wasm, None if the canister is empty.
+    pub module_hash: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeployedToken {
+    pub name: String,
+    pub symbol: String,
+    pub principal: Principal,
+    pub owner: Principal,
+    pub created_at: u64,
+    /// Time the token was decommissioned, see `decommission_token`.
+    pub retired_at: Option<u64>,
 }
 
 #[derive(CandidType, Deserialize, Default)]
@@ -42,3 +419,29 @@ impl Versioned for StableStateV1 {
         Self::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::alice;
+
+    use super::*;
+
+    #[test]
+    fn failed_creation_credits_paid_fee() {
+        let mut state = State::default();
+        for fee in [
+            CreationFee::Credit,
+            CreationFee::Cycles(1),
+            CreationFee::ApprovedIcp(1),
+            CreationFee::DepositedIcp(1),
+        ] {
+            state.record_failed_creation(alice(), fee);
+            assert!(state.take_creation_credit(alice()), "{fee:?}");
+            assert!(!state.take_creation_credit(alice()));
+        }
+
+        state.record_failed_creation(alice(), CreationFee::Free);
+        assert!(!state.take_creation_credit(alice()));
+        assert_eq!(state.stats.failed_creations, 5);
+    }
+}
@@ -0,0 +1,88 @@
+//! Benchmarks of the paths that get slower as the token grows: the transaction history query, the
+//! holders query and the transfer, on a state with 10k, 100k and 1M holders and transactions.
+//!
+//! ```shell
+//! cargo bench -p is20-token --bench hot_paths
+//! ```
+//!
+//! The native timings show the trends only. The wasm instruction counts, which are what the
+//! canister limits are about, are measured with the `bench_fill` and `bench_instructions` methods
+//! of the `token_test` canister.
+
+use candid::Principal;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ic_canister::ic_kit::MockContext;
+use ic_helpers::tokens::Tokens128;
+use is20_token::canister::erc20_transactions::transfer_tokens;
+use is20_token::state::CanisterState;
+use is20_token::types::SortOrder;
+
+const SIZES: &[u64] = &[10_000, 100_000, 1_000_000];
+
+fn holder(index: u64) -> Principal {
+    Principal::from_slice(&index.to_be_bytes())
+}
+
+/// Adds the holders and the transactions with the indices in `from..to` to the state. The sizes
+/// are benchmarked in the ascending order, so the state of the previous size is reused.
+fn fill(state: &mut CanisterState, from: u64, to: u64) {
+    for index in from..to {
+        let amount = Tokens128::from(index as u128 + 1);
+        state.balances.set(holder(index), amount);
+        state.ledger.mint(holder(0), holder(index), amount);
+    }
+}
+
+fn hot_paths(c: &mut Criterion) {
+    MockContext::new().inject();
+    let mut state = CanisterState::default();
+    // Sends the benchmarked transfers, so its balance never runs out.
+    let sender = Principal::management_canister();
+    state.balances.set(sender, Tokens128::from(u64::MAX as u128));
+
+    let mut group = c.benchmark_group("hot_paths");
+    group.sample_size(10);
+    let mut filled = 0;
+    for &size in SIZES {
+        fill(&mut state, filled, size);
+        filled = size;
+
+        group.bench_with_input(BenchmarkId::new("get_transactions", size), &size, |b, _| {
+            b.iter(|| {
+                state
+                    .ledger
+                    .get_transactions(None, 100, None, SortOrder::Desc)
+            })
+        });
+        // The worst case: the user has a single transaction, at the start of the history.
+        group.bench_with_input(
+            BenchmarkId::new("get_user_transactions", size),
+            &size,
+            |b, _| {
+                b.iter(|| {
+                    state
+                        .ledger
+                        .get_transactions(Some(holder(2)), 100, None, SortOrder::Desc)
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("get_holders", size), &size, |b, _| {
+            b.iter(|| state.balances.get_holders(None, 100))
+        });
+        group.bench_with_input(BenchmarkId::new("transfer", size), &size, |b, _| {
+            b.iter(|| {
+                transfer_tokens(
+                    &mut state,
+                    sender,
+                    holder(1),
+                    black_box(Tokens128::from(1)),
+                    None,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hot_paths);
+criterion_main!(benches);
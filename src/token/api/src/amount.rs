@@ -0,0 +1,124 @@
+//! Conversion of the token amounts between the smallest units and the decimal strings shown to the
+//! users, e.g. `123450000` is `"1.2345"` for a token with 8 decimals, and from the candid `nat`
+//! amounts of any size.
+
+use candid::Nat;
+use ic_helpers::tokens::Tokens128;
+
+use crate::types::TxError;
+
+/// Formats the amount as a decimal string with up to `decimals` fractional digits. Trailing zeros
+/// of the fractional part are omitted, as well as the decimal point for the whole amounts.
+pub fn format_amount(amount: Tokens128, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    let digits = amount.amount.to_string();
+    let digits = format!("{digits:0>width$}", width = decimals + 1);
+
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{whole}.{fraction}")
+    }
+}
+
+/// Parses a decimal string with up to `decimals` fractional digits into the amount in the
+/// smallest units. Returns `InvalidAmount` if the string is not a decimal number or has more
+/// fractional digits than the token, and `AmountOverflow` if the amount doesn't fit into 128 bits.
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<Tokens128, TxError> {
+    let decimals = decimals as usize;
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty()
+        || !is_digits(whole)
+        || !is_digits(fraction)
+        || (amount.contains('.') && fraction.is_empty())
+        || fraction.len() > decimals
+    {
+        return Err(TxError::InvalidAmount);
+    }
+
+    let digits = format!("{whole}{fraction:0<decimals$}");
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(Tokens128::ZERO);
+    }
+
+    digits
+        .parse::<u128>()
+        .map(Tokens128::from)
+        .map_err(|_| TxError::AmountOverflow)
+}
+
+/// Converts the `nat` amount given at the candid boundary into the amount in the smallest units.
+/// Returns `AmountOverflow` if the amount doesn't fit into 128 bits, e.g. for a bridged asset with
+/// 18 decimals and a huge supply, instead of failing the decoding of the call arguments.
+pub fn nat_to_amount(amount: &Nat) -> Result<Tokens128, TxError> {
+    u128::try_from(&amount.0)
+        .map(Tokens128::from)
+        .map_err(|_| TxError::AmountOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(0, 8, "0")]
+    #[test_case(1, 8, "0.00000001")]
+    #[test_case(123450000, 8, "1.2345")]
+    #[test_case(100000000, 8, "1")]
+    #[test_case(1000, 0, "1000")]
+    #[test_case(u128::MAX, 18, "340282366920938463463.374607431768211455")]
+    #[test_case(5, 40, "0.0000000000000000000000000000000000000005")]
+    fn format(amount: u128, decimals: u8, expected: &str) {
+        assert_eq!(format_amount(Tokens128::from(amount), decimals), expected);
+    }
+
+    #[test_case("0", 8, 0)]
+    #[test_case("0.00000001", 8, 1)]
+    #[test_case("1.2345", 8, 123450000)]
+    #[test_case("001", 8, 100000000)]
+    #[test_case("1000", 0, 1000)]
+    #[test_case("340282366920938463463.374607431768211455", 18, u128::MAX)]
+    fn parse(amount: &str, decimals: u8, expected: u128) {
+        assert_eq!(
+            parse_amount(amount, decimals),
+            Ok(Tokens128::from(expected))
+        );
+    }
+
+    #[test_case("", 8)]
+    #[test_case(".5", 8)]
+    #[test_case("1.", 8)]
+    #[test_case("-1", 8)]
+    #[test_case("1,5", 8)]
+    #[test_case("1.5", 0)]
+    #[test_case("0.000000001", 8)]
+    fn parse_invalid(amount: &str, decimals: u8) {
+        assert_eq!(parse_amount(amount, decimals), Err(TxError::InvalidAmount));
+    }
+
+    #[test]
+    fn parse_overflow() {
+        assert_eq!(
+            parse_amount("340282366920938463463.374607431768211456", 18),
+            Err(TxError::AmountOverflow)
+        );
+    }
+
+    #[test]
+    fn nat_conversion() {
+        assert_eq!(nat_to_amount(&Nat::from(0u64)), Ok(Tokens128::ZERO));
+        assert_eq!(
+            nat_to_amount(&Nat::from(u128::MAX)),
+            Ok(Tokens128::from(u128::MAX))
+        );
+        assert_eq!(
+            nat_to_amount(&(Nat::from(u128::MAX) + Nat::from(1u64))),
+            Err(TxError::AmountOverflow)
+        );
+    }
+}
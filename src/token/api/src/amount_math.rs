@@ -0,0 +1,157 @@
+//! Checked arithmetic of the balances, the total supply and the fee and reward shares.
+//!
+//! The operations that can fail because of the user input return a [TxError], so the callers can
+//! reject the call before changing the state. The share computations cannot fail for any input:
+//! the parts are capped at the total, so a share is never larger than the amount it's taken from.
+
+use ic_helpers::tokens::Tokens128;
+
+use crate::types::TxError;
+
+/// Precision of the ratios given as `f64`.
+const RATIO_PRECISION: u128 = 1_000_000_000_000;
+
+/// Adds the `amount` to the balance or the total supply. Returns `AmountOverflow` if the result
+/// doesn't fit into 128 bits.
+pub fn credit(balance: Tokens128, amount: Tokens128) -> Result<Tokens128, TxError> {
+    (balance + amount).ok_or(TxError::AmountOverflow)
+}
+
+/// Subtracts the `amount` from the balance or the total supply. Returns `InsufficientBalance` if
+/// the balance is less than the amount.
+pub fn debit(balance: Tokens128, amount: Tokens128) -> Result<Tokens128, TxError> {
+    (balance - amount).ok_or(TxError::InsufficientBalance)
+}
+
+/// Addition clamped at the maximum amount, for the counters that must not fail the operation
+/// they count, e.g. the total minted amount.
+pub fn saturating_add(amount: Tokens128, other: Tokens128) -> Tokens128 {
+    Tokens128::from(amount.amount.saturating_add(other.amount))
+}
+
+/// Subtraction clamped at zero, for the amounts that include the subtracted one by construction,
+/// e.g. the auction balance that includes the pending rewards.
+pub fn saturating_sub(amount: Tokens128, other: Tokens128) -> Tokens128 {
+    Tokens128::from(amount.amount.saturating_sub(other.amount))
+}
+
+/// `amount * part / total` rounded down. The `part` is capped at the `total`, and the share of a
+/// zero total is zero.
+pub fn proportional_share(amount: Tokens128, part: u128, total: u128) -> Tokens128 {
+    if total == 0 {
+        return Tokens128::ZERO;
+    }
+
+    let part = part.min(total);
+    (amount * Tokens128::from(part) / total)
+        .and_then(|share| share.to_tokens128())
+        .expect("division by a non-zero total, and the share is not over the amount")
+}
+
+/// `amount * ratio` rounded down, with the ratio clamped to `[0.0, 1.0]`. The ratio is applied
+/// with 12 digits of precision.
+pub fn ratio_share(amount: Tokens128, ratio: f64) -> Tokens128 {
+    let ratio = ratio.clamp(0.0, 1.0);
+    let part = (ratio * RATIO_PRECISION as f64) as u128;
+    proportional_share(amount, part, RATIO_PRECISION)
+}
+
+/// Splits the `amount` between the `parts` proportionally to `part / total`, rounding the shares
+/// down. The units left after rounding are given out one per part starting from the first one, so
+/// if the parts sum up to the total, the shares sum up to the amount exactly. The shares never sum
+/// up to more than the amount.
+pub fn split_proportionally(amount: Tokens128, parts: &[u128], total: u128) -> Vec<Tokens128> {
+    let mut shares = parts
+        .iter()
+        .map(|part| proportional_share(amount, *part, total).amount)
+        .collect::<Vec<_>>();
+
+    let sum = |values: &[u128]| values.iter().fold(0u128, |sum, v| sum.saturating_add(*v));
+    let leftover = amount.amount.saturating_sub(sum(&shares));
+    for share in shares
+        .iter_mut()
+        .take(leftover.min(usize::MAX as u128) as usize)
+    {
+        *share += 1;
+    }
+
+    debug_assert!(
+        sum(parts) > total || sum(&shares) <= amount.amount,
+        "shares are over the amount"
+    );
+
+    shares.into_iter().map(Tokens128::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn credit_and_debit() {
+        assert_eq!(
+            credit(Tokens128::from(1), Tokens128::from(2)),
+            Ok(Tokens128::from(3))
+        );
+        assert_eq!(
+            credit(Tokens128::from(u128::MAX), Tokens128::from(1)),
+            Err(TxError::AmountOverflow)
+        );
+        assert_eq!(
+            debit(Tokens128::from(3), Tokens128::from(2)),
+            Ok(Tokens128::from(1))
+        );
+        assert_eq!(
+            debit(Tokens128::from(1), Tokens128::from(2)),
+            Err(TxError::InsufficientBalance)
+        );
+        assert_eq!(
+            saturating_sub(Tokens128::from(1), Tokens128::from(2)),
+            Tokens128::ZERO
+        );
+        assert_eq!(
+            saturating_add(Tokens128::from(u128::MAX), Tokens128::from(1)),
+            Tokens128::from(u128::MAX)
+        );
+    }
+
+    #[test_case(100, 1, 3, 33)]
+    #[test_case(100, 5, 3, 100)]
+    #[test_case(100, 1, 0, 0)]
+    #[test_case(u128::MAX, 1, 1, u128::MAX)]
+    fn shares(amount: u128, part: u128, total: u128, expected: u128) {
+        assert_eq!(
+            proportional_share(Tokens128::from(amount), part, total),
+            Tokens128::from(expected)
+        );
+    }
+
+    #[test_case(0.5, 50)]
+    #[test_case(-1.0, 0)]
+    #[test_case(2.0, 100)]
+    fn ratio_shares(ratio: f64, expected: u128) {
+        assert_eq!(
+            ratio_share(Tokens128::from(100), ratio),
+            Tokens128::from(expected)
+        );
+    }
+
+    #[test]
+    fn split() {
+        let shares = split_proportionally(Tokens128::from(100), &[1, 1, 1], 3);
+        assert_eq!(
+            shares,
+            vec![
+                Tokens128::from(34),
+                Tokens128::from(33),
+                Tokens128::from(33)
+            ]
+        );
+
+        // If the parts sum up to less than the total, each part gets at most one extra unit.
+        let shares = split_proportionally(Tokens128::from(100), &[1, 1], 4);
+        assert_eq!(shares, vec![Tokens128::from(26), Tokens128::from(26)]);
+    }
+}
@@ -1,30 +1,69 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::Duration;
 
 use ic_canister::generate_exports;
 use ic_canister::Canister;
 use ic_canister::MethodType;
-use ic_cdk::export::candid::Principal;
+use ic_cdk::export::candid::{CandidType, Deserialize, Nat, Principal};
 use ic_storage::IcStorage;
 
-use crate::state::CanisterState;
+use crate::state::{
+    AdminOp, AdminProposal, BalanceLock, CanisterState, CyclesMonitor, Distribution, Escrow,
+    MultisigConfig, PendingOwnerChange, QueuedUpdate, ScheduledTransfer, Stream, Subscription,
+    TimelockedUpdate, Vesting,
+};
 
 use ic_canister::{query, update, AsyncReturn};
 use ic_helpers::tokens::Tokens128;
 
+use crate::amount::{format_amount, parse_amount};
+use crate::amount_math::{saturating_add, saturating_sub};
 use crate::canister::erc20_transactions::{
-    approve, burn_as_owner, burn_own_tokens, mint_as_owner, mint_test_token, transfer,
-    transfer_from,
+    approve, burn_as_owner, burn_own_tokens, fee_for_operation, icrc1_transfer, mint_as_minter,
+    mint_test_token, transfer, transfer_from,
 };
+use crate::canister::is20_account_data::set_account_data;
+use crate::canister::is20_airdrop::{claim_airdrop, set_airdrop};
 use crate::canister::is20_auction::{
-    auction_info, bid_cycles, bidding_info, run_auction, AuctionError, BiddingInfo,
+    auction_history, auction_info, auctions_by_time, bid_cycles, bid_tokens, bidding_info,
+    disburse_rewards, fee_ratio_details, run_auction, run_auction_with_randomness,
+    send_reward_notifications, withdraw_bid, AuctionError, BiddingInfo, FeeRatioDetails,
+};
+use crate::canister::is20_cycles::request_top_up;
+use crate::canister::is20_distribution::{distribute, process_distribution};
+use crate::canister::is20_escrow::{create_escrow, refund_escrow, release_escrow};
+use crate::canister::is20_freeze::{freeze_account, unfreeze_account};
+use crate::canister::is20_locks::lock_balance;
+use crate::canister::is20_migration::{export_allowances, export_ledger, import_allowances};
+use crate::canister::is20_multisig::{approve_admin_op, enable_multisig, propose_admin_op};
+use crate::canister::is20_notify::{
+    approve_and_call, approve_and_notify, consume_notification, notify, notify_batch,
+    retry_failed_notifications, retry_notification, safe_transfer, transfer_and_call,
+    transfer_with_hook,
+};
+use crate::canister::is20_ownership::{
+    apply_owner_change, cancel_owner_change, initiate_owner_change,
+};
+use crate::canister::is20_scheduler::{
+    cancel_scheduled_transfer, execute_scheduled_transfers, schedule_transfer,
+};
+use crate::canister::is20_streams::{close_stream, open_stream, withdraw_from_stream};
+use crate::canister::is20_subscriptions::{publish_events, subscribe, unsubscribe};
+use crate::canister::is20_timelock::{
+    cancel_update, execute_update, queue_update, set_timelock_delay,
 };
-use crate::canister::is20_notify::{approve_and_notify, consume_notification, notify};
 use crate::canister::is20_transactions::{batch_transfer, transfer_include_fee};
-use crate::principal::{CheckedPrincipal, Owner};
+use crate::canister::is20_vesting::{claim_vested, create_vesting};
+use crate::principal::{CheckedPrincipal, SettingsRole};
 use crate::types::{
-    AuctionInfo, Metadata, PaginatedResult, StatsData, Timestamp, TokenInfo, TxError, TxId,
-    TxReceipt, TxRecord,
+    valid_basis_points, valid_fee_bounds, valid_fee_distribution, valid_metadata_key, Account,
+    AccountError, AdminEvent, AllowanceEntry, AllowancesExport, AuctionInfo, AuctionRewardAsset,
+    AuctionStats, BidToken, Cycles, DailyMetrics, Event, FailedNotification, FeeMode, FeeSplit,
+    HealthInfo, HolderStats, HttpRequest, HttpResponse, LedgerChunk, Metadata, MethodPolicy,
+    Operation, PaginatedAuctions, PaginatedResult, SortOrder, StandardRecord, StatsData,
+    SubscriptionFilter, Timestamp, TokenInfo, TransferArg, TransferError, TxError, TxId, TxReceipt,
+    TxRecord, Value,
 };
 
 pub use inspect::AcceptReason;
@@ -33,22 +72,80 @@ pub mod erc20_transactions;
 
 mod inspect;
 
+pub mod is20_account_data;
+pub mod is20_airdrop;
 pub mod is20_auction;
+pub mod is20_cycles;
+pub mod is20_distribution;
+pub mod is20_escrow;
+pub mod is20_freeze;
+pub mod is20_http;
+pub mod is20_locks;
+pub mod is20_migration;
+pub mod is20_multisig;
 pub mod is20_notify;
+pub mod is20_ownership;
+pub mod is20_scheduler;
+pub mod is20_streams;
+pub mod is20_subscriptions;
+pub mod is20_timelock;
 pub mod is20_transactions;
+pub mod is20_vesting;
 
 pub(crate) const MAX_TRANSACTION_QUERY_LEN: usize = 1000;
+pub(crate) const MAX_HOLDERS_QUERY_LEN: usize = 1000;
+const TOP_HOLDERS_COUNT: usize = 10;
+const IS20_SPEC_URL: &str = "https://github.com/infinity-swap/IS20/blob/main/spec/IS20.md";
+const ICRC1_SPEC_URL: &str = "https://github.com/dfinity/ICRC-1";
 // 1 day in nanoseconds.
-pub const DEFAULT_AUCTION_PERIOD: Timestamp = 24 * 60 * 60 * 1_000_000;
+pub const DEFAULT_AUCTION_PERIOD: Timestamp = 24 * 60 * 60 * 1_000_000_000;
+/// Period of the timer running the background tasks, see [run_timer_tasks].
+pub const TIMER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Background tasks of the canister. They are run by the timer set up in `init` and
+/// `post_upgrade`, so that their cost is not added to the update calls of the users, and they are
+/// done even when there are no update calls.
+pub async fn run_timer_tasks(canister: &impl TokenCanisterAPI) {
+    // The rewards of the last auction are paid in chunks, one chunk per run.
+    disburse_rewards(canister);
+    process_distribution(canister);
+    execute_scheduled_transfers(canister);
+    canister.state().borrow().ledger.record_balance_changes();
+    request_top_up(canister).await;
+    publish_events(canister).await;
+    retry_failed_notifications(canister).await;
+}
 
 pub fn pre_update(canister: &impl TokenCanisterAPI, method_name: &str, _method_type: MethodType) {
-    if method_name != "runAuction" {
-        if let Err(auction_error) = canister.runAuction() {
+    // `inspect_message` is only run for the ingress calls, so the method policies are enforced
+    // here as well to apply them to the calls from other canisters.
+    let caller = ic_canister::ic_kit::ic::caller();
+    let policy_check =
+        inspect::check_method_policy(&canister.state().borrow(), method_name, caller);
+    if let Err(reason) = policy_check {
+        ic_canister::ic_kit::ic::trap(reason);
+    }
+
+    // Auctions with pending bids need a random seed from the management canister to split the
+    // rewards, which can only be obtained asynchronously, so such auctions are held only by the
+    // `runAuction` call.
+    let (has_bids, auction_disabled) = {
+        let state = canister.state();
+        let state = state.borrow();
+        (
+            !state.bidding_state.bids.is_empty(),
+            state.stats.auction_disabled,
+        )
+    };
+    if method_name != "runAuction" && !has_bids && !auction_disabled {
+        if let Err(auction_error) = run_auction(canister, Vec::new()) {
             ic_cdk::println!("Auction error: {auction_error:#?}");
         }
     }
 }
 
+/// Parameter change made by the owner. The changes are recorded in the event log.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
 pub enum CanisterUpdate {
     Name(String),
     Logo(String),
@@ -57,6 +154,39 @@ pub enum CanisterUpdate {
     Owner(Principal),
     MinCycles(u64),
     AuctionPeriod(u64),
+    BidExtensionWindow(u64),
+    CustomMetadata(Vec<(String, Value)>),
+    MetadataEntry(String, Option<Value>),
+    Guardian(Option<Principal>),
+    RewardAsset(AuctionRewardAsset),
+    BidToken(Option<BidToken>),
+    BidWithdrawalPenalty(u16),
+    NotifyBidders(bool),
+    AuctionDisabled(bool),
+    MintingAccount(Option<Principal>),
+    AddMinter(Principal),
+    RemoveMinter(Principal),
+    AddPauser(Principal),
+    RemovePauser(Principal),
+    AddAdmin(Principal),
+    RemoveAdmin(Principal),
+    AddReservedAccount(Principal),
+    RemoveReservedAccount(Principal),
+    AddNotificationConsumer(Principal),
+    RemoveNotificationConsumer(Principal),
+    MethodPolicy(String, Option<MethodPolicy>),
+    FeeMode(FeeMode),
+    FeeBounds {
+        min: Option<Tokens128>,
+        max: Option<Tokens128>,
+    },
+    FeeBurnRatio(u16),
+    FeeDistribution(Vec<(Principal, u32)>),
+    CyclesMonitor {
+        threshold: Cycles,
+        provider: Option<Principal>,
+    },
+    FaucetLimit(Option<Tokens128>),
 }
 
 #[allow(non_snake_case)]
@@ -100,11 +230,40 @@ pub trait TokenCanisterAPI: Canister + Sized {
         self.state().borrow().stats.decimals
     }
 
+    /// Formats the amount in the smallest units as a decimal string according to the token
+    /// `decimals`, e.g. `"1.2345"` for `123450000` with 8 decimals.
+    #[query(trait = true)]
+    fn formatAmount(&self, amount: Tokens128) -> String {
+        format_amount(amount, self.state().borrow().stats.decimals)
+    }
+
+    /// Parses a decimal string into the amount in the smallest units according to the token
+    /// `decimals`. The inverse of `formatAmount`.
+    #[query(trait = true)]
+    fn parseAmount(&self, amount: String) -> Result<Tokens128, TxError> {
+        parse_amount(&amount, self.state().borrow().stats.decimals)
+    }
+
     #[query(trait = true)]
     fn totalSupply(&self) -> Tokens128 {
         self.state().borrow().stats.total_supply
     }
 
+    /// Returns the total supply without the balances of the reserved accounts, see
+    /// `addReservedAccount`.
+    #[query(trait = true)]
+    fn circulatingSupply(&self) -> Tokens128 {
+        let state = self.state();
+        let state = state.borrow();
+        state
+            .stats
+            .reserved_accounts
+            .iter()
+            .fold(state.stats.total_supply, |supply, account| {
+                saturating_sub(supply, state.balances.balance_of(account))
+            })
+    }
+
     #[query(trait = true)]
     fn owner(&self) -> Principal {
         self.state().borrow().stats.owner
@@ -115,11 +274,57 @@ pub trait TokenCanisterAPI: Canister + Sized {
         self.state().borrow().get_metadata()
     }
 
+    /// Returns the token metadata in the ICRC-1 format, including the custom entries set by the
+    /// owner with `setMetadataEntry` or `setCustomMetadata`.
+    #[query(trait = true)]
+    fn icrc1_metadata(&self) -> Vec<(String, Value)> {
+        self.state().borrow().icrc1_metadata()
+    }
+
+    /// Returns the standards and the optional IS20 feature sets the canister was compiled with, so
+    /// the integrators can check if a method is available before calling it. The auction feature
+    /// set is listed only while the auctions are enabled.
+    #[query(trait = true)]
+    fn icrc1_supported_standards(&self) -> Vec<StandardRecord> {
+        let standard = |name: &str| StandardRecord {
+            name: name.to_string(),
+            url: IS20_SPEC_URL.to_string(),
+        };
+
+        let mut standards = vec![
+            StandardRecord {
+                name: "ICRC-1".to_string(),
+                url: ICRC1_SPEC_URL.to_string(),
+            },
+            standard("IS20"),
+        ];
+        if !self.state().borrow().stats.auction_disabled {
+            standards.push(standard("IS20-auction"));
+        }
+        if cfg!(feature = "mint_burn") {
+            standards.push(standard("IS20-mint_burn"));
+        }
+        if cfg!(feature = "transfer") {
+            standards.push(standard("IS20-transfer"));
+        }
+
+        standards
+    }
+
+    /// Serves the HTTP gateway requests: the canister metrics in the Prometheus text format, the
+    /// token info, logo, holders and transactions. See the [is20_http] module for the routes.
+    #[query(trait = true)]
+    fn http_request(&self, request: HttpRequest) -> HttpResponse {
+        is20_http::http_request(self, request)
+    }
+
     #[query(trait = true)]
     fn getTokenInfo(&self) -> TokenInfo {
         let StatsData {
             fee_to,
             deploy_time,
+            total_minted,
+            total_burned,
             ..
         } = self.state().borrow().stats;
         TokenInfo {
@@ -127,14 +332,70 @@ pub trait TokenCanisterAPI: Canister + Sized {
             feeTo: fee_to,
             historySize: self.state().borrow().ledger.len(),
             deployTime: deploy_time,
-            holderNumber: self.state().borrow().balances.0.len(),
+            holderNumber: self.state().borrow().balances.len(),
+            cycles: ic_canister::ic_kit::ic::balance(),
+            totalMinted: total_minted,
+            totalBurned: total_burned,
+        }
+    }
+
+    /// Returns the cycles balance, the memory usage, the history length, the number of the pending
+    /// notifications and the time of the last auction in one call.
+    #[query(trait = true)]
+    fn getHealth(&self) -> HealthInfo {
+        let state = self.state();
+        let state = state.borrow();
+        let pending_notifications = state.bidding_state.pending_notifications.len()
+            + state.ledger.failed_notifications.len();
+        HealthInfo {
             cycles: ic_canister::ic_kit::ic::balance(),
+            stable_memory_size: is20_http::stable_memory_size(),
+            heap_memory_size: is20_http::heap_memory_size(),
+            ledger_len: state.ledger.len(),
+            pending_notifications: pending_notifications as u64,
+            last_auction: state.bidding_state.last_auction,
         }
     }
 
+    /// Returns up to `limit` token holders ordered by balance descending and then by principal.
+    /// To get the next page, pass the last holder of the previous page as `after`.
+    #[query(trait = true)]
+    fn getHolders(
+        &self,
+        after: Option<(Principal, Tokens128)>,
+        limit: usize,
+    ) -> Vec<(Principal, Tokens128)> {
+        self.state()
+            .borrow()
+            .balances
+            .get_holders(after, limit.min(MAX_HOLDERS_QUERY_LEN))
+    }
+
+    /// Returns the number of holders, the share of the supply held by the largest holders and the
+    /// histogram of the holder balances.
     #[query(trait = true)]
-    fn getHolders(&self, start: usize, limit: usize) -> Vec<(Principal, Tokens128)> {
-        self.state().borrow().balances.get_holders(start, limit)
+    fn getHolderStats(&self) -> HolderStats {
+        let state = self.state();
+        let state = state.borrow();
+        let top_holders = state.balances.get_holders(None, TOP_HOLDERS_COUNT);
+        let top_holders_balance = top_holders
+            .iter()
+            .fold(Tokens128::ZERO, |acc, (_, amount)| {
+                saturating_add(acc, *amount)
+            });
+        let top_holders_share = if state.stats.total_supply.is_zero() {
+            0.0
+        } else {
+            top_holders_balance.amount as f64 / state.stats.total_supply.amount as f64
+        };
+
+        HolderStats {
+            holder_count: state.balances.len() as u64,
+            top_holders_balance,
+            top_holders_count: top_holders.len() as u64,
+            top_holders_share,
+            histogram: state.balances.histogram().to_vec(),
+        }
     }
 
     #[query(trait = true)]
@@ -147,11 +408,64 @@ pub trait TokenCanisterAPI: Canister + Sized {
         self.state().borrow().user_approvals(who)
     }
 
+    /// Returns the owners that approved the `spender` with the approved amounts.
+    #[query(trait = true)]
+    fn getApprovalsForSpender(&self, spender: Principal) -> Vec<(Principal, Tokens128)> {
+        self.state().borrow().spender_approvals(spender)
+    }
+
+    /// Returns a page of all the allowances of the token, ordered by the `(owner, spender)` pair
+    /// and starting right after the `cursor`. Used to move the existing approvals to another
+    /// canister or data model during migrations.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[query(trait = true)]
+    fn exportAllowances(
+        &self,
+        cursor: Option<(Principal, Principal)>,
+    ) -> Result<AllowancesExport, TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        Ok(export_allowances(self, caller, cursor))
+    }
+
+    /// Returns a chunk of the transaction history starting from the `offset` transaction id, for
+    /// off-chain backups and analytics. The chunk holds the transactions while their total candid
+    /// encoded size is within `max_bytes`, which is capped at 1 MiB so the response is never over
+    /// the size limit.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[query(trait = true)]
+    fn exportLedger(&self, offset: TxId, max_bytes: u64) -> Result<LedgerChunk, TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        Ok(export_ledger(self, caller, offset, max_bytes))
+    }
+
+    /// Sets the given allowances, e.g. the ones received from `exportAllowances` of the token
+    /// being migrated. Zero amount removes the allowance. The allowances can only be imported
+    /// before any transaction is made after the token creation.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn importAllowances(&self, entries: Vec<AllowanceEntry>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        import_allowances(self, caller, entries)
+    }
+
     #[query(trait = true)]
     fn balanceOf(&self, holder: Principal) -> Tokens128 {
         self.state().borrow().balances.balance_of(&holder)
     }
 
+    /// Returns the balance of the `holder` right after the transaction `transaction_id`. The
+    /// balances changed by every transaction, including the received fees, are recorded with it.
+    #[query(trait = true)]
+    fn balanceOfAt(&self, holder: Principal, transaction_id: TxId) -> Result<Tokens128, TxError> {
+        self.state()
+            .borrow()
+            .balance_of_at(holder, transaction_id)
+            .ok_or(TxError::TransactionDoesNotExist)
+    }
+
     #[query(trait = true)]
     fn allowance(&self, owner: Principal, spender: Principal) -> Tokens128 {
         self.state().borrow().allowance(owner, spender)
@@ -162,8 +476,19 @@ pub trait TokenCanisterAPI: Canister + Sized {
         self.state().borrow().ledger.len()
     }
 
-    fn update_stats(&self, _caller: CheckedPrincipal<Owner>, update: CanisterUpdate) {
+    /// Returns the activity counters of the days with transactions among the last `days` days,
+    /// the newest first. The counters are kept for the last 90 days with transactions.
+    #[query(trait = true)]
+    fn getMetrics(&self, days: u64) -> Vec<DailyMetrics> {
+        self.state().borrow().ledger.metrics(days)
+    }
+
+    fn update_stats<R: SettingsRole>(&self, caller: CheckedPrincipal<R>, update: CanisterUpdate) {
         use CanisterUpdate::*;
+        self.state()
+            .borrow_mut()
+            .ledger
+            .record_admin_event(caller.inner(), AdminEvent::Update(update.clone()));
         match update {
             Name(name) => self.state().borrow_mut().stats.name = name,
             Logo(logo) => self.state().borrow_mut().stats.logo = logo,
@@ -172,241 +497,1520 @@ pub trait TokenCanisterAPI: Canister + Sized {
             Owner(owner) => self.state().borrow_mut().stats.owner = owner,
             MinCycles(min_cycles) => self.state().borrow_mut().stats.min_cycles = min_cycles,
             AuctionPeriod(period_sec) => {
-                self.state().borrow_mut().bidding_state.auction_period = period_sec * 1_000_000
+                self.state().borrow_mut().bidding_state.auction_period =
+                    period_sec.saturating_mul(1_000_000_000)
+            }
+            BidExtensionWindow(window_sec) => {
+                self.state().borrow_mut().bidding_state.extension_window =
+                    window_sec.saturating_mul(1_000_000_000)
+            }
+            CustomMetadata(entries) => {
+                self.state().borrow_mut().custom_metadata = entries.into_iter().collect()
+            }
+            MetadataEntry(key, Some(value)) => {
+                self.state().borrow_mut().custom_metadata.insert(key, value);
+            }
+            MetadataEntry(key, None) => {
+                self.state().borrow_mut().custom_metadata.remove(&key);
+            }
+            Guardian(guardian) => self.state().borrow_mut().owner_rotation.guardian = guardian,
+            RewardAsset(asset) => self.state().borrow_mut().bidding_state.reward_asset = asset,
+            BidToken(token) => self.state().borrow_mut().bidding_state.bid_token = token,
+            AuctionDisabled(disabled) => {
+                self.state().borrow_mut().stats.auction_disabled = disabled
+            }
+            NotifyBidders(enabled) => {
+                self.state().borrow_mut().bidding_state.notify_bidders = enabled
+            }
+            BidWithdrawalPenalty(penalty_bps) => {
+                self.state()
+                    .borrow_mut()
+                    .bidding_state
+                    .withdrawal_penalty_bps = penalty_bps
+            }
+            MintingAccount(account) => self.state().borrow_mut().stats.minting_account = account,
+            AddMinter(minter) => {
+                self.state().borrow_mut().stats.minters.insert(minter);
+            }
+            RemoveMinter(minter) => {
+                self.state().borrow_mut().stats.minters.remove(&minter);
+            }
+            AddPauser(pauser) => {
+                self.state().borrow_mut().stats.pausers.insert(pauser);
             }
+            RemovePauser(pauser) => {
+                self.state().borrow_mut().stats.pausers.remove(&pauser);
+            }
+            AddAdmin(admin) => {
+                self.state().borrow_mut().stats.admins.insert(admin);
+            }
+            RemoveAdmin(admin) => {
+                self.state().borrow_mut().stats.admins.remove(&admin);
+            }
+            AddReservedAccount(account) => {
+                self.state()
+                    .borrow_mut()
+                    .stats
+                    .reserved_accounts
+                    .insert(account);
+            }
+            RemoveReservedAccount(account) => {
+                self.state()
+                    .borrow_mut()
+                    .stats
+                    .reserved_accounts
+                    .remove(&account);
+            }
+            AddNotificationConsumer(consumer) => {
+                self.state()
+                    .borrow_mut()
+                    .notification_consumers
+                    .insert(consumer);
+            }
+            RemoveNotificationConsumer(consumer) => {
+                self.state()
+                    .borrow_mut()
+                    .notification_consumers
+                    .remove(&consumer);
+            }
+            MethodPolicy(method, Some(policy)) => {
+                self.state()
+                    .borrow_mut()
+                    .method_policies
+                    .insert(method, policy);
+            }
+            FeeMode(mode) => self.state().borrow_mut().stats.fee_mode = mode,
+            FeeBurnRatio(ratio) => self.state().borrow_mut().stats.fee_burn_ratio = ratio,
+            FeeDistribution(distribution) => {
+                self.state().borrow_mut().stats.fee_distribution = distribution
+            }
+            FeeBounds { min, max } => {
+                let state = self.state();
+                let mut state = state.borrow_mut();
+                state.stats.fee_min = min;
+                state.stats.fee_max = max;
+            }
+            MethodPolicy(method, None) => {
+                self.state().borrow_mut().method_policies.remove(&method);
+            }
+            CyclesMonitor {
+                threshold,
+                provider,
+            } => {
+                let state = self.state();
+                let mut state = state.borrow_mut();
+                state.cycles_monitor.threshold = threshold;
+                state.cycles_monitor.provider = provider;
+            }
+            FaucetLimit(limit) => self.state().borrow_mut().faucet.daily_limit = limit,
         }
     }
 
     #[update(trait = true)]
     fn setName(&self, name: String) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
         self.update_stats(caller, CanisterUpdate::Name(name));
         Ok(())
     }
 
     #[update(trait = true)]
     fn setLogo(&self, logo: String) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
         self.update_stats(caller, CanisterUpdate::Logo(logo));
         Ok(())
     }
 
     #[update(trait = true)]
     fn setFee(&self, fee: Tokens128) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.state().borrow().timelock.check_disabled()?;
         self.update_stats(caller, CanisterUpdate::Fee(fee));
         Ok(())
     }
 
     #[update(trait = true)]
     fn setFeeTo(&self, fee_to: Principal) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.state().borrow().timelock.check_disabled()?;
         self.update_stats(caller, CanisterUpdate::FeeTo(fee_to));
         Ok(())
     }
 
+    /// Sets how the transaction fee is calculated: the flat `fee` or basis points of the
+    /// transaction amount. The basis points cannot be over 10000.
+    ///
+    /// Only the owner is allowed to call this method.
     #[update(trait = true)]
-    fn setOwner(&self, owner: Principal) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
-        self.update_stats(caller, CanisterUpdate::Owner(owner));
+    fn setFeeMode(&self, mode: FeeMode) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.state().borrow().timelock.check_disabled()?;
+        if !mode.is_valid() {
+            return Err(TxError::InvalidFeeConfig);
+        }
+
+        self.update_stats(caller, CanisterUpdate::FeeMode(mode));
         Ok(())
     }
 
-    #[update(trait = true)]
-    fn approve(&self, spender: Principal, amount: Tokens128) -> TxReceipt {
-        let caller = CheckedPrincipal::with_recipient(spender)?;
-        approve(self, caller, amount)
+    #[query(trait = true)]
+    fn getFeeMode(&self) -> FeeMode {
+        self.state().borrow().stats.fee_mode
     }
 
-    /********************** TRANSFERS ***********************/
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn transfer(
-        &self,
-        to: Principal,
-        amount: Tokens128,
-        fee_limit: Option<Tokens128>,
-    ) -> TxReceipt {
-        let caller = CheckedPrincipal::with_recipient(to)?;
-        transfer(self, caller, amount, fee_limit)
-    }
+    /// Sets the bounds the fee is clamped to in the `BasisPoints` fee mode, so that small
+    /// transfers are not free and the fee of large transfers is limited. `None` removes the bound.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setFeeBounds(&self, min: Option<Tokens128>, max: Option<Tokens128>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.state().borrow().timelock.check_disabled()?;
+        if !valid_fee_bounds(min, max) {
+            return Err(TxError::InvalidFeeConfig);
+        }
 
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn transferFrom(&self, from: Principal, to: Principal, amount: Tokens128) -> TxReceipt {
-        let caller = CheckedPrincipal::from_to(from, to)?;
-        transfer_from(self, caller, amount)
+        self.update_stats(caller, CanisterUpdate::FeeBounds { min, max });
+        Ok(())
     }
 
-    /// Transfers `value` amount to the `to` principal, applying American style fee. This means, that
-    /// the recipient will receive `value - fee`, and the sender account will be reduced exactly by `value`.
+    /// Sets the share of every fee that is burned instead of going to the fee receiver, in basis
+    /// points. The burns are recorded as `Burn` transactions.
     ///
-    /// Note, that the `value` cannot be less than the `fee` amount. If the value given is too small,
-    /// transaction will fail with `TxError::AmountTooSmall` error.
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn transferIncludeFee(&self, to: Principal, amount: Tokens128) -> TxReceipt {
-        let caller = CheckedPrincipal::with_recipient(to)?;
-        transfer_include_fee(self, caller, amount)
-    }
-
-    /// Takes a list of transfers, each of which is a pair of `to` and `value` fields, it returns a `TxReceipt` which contains
-    /// a vec of transaction index or an error message. The list of transfers is processed in the order they are given. if the `fee`
-    /// is set, the `fee` amount is applied to each transfer.
-    /// The balance of the caller is reduced by sum of `value + fee` amount for each transfer. If the total sum of `value + fee` for all transfers,
-    /// is less than the `balance` of the caller, the transaction will fail with `TxError::InsufficientBalance` error.
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn batchTransfer(&self, transfers: Vec<(Principal, Tokens128)>) -> Result<Vec<TxId>, TxError> {
-        for (to, _) in transfers.clone() {
-            let _ = CheckedPrincipal::with_recipient(to)?;
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setFeeBurnRatio(&self, ratio: u16) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.state().borrow().timelock.check_disabled()?;
+        if !valid_basis_points(ratio) {
+            return Err(TxError::InvalidFeeConfig);
         }
-        batch_transfer(self, transfers)
+
+        self.update_stats(caller, CanisterUpdate::FeeBurnRatio(ratio));
+        Ok(())
     }
 
-    #[cfg_attr(feature = "mint_burn", update(trait = true))]
-    fn mint(&self, to: Principal, amount: Tokens128) -> TxReceipt {
-        if self.isTestToken() {
-            let test_user = CheckedPrincipal::test_user(&self.state().borrow().stats)?;
-            mint_test_token(&mut *self.state().borrow_mut(), test_user, to, amount)
-        } else {
-            let owner = CheckedPrincipal::owner(&self.state().borrow().stats)?;
-            mint_as_owner(&mut *self.state().borrow_mut(), owner, to, amount)
-        }
+    #[query(trait = true)]
+    fn getFeeBurnRatio(&self) -> u16 {
+        self.state().borrow().stats.fee_burn_ratio
     }
 
-    /// Burn `amount` of tokens from `from` principal.
-    /// If `from` is None, then caller's tokens will be burned.
-    /// If `from` is Some(_) but method called not by owner, `TxError::Unauthorized` will be returned.
-    /// If owner calls this method and `from` is Some(who), then who's tokens will be burned.
-    #[cfg_attr(feature = "mint_burn", update(trait = true))]
-    fn burn(&self, from: Option<Principal>, amount: Tokens128) -> TxReceipt {
-        match from {
-            None => burn_own_tokens(&mut *self.state().borrow_mut(), amount),
-            Some(from) if from == ic_canister::ic_kit::ic::caller() => {
-                burn_own_tokens(&mut *self.state().borrow_mut(), amount)
-            }
-            Some(from) => {
-                let caller = CheckedPrincipal::owner(&self.state().borrow().stats)?;
-                burn_as_owner(&mut *self.state().borrow_mut(), caller, from, amount)
-            }
+    /// Sets the fee receivers with their weights. The fee share that isn't burned or sent to the
+    /// auction is split between the receivers by their weights, and the rounding remainder goes to
+    /// the first receiver. An empty list sends the whole share to `feeTo`.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setFeeDistribution(&self, distribution: Vec<(Principal, u32)>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.state().borrow().timelock.check_disabled()?;
+        if !valid_fee_distribution(&distribution) {
+            return Err(TxError::InvalidFeeConfig);
         }
+
+        self.update_stats(caller, CanisterUpdate::FeeDistribution(distribution));
+        Ok(())
     }
 
-    /********************** AUCTION ***********************/
+    #[query(trait = true)]
+    fn getFeeDistribution(&self) -> Vec<(Principal, u32)> {
+        self.state().borrow().stats.fee_distribution.clone()
+    }
 
-    /// Bid cycles for the next cycle auction.
-    ///
-    /// This method must be called with the cycles provided in the call. The amount of cycles cannot be
-    /// less than 1_000_000. The provided cycles are accepted by the canister, and the user bid is
-    /// saved for the next auction.
-    #[update(trait = true)]
-    fn bidCycles(&self, bidder: Principal) -> Result<u64, AuctionError> {
-        bid_cycles(self, bidder)
+    /// Returns the fee that would be charged from the `caller` right now for the `operation` with
+    /// the given `amount`, and how it would be split. Wallets can use it to show the cost of the
+    /// transaction and to set the `fee_limit`.
+    #[query(trait = true)]
+    fn getFeeFor(&self, caller: Principal, amount: Tokens128, operation: Operation) -> FeeSplit {
+        fee_for_operation(&self.state().borrow(), caller, amount, operation)
     }
 
-    /// Current information about bids and auction.
-    #[update(trait = true)]
-    fn biddingInfo(&self) -> BiddingInfo {
-        bidding_info(self)
+    #[query(trait = true)]
+    fn getFeeBounds(&self) -> (Option<Tokens128>, Option<Tokens128>) {
+        let state = self.state();
+        let state = state.borrow();
+        (state.stats.fee_min, state.stats.fee_max)
     }
 
-    /// Starts the cycle auction.
+    /// Sets the minting account. Transfers from the minting account create new tokens and are
+    /// recorded as `Mint` transactions, and transfers to it destroy the tokens and are recorded as
+    /// `Burn` transactions. No fee is charged for such transfers. `None` disables this behaviour.
     ///
-    /// This method can be called only once in a [BiddingState.auction_period]. If the time elapsed
-    /// since the last auction is less than the set period, [AuctionError::TooEarly] will be returned.
-    ///
-    /// The auction will distribute the accumulated fees in proportion to the user cycle bids, and
-    /// then will update the fee ratio until the next auction.
+    /// Only the owner is allowed to call this method.
     #[update(trait = true)]
-    fn runAuction(&self) -> Result<AuctionInfo, AuctionError> {
-        run_auction(self)
+    fn setMintingAccount(&self, minting_account: Option<Principal>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::MintingAccount(minting_account));
+        Ok(())
     }
 
-    /// Returns the information about a previously held auction.
-    #[update(trait = true)]
-    fn auctionInfo(&self, id: usize) -> Result<AuctionInfo, AuctionError> {
-        auction_info(self, id)
+    #[query(trait = true)]
+    fn getMintingAccount(&self) -> Option<Principal> {
+        self.state().borrow().stats.minting_account
     }
 
-    /// Returns the minimum cycles set for the canister.
-    ///
-    /// This value affects the fee ratio set by the auctions. The more cycles available in the canister
-    /// the less proportion of the fees will be transferred to the auction participants. If the amount
-    /// of cycles in the canister drops below this value, all the fees will be used for cycle auction.
+    /// Allows the `minter` to mint tokens along with the owner, e.g. for a bridge or a staking
+    /// canister. Only the owner is allowed to call this method.
     #[update(trait = true)]
-    fn getMinCycles(&self) -> u64 {
-        self.state().borrow().stats.min_cycles
+    fn addMinter(&self, minter: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::AddMinter(minter));
+        Ok(())
     }
 
-    /// Sets the minimum cycles for the canister. For more information about this value, read [get_min_cycles].
-    ///
-    /// Only the owner is allowed to call this method.
+    /// Revokes the minting permission from the `minter`. Only the owner is allowed to call this
+    /// method.
     #[update(trait = true)]
-    fn setMinCycles(&self, min_cycles: u64) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
-        self.update_stats(caller, CanisterUpdate::MinCycles(min_cycles));
+    fn removeMinter(&self, minter: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::RemoveMinter(minter));
         Ok(())
     }
 
-    /// Sets the minimum time between two consecutive auctions, in seconds.
-    ///
-    /// Only the owner is allowed to call this method.
+    /// Marks the `account` as reserved, so its balance is not counted in the circulating supply,
+    /// e.g. the treasury, the vesting or the auction account. Only the owner is allowed to call
+    /// this method.
     #[update(trait = true)]
-    fn setAuctionPeriod(&self, period_sec: u64) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&self.state().borrow_mut().stats)?;
-        // IC timestamp is in nanoseconds, thus multiplying
-        self.update_stats(caller, CanisterUpdate::AuctionPeriod(period_sec));
+    fn addReservedAccount(&self, account: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::AddReservedAccount(account));
         Ok(())
     }
 
+    /// Returns the balance of the reserved `account` to the circulating supply. Only the owner is
+    /// allowed to call this method.
     #[update(trait = true)]
-    fn consume_notification<'a>(&'a self, transaction_id: TxId) -> AsyncReturn<TxReceipt> {
-        let fut = async move { consume_notification(self, transaction_id).await };
+    fn removeReservedAccount(&self, account: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::RemoveReservedAccount(account));
+        Ok(())
+    }
 
-        Box::pin(fut)
+    /// Returns the accounts whose balances are not counted in the circulating supply.
+    #[query(trait = true)]
+    fn getReservedAccounts(&self) -> Vec<Principal> {
+        self.state()
+            .borrow()
+            .stats
+            .reserved_accounts
+            .iter()
+            .copied()
+            .collect()
     }
 
+    /// Returns the principals allowed to mint tokens besides the owner.
+    #[query(trait = true)]
+    fn getMinters(&self) -> Vec<Principal> {
+        self.state()
+            .borrow()
+            .stats
+            .minters
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Creates a vesting entry for the `beneficiary`. The `amount` is minted if `mint` is set, or
+    /// transferred from the owner balance otherwise. The tokens are released linearly over the
+    /// `duration`, and none of them can be claimed before the `cliff`. Both are counted from now,
+    /// in nanoseconds. Returns the id of the entry.
+    ///
+    /// Only the owner is allowed to call this method. Minting requires the multisig to be
+    /// disabled.
     #[update(trait = true)]
-    fn approveAndNotify<'a>(
-        &'a self,
-        spender: Principal,
+    fn createVesting(
+        &self,
+        beneficiary: Principal,
         amount: Tokens128,
-    ) -> AsyncReturn<TxReceipt> {
-        let caller = CheckedPrincipal::with_recipient(spender);
-        let fut = async move { approve_and_notify(self, caller?, amount).await };
-        Box::pin(fut)
+        cliff: Timestamp,
+        duration: Timestamp,
+        mint: bool,
+    ) -> Result<u64, TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        create_vesting(self, caller, beneficiary, amount, cliff, duration, mint)
     }
 
+    /// Transfers the released tokens of all the caller vesting entries to the caller.
     #[update(trait = true)]
-    fn notify<'a>(&'a self, transaction_id: TxId, to: Principal) -> AsyncReturn<TxReceipt> {
-        let fut = async move { notify(self, transaction_id, to).await };
-
-        Box::pin(fut)
+    fn claimVested(&self) -> TxReceipt {
+        claim_vested(self)
     }
 
-    /********************** Transactions ***********************/
     #[query(trait = true)]
-    fn getTransaction(&self, id: TxId) -> TxRecord {
-        self.state().borrow().ledger.get(id).unwrap_or_else(|| {
-            ic_canister::ic_kit::ic::trap(&format!("Transaction {} does not exist", id))
-        })
+    fn getVesting(&self, beneficiary: Principal) -> Vec<Vesting> {
+        self.state()
+            .borrow()
+            .vesting
+            .entries
+            .values()
+            .filter(|entry| entry.beneficiary == beneficiary)
+            .cloned()
+            .collect()
     }
 
-    /// Returns a list of transactions in paginated form. The `who` is optional, if given, only transactions of the `who` are
-    /// returned. `count` is the number of transactions to return, `transaction_id` is the transaction index which is used as
-    /// the offset of the first transaction to return, any
-    ///
-    /// It returns `PaginatedResult` a struct, which contains `result` which is a list of transactions `Vec<TxRecord>` that meet the requirements of the query,
-    /// and `next_id` which is the index of the next transaction to return.
-    #[query(trait = true)]
-    fn getTransactions(
+    /// Moves the `amount` from the caller balance into an escrow for `to` and returns the escrow
+    /// id. The tokens are released to `to` by the caller or the `arbiter`, or refunded by `to` or
+    /// the `arbiter`. After the `deadline` the caller can refund the tokens too.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn createEscrow(
         &self,
-        who: Option<Principal>,
-        count: usize,
+        to: Principal,
+        amount: Tokens128,
+        arbiter: Principal,
+        deadline: Timestamp,
+    ) -> Result<u64, TxError> {
+        let caller = CheckedPrincipal::with_recipient(to)?;
+        create_escrow(self, caller, amount, arbiter, deadline)
+    }
+
+    /// Transfers the escrowed tokens to the recipient.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn releaseEscrow(&self, id: u64) -> TxReceipt {
+        release_escrow(self, id)
+    }
+
+    /// Returns the escrowed tokens to the payer.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn refundEscrow(&self, id: u64) -> TxReceipt {
+        refund_escrow(self, id)
+    }
+
+    /// Returns the escrows where `who` is the payer, the recipient or the arbiter.
+    #[query(trait = true)]
+    fn getEscrows(&self, who: Principal) -> Vec<Escrow> {
+        self.state()
+            .borrow()
+            .escrows
+            .entries
+            .values()
+            .filter(|escrow| who == escrow.from || who == escrow.to || who == escrow.arbiter)
+            .cloned()
+            .collect()
+    }
+
+    /// Opens a payment stream to `to`, moving the `deposit` from the caller balance. The deposit
+    /// accrues to `to` at the `rate` tokens per second, and `to` can withdraw the accrued tokens at
+    /// any time. Returns the stream id.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn openStream(
+        &self,
+        to: Principal,
+        rate: Tokens128,
+        deposit: Tokens128,
+    ) -> Result<u64, TxError> {
+        let caller = CheckedPrincipal::with_recipient(to)?;
+        open_stream(self, caller, rate, deposit)
+    }
+
+    /// Transfers the accrued tokens of the stream to the caller, who must be the stream recipient.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn withdrawFromStream(&self, id: u64) -> TxReceipt {
+        withdraw_from_stream(self, id)
+    }
+
+    /// Closes the stream, transferring the accrued tokens to the recipient and the rest of the
+    /// deposit back to the payer. Can be called by the payer or the recipient.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn closeStream(&self, id: u64) -> Result<Vec<TxId>, TxError> {
+        close_stream(self, id)
+    }
+
+    /// Returns the streams where `who` is the payer or the recipient.
+    #[query(trait = true)]
+    fn getStreams(&self, who: Principal) -> Vec<Stream> {
+        self.state()
+            .borrow()
+            .streams
+            .entries
+            .values()
+            .filter(|stream| who == stream.from || who == stream.to)
+            .cloned()
+            .collect()
+    }
+
+    /// Commits the merkle `root` of the airdrop allocations, replacing the previous airdrop. The
+    /// allocations are minted if `mint` is set, or transferred from the owner balance otherwise.
+    /// See the `is20_airdrop` module for the tree layout.
+    ///
+    /// Only the owner is allowed to call this method. Minting requires the multisig to be
+    /// disabled.
+    #[update(trait = true)]
+    fn setAirdrop(&self, root: Vec<u8>, mint: bool) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        set_airdrop(self, caller, root, mint)
+    }
+
+    /// Claims the airdrop allocation `(index, caller, amount)` proven by the sibling hashes of the
+    /// `proof`, from the leaf to the root. Each allocation can only be claimed once.
+    #[update(trait = true)]
+    fn claimAirdrop(&self, index: u64, amount: Tokens128, proof: Vec<Vec<u8>>) -> TxReceipt {
+        claim_airdrop(self, index, amount, proof)
+    }
+
+    #[query(trait = true)]
+    fn isAirdropClaimed(&self, index: u64) -> bool {
+        self.state()
+            .borrow()
+            .airdrop
+            .as_ref()
+            .map_or(false, |airdrop| airdrop.is_claimed(index))
+    }
+
+    /// Locks the `amount` of the caller balance until the `until` timestamp. The locked tokens
+    /// cannot be transferred or burned until the lock expires. Locks cannot be removed early.
+    #[update(trait = true)]
+    fn lock(&self, amount: Tokens128, until: Timestamp) -> Result<(), TxError> {
+        lock_balance(self, amount, until)
+    }
+
+    /// Returns the balance locks of the `holder` that are not expired yet.
+    #[query(trait = true)]
+    fn getLocks(&self, holder: Principal) -> Vec<BalanceLock> {
+        self.state().borrow().balances.locks(&holder)
+    }
+
+    /// Freezes the account of `who`, so that it cannot send, receive or approve tokens. The freeze
+    /// is recorded in the transaction history. Only the owner and the pausers are allowed to call
+    /// this method.
+    #[update(trait = true)]
+    fn freezeAccount(&self, who: Principal) -> TxReceipt {
+        let caller = CheckedPrincipal::pauser(&self.state().borrow())?;
+        freeze_account(self, caller, who)
+    }
+
+    /// Unfreezes the account of `who` frozen with `freezeAccount`. Only the owner and the pausers
+    /// are allowed to call this method.
+    #[update(trait = true)]
+    fn unfreezeAccount(&self, who: Principal) -> TxReceipt {
+        let caller = CheckedPrincipal::pauser(&self.state().borrow())?;
+        unfreeze_account(self, caller, who)
+    }
+
+    /// Allows the `pauser` to freeze and unfreeze accounts along with the owner. Only the owner
+    /// and the admins are allowed to call this method.
+    #[update(trait = true)]
+    fn addPauser(&self, pauser: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::admin(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::AddPauser(pauser));
+        Ok(())
+    }
+
+    /// Revokes the pauser role from the `pauser`. Only the owner and the admins are allowed to
+    /// call this method.
+    #[update(trait = true)]
+    fn removePauser(&self, pauser: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::admin(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::RemovePauser(pauser));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn getPausers(&self) -> Vec<Principal> {
+        self.state()
+            .borrow()
+            .stats
+            .pausers
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Allows the `admin` to manage the pausers along with the owner. Only the owner is allowed to
+    /// call this method.
+    #[update(trait = true)]
+    fn addAdmin(&self, admin: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::AddAdmin(admin));
+        Ok(())
+    }
+
+    /// Revokes the admin role from the `admin`. Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn removeAdmin(&self, admin: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::RemoveAdmin(admin));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn getAdmins(&self) -> Vec<Principal> {
+        self.state().borrow().stats.admins.iter().copied().collect()
+    }
+
+    #[query(trait = true)]
+    fn isAccountFrozen(&self, who: Principal) -> bool {
+        self.state().borrow().frozen_accounts.contains(&who)
+    }
+
+    /// Enables the multisig mode. In this mode the fee, the fee receiver, the owner, the minters and
+    /// the minting by the owner can only be changed with the `proposeAdminOp` proposals approved by
+    /// `config.threshold` of the signers, and the canister upgrades need an `ApproveUpgrade`
+    /// proposal. The other owner-only methods can still be called by the owner.
+    ///
+    /// Only the owner is allowed to call this method, while the multisig is disabled.
+    #[update(trait = true)]
+    fn enableMultisig(&self, config: MultisigConfig) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        enable_multisig(self, caller, config)
+    }
+
+    /// Proposes an admin operation, approved by the caller. Returns the proposal id. The operation
+    /// is executed once it's approved by the threshold number of the signers.
+    ///
+    /// Only the multisig signers are allowed to call this method.
+    #[update(trait = true)]
+    fn proposeAdminOp<'a>(&'a self, op: AdminOp) -> AsyncReturn<Result<u64, TxError>> {
+        let caller = CheckedPrincipal::signer(&self.state().borrow().multisig);
+        let fut = async move { propose_admin_op(self, caller?, op).await };
+        Box::pin(fut)
+    }
+
+    /// Approves the proposal. Returns `true` if the proposal was executed with this approval.
+    ///
+    /// Only the multisig signers are allowed to call this method.
+    #[update(trait = true)]
+    fn approveAdminOp<'a>(&'a self, proposal_id: u64) -> AsyncReturn<Result<bool, TxError>> {
+        let caller = CheckedPrincipal::signer(&self.state().borrow().multisig);
+        let fut = async move { approve_admin_op(self, caller?, proposal_id).await };
+        Box::pin(fut)
+    }
+
+    #[query(trait = true)]
+    fn getAdminProposals(&self) -> Vec<AdminProposal> {
+        self.state()
+            .borrow()
+            .multisig
+            .proposals
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    #[query(trait = true)]
+    fn getMultisig(&self) -> Option<MultisigConfig> {
+        self.state().borrow().multisig.config.clone()
+    }
+
+    /// Sets the delay between an update is queued with `queueUpdate` and it can be executed, in
+    /// nanoseconds. While the delay is not zero, the fee, the fee receiver and the owner can only be
+    /// changed with the queued updates. The delay can be increased right away, but decreasing it
+    /// requires a queued `Delay` update.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setTimelockDelay(&self, delay: Timestamp) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        set_timelock_delay(self, caller, delay)
+    }
+
+    #[query(trait = true)]
+    fn getTimelockDelay(&self) -> Timestamp {
+        self.state().borrow().timelock.delay
+    }
+
+    /// Queues the parameter update and returns its id. The update can be executed with
+    /// `executeUpdate` after the timelock delay.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn queueUpdate(&self, update: TimelockedUpdate) -> Result<u64, TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        queue_update(self, caller, update)
+    }
+
+    /// Executes the queued update once its delay is over. The `Owner` update initiates the owner
+    /// change the same way as `setOwner`.
+    ///
+    /// Only the owner is allowed to call this method, or the multisig signers while the multisig
+    /// is enabled.
+    #[update(trait = true)]
+    fn executeUpdate<'a>(&'a self, update_id: u64) -> AsyncReturn<Result<(), TxError>> {
+        let caller = {
+            let state = self.state();
+            let state = state.borrow();
+            if state.multisig.is_enabled() {
+                CheckedPrincipal::signer(&state.multisig).map(|caller| caller.inner())
+            } else {
+                CheckedPrincipal::owner(&state).map(|caller| caller.inner())
+            }
+        };
+        let fut = async move { execute_update(self, caller?, update_id).await };
+        Box::pin(fut)
+    }
+
+    /// Removes the update from the queue.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn cancelUpdate(&self, update_id: u64) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner_outside_multisig(&self.state().borrow())?;
+        cancel_update(self, caller, update_id)
+    }
+
+    /// Returns the queued updates, so the holders can see the upcoming changes before they take
+    /// effect.
+    #[query(trait = true)]
+    fn getPendingUpdates(&self) -> Vec<QueuedUpdate> {
+        self.state()
+            .borrow()
+            .timelock
+            .queue
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Initiates the change of the token owner. The change can be applied with `applyOwnerChange`
+    /// after [is20_ownership::OWNER_CHANGE_DELAY], and until then the current owner can cancel it
+    /// with `cancelOwnerChange`. If a guardian is set, it is notified with the
+    /// `owner_change_initiated` call.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setOwner<'a>(&'a self, owner: Principal) -> AsyncReturn<Result<(), TxError>> {
+        let caller =
+            CheckedPrincipal::owner_outside_multisig(&self.state().borrow()).and_then(|caller| {
+                let state = self.state();
+                state.borrow().timelock.check_disabled()?;
+                Ok(caller)
+            });
+        let fut = async move { initiate_owner_change(self, caller?, owner).await };
+        Box::pin(fut)
+    }
+
+    /// Applies the pending owner change once its delay window is over. The registry the token was
+    /// created by is notified with the `notify_owner_changed` call.
+    ///
+    /// Can be called by the current owner or by the new owner.
+    #[update(trait = true)]
+    fn applyOwnerChange<'a>(&'a self) -> AsyncReturn<Result<(), TxError>> {
+        let fut = async move { apply_owner_change(self).await };
+        Box::pin(fut)
+    }
+
+    /// Cancels the pending owner change.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn cancelOwnerChange(&self) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        cancel_owner_change(self, caller)
+    }
+
+    #[query(trait = true)]
+    fn getPendingOwnerChange(&self) -> Option<PendingOwnerChange> {
+        self.state().borrow().owner_rotation.pending.clone()
+    }
+
+    /// Sets the principal to be notified when an owner change is initiated.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setGuardian(&self, guardian: Option<Principal>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::Guardian(guardian));
+        Ok(())
+    }
+
+    /// Replaces the custom metadata entries returned by `icrc1_metadata`, e.g. website or
+    /// description of the token.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setCustomMetadata(&self, entries: Vec<(String, Value)>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        if !entries.iter().all(|(key, _)| valid_metadata_key(key)) {
+            return Err(TxError::InvalidMetadataKey);
+        }
+
+        self.update_stats(caller, CanisterUpdate::CustomMetadata(entries));
+        Ok(())
+    }
+
+    /// Sets a single custom metadata entry, replacing the entry with the same key if any. The key
+    /// cannot be empty, longer than 64 bytes or start with `icrc1:`.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setMetadataEntry(&self, key: String, value: Value) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        if !valid_metadata_key(&key) {
+            return Err(TxError::InvalidMetadataKey);
+        }
+
+        self.update_stats(caller, CanisterUpdate::MetadataEntry(key, Some(value)));
+        Ok(())
+    }
+
+    /// Removes the custom metadata entry. Returns `AlreadyActioned` if there is no such entry.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn removeMetadataEntry(&self, key: String) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        if !self.state().borrow().custom_metadata.contains_key(&key) {
+            return Err(TxError::AlreadyActioned);
+        }
+
+        self.update_stats(caller, CanisterUpdate::MetadataEntry(key, None));
+        Ok(())
+    }
+
+    /// Sets the policy of accepting the calls of the update `method`, e.g. to disable a method
+    /// without upgrading the canister. `None` restores the default checks. The `OwnerOnly` and
+    /// `Deny` policies apply to the ingress calls and to the calls from other canisters, `Open`
+    /// only skips the checks of `inspect_message`. The policy doesn't apply to this method
+    /// itself, so that the owner cannot lock themselves out. Query methods cannot be restricted,
+    /// as the policies are enforced in the update calls.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setMethodPolicy(&self, method: String, policy: Option<MethodPolicy>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        if policy.is_some() && inspect::is_query_method(&method) {
+            return Err(TxError::InvalidMethodPolicy);
+        }
+
+        self.update_stats(caller, CanisterUpdate::MethodPolicy(method, policy));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn getMethodPolicies(&self) -> Vec<(String, MethodPolicy)> {
+        self.state()
+            .borrow()
+            .method_policies
+            .iter()
+            .map(|(method, policy)| (method.clone(), *policy))
+            .collect()
+    }
+
+    #[update(trait = true)]
+    fn approve(&self, spender: Principal, amount: Tokens128) -> TxReceipt {
+        let caller = CheckedPrincipal::with_recipient(spender)?;
+        approve(self, caller, amount)
+    }
+
+    /********************** TRANSFERS ***********************/
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer(
+        &self,
+        to: Principal,
+        amount: Tokens128,
+        fee_limit: Option<Tokens128>,
+    ) -> TxReceipt {
+        let caller = CheckedPrincipal::with_recipient(to)?;
+        transfer(self, caller, amount, fee_limit)
+    }
+
+    /// ICRC-1 transfer. Same as `transfer`, but the errors are returned as the structured ICRC-1
+    /// variants, and the IS20 errors with no ICRC-1 counterpart as `GenericError` with a stable
+    /// error code. Only the default subaccounts are supported.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn icrc1_transfer(&self, transfer: TransferArg) -> Result<Nat, TransferError> {
+        let caller = CheckedPrincipal::with_recipient(transfer.to.owner)?;
+        icrc1_transfer(self, caller, transfer)
+    }
+
+    /// Schedules a transfer from the caller balance to `to`, executed at the `start` time and then
+    /// every `interval` if it's set, until it's cancelled. The transfers are executed by the timer
+    /// after they are due, and are recorded in the ledger as usual transfers. Scheduling is charged
+    /// the approval fee. Returns the id of the scheduled transfer.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn scheduleTransfer(
+        &self,
+        to: Principal,
+        amount: Tokens128,
+        start: Timestamp,
+        interval: Option<Timestamp>,
+    ) -> Result<u64, TxError> {
+        let caller = CheckedPrincipal::with_recipient(to)?;
+        schedule_transfer(self, caller, amount, start, interval)
+    }
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn cancelScheduledTransfer(&self, id: u64) -> Result<(), TxError> {
+        cancel_scheduled_transfer(self, id)
+    }
+
+    #[query(trait = true)]
+    fn getScheduledTransfers(&self, from: Principal) -> Vec<ScheduledTransfer> {
+        self.state()
+            .borrow()
+            .scheduler
+            .transfers
+            .values()
+            .filter(|scheduled| scheduled.from == from)
+            .cloned()
+            .collect()
+    }
+
+    /// Executes the due scheduled transfers right away, without waiting for the timer. Returns the
+    /// number of the transfers executed.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn executeScheduledTransfers(&self) -> usize {
+        execute_scheduled_transfers(self)
+    }
+
+    /// Same as `transfer`, but if the recipient is a canister, it first checks that the canister
+    /// implements the IS20 receiver interface, and rejects the transfer with
+    /// `TxError::ReceiverNotSupported` otherwise.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn safeTransfer<'a>(
+        &'a self,
+        to: Principal,
+        amount: Tokens128,
+        fee_limit: Option<Tokens128>,
+    ) -> AsyncReturn<TxReceipt> {
+        let caller = CheckedPrincipal::with_recipient(to);
+        let fut = async move { safe_transfer(self, caller?, amount, fee_limit).await };
+
+        Box::pin(fut)
+    }
+
+    /// Transfers the tokens to a canister implementing the IS20 receiver interface and calls its
+    /// `is20_on_received` method with the `memo`. The transfer is not made if the receiver doesn't
+    /// declare the method.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transferWithHook<'a>(
+        &'a self,
+        to: Principal,
+        amount: Tokens128,
+        memo: Option<Vec<u8>>,
+    ) -> AsyncReturn<TxReceipt> {
+        let caller = CheckedPrincipal::with_recipient(to);
+        let fut = async move { transfer_with_hook(self, caller?, amount, memo).await };
+
+        Box::pin(fut)
+    }
+
+    /// Transfers the tokens and calls the `method` of the receiver with the transaction details and
+    /// the `payload`. The method must start with `is20_on_transfer_`. If the call fails, the
+    /// transfer is still completed, the notification is retried later, and
+    /// `TxError::NotificationFailed` is returned.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transferAndCall<'a>(
+        &'a self,
+        to: Principal,
+        amount: Tokens128,
+        method: String,
+        payload: Vec<u8>,
+    ) -> AsyncReturn<TxReceipt> {
+        let caller = CheckedPrincipal::with_recipient(to);
+        let fut = async move { transfer_and_call(self, caller?, amount, method, payload).await };
+
+        Box::pin(fut)
+    }
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transferFrom(&self, from: Principal, to: Principal, amount: Tokens128) -> TxReceipt {
+        let caller = CheckedPrincipal::from_to(from, to)?;
+        transfer_from(self, caller, amount)
+    }
+
+    /// Transfers `value` amount to the `to` principal, applying American style fee. This means, that
+    /// the recipient will receive `value - fee`, and the sender account will be reduced exactly by `value`.
+    ///
+    /// Note, that the `value` cannot be less than the `fee` amount. If the value given is too small,
+    /// transaction will fail with `TxError::AmountTooSmall` error.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transferIncludeFee(&self, to: Principal, amount: Tokens128) -> TxReceipt {
+        let caller = CheckedPrincipal::with_recipient(to)?;
+        transfer_include_fee(self, caller, amount)
+    }
+
+    /// Takes a list of transfers, each of which is a pair of `to` and `value` fields, it returns a `TxReceipt` which contains
+    /// a vec of transaction index or an error message. The list of transfers is processed in the order they are given. if the `fee`
+    /// is set, the `fee` amount is applied to each transfer.
+    /// The balance of the caller is reduced by sum of `value + fee` amount for each transfer. If a transfer cannot be applied,
+    /// none of the transfers are executed and the `TxError::BatchTransferFailed` error tells the index of this transfer.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn batchTransfer(&self, transfers: Vec<(Principal, Tokens128)>) -> Result<Vec<TxId>, TxError> {
+        for (to, _) in transfers.clone() {
+            let _ = CheckedPrincipal::with_recipient(to)?;
+        }
+        batch_transfer(self, transfers)
+    }
+
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn mint(&self, to: Principal, amount: Tokens128) -> TxReceipt {
+        if self.isTestToken() {
+            let test_user = CheckedPrincipal::test_user(&self.state().borrow().stats)?;
+            mint_test_token(&mut *self.state().borrow_mut(), test_user, to, amount)
+        } else {
+            let minter = CheckedPrincipal::minter(&self.state().borrow())?;
+            mint_as_minter(&mut *self.state().borrow_mut(), minter, to, amount)
+        }
+    }
+
+    /// Mints the `amount` of a test token to the caller, within the daily limit set with
+    /// `setFaucetLimit`.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn faucet(&self, amount: Tokens128) -> TxReceipt {
+        let test_user = CheckedPrincipal::test_user(&self.state().borrow().stats)?;
+        let to = test_user.inner();
+        mint_test_token(&mut *self.state().borrow_mut(), test_user, to, amount)
+    }
+
+    /// Sets the maximum amount of a test token one principal can mint in a day, with `mint` or
+    /// `faucet`. `None` removes the limit. The owner is not limited.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setFaucetLimit(&self, limit: Option<Tokens128>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::FaucetLimit(limit));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn getFaucetLimit(&self) -> Option<Tokens128> {
+        self.state().borrow().faucet.daily_limit
+    }
+
+    /// Burn `amount` of tokens from `from` principal.
+    /// If `from` is None, then caller's tokens will be burned.
+    /// If `from` is Some(_) but method called not by owner, `TxError::Unauthorized` will be returned.
+    /// If owner calls this method and `from` is Some(who), then who's tokens will be burned.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn burn(&self, from: Option<Principal>, amount: Tokens128) -> TxReceipt {
+        match from {
+            None => burn_own_tokens(&mut *self.state().borrow_mut(), amount),
+            Some(from) if from == ic_canister::ic_kit::ic::caller() => {
+                burn_own_tokens(&mut *self.state().borrow_mut(), amount)
+            }
+            Some(from) => {
+                let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+                burn_as_owner(&mut *self.state().borrow_mut(), caller, from, amount)
+            }
+        }
+    }
+
+    /********************** AUCTION ***********************/
+
+    /// Bid cycles for the next cycle auction.
+    ///
+    /// This method must be called with the cycles provided in the call. The amount of cycles cannot be
+    /// less than 1_000_000. The provided cycles are accepted by the canister, and the user bid is
+    /// saved for the next auction.
+    #[update(trait = true)]
+    fn bidCycles(&self, bidder: Principal) -> Result<u64, AuctionError> {
+        bid_cycles(self, bidder)
+    }
+
+    /// Bid for the next cycle auction with the tokens of the ledger set by `setBidToken`, e.g. ICP.
+    ///
+    /// The `amount` is taken from the caller with the ledger `icrc2_transfer_from` method, so it
+    /// must be approved to this canister first. The bid is credited with `amount * cycles_per_unit`
+    /// cycles, which cannot be less than 1_000_000. The tokens are sent to the treasury set by the
+    /// owner, and the part of the bid paid in tokens cannot be withdrawn.
+    #[update(trait = true)]
+    fn bidTokens<'a>(&'a self, amount: Tokens128) -> AsyncReturn<Result<u64, AuctionError>> {
+        let fut = async move { bid_tokens(self, amount).await };
+
+        Box::pin(fut)
+    }
+
+    /// Withdraws the caller bid for the upcoming auction. The bid cycles minus the withdrawal
+    /// penalty are deposited to the caller canister with the management canister `deposit_cycles`
+    /// method. If the deposit fails, the bid is kept.
+    #[update(trait = true)]
+    fn withdrawBid<'a>(&'a self) -> AsyncReturn<Result<u64, AuctionError>> {
+        let fut = async move { withdraw_bid(self).await };
+
+        Box::pin(fut)
+    }
+
+    /// Current information about bids and auction.
+    #[update(trait = true)]
+    fn biddingInfo(&self) -> BiddingInfo {
+        bidding_info(self)
+    }
+
+    /// Starts the cycle auction.
+    ///
+    /// This method can be called only once in a [BiddingState.auction_period]. If the time elapsed
+    /// since the last auction is less than the set period, [AuctionError::TooEarly] will be returned.
+    ///
+    /// The auction will distribute the accumulated fees in proportion to the user cycle bids, and
+    /// then will update the fee ratio until the next auction. The tokens left after rounding down
+    /// the shares are given to the largest bids, with equal bids ordered by a random seed obtained
+    /// from the management canister. The seed is saved in the [AuctionInfo].
+    #[update(trait = true)]
+    fn runAuction<'a>(&'a self) -> AsyncReturn<Result<AuctionInfo, AuctionError>> {
+        let fut = async move { run_auction_with_randomness(self).await };
+
+        Box::pin(fut)
+    }
+
+    /// Pays the next chunk of the pending auction rewards. Returns `true` if all the rewards are
+    /// paid.
+    ///
+    /// The rewards of the auctions with many bidders are paid in chunks to not hit the instruction
+    /// limit. A chunk is paid on every run of the canister timer, and this method allows paying
+    /// them without waiting for the timer.
+    #[update(trait = true)]
+    fn disburseRewards(&self) -> bool {
+        disburse_rewards(self)
+    }
+
+    /// Distributes the `amount` from the owner balance to all the holders proportionally to their
+    /// balances. The tokens are paid in chunks by the canister timer. Returns the id of the
+    /// transfer to the distribution principal.
+    ///
+    /// Only the owner is allowed to call this method, and only one distribution can be in
+    /// progress.
+    #[update(trait = true)]
+    fn distribute(&self, amount: Tokens128) -> TxReceipt {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        distribute(self, caller, amount)
+    }
+
+    /// Pays the next chunk of the pending distribution. Returns `true` if the distribution is
+    /// over.
+    #[update(trait = true)]
+    fn processDistribution(&self) -> bool {
+        process_distribution(self)
+    }
+
+    #[query(trait = true)]
+    fn getDistribution(&self) -> Option<Distribution> {
+        self.state().borrow().distribution.clone()
+    }
+
+    /// Sends the pending auction reward notifications to the bidders. The notifications are sent
+    /// after each auction, and this method allows retrying the failed ones. Returns the number of
+    /// the notifications sent.
+    #[update(trait = true)]
+    fn sendRewardNotifications<'a>(&'a self) -> AsyncReturn<usize> {
+        let fut = async move { send_reward_notifications(self).await };
+
+        Box::pin(fut)
+    }
+
+    /// Returns the information about a previously held auction.
+    #[update(trait = true)]
+    fn auctionInfo(&self, id: usize) -> Result<AuctionInfo, AuctionError> {
+        auction_info(self, id)
+    }
+
+    /// Returns the current cycles balance, `min_cycles` and the fee ratios computed from them, so
+    /// that the share of the fees going to the auction can be verified.
+    #[query(trait = true)]
+    fn getFeeRatioDetails(&self) -> FeeRatioDetails {
+        fee_ratio_details(self)
+    }
+
+    /// Returns the totals of all the held auctions.
+    #[query(trait = true)]
+    fn getAuctionStats(&self) -> AuctionStats {
+        self.state().borrow().auction_stats.clone()
+    }
+
+    /// Returns up to `limit` auctions starting from the `offset` auction id, oldest first. No more
+    /// than 100 auctions are returned at once.
+    #[query(trait = true)]
+    fn getAuctionHistory(&self, offset: usize, limit: usize) -> PaginatedAuctions {
+        auction_history(self, offset, limit)
+    }
+
+    /// Returns the auctions held between `from` and `to` timestamps inclusive, oldest first. No
+    /// more than 100 auctions are returned at once, the rest can be queried with
+    /// `getAuctionHistory` starting from the `next` id.
+    #[query(trait = true)]
+    fn getAuctionsByTime(&self, from: Timestamp, to: Timestamp) -> PaginatedAuctions {
+        auctions_by_time(self, from, to)
+    }
+
+    /// Returns the minimum cycles set for the canister.
+    ///
+    /// This value affects the fee ratio set by the auctions. The more cycles available in the canister
+    /// the less proportion of the fees will be transferred to the auction participants. If the amount
+    /// of cycles in the canister drops below this value, all the fees will be used for cycle auction.
+    #[update(trait = true)]
+    fn getMinCycles(&self) -> u64 {
+        self.state().borrow().stats.min_cycles
+    }
+
+    /// Sets the minimum cycles for the canister. For more information about this value, read [get_min_cycles].
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setMinCycles(&self, min_cycles: u64) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::MinCycles(min_cycles));
+        Ok(())
+    }
+
+    /// Sets the cycles balance below which the `LowCycles` event is recorded in the event log, and
+    /// the canister asked to top up the token with `cycles_top_up_request(balance, threshold)`
+    /// calls. Zero threshold disables the monitoring.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setCyclesMonitor(
+        &self,
+        threshold: Cycles,
+        provider: Option<Principal>,
+    ) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(
+            caller,
+            CanisterUpdate::CyclesMonitor {
+                threshold,
+                provider,
+            },
+        );
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn getCyclesMonitor(&self) -> CyclesMonitor {
+        self.state().borrow().cycles_monitor.clone()
+    }
+
+    /// Checks the cycles balance and, if it's below the threshold, asks the top-up provider to top
+    /// up the token right away, without waiting for the timer. The requests are sent at most once
+    /// an hour. Returns `true` if the request was sent.
+    #[update(trait = true)]
+    fn checkCycles<'a>(&'a self) -> AsyncReturn<bool> {
+        let fut = async move { request_top_up(self).await };
+
+        Box::pin(fut)
+    }
+
+    /// Sets the minimum time between two consecutive auctions, in seconds.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setAuctionPeriod(&self, period_sec: u64) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        // IC timestamp is in nanoseconds, thus multiplying
+        self.update_stats(caller, CanisterUpdate::AuctionPeriod(period_sec));
+        Ok(())
+    }
+
+    /// Sets the anti-sniping window, in seconds. A bid made within the window before the auction
+    /// is due moves the auction so it's due a window after the bid, up to one auction period in
+    /// total. Zero disables the extension.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setBidExtensionWindow(&self, window_sec: u64) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::BidExtensionWindow(window_sec));
+        Ok(())
+    }
+
+    /// Sets the share of the bid that is kept by the canister when the bid is withdrawn, in basis
+    /// points. Values over 10000 are treated as 100%.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setBidWithdrawalPenalty(&self, penalty_bps: u16) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::BidWithdrawalPenalty(penalty_bps));
+        Ok(())
+    }
+
+    /// Disables or enables the cycle auctions. While the auctions are disabled, no cycles can be
+    /// bid, no auctions are held, and the whole fee goes to the fee receivers. The existing bids
+    /// can still be withdrawn.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setAuctionDisabled(&self, disabled: bool) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::AuctionDisabled(disabled));
+        Ok(())
+    }
+
+    /// Enables or disables notifying the bidders about their auction rewards.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setNotifyBidders(&self, enabled: bool) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::NotifyBidders(enabled));
+        Ok(())
+    }
+
+    /// Sets the asset the auction rewards are paid in. If ICP is chosen, the accumulated fees are
+    /// swapped by the given exchange canister, which must implement the `swap_to_icp` method, for
+    /// at least the given amount of ICP per token.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setAuctionRewardAsset(&self, asset: AuctionRewardAsset) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::RewardAsset(asset));
+        Ok(())
+    }
+
+    /// Sets the token the auction bids can be paid in besides the cycles with `bidTokens`, or
+    /// disables the token bids if `None` is given.
+    ///
+    /// Only the owner is allowed to call this method.
+    #[update(trait = true)]
+    fn setBidToken(&self, token: Option<BidToken>) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::BidToken(token));
+        Ok(())
+    }
+
+    /// Allows the `consumer` to consume the notifications of any transaction, e.g. for a relay
+    /// canister processing the notifications on behalf of the receivers. Only the owner is allowed
+    /// to call this method.
+    #[update(trait = true)]
+    fn addNotificationConsumer(&self, consumer: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::AddNotificationConsumer(consumer));
+        Ok(())
+    }
+
+    /// Revokes the permission given with `addNotificationConsumer`. Only the owner is allowed to
+    /// call this method.
+    #[update(trait = true)]
+    fn removeNotificationConsumer(&self, consumer: Principal) -> Result<(), TxError> {
+        let caller = CheckedPrincipal::owner(&self.state().borrow())?;
+        self.update_stats(caller, CanisterUpdate::RemoveNotificationConsumer(consumer));
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn getNotificationConsumers(&self) -> Vec<Principal> {
+        self.state()
+            .borrow()
+            .notification_consumers
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Marks the notification of the transaction as consumed, so it cannot be sent anymore. Only
+    /// the notification receiver, or the transaction recipient if the notification was not sent
+    /// yet, and the consumers set by the owner can consume it.
+    #[update(trait = true)]
+    fn consume_notification<'a>(&'a self, transaction_id: TxId) -> AsyncReturn<TxReceipt> {
+        let fut = async move { consume_notification(self, transaction_id).await };
+
+        Box::pin(fut)
+    }
+
+    /// Approves the tokens and calls `is20_on_approval` of the spender with the transaction
+    /// details, the caller and the `payload`, so that the spender can act on the allowance
+    /// without a follow-up call.
+    #[update(trait = true)]
+    fn approveAndCall<'a>(
+        &'a self,
+        spender: Principal,
+        amount: Tokens128,
+        payload: Vec<u8>,
+    ) -> AsyncReturn<TxReceipt> {
+        let caller = CheckedPrincipal::with_recipient(spender);
+        let fut = async move { approve_and_call(self, caller?, amount, payload).await };
+        Box::pin(fut)
+    }
+
+    #[update(trait = true)]
+    fn approveAndNotify<'a>(
+        &'a self,
+        spender: Principal,
+        amount: Tokens128,
+    ) -> AsyncReturn<TxReceipt> {
+        let caller = CheckedPrincipal::with_recipient(spender);
+        let fut = async move { approve_and_notify(self, caller?, amount).await };
+        Box::pin(fut)
+    }
+
+    #[update(trait = true)]
+    fn notify<'a>(&'a self, transaction_id: TxId, to: Principal) -> AsyncReturn<TxReceipt> {
+        let fut = async move { notify(self, transaction_id, to).await };
+
+        Box::pin(fut)
+    }
+
+    /// Returns up to `limit` events of the event log starting from the `since` sequence number. The
+    /// event log contains the transactions and the parameter changes that are not recorded in the
+    /// transaction history. The sequence numbers have no gaps, so the consumers can continue from
+    /// the last sequence number they processed. No more than 1000 events are returned at once.
+    #[query(trait = true)]
+    fn getEvents(&self, since: u64, limit: usize) -> Vec<Event> {
+        self.state()
+            .borrow()
+            .ledger
+            .get_events(since, limit.min(MAX_TRANSACTION_QUERY_LEN))
+    }
+
+    /// Subscribes the calling canister to the transactions matching the `filter`. The matching
+    /// transactions are sent in batches with one-way `is20_events` calls by the canister timer,
+    /// starting from the next transaction. Calling it again changes the filter of the subscription.
+    ///
+    /// Only canisters can subscribe. A new subscription is charged the approval fee.
+    #[update(trait = true)]
+    fn subscribe(&self, filter: SubscriptionFilter) -> Result<(), TxError> {
+        subscribe(self, filter)
+    }
+
+    #[update(trait = true)]
+    fn unsubscribe(&self) -> Result<(), TxError> {
+        unsubscribe(self)
+    }
+
+    #[query(trait = true)]
+    fn getSubscription(&self, subscriber: Principal) -> Option<Subscription> {
+        self.state()
+            .borrow()
+            .subscriptions
+            .get(&subscriber)
+            .cloned()
+    }
+
+    /// Sends the notifications given as `(transaction_id, to)` pairs, e.g. to the recipients of a
+    /// `batchTransfer`. Each notification is checked in the same way as in `notify`. Returns the
+    /// receipts in the order of the notifications. No more than 100 notifications can be sent at
+    /// once.
+    #[update(trait = true)]
+    fn notifyBatch<'a>(
+        &'a self,
+        notifications: Vec<(TxId, Principal)>,
+    ) -> AsyncReturn<Result<Vec<TxReceipt>, TxError>> {
+        let fut = async move { notify_batch(self, notifications).await };
+
+        Box::pin(fut)
+    }
+
+    /// Returns the notifications that failed to be delivered and are waiting to be retried.
+    #[query(trait = true)]
+    fn getFailedNotifications(&self) -> Vec<FailedNotification> {
+        self.state()
+            .borrow()
+            .ledger
+            .failed_notifications
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Retries the failed notification of the transaction right away.
+    #[update(trait = true)]
+    fn retryNotification<'a>(&'a self, transaction_id: TxId) -> AsyncReturn<TxReceipt> {
+        let fut = async move { retry_notification(self, transaction_id).await };
+
+        Box::pin(fut)
+    }
+
+    /********************** Transactions ***********************/
+    #[query(trait = true)]
+    fn getTransaction(&self, id: TxId) -> TxRecord {
+        self.state().borrow().ledger.get(id).unwrap_or_else(|| {
+            ic_canister::ic_kit::ic::trap(&format!("Transaction {} does not exist", id))
+        })
+    }
+
+    /// Returns the transactions with the given ids, or `None` for the ids that don't exist. At most
+    /// `MAX_TRANSACTION_QUERY_LEN` ids are looked up, the rest are ignored.
+    #[query(trait = true)]
+    fn getTransactionsByIds(&self, ids: Vec<TxId>) -> Vec<Option<TxRecord>> {
+        let state = self.state();
+        let state = state.borrow();
+        ids.into_iter()
+            .take(MAX_TRANSACTION_QUERY_LEN)
+            .map(|id| state.ledger.get(id))
+            .collect()
+    }
+
+    /// Returns a list of transactions in paginated form. The `who` is optional, if given, only transactions of the `who` are
+    /// returned. `count` is the number of transactions to return, `transaction_id` is the transaction index which is used as
+    /// the offset of the first transaction to return, any
+    ///
+    /// It returns `PaginatedResult` a struct, which contains `result` which is a list of transactions `Vec<TxRecord>` that meet the requirements of the query,
+    /// and `next_id` which is the index of the next transaction to return.
+    ///
+    /// The transactions are returned newest first, unless the `order` is `Asc`.
+    #[query(trait = true)]
+    fn getTransactions(
+        &self,
+        who: Option<Principal>,
+        count: usize,
         transaction_id: Option<TxId>,
+        order: Option<SortOrder>,
     ) -> PaginatedResult {
         // We don't trap if the transaction count is greater than the MAX_TRANSACTION_QUERY_LEN, we take the MAX_TRANSACTION_QUERY_LEN instead.
         self.state().borrow().ledger.get_transactions(
             who,
             count.min(MAX_TRANSACTION_QUERY_LEN),
             transaction_id,
+            order.unwrap_or_default(),
+        )
+    }
+
+    /// Same as `getTransactions`, but takes the user account in the ICRC-1 textual encoding.
+    ///
+    /// The ledger doesn't track subaccounts yet, so only accounts with the default subaccount are
+    /// accepted.
+    #[query(trait = true)]
+    fn getAccountTransactions(
+        &self,
+        account: String,
+        count: usize,
+        transaction_id: Option<TxId>,
+        order: Option<SortOrder>,
+    ) -> Result<PaginatedResult, AccountError> {
+        let account = Account::from_text(&account)?;
+        if !account.is_default() {
+            return Err(AccountError::SubaccountNotSupported);
+        }
+
+        Ok(self.getTransactions(Some(account.owner), count, transaction_id, order))
+    }
+
+    /// Sets the `value` for the `key` in the data attached to the caller account. Empty value
+    /// removes the entry. The keys are limited to 64 bytes, and all the entries of an account
+    /// together to 4096 bytes.
+    ///
+    /// Only the token holders are allowed to call this method.
+    #[update(trait = true)]
+    fn setAccountData(&self, key: String, value: Vec<u8>) -> Result<(), TxError> {
+        set_account_data(self, ic_canister::ic_kit::ic::caller(), key, value)
+    }
+
+    /// Returns the value stored by `who` for the `key` with `setAccountData`.
+    #[query(trait = true)]
+    fn getAccountData(&self, who: Principal, key: String) -> Option<Vec<u8>> {
+        self.state().borrow().account_data.get(who, &key)
+    }
+
+    /// Returns the ICRC-1 textual encoding of the account.
+    #[query(trait = true)]
+    fn encodeAccount(&self, account: Account) -> String {
+        account.to_string()
+    }
+
+    /// Parses the account from the ICRC-1 textual encoding, verifying the checksum.
+    #[query(trait = true)]
+    fn decodeAccount(&self, account: String) -> Result<Account, AccountError> {
+        Account::from_text(&account)
+    }
+
+    /// Returns a page of the transactions of the user `who`. Unlike `getTransactions`, the pages are
+    /// counted in the positions of the user transactions, with 0 being the oldest one, and the
+    /// `next` field of the result is the position to start the next page from.
+    ///
+    /// If `start` is not given, the first page starts from the oldest user transaction for `Asc`
+    /// order and from the newest one for `Desc` order, which is the default.
+    #[query(trait = true)]
+    fn getUserTransactions(
+        &self,
+        who: Principal,
+        start: Option<u64>,
+        limit: usize,
+        order: Option<SortOrder>,
+    ) -> PaginatedResult {
+        self.state().borrow().ledger.get_user_transactions(
+            who,
+            start,
+            limit.min(MAX_TRANSACTION_QUERY_LEN),
+            order.unwrap_or_default(),
         )
     }
 
@@ -1,13 +1,32 @@
+use candid::Nat;
+use ic_canister::ic_kit::ic;
 use ic_cdk::export::Principal;
 use ic_helpers::tokens::Tokens128;
+use sha2::{Digest, Sha256};
 
+use crate::amount::nat_to_amount;
+use crate::amount_math::{
+    credit, debit, proportional_share, ratio_share, saturating_add, saturating_sub,
+};
 use crate::canister::is20_auction::auction_principal;
-use crate::principal::{CheckedPrincipal, Owner, SenderRecipient, TestNet, WithRecipient};
+use crate::principal::{CheckedPrincipal, Minter, Owner, SenderRecipient, TestNet, WithRecipient};
 use crate::state::{Balances, CanisterState};
-use crate::types::{TxError, TxReceipt};
+use crate::types::BASIS_POINTS_DENOMINATOR;
+use crate::types::{
+    AccountError, FeeSplit, Operation, StatsData, Timestamp, TransferArg, TransferError, TxError,
+    TxId, TxReceipt,
+};
 
 use super::TokenCanisterAPI;
 
+/// How long an ICRC-1 transfer with `created_at_time` is valid, in nanoseconds.
+pub const TX_WINDOW: Timestamp = 24 * 60 * 60 * 1_000_000_000;
+/// Allowed difference between the `created_at_time` and the canister time, in nanoseconds.
+pub const PERMITTED_DRIFT: Timestamp = 2 * 60 * 1_000_000_000;
+/// `GenericError` code of the transfers from or to non-default subaccounts. It's out of the range
+/// of the [TxError::error_code] values.
+pub const SUBACCOUNT_NOT_SUPPORTED_CODE: u64 = 1000;
+
 pub fn transfer(
     canister: &impl TokenCanisterAPI,
     caller: CheckedPrincipal<WithRecipient>,
@@ -16,9 +35,107 @@ pub fn transfer(
 ) -> TxReceipt {
     let state = canister.state();
     let mut state = state.borrow_mut();
+    transfer_tokens(
+        &mut state,
+        caller.inner(),
+        caller.recipient(),
+        amount,
+        fee_limit,
+    )
+}
+
+/// ICRC-1 transfer from the caller to `transfer.to`. Only the default subaccounts are supported.
+/// The transfers with `created_at_time` are deduplicated within [TX_WINDOW], and the memo is only
+/// used for the deduplication. The errors are returned as the ICRC-1 variants, so the clients can
+/// handle them in the same way as for any other ICRC-1 ledger.
+pub fn icrc1_transfer(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    transfer: TransferArg,
+) -> Result<Nat, TransferError> {
+    let from_default = transfer.from_subaccount.map_or(true, |sub| sub == [0; 32]);
+    if !from_default || !transfer.to.is_default() {
+        return Err(TransferError::GenericError {
+            error_code: Nat::from(SUBACCOUNT_NOT_SUPPORTED_CODE),
+            message: AccountError::SubaccountNotSupported.to_string(),
+        });
+    }
+
+    let amount = nat_to_amount(&transfer.amount)?;
+    let fee = transfer.fee.as_ref().map(nat_to_amount).transpose()?;
+
+    if let Some(created_at) = transfer.created_at_time {
+        let now = ic::time();
+        if created_at.saturating_add(TX_WINDOW + PERMITTED_DRIFT) < now {
+            return Err(TransferError::TooOld);
+        }
+        if created_at > now.saturating_add(PERMITTED_DRIFT) {
+            return Err(TransferError::CreatedInFuture { ledger_time: now });
+        }
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let (from, to) = (caller.inner(), caller.recipient());
+
+    let now = ic::time();
+    state
+        .recent_transfers
+        .expire(now.saturating_sub(TX_WINDOW + PERMITTED_DRIFT));
+    let hash = transfer
+        .created_at_time
+        .map(|created_at| (created_at, transfer_hash(from, &transfer)));
+    if let Some(duplicate_of) = hash
+        .as_ref()
+        .and_then(|(_, hash)| state.recent_transfers.get(hash))
+    {
+        return Err(TransferError::Duplicate {
+            duplicate_of: Nat::from(duplicate_of),
+        });
+    }
+
+    let expected_fee = if is_mint_or_burn(&state.stats, from, to) {
+        Tokens128::ZERO
+    } else {
+        state.stats.fee_for(amount)
+    };
+    if matches!(fee, Some(fee) if fee != expected_fee) {
+        return Err(TransferError::BadFee { expected_fee });
+    }
+
+    let balance = state.balances.transferable(&from);
+    let id = transfer_tokens(&mut state, from, to, amount, None).map_err(|error| match error {
+        TxError::InsufficientBalance => TransferError::InsufficientFunds { balance },
+        error => error.into(),
+    })?;
+    if let Some((created_at, hash)) = hash {
+        state.recent_transfers.insert(hash, created_at, id);
+    }
+
+    Ok(Nat::from(id))
+}
 
-    let (fee, fee_to) = state.stats.fee_info();
-    let fee_ratio = state.bidding_state.fee_ratio;
+/// Hash identifying the ICRC-1 transfer for the deduplication.
+fn transfer_hash(caller: Principal, transfer: &TransferArg) -> Vec<u8> {
+    let bytes = candid::encode_args((caller, transfer)).expect("transfer arguments are encodable");
+    Sha256::digest(bytes).to_vec()
+}
+
+/// Transfers the tokens from `from` to `to`, charging the fee from `from`. The caller is
+/// responsible for checking that `from` authorized the transfer.
+pub fn transfer_tokens(
+    state: &mut CanisterState,
+    from: Principal,
+    to: Principal,
+    amount: Tokens128,
+    fee_limit: Option<Tokens128>,
+) -> TxReceipt {
+    state.check_not_frozen(&[from, to])?;
+    if is_mint_or_burn(&state.stats, from, to) {
+        return mint_or_burn(state, from, from, to, amount);
+    }
+
+    let fee = state.stats.fee_for(amount);
 
     if let Some(fee_limit) = fee_limit {
         if fee > fee_limit {
@@ -26,23 +143,17 @@ pub fn transfer(
         }
     }
 
-    if state.balances.balance_of(&caller.inner()) < (amount + fee).ok_or(TxError::AmountOverflow)? {
+    if state.balances.transferable(&from) < credit(amount, fee)? {
         return Err(TxError::InsufficientBalance);
     }
 
-    charge_fee(&mut state.balances, caller.inner(), fee_to, fee, fee_ratio)
-        .expect("never fails due to checks above");
-    transfer_balance(
-        &mut state.balances,
-        caller.inner(),
-        caller.recipient(),
-        amount,
-    )
-    .expect("never fails due to checks above");
+    let mut update = BalanceUpdate::default();
+    let split = plan_fee(state, &mut update, from, fee)?;
+    update.transfer(&state.balances, from, to, amount)?;
 
-    let id = state
-        .ledger
-        .transfer(caller.inner(), caller.recipient(), amount, fee);
+    update.apply(&mut state.balances);
+    record_fee(state, from, &split);
+    let id = state.ledger.transfer(from, to, amount, fee);
     Ok(id)
 }
 
@@ -53,48 +164,48 @@ pub fn transfer_from(
 ) -> TxReceipt {
     let state = canister.state();
     let mut state = state.borrow_mut();
+    state.check_not_frozen(&[caller.inner(), caller.from(), caller.to()])?;
     let from_allowance = state.allowance(caller.from(), caller.inner());
-    let CanisterState {
-        ref mut balances,
-        ref bidding_state,
-        ref stats,
-        ..
-    } = &mut *state;
 
-    let (fee, fee_to) = stats.fee_info();
-    let fee_ratio = bidding_state.fee_ratio;
+    if is_mint_or_burn(&state.stats, caller.from(), caller.to()) {
+        let allowance =
+            debit(from_allowance, amount).map_err(|_| TxError::InsufficientAllowance)?;
+        let id = mint_or_burn(
+            &mut state,
+            caller.inner(),
+            caller.from(),
+            caller.to(),
+            amount,
+        )?;
+        state
+            .allowances
+            .set(caller.from(), caller.inner(), allowance);
+        return Ok(id);
+    }
 
-    let value_with_fee = (amount + fee).ok_or(TxError::AmountOverflow)?;
+    let fee = state.stats.fee_for(amount);
+
+    let value_with_fee = credit(amount, fee)?;
     if from_allowance < value_with_fee {
         return Err(TxError::InsufficientAllowance);
     }
 
-    let from_balance = balances.balance_of(&caller.from());
+    let from_balance = state.balances.transferable(&caller.from());
     if from_balance < value_with_fee {
         return Err(TxError::InsufficientBalance);
     }
 
-    charge_fee(balances, caller.from(), fee_to, fee, fee_ratio)
-        .expect("never fails due to checks above");
-    transfer_balance(balances, caller.from(), caller.to(), amount)
-        .expect("never fails due to checks above");
+    let allowance =
+        debit(from_allowance, value_with_fee).map_err(|_| TxError::InsufficientAllowance)?;
+    let mut update = BalanceUpdate::default();
+    let split = plan_fee(&state, &mut update, caller.from(), fee)?;
+    update.transfer(&state.balances, caller.from(), caller.to(), amount)?;
 
-    let allowances = state
+    update.apply(&mut state.balances);
+    record_fee(&mut state, caller.from(), &split);
+    state
         .allowances
-        .get_mut(&caller.from())
-        .expect("allowance existing is checked above when check allowance sufficiency");
-    let allowance = allowances
-        .get_mut(&caller.inner())
-        .expect("allowance existing is checked above when check allowance sufficiency");
-    *allowance = (*allowance - value_with_fee).expect("allowance sufficiency checked above");
-
-    if *allowance == Tokens128::from(0u128) {
-        allowances.remove(&caller.inner());
-
-        if allowances.is_empty() {
-            state.allowances.remove(&caller.from());
-        }
-    }
+        .set(caller.from(), caller.inner(), allowance);
 
     let id = state
         .ledger
@@ -109,37 +220,23 @@ pub fn approve(
 ) -> TxReceipt {
     let state = canister.state();
     let mut state = state.borrow_mut();
-    let CanisterState {
-        ref mut bidding_state,
-        ref mut balances,
-        ref stats,
-        ..
-    } = &mut *state;
-
-    let (fee, fee_to) = stats.fee_info();
-    let fee_ratio = bidding_state.fee_ratio;
-    if balances.balance_of(&caller.inner()) < fee {
+    state.check_not_frozen(&[caller.inner(), caller.recipient()])?;
+
+    // Approvals don't move tokens, so the fee doesn't depend on the approved amount.
+    let fee = state.stats.fee_for(Tokens128::ZERO);
+    if state.balances.transferable(&caller.inner()) < fee {
         return Err(TxError::InsufficientBalance);
     }
 
-    charge_fee(balances, caller.inner(), fee_to, fee, fee_ratio)
-        .expect("never fails due to checks above");
-    let amount_with_fee = (amount + fee).ok_or(TxError::AmountOverflow)?;
+    let amount_with_fee = credit(amount, fee)?;
+    let mut update = BalanceUpdate::default();
+    let split = plan_fee(&state, &mut update, caller.inner(), fee)?;
 
-    if amount_with_fee == Tokens128::from(0u128) {
-        if let Some(allowances) = state.allowances.get_mut(&caller.inner()) {
-            allowances.remove(&caller.recipient());
-            if allowances.is_empty() {
-                state.allowances.remove(&caller.inner());
-            }
-        }
-    } else {
-        state
-            .allowances
-            .entry(caller.inner())
-            .or_default()
-            .insert(caller.recipient(), amount_with_fee);
-    }
+    update.apply(&mut state.balances);
+    record_fee(&mut state, caller.inner(), &split);
+    state
+        .allowances
+        .set(caller.inner(), caller.recipient(), amount_with_fee);
 
     let id = state
         .ledger
@@ -147,22 +244,56 @@ pub fn approve(
     Ok(id)
 }
 
+/// Whether the transfer from `from` to `to` is made from or to the minting account. Such transfers
+/// are mints and burns, which are not charged a fee.
+pub(crate) fn is_mint_or_burn(stats: &StatsData, from: Principal, to: Principal) -> bool {
+    matches!(stats.minting_account, Some(account) if account == from || account == to)
+}
+
+/// Applies the transfer from or to the minting account made by the `caller`: the transfers from
+/// the minting account are recorded as mints, and the transfers to it as burns. Must only be
+/// called for the transfers satisfying [is_mint_or_burn].
+pub(crate) fn mint_or_burn(
+    state: &mut CanisterState,
+    caller: Principal,
+    from: Principal,
+    to: Principal,
+    amount: Tokens128,
+) -> TxReceipt {
+    if state.stats.minting_account == Some(from) {
+        mint(state, caller, to, amount)
+    } else {
+        burn(state, caller, from, amount)
+    }
+}
+
 pub fn mint(
     state: &mut CanisterState,
     caller: Principal,
     to: Principal,
     amount: Tokens128,
 ) -> TxReceipt {
-    state.stats.total_supply =
-        (state.stats.total_supply + amount).ok_or(TxError::AmountOverflow)?;
-    let balance = state.balances.0.entry(to).or_default();
-    let new_balance = (*balance + amount)
-        .expect("balance cannot be larger than total_supply which is already checked");
-    *balance = new_balance;
+    let total_supply = credit(state.stats.total_supply, amount)?;
+    if matches!(state.stats.max_supply, Some(max_supply) if total_supply > max_supply) {
+        return Err(TxError::MaxSupplyExceeded);
+    }
+    let new_balance = credit(state.balances.balance_of(&to), amount)?;
 
-    let id = state.ledger.mint(caller, to, amount);
+    state.balances.set(to, new_balance);
+    Ok(record_mint(state, caller, to, amount))
+}
 
-    Ok(id)
+/// Adds the minted `amount` to the total supply and records the mint. The balance of `to` must
+/// already include the amount.
+pub(crate) fn record_mint(
+    state: &mut CanisterState,
+    caller: Principal,
+    to: Principal,
+    amount: Tokens128,
+) -> TxId {
+    state.stats.total_supply = saturating_add(state.stats.total_supply, amount);
+    state.stats.total_minted = saturating_add(state.stats.total_minted, amount);
+    state.ledger.mint(caller, to, amount)
 }
 
 pub fn mint_test_token(
@@ -171,12 +302,22 @@ pub fn mint_test_token(
     to: Principal,
     amount: Tokens128,
 ) -> TxReceipt {
-    mint(state, caller.inner(), to, amount)
+    // The owner is not limited by the faucet.
+    if caller.inner() == state.stats.owner {
+        return mint(state, caller.inner(), to, amount);
+    }
+
+    let now = ic::time();
+    let claimed = state.faucet.check_claim(&caller.inner(), amount, now)?;
+    let id = mint(state, caller.inner(), to, amount)?;
+    state.faucet.record_claim(caller.inner(), claimed, now);
+
+    Ok(id)
 }
 
-pub fn mint_as_owner(
+pub fn mint_as_minter(
     state: &mut CanisterState,
-    caller: CheckedPrincipal<Owner>,
+    caller: CheckedPrincipal<Minter>,
     to: Principal,
     amount: Tokens128,
 ) -> TxReceipt {
@@ -189,25 +330,28 @@ pub fn burn(
     from: Principal,
     amount: Tokens128,
 ) -> TxReceipt {
-    match state.balances.0.get_mut(&from) {
-        Some(balance) => {
-            *balance = (*balance - amount).ok_or(TxError::InsufficientBalance)?;
-            if *balance == Tokens128::ZERO {
-                state.balances.0.remove(&from);
-            }
-        }
-        None => {
-            if !amount.is_zero() {
-                return Err(TxError::InsufficientBalance);
-            }
-        }
+    let balance = debit(state.balances.balance_of(&from), amount)?;
+    if balance < state.balances.locked(&from) {
+        return Err(TxError::InsufficientBalance);
     }
+    debit(state.stats.total_supply, amount)?;
 
-    state.stats.total_supply =
-        (state.stats.total_supply - amount).expect("total supply cannot be less then user balance");
+    state.balances.set(from, balance);
+    Ok(record_burn(state, caller, from, amount))
+}
 
-    let id = state.ledger.burn(caller, from, amount);
-    Ok(id)
+/// Subtracts the burned `amount` from the total supply and records the burn. The balance of `from`
+/// must already be reduced by the amount.
+pub(crate) fn record_burn(
+    state: &mut CanisterState,
+    caller: Principal,
+    from: Principal,
+    amount: Tokens128,
+) -> TxId {
+    // The burned tokens are a part of the total supply.
+    state.stats.total_supply = saturating_sub(state.stats.total_supply, amount);
+    state.stats.total_burned = saturating_add(state.stats.total_burned, amount);
+    state.ledger.burn(caller, from, amount)
 }
 
 pub fn burn_own_tokens(state: &mut CanisterState, amount: Tokens128) -> TxReceipt {
@@ -234,53 +378,199 @@ pub fn transfer_balance(
         return Ok(());
     }
 
-    {
-        let from_balance = balances
-            .0
-            .get_mut(&from)
-            .ok_or(TxError::InsufficientBalance)?;
-        *from_balance = (*from_balance - amount).ok_or(TxError::InsufficientBalance)?;
+    // The locked tokens cannot be moved, see `lock`.
+    let from_balance = debit(balances.balance_of(&from), amount)?;
+    if from_balance < balances.locked(&from) {
+        return Err(TxError::InsufficientBalance);
+    }
+    if from == to {
+        return Ok(());
     }
 
-    {
-        let to_balance = balances.0.entry(to).or_default();
-        *to_balance = (*to_balance + amount).expect(
-            "never overflows since `from_balance + to_balance` is limited by `total_supply` amount",
-        );
+    let to_balance = credit(balances.balance_of(&to), amount)?;
+    balances.set(from, from_balance);
+    balances.set(to, to_balance);
+
+    Ok(())
+}
+
+/// Splits the `fee` into the burned share, the auction share and the fee receiver shares. The
+/// `fee_burn_ratio` share of the fee is burned, and the rest is split between the auction and the
+/// fee receivers by the current fee ratio. The burned amount is rounded down.
+pub(crate) fn split_fee(state: &CanisterState, fee: Tokens128) -> FeeSplit {
+    let fee_ratio = if state.stats.auction_disabled {
+        0.0
+    } else {
+        state.bidding_state.fee_ratio
+    };
+
+    let burned = proportional_share(
+        fee,
+        state.stats.fee_burn_ratio as u128,
+        BASIS_POINTS_DENOMINATOR,
+    );
+    let rest = saturating_sub(fee, burned);
+
+    // The ratio is clamped to `[0.0, 1.0]` by `ratio_share`.
+    let auction = ratio_share(rest, fee_ratio);
+    let owner_fee_amount = saturating_sub(rest, auction);
+
+    FeeSplit {
+        fee,
+        burned,
+        auction,
+        receivers: state.stats.fee_split(owner_fee_amount),
+    }
+}
+
+/// The fee the `caller` would be charged right now for the `operation` with the given `amount`,
+/// and how it would be split. Operations other than transfers and approvals are not charged a fee.
+pub fn fee_for_operation(
+    state: &CanisterState,
+    caller: Principal,
+    amount: Tokens128,
+    operation: Operation,
+) -> FeeSplit {
+    let fee = match operation {
+        // Transfers from the minting account are mints.
+        Operation::Transfer if state.stats.minting_account == Some(caller) => Tokens128::ZERO,
+        Operation::Transfer | Operation::TransferFrom => state.stats.fee_for(amount),
+        Operation::Approve => state.stats.fee_for(Tokens128::ZERO),
+        _ => Tokens128::ZERO,
+    };
+
+    split_fee(state, fee)
+}
+
+/// New balances of the accounts changed by an operation. An operation computes and checks all the
+/// new balances first, and writes them with [BalanceUpdate::apply] only if every one of them is
+/// valid, so a failed operation never leaves the balances half-updated.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct BalanceUpdate {
+    balances: Vec<(Principal, Tokens128)>,
+}
+
+impl BalanceUpdate {
+    /// Balance of `who` with the changes added so far.
+    fn balance_of(&self, balances: &Balances, who: &Principal) -> Tokens128 {
+        self.balances
+            .iter()
+            .find(|(account, _)| account == who)
+            .map_or_else(|| balances.balance_of(who), |(_, balance)| *balance)
     }
 
-    if *balances.0.get(&from).expect("checked above") == Tokens128::from(0) {
-        balances.0.remove(&from);
+    fn set(&mut self, who: Principal, balance: Tokens128) {
+        match self
+            .balances
+            .iter_mut()
+            .find(|(account, _)| *account == who)
+        {
+            Some(entry) => entry.1 = balance,
+            None => self.balances.push((who, balance)),
+        }
     }
 
-    Ok(())
+    /// Takes the `amount` from `who`. The locked tokens cannot be taken, see `lock`.
+    pub(crate) fn debit(
+        &mut self,
+        balances: &Balances,
+        who: Principal,
+        amount: Tokens128,
+    ) -> Result<(), TxError> {
+        let balance = debit(self.balance_of(balances, &who), amount)?;
+        if balance < balances.locked(&who) {
+            return Err(TxError::InsufficientBalance);
+        }
+
+        self.set(who, balance);
+        Ok(())
+    }
+
+    pub(crate) fn credit(
+        &mut self,
+        balances: &Balances,
+        who: Principal,
+        amount: Tokens128,
+    ) -> Result<(), TxError> {
+        let balance = credit(self.balance_of(balances, &who), amount)?;
+        self.set(who, balance);
+        Ok(())
+    }
+
+    pub(crate) fn transfer(
+        &mut self,
+        balances: &Balances,
+        from: Principal,
+        to: Principal,
+        amount: Tokens128,
+    ) -> Result<(), TxError> {
+        if amount.is_zero() {
+            return Ok(());
+        }
+
+        self.debit(balances, from, amount)?;
+        self.credit(balances, to, amount)
+    }
+
+    /// Writes the new balances.
+    pub(crate) fn apply(self, balances: &mut Balances) {
+        for (who, balance) in self.balances {
+            balances.set(who, balance);
+        }
+    }
 }
 
-pub(crate) fn charge_fee(
-    balances: &mut Balances,
+/// Adds the charge of the `fee` from the `user`, split as in `split_fee`, to the `update`. The
+/// returned split must be passed to [record_fee] once the update is applied.
+pub(crate) fn plan_fee(
+    state: &CanisterState,
+    update: &mut BalanceUpdate,
     user: Principal,
-    fee_to: Principal,
     fee: Tokens128,
-    fee_ratio: f64,
-) -> Result<(), TxError> {
-    // todo: check if this is enforced
-    debug_assert!((0.0..=1.0).contains(&fee_ratio));
+) -> Result<FeeSplit, TxError> {
+    let split = split_fee(state, fee);
+    for &(receiver, amount) in &split.receivers {
+        update.transfer(&state.balances, user, receiver, amount)?;
+    }
+    update.transfer(&state.balances, user, auction_principal(), split.auction)?;
+    update.debit(&state.balances, user, split.burned)?;
+
+    Ok(split)
+}
+
+/// Removes the burned share of the fee paid by the `user` from the total supply and records the
+/// burn.
+pub(crate) fn record_fee(state: &mut CanisterState, user: Principal, split: &FeeSplit) {
+    if split.burned.is_zero() {
+        return;
+    }
 
-    if fee == Tokens128::from(0) {
+    state.stats.total_supply = saturating_sub(state.stats.total_supply, split.burned);
+    state.stats.total_burned = saturating_add(state.stats.total_burned, split.burned);
+    state.ledger.fee_burn(user, split.burned);
+}
+
+/// Charges the approval fee from the `user` for a service of the canister that has no other cost,
+/// e.g. scheduling a transfer. The fee is recorded in the ledger as a transfer of zero tokens to
+/// `to`, the account the service is for.
+pub(crate) fn charge_service_fee(
+    state: &mut CanisterState,
+    user: Principal,
+    to: Principal,
+) -> Result<(), TxError> {
+    let fee = state.stats.fee_for(Tokens128::ZERO);
+    if fee.is_zero() {
         return Ok(());
     }
+    if state.balances.transferable(&user) < fee {
+        return Err(TxError::InsufficientBalance);
+    }
 
-    // todo: test and figure out overflows
-    const INT_CONVERSION_K: u128 = 1_000_000_000_000;
-    let auction_fee_amount = (fee * Tokens128::from((fee_ratio * INT_CONVERSION_K as f64) as u128)
-        / INT_CONVERSION_K)
-        .expect("never division by 0");
-    let auction_fee_amount = auction_fee_amount
-        .to_tokens128()
-        .expect("fee is always greater");
-    let owner_fee_amount = (fee - auction_fee_amount).expect("fee is always greater");
-    transfer_balance(balances, user, fee_to, owner_fee_amount)?;
-    transfer_balance(balances, user, auction_principal(), auction_fee_amount)?;
+    let mut update = BalanceUpdate::default();
+    let split = plan_fee(state, &mut update, user, fee)?;
+    update.apply(&mut state.balances);
+    record_fee(state, user, &split);
+    state.ledger.transfer(user, to, Tokens128::ZERO, fee);
 
     Ok(())
 }
@@ -295,25 +585,15 @@ mod tests {
     use ic_canister::Canister;
 
     use crate::mock::*;
-    use crate::types::{Metadata, Operation, TransactionStatus};
+    use crate::state::RecentTransfers;
+    use crate::types::{
+        Account, FeeMode, Metadata, Operation, SortOrder, TransactionStatus, Value,
+    };
 
     use super::*;
 
     fn test_context() -> (&'static MockContext, TokenCanisterMock) {
-        let context = MockContext::new().with_caller(alice()).inject();
-
-        let canister = TokenCanisterMock::init_instance();
-        canister.init(Metadata {
-            logo: "".to_string(),
-            name: "".to_string(),
-            symbol: "".to_string(),
-            decimals: 8,
-            totalSupply: Tokens128::from(1000),
-            owner: alice(),
-            fee: Tokens128::from(0),
-            feeTo: alice(),
-            isTestToken: None,
-        });
+        let (context, canister) = crate::mock::test_context();
 
         // This is to make tests that don't rely on auction state
         // pass, because since we are running auction state on each
@@ -368,20 +648,356 @@ mod tests {
     }
 
     #[test]
-    fn fees_with_auction_enabled() {
+    fn icrc1_transfer_errors() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(100);
+        canister.state().borrow_mut().stats.fee_to = john();
+
+        let transfer = |amount: u128, fee: Option<u128>| TransferArg {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: Nat::from(amount),
+            fee: fee.map(Nat::from),
+            memo: None,
+            created_at_time: None,
+        };
+
+        assert_eq!(
+            canister.icrc1_transfer(transfer(200, Some(100))),
+            Ok(Nat::from(1u64))
+        );
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(200));
+
+        assert_eq!(
+            canister.icrc1_transfer(transfer(200, Some(50))),
+            Err(TransferError::BadFee {
+                expected_fee: Tokens128::from(100)
+            })
+        );
+        assert_eq!(
+            canister.icrc1_transfer(transfer(1000, None)),
+            Err(TransferError::InsufficientFunds {
+                balance: Tokens128::from(700)
+            })
+        );
+
+        let now = ic::time();
+        assert_eq!(
+            canister.icrc1_transfer(TransferArg {
+                created_at_time: Some(now + PERMITTED_DRIFT + 1),
+                ..transfer(10, None)
+            }),
+            Err(TransferError::CreatedInFuture { ledger_time: now })
+        );
+
+        canister.freezeAccount(bob()).unwrap();
+        assert_eq!(
+            canister.icrc1_transfer(transfer(10, None)),
+            Err(TransferError::GenericError {
+                error_code: Nat::from(TxError::AccountFrozen.error_code()),
+                message: TxError::AccountFrozen.to_string(),
+            })
+        );
+
+        assert!(matches!(
+            canister.icrc1_transfer(TransferArg {
+                to: Account::new(bob(), Some([1; 32])),
+                ..transfer(10, None)
+            }),
+            Err(TransferError::GenericError { .. })
+        ));
+
+        assert_eq!(
+            canister.icrc1_transfer(TransferArg {
+                amount: Nat::from(u128::MAX) + Nat::from(1u64),
+                ..transfer(0, None)
+            }),
+            Err(TransferError::GenericError {
+                error_code: Nat::from(TxError::AmountOverflow.error_code()),
+                message: TxError::AmountOverflow.to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn icrc1_transfer_deduplication() {
+        let canister = test_canister();
+        let transfer = TransferArg {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: Nat::from(100u64),
+            fee: None,
+            memo: Some(vec![1]),
+            created_at_time: Some(ic::time()),
+        };
+
+        let id = canister.icrc1_transfer(transfer.clone()).unwrap();
+        assert_eq!(
+            canister.icrc1_transfer(transfer.clone()),
+            Err(TransferError::Duplicate { duplicate_of: id })
+        );
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+
+        // Another memo or no `created_at_time` makes a new transfer.
+        canister
+            .icrc1_transfer(TransferArg {
+                memo: Some(vec![2]),
+                ..transfer.clone()
+            })
+            .unwrap();
+        let repeated = TransferArg {
+            created_at_time: None,
+            ..transfer
+        };
+        canister.icrc1_transfer(repeated.clone()).unwrap();
+        canister.icrc1_transfer(repeated).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(400));
+
+        // The transfers are forgotten after the window.
+        let mut recent = canister.state().borrow().recent_transfers.clone();
+        recent.expire(ic::time() + 1);
+        assert_eq!(recent, RecentTransfers::default());
+    }
+
+    #[test]
+    fn transfer_with_bps_fee() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee_to = john();
+        canister.setFeeMode(FeeMode::BasisPoints(250)).unwrap();
+        assert_eq!(
+            canister.setFeeMode(FeeMode::BasisPoints(10_001)),
+            Err(TxError::InvalidFeeConfig)
+        );
+
+        canister
+            .transfer(bob(), Tokens128::from(400), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(400));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(590));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(10));
+        assert_eq!(canister.getTransaction(1).fee, Tokens128::from(10));
+
+        assert_eq!(
+            canister.transfer(bob(), Tokens128::from(100), Some(Tokens128::from(1))),
+            Err(TxError::FeeExceededLimit)
+        );
+
+        // The fee is rounded down.
+        canister
+            .transfer(bob(), Tokens128::from(39), Some(Tokens128::ZERO))
+            .unwrap();
+    }
+
+    #[test]
+    fn bps_fee_bounds() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee_to = john();
+        canister.setFeeMode(FeeMode::BasisPoints(100)).unwrap();
+        canister
+            .setFeeBounds(Some(Tokens128::from(2)), Some(Tokens128::from(5)))
+            .unwrap();
+        assert_eq!(
+            canister.setFeeBounds(Some(Tokens128::from(6)), Some(Tokens128::from(5))),
+            Err(TxError::InvalidFeeConfig)
+        );
+
+        canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(2));
+        canister
+            .transfer(bob(), Tokens128::from(300), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(5));
+        canister
+            .transfer(bob(), Tokens128::from(600), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(10));
+
+        // The fee limit is checked against the clamped fee.
+        assert_eq!(
+            canister.transfer(bob(), Tokens128::from(1), Some(Tokens128::from(1))),
+            Err(TxError::FeeExceededLimit)
+        );
+    }
+
+    #[test]
+    fn fee_burn() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(15);
+        canister.state().borrow_mut().stats.fee_to = john();
+        canister.setFeeBurnRatio(5000).unwrap();
+        assert_eq!(
+            canister.setFeeBurnRatio(10_001),
+            Err(TxError::InvalidFeeConfig)
+        );
+
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(885));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        // The burned share is rounded down.
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(8));
+        assert_eq!(canister.totalSupply(), Tokens128::from(993));
+        assert_eq!(canister.getTokenInfo().totalBurned, Tokens128::from(7));
+
+        let burn = canister.getTransaction(id - 1);
+        assert_eq!(burn.operation, Operation::Burn);
+        assert_eq!(burn.amount, Tokens128::from(7));
+        assert_eq!(burn.from, alice());
+        assert_eq!(burn.caller, None);
+        assert_eq!(canister.getTransaction(id).fee, Tokens128::from(15));
+
+        // The balance changes of the fee burn are recorded with the transaction it is charged for.
+        assert_eq!(canister.balanceOfAt(alice(), id), Ok(Tokens128::from(885)));
+        assert_eq!(
+            canister.balanceOfAt(alice(), id - 1),
+            Ok(Tokens128::from(1000))
+        );
+        assert_eq!(canister.balanceOfAt(alice(), 0), Ok(Tokens128::from(1000)));
+        assert_eq!(canister.balanceOfAt(john(), id), Ok(Tokens128::from(8)));
+    }
+
+    #[test]
+    fn fee_distribution() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(11);
+        canister
+            .setFeeDistribution(vec![(john(), 2), (xtc(), 1)])
+            .unwrap();
+        assert_eq!(canister.getFeeDistribution(), vec![(john(), 2), (xtc(), 1)]);
+
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(889));
+        // The unit left after rounding goes to the first receiver.
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(8));
+        assert_eq!(canister.balanceOf(xtc()), Tokens128::from(3));
+
+        canister.setFeeDistribution(vec![]).unwrap();
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(789));
+    }
+
+    #[test]
+    fn invalid_fee_distribution() {
+        let canister = test_canister();
+        assert_eq!(
+            canister.setFeeDistribution(vec![(john(), 1), (john(), 2)]),
+            Err(TxError::InvalidFeeConfig)
+        );
+        assert_eq!(
+            canister.setFeeDistribution(vec![(john(), 1), (xtc(), 0)]),
+            Err(TxError::InvalidFeeConfig)
+        );
+    }
+
+    #[test]
+    fn fee_estimation() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(20);
+        canister.state().borrow_mut().stats.min_cycles = crate::types::DEFAULT_MIN_CYCLES;
+        canister.state().borrow_mut().bidding_state.fee_ratio = 0.5;
+        canister.setFeeBurnRatio(5000).unwrap();
+        canister
+            .setFeeDistribution(vec![(john(), 1), (xtc(), 1)])
+            .unwrap();
+
+        let estimate = canister.getFeeFor(alice(), Tokens128::from(100), Operation::Transfer);
+        assert_eq!(
+            estimate,
+            FeeSplit {
+                fee: Tokens128::from(20),
+                burned: Tokens128::from(10),
+                auction: Tokens128::from(5),
+                receivers: vec![(john(), Tokens128::from(3)), (xtc(), Tokens128::from(2))],
+            }
+        );
+        assert_eq!(
+            canister
+                .getFeeFor(alice(), Tokens128::from(100), Operation::Mint)
+                .fee,
+            Tokens128::ZERO
+        );
+
+        canister
+            .transfer(bob(), Tokens128::from(100), Some(estimate.fee))
+            .unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(3));
+        assert_eq!(canister.balanceOf(xtc()), Tokens128::from(2));
+        assert_eq!(canister.balanceOf(auction_principal()), Tokens128::from(5));
+        assert_eq!(canister.totalSupply(), Tokens128::from(990));
+    }
+
+    #[test]
+    fn fees_with_auction_enabled() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(50);
+        canister.state().borrow_mut().stats.fee_to = john();
+        canister.state().borrow_mut().stats.min_cycles = crate::types::DEFAULT_MIN_CYCLES;
+        canister.state().borrow_mut().bidding_state.fee_ratio = 0.5;
+
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(850));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(25));
+        assert_eq!(canister.balanceOf(auction_principal()), Tokens128::from(25));
+    }
+
+    #[test]
+    fn transfer_from_minting_account() {
+        let (ctx, canister) = test_context();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        canister.setMintingAccount(Some(john())).unwrap();
+
+        ctx.update_caller(john());
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), Some(Tokens128::ZERO))
+            .unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(canister.balanceOf(john()), Tokens128::ZERO);
+        assert_eq!(canister.totalSupply(), Tokens128::from(1100));
+        assert_eq!(canister.getTransaction(id).operation, Operation::Mint);
+    }
+
+    #[test]
+    fn transfer_to_minting_account() {
         let canister = test_canister();
-        canister.state().borrow_mut().stats.fee = Tokens128::from(50);
-        canister.state().borrow_mut().stats.fee_to = john();
-        canister.state().borrow_mut().stats.min_cycles = crate::types::DEFAULT_MIN_CYCLES;
-        canister.state().borrow_mut().bidding_state.fee_ratio = 0.5;
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        canister.setMintingAccount(Some(john())).unwrap();
 
-        canister
-            .transfer(bob(), Tokens128::from(100), None)
+        let id = canister
+            .transfer(john(), Tokens128::from(100), None)
             .unwrap();
-        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
-        assert_eq!(canister.balanceOf(alice()), Tokens128::from(850));
-        assert_eq!(canister.balanceOf(john()), Tokens128::from(25));
-        assert_eq!(canister.balanceOf(auction_principal()), Tokens128::from(25));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(900));
+        assert_eq!(canister.balanceOf(john()), Tokens128::ZERO);
+        assert_eq!(canister.totalSupply(), Tokens128::from(900));
+        assert_eq!(canister.getTransaction(id).operation, Operation::Burn);
+    }
+
+    #[test]
+    fn transfer_from_to_minting_account() {
+        let (ctx, canister) = test_context();
+        canister.approve(bob(), Tokens128::from(200)).unwrap();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        canister.setMintingAccount(Some(john())).unwrap();
+
+        ctx.update_caller(bob());
+        let id = canister
+            .transferFrom(alice(), john(), Tokens128::from(100))
+            .unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(900));
+        assert_eq!(canister.balanceOf(john()), Tokens128::ZERO);
+        assert_eq!(canister.totalSupply(), Tokens128::from(900));
+        assert_eq!(canister.allowance(alice(), bob()), Tokens128::from(100));
+        let burn = canister.getTransaction(id);
+        assert_eq!(burn.operation, Operation::Burn);
+        assert_eq!(burn.caller, Some(bob()));
+        assert_eq!(burn.from, alice());
     }
 
     #[test]
@@ -469,6 +1085,39 @@ mod tests {
         assert_eq!(canister.balanceOf(bob()), Tokens128::from(5000));
     }
 
+    #[test]
+    fn faucet_limit() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.is_test_token = true;
+        canister.setFaucetLimit(Some(Tokens128::from(100))).unwrap();
+        assert_eq!(canister.getFaucetLimit(), Some(Tokens128::from(100)));
+
+        // The owner is not limited.
+        canister.mint(alice(), Tokens128::from(1000)).unwrap();
+
+        let context = MockContext::new().with_caller(bob()).inject();
+        canister.faucet(Tokens128::from(60)).unwrap();
+        canister.mint(john(), Tokens128::from(30)).unwrap();
+        assert_eq!(
+            canister.faucet(Tokens128::from(20)),
+            Err(TxError::FaucetLimitExceeded {
+                remaining: Tokens128::from(10)
+            })
+        );
+        assert_eq!(
+            canister.faucet(Tokens128::from(u128::MAX)),
+            Err(TxError::AmountOverflow)
+        );
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(60));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(30));
+
+        context.add_time(24 * 60 * 60 * 1_000_000_000);
+        canister.faucet(Tokens128::from(100)).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(160));
+
+        assert_eq!(canister.setFaucetLimit(None), Err(TxError::Unauthorized));
+    }
+
     #[test]
     fn mint_by_owner() {
         let canister = test_canister();
@@ -479,6 +1128,82 @@ mod tests {
         assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(8000));
     }
 
+    #[test]
+    fn mint_by_minter() {
+        let (ctx, canister) = test_context();
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.mint(bob(), Tokens128::from(100)),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(canister.addMinter(bob()), Err(TxError::Unauthorized));
+
+        ctx.update_caller(alice());
+        canister.addMinter(bob()).unwrap();
+        assert_eq!(canister.getMinters(), vec![bob()]);
+
+        ctx.update_caller(bob());
+        assert!(canister.mint(john(), Tokens128::from(100)).is_ok());
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(100));
+        assert_eq!(canister.getTransaction(1).caller, Some(bob()));
+
+        ctx.update_caller(alice());
+        canister.removeMinter(bob()).unwrap();
+        assert!(canister.getMinters().is_empty());
+
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.mint(john(), Tokens128::from(100)),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn circulating_supply() {
+        let (ctx, canister) = test_context();
+        canister
+            .transfer(bob(), Tokens128::from(300), None)
+            .unwrap();
+        assert_eq!(canister.circulatingSupply(), Tokens128::from(1000));
+
+        canister.addReservedAccount(alice()).unwrap();
+        canister.addReservedAccount(john()).unwrap();
+        assert_eq!(canister.getReservedAccounts().len(), 2);
+        assert_eq!(canister.circulatingSupply(), Tokens128::from(300));
+
+        canister.removeReservedAccount(alice()).unwrap();
+        assert_eq!(canister.getReservedAccounts(), vec![john()]);
+        assert_eq!(canister.circulatingSupply(), Tokens128::from(1000));
+
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.addReservedAccount(bob()),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn mint_over_max_supply() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.max_supply = Some(Tokens128::from(1500));
+
+        assert!(canister.mint(bob(), Tokens128::from(500)).is_ok());
+        assert_eq!(
+            canister.mint(bob(), Tokens128::from(1)),
+            Err(TxError::MaxSupplyExceeded)
+        );
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(500));
+        assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(1500));
+        assert_eq!(
+            canister.getMetadata().maxSupply,
+            Some(Tokens128::from(1500))
+        );
+
+        // Burned tokens can be minted again.
+        canister.burn(None, Tokens128::from(100)).unwrap();
+        assert!(canister.mint(bob(), Tokens128::from(100)).is_ok());
+    }
+
     #[test]
     fn mint_saved_into_history() {
         let (ctx, canister) = test_context();
@@ -515,6 +1240,25 @@ mod tests {
         assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(900));
     }
 
+    #[test]
+    fn total_minted_and_burned() {
+        let canister = test_canister();
+        assert_eq!(canister.getTokenInfo().totalMinted, Tokens128::from(1000));
+
+        canister.mint(bob(), Tokens128::from(300)).unwrap();
+        canister.burn(None, Tokens128::from(100)).unwrap();
+        canister.burn(None, Tokens128::from(50)).unwrap();
+        assert_eq!(
+            canister.burn(None, Tokens128::from(10_000)),
+            Err(TxError::InsufficientBalance)
+        );
+
+        let info = canister.getTokenInfo();
+        assert_eq!(info.totalMinted, Tokens128::from(1300));
+        assert_eq!(info.totalBurned, Tokens128::from(150));
+        assert_eq!(info.metadata.totalSupply, Tokens128::from(1150));
+    }
+
     #[test]
     fn burn_too_much() {
         let canister = test_canister();
@@ -683,6 +1427,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn approvals_for_spender() {
+        let (ctx, canister) = test_context();
+        canister.approve(bob(), Tokens128::from(500)).unwrap();
+        ctx.update_caller(john());
+        canister.approve(bob(), Tokens128::from(300)).unwrap();
+        canister.approve(alice(), Tokens128::from(100)).unwrap();
+
+        let approvals = canister.getApprovalsForSpender(bob());
+        assert_eq!(approvals.len(), 2);
+        assert!(approvals.contains(&(alice(), Tokens128::from(500))));
+        assert!(approvals.contains(&(john(), Tokens128::from(300))));
+
+        canister.approve(bob(), Tokens128::from(0)).unwrap();
+        assert_eq!(
+            canister.getApprovalsForSpender(bob()),
+            vec![(alice(), Tokens128::from(500))]
+        );
+        assert_eq!(
+            canister.getApprovalsForSpender(alice()),
+            vec![(john(), Tokens128::from(100))]
+        );
+    }
+
     #[test]
     fn multiple_approves() {
         let canister = test_canister();
@@ -796,47 +1564,335 @@ mod tests {
             .transfer(john(), Tokens128::from(10), None)
             .unwrap();
 
-        assert_eq!(canister.getTransactions(None, 10, None).result.len(), 9);
-        assert_eq!(canister.getTransactions(None, 10, Some(3)).result.len(), 4);
         assert_eq!(
-            canister.getTransactions(Some(bob()), 10, None).result.len(),
+            canister.getTransactions(None, 10, None, None).result.len(),
+            9
+        );
+        assert_eq!(
+            canister
+                .getTransactions(None, 10, Some(3), None)
+                .result
+                .len(),
+            4
+        );
+        assert_eq!(
+            canister
+                .getTransactions(Some(bob()), 10, None, None)
+                .result
+                .len(),
             6
         );
         assert_eq!(
-            canister.getTransactions(Some(xtc()), 5, None).result.len(),
+            canister
+                .getTransactions(Some(xtc()), 5, None, None)
+                .result
+                .len(),
             1
         );
         assert_eq!(
             canister
-                .getTransactions(Some(alice()), 10, Some(5))
+                .getTransactions(Some(alice()), 10, Some(5), None)
                 .result
                 .len(),
             6
         );
-        assert_eq!(canister.getTransactions(None, 5, None).next, Some(3));
+        assert_eq!(canister.getTransactions(None, 5, None, None).next, Some(3));
+        assert_eq!(canister.getTransactions(None, 5, None, None).total_count, 9);
+        assert_eq!(
+            canister
+                .getTransactions(Some(bob()), 2, None, None)
+                .total_count,
+            6
+        );
         assert_eq!(
-            canister.getTransactions(Some(alice()), 3, Some(5)).next,
+            canister
+                .getTransactions(Some(alice()), 3, Some(5), None)
+                .next,
             Some(2)
         );
-        assert_eq!(canister.getTransactions(Some(bob()), 3, Some(2)).next, None);
+        assert_eq!(
+            canister.getTransactions(Some(bob()), 3, Some(2), None).next,
+            None
+        );
 
         for _ in 1..=10 {
             canister.transfer(bob(), Tokens128::from(10), None).unwrap();
         }
 
-        let txn = canister.getTransactions(None, 5, None);
+        let txn = canister.getTransactions(None, 5, None, None);
         assert_eq!(txn.result[0].index, 18);
         assert_eq!(txn.result[1].index, 17);
         assert_eq!(txn.result[2].index, 16);
         assert_eq!(txn.result[3].index, 15);
         assert_eq!(txn.result[4].index, 14);
-        let txn2 = canister.getTransactions(None, 5, txn.next);
+        let txn2 = canister.getTransactions(None, 5, txn.next, None);
         assert_eq!(txn2.result[0].index, 13);
         assert_eq!(txn2.result[1].index, 12);
         assert_eq!(txn2.result[2].index, 11);
         assert_eq!(txn2.result[3].index, 10);
         assert_eq!(txn2.result[4].index, 9);
-        assert_eq!(canister.getTransactions(None, 5, txn.next).next, Some(8));
+        assert_eq!(
+            canister.getTransactions(None, 5, txn.next, None).next,
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn get_holders_with_cursor() {
+        let canister = test_canister();
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        canister
+            .transfer(john(), Tokens128::from(100), None)
+            .unwrap();
+        canister.transfer(xtc(), Tokens128::from(50), None).unwrap();
+
+        let (first, second) = if bob() < john() {
+            (bob(), john())
+        } else {
+            (john(), bob())
+        };
+
+        let page = canister.getHolders(None, 2);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].0, alice());
+        assert_eq!(page[1], (first, Tokens128::from(100)));
+
+        let page = canister.getHolders(page.last().cloned(), 2);
+        assert_eq!(
+            page,
+            vec![(second, Tokens128::from(100)), (xtc(), Tokens128::from(50))]
+        );
+
+        assert!(canister.getHolders(page.last().cloned(), 2).is_empty());
+    }
+
+    #[test]
+    fn balance_of_at() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        canister.state().borrow_mut().stats.fee_to = john();
+        canister.state().borrow_mut().stats.min_cycles = crate::types::DEFAULT_MIN_CYCLES;
+        canister.state().borrow_mut().bidding_state.fee_ratio = 0.5;
+
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        canister.mint(bob(), Tokens128::from(50)).unwrap();
+        canister
+            .transfer(xtc(), Tokens128::from(200), None)
+            .unwrap();
+
+        assert_eq!(canister.balanceOfAt(alice(), 0), Ok(Tokens128::from(1000)));
+        assert_eq!(canister.balanceOfAt(alice(), 1), Ok(Tokens128::from(890)));
+        assert_eq!(canister.balanceOfAt(alice(), 3), Ok(Tokens128::from(680)));
+        assert_eq!(canister.balanceOfAt(bob(), 0), Ok(Tokens128::ZERO));
+        assert_eq!(canister.balanceOfAt(bob(), 1), Ok(Tokens128::from(100)));
+        assert_eq!(canister.balanceOfAt(bob(), 2), Ok(Tokens128::from(150)));
+        assert_eq!(
+            canister.balanceOfAt(bob(), 4),
+            Err(TxError::TransactionDoesNotExist)
+        );
+
+        // The fee receiver and the auction are not parties of the transactions, but their
+        // balances are recorded with them.
+        assert_eq!(canister.balanceOfAt(john(), 0), Ok(Tokens128::ZERO));
+        assert_eq!(canister.balanceOfAt(john(), 1), Ok(Tokens128::from(5)));
+        assert_eq!(canister.balanceOfAt(john(), 2), Ok(Tokens128::from(5)));
+        assert_eq!(canister.balanceOfAt(john(), 3), Ok(Tokens128::from(10)));
+        assert_eq!(
+            canister.balanceOfAt(auction_principal(), 1),
+            Ok(Tokens128::from(5))
+        );
+        assert_eq!(
+            canister.balanceOfAt(auction_principal(), 3),
+            Ok(Tokens128::from(10))
+        );
+
+        // A balance change made without a transaction is recorded with the next one.
+        canister
+            .state()
+            .borrow_mut()
+            .balances
+            .set(xtc(), Tokens128::from(500));
+        canister.state().borrow().ledger.record_balance_changes();
+        canister.transfer(bob(), Tokens128::from(30), None).unwrap();
+
+        assert_eq!(canister.balanceOfAt(xtc(), 3), Ok(Tokens128::from(200)));
+        assert_eq!(canister.balanceOfAt(xtc(), 4), Ok(Tokens128::from(500)));
+        assert_eq!(canister.balanceOfAt(bob(), 3), Ok(Tokens128::from(150)));
+        assert_eq!(canister.balanceOfAt(bob(), 4), Ok(Tokens128::from(180)));
+        assert_eq!(canister.balanceOfAt(alice(), 4), Ok(Tokens128::from(640)));
+        assert_eq!(canister.balanceOfAt(john(), 4), Ok(Tokens128::from(15)));
+    }
+
+    #[test]
+    fn balance_of_at_batch_transfer() {
+        let canister = test_canister();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        canister.state().borrow_mut().stats.fee_to = john();
+
+        let ids = canister
+            .batchTransfer(vec![
+                (bob(), Tokens128::from(100)),
+                (xtc(), Tokens128::from(200)),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            canister.balanceOfAt(alice(), ids[0]),
+            Ok(Tokens128::from(890))
+        );
+        assert_eq!(
+            canister.balanceOfAt(bob(), ids[0]),
+            Ok(Tokens128::from(100))
+        );
+        assert_eq!(canister.balanceOfAt(xtc(), ids[0]), Ok(Tokens128::ZERO));
+        assert_eq!(
+            canister.balanceOfAt(john(), ids[0]),
+            Ok(Tokens128::from(10))
+        );
+        assert_eq!(
+            canister.balanceOfAt(alice(), ids[1]),
+            Ok(Tokens128::from(680))
+        );
+        assert_eq!(
+            canister.balanceOfAt(xtc(), ids[1]),
+            Ok(Tokens128::from(200))
+        );
+        assert_eq!(
+            canister.balanceOfAt(john(), ids[1]),
+            Ok(Tokens128::from(20))
+        );
+    }
+
+    #[test]
+    fn holder_stats() {
+        let canister = test_canister();
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        canister.transfer(john(), Tokens128::from(5), None).unwrap();
+        canister
+            .transfer(xtc(), Tokens128::from(895), None)
+            .unwrap();
+
+        let stats = canister.getHolderStats();
+        assert_eq!(stats.holder_count, 3);
+        assert_eq!(stats.top_holders_count, 3);
+        assert_eq!(stats.top_holders_balance, Tokens128::from(1000));
+        assert_eq!(stats.top_holders_share, 1.0);
+        assert_eq!(stats.histogram, vec![1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn get_transactions_by_ids() {
+        let canister = test_canister();
+        canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+
+        let result = canister.getTransactionsByIds(vec![1, 5, 0]);
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].as_ref().unwrap().to, bob());
+        assert!(result[1].is_none());
+        assert_eq!(result[2].as_ref().unwrap().index, 0);
+    }
+
+    #[test]
+    fn get_user_transactions_test() {
+        let canister = test_canister();
+
+        for _ in 1..=3 {
+            canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+            canister.transfer(xtc(), Tokens128::from(10), None).unwrap();
+        }
+
+        // Bob's transactions have ids 1, 3 and 5.
+        let page = canister.getUserTransactions(bob(), None, 2, None);
+        let ids = page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![5, 3]);
+        assert_eq!(page.next, Some(0));
+        assert_eq!(page.total_count, 3);
+
+        let page = canister.getUserTransactions(bob(), page.next, 2, None);
+        let ids = page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1]);
+        assert_eq!(page.next, None);
+
+        let page = canister.getUserTransactions(bob(), None, 2, Some(SortOrder::Asc));
+        let ids = page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(page.next, Some(2));
+
+        // New transactions don't move the cursor.
+        canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+        let page = canister.getUserTransactions(bob(), page.next, 2, Some(SortOrder::Asc));
+        let ids = page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![5, 7]);
+        assert_eq!(page.next, None);
+
+        assert!(canister
+            .getUserTransactions(john(), None, 2, None)
+            .result
+            .is_empty());
+    }
+
+    #[test]
+    fn get_transactions_ascending() {
+        let canister = test_canister();
+
+        for _ in 1..=5 {
+            canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+        }
+        canister.transfer(xtc(), Tokens128::from(10), None).unwrap();
+
+        let txn = canister.getTransactions(None, 3, None, Some(SortOrder::Asc));
+        let ids = txn.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(txn.next, Some(3));
+
+        let txn = canister.getTransactions(None, 3, txn.next, Some(SortOrder::Asc));
+        let ids = txn.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4, 5]);
+        assert_eq!(txn.next, Some(6));
+
+        let txn = canister.getTransactions(Some(xtc()), 3, Some(1), Some(SortOrder::Asc));
+        assert_eq!(txn.result.len(), 1);
+        assert_eq!(txn.result[0].index, 6);
+        assert_eq!(txn.next, None);
+    }
+
+    #[test]
+    fn genesis_allocation() {
+        MockContext::new().with_caller(alice()).inject();
+        let canister = TokenCanisterMock::init_instance();
+        canister.init(Metadata {
+            genesisAllocation: Some(vec![
+                (bob(), Tokens128::from(600)),
+                (john(), Tokens128::from(300)),
+                (bob(), Tokens128::from(100)),
+            ]),
+            ..test_metadata()
+        });
+
+        assert_eq!(canister.balanceOf(alice()), Tokens128::ZERO);
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(700));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(300));
+        assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(1000));
+        assert_eq!(canister.historySize(), 3);
+        assert_eq!(canister.getTransaction(1).operation, Operation::Mint);
+        assert_eq!(canister.getTransaction(1).to, john());
+    }
+
+    #[test]
+    #[should_panic]
+    fn genesis_allocation_mismatch() {
+        MockContext::new().with_caller(alice()).inject();
+        let canister = TokenCanisterMock::init_instance();
+        canister.init(Metadata {
+            genesisAllocation: Some(vec![(bob(), Tokens128::from(600))]),
+            ..test_metadata()
+        });
     }
 
     #[test]
@@ -855,6 +1911,50 @@ mod tests {
         }
         assert_eq!(canister.getUserTransactionCount(alice()), COUNT);
     }
+
+    #[test]
+    fn metadata_entries() {
+        let canister = test_canister();
+        canister
+            .setMetadataEntry(
+                "website".to_string(),
+                Value::Text("https://example.com".to_string()),
+            )
+            .unwrap();
+
+        let entry = (
+            "website".to_string(),
+            Value::Text("https://example.com".to_string()),
+        );
+        assert!(canister.icrc1_metadata().contains(&entry));
+        assert_eq!(canister.getMetadata().customMetadata, Some(vec![entry]));
+
+        assert_eq!(
+            canister.setMetadataEntry("icrc1:name".to_string(), Value::Text("".to_string())),
+            Err(TxError::InvalidMetadataKey)
+        );
+
+        canister.removeMetadataEntry("website".to_string()).unwrap();
+        assert_eq!(canister.getMetadata().customMetadata, Some(vec![]));
+        assert_eq!(
+            canister.removeMetadataEntry("website".to_string()),
+            Err(TxError::AlreadyActioned)
+        );
+    }
+
+    #[test]
+    fn health_info() {
+        let canister = test_canister();
+        canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+
+        let health = canister.getHealth();
+        assert_eq!(health.ledger_len, 2);
+        assert_eq!(health.pending_notifications, 0);
+        assert_eq!(
+            health.last_auction,
+            canister.state().borrow().bidding_state.last_auction
+        );
+    }
 }
 
 #[cfg(test)]
@@ -1011,6 +2111,13 @@ mod proptests {
                 fee,
                 feeTo: fee_to,
                 isTestToken: None,
+                maxSupply: None,
+                auctionDisabled: None,
+                auctionPeriod: None,
+                minCycles: None,
+                genesisAllocation: None,
+                customMetadata: None,
+                registry: None,
             };
             let canister = TokenCanisterMock::init_instance();
             canister.init(meta);
@@ -1069,7 +2176,7 @@ mod proptests {
                         MockContext::new().with_caller(caller).inject();
                         let from_balance = canister.balanceOf(from);
                         let to_balance = canister.balanceOf(to);
-                        let (fee , _) = canister.state().borrow().stats.fee_info();
+                        let (fee , _) = canister.state().borrow().stats.fee_info(amount);
                         let amount_with_fee = (fee + amount).unwrap();
                         let res = canister.transferFrom(from, to, amount);
                         let _ = canister.approve(from, amount);
@@ -1099,7 +2206,7 @@ mod proptests {
                         MockContext::new().with_caller(from).inject();
                         let from_balance = canister.balanceOf(from);
                         let to_balance = canister.balanceOf(to);
-                        let (fee , fee_to) = canister.state().borrow().stats.fee_info();
+                        let (fee , fee_to) = canister.state().borrow().stats.fee_info(amount);
                         let amount_with_fee = (amount + fee).unwrap();
                         let res = canister.transfer(to, amount, fee_limit);
 
@@ -1141,7 +2248,7 @@ mod proptests {
                         MockContext::new().with_caller(from).inject();
                         let from_balance = canister.balanceOf(from);
                         let to_balance = canister.balanceOf(to);
-                        let (fee , fee_to) = canister.state().borrow().stats.fee_info();
+                        let (fee , fee_to) = canister.state().borrow().stats.fee_info(amount);
                         let res = canister.transferIncludeFee(to, amount);
 
                         if to == from {
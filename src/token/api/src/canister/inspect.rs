@@ -1,5 +1,5 @@
 use crate::state::CanisterState;
-use crate::types::TxId;
+use crate::types::{MethodPolicy, TxId};
 use candid::{Nat, Principal};
 use ic_helpers::tokens::Tokens128;
 use ic_storage::IcStorage;
@@ -8,44 +8,137 @@ static PUBLIC_METHODS: &[&str] = &[
     "allowance",
     "auctionInfo",
     "balanceOf",
+    "balanceOfAt",
     "biddingInfo",
+    "circulatingSupply",
     "decimals",
+    "decodeAccount",
+    "encodeAccount",
+    "formatAmount",
+    "getAccountData",
+    "getAccountTransactions",
+    "getAdminProposals",
+    "getAdmins",
     "getAllowanceSize",
+    "getApprovalsForSpender",
+    "getAuctionStats",
+    "getAuctionHistory",
+    "getAuctionsByTime",
+    "getCyclesMonitor",
+    "getDistribution",
+    "getEscrows",
+    "getEvents",
+    "getFailedNotifications",
+    "getFaucetLimit",
+    "getFeeBounds",
+    "getFeeBurnRatio",
+    "getFeeDistribution",
+    "getFeeFor",
+    "getFeeMode",
+    "getFeeRatioDetails",
+    "getHealth",
+    "getHolderStats",
     "getHolders",
+    "getLocks",
     "getMetadata",
+    "getMethodPolicies",
+    "getMetrics",
+    "getMinters",
+    "getMultisig",
+    "getNotificationConsumers",
+    "getMintingAccount",
+    "getPendingOwnerChange",
+    "getPausers",
+    "getPendingUpdates",
+    "getReservedAccounts",
+    "getScheduledTransfers",
+    "getStreams",
+    "getSubscription",
+    "getTimelockDelay",
     "getTokenInfo",
     "getTransaction",
     "getTransactions",
+    "getTransactionsByIds",
     "getUserApprovals",
     "getUserTransactionAmount",
     "getUserTransactions",
+    "getVesting",
+    "isAirdropClaimed",
     "historySize",
+    "http_request",
+    "isAccountFrozen",
+    "icrc1_metadata",
+    "icrc1_supported_standards",
     "logo",
     "name",
     "owner",
+    "parseAmount",
     "symbol",
     "totalSupply",
     "isTestToken",
 ];
 
 static OWNER_METHODS: &[&str] = &[
-    "mint",
+    "addAdmin",
+    "addMinter",
+    "addNotificationConsumer",
+    "addReservedAccount",
+    "cancelOwnerChange",
+    "cancelUpdate",
+    "createVesting",
+    "distribute",
+    "setAirdrop",
+    "enableMultisig",
+    "importAllowances",
+    "queueUpdate",
+    "removeAdmin",
+    "removeMetadataEntry",
+    "removeMinter",
+    "removeNotificationConsumer",
+    "removeReservedAccount",
+    "setAuctionDisabled",
     "setAuctionPeriod",
+    "setAuctionRewardAsset",
+    "setBidExtensionWindow",
+    "setBidToken",
+    "setBidWithdrawalPenalty",
+    "setCustomMetadata",
+    "setCyclesMonitor",
+    "setFaucetLimit",
     "setFee",
+    "setFeeBounds",
+    "setFeeBurnRatio",
+    "setFeeDistribution",
+    "setFeeMode",
     "setFeeTo",
+    "setGuardian",
     "setLogo",
+    "setMetadataEntry",
+    "setMintingAccount",
+    "setMethodPolicy",
     "setMinCycles",
+    "setNotifyBidders",
     "setName",
     "setOwner",
+    "setTimelockDelay",
     "toggleTest",
 ];
 
 static TRANSACTION_METHODS: &[&str] = &[
     "approve",
+    "approveAndCall",
     "approveAndNotify",
+    "batchTransfer",
     "burn",
+    "createEscrow",
+    "icrc1_transfer",
+    "lock",
+    "openStream",
+    "safeTransfer",
     "transfer",
+    "transferAndCall",
     "transferIncludeFee",
+    "transferWithHook",
 ];
 
 /// Reason why the method may be accepted.
@@ -57,6 +150,33 @@ pub enum AcceptReason {
     NotIS20Method,
 }
 
+/// Returns `true` if the query `method` is exported by the token canister.
+pub(crate) fn is_query_method(method: &str) -> bool {
+    PUBLIC_METHODS.contains(&method)
+}
+
+/// Checks the call of the `method` against the policy set by the owner with `setMethodPolicy`.
+/// Returns `Ok(true)` if the policy is `Open`, so the default checks of `inspect_message` can be
+/// skipped. The policies don't apply to `setMethodPolicy`, so the owner cannot lock themselves out.
+pub(crate) fn check_method_policy(
+    state: &CanisterState,
+    method: &str,
+    caller: Principal,
+) -> Result<bool, &'static str> {
+    if method == "setMethodPolicy" {
+        return Ok(false);
+    }
+
+    match state.method_policies.get(method) {
+        Some(MethodPolicy::Open) => Ok(true),
+        Some(MethodPolicy::OwnerOnly) if caller != state.stats.owner => {
+            Err("Method is restricted to the owner. Rejecting.")
+        }
+        Some(MethodPolicy::Deny) => Err("Method is disabled by the owner. Rejecting."),
+        _ => Ok(false),
+    }
+}
+
 /// This function checks if the canister should accept ingress message or not. We allow query
 /// calls for anyone, but update calls have different checks to see, if it's reasonable to spend
 /// canister cycles on accepting this call. Check the comments in this method for details on
@@ -66,11 +186,37 @@ pub fn inspect_message(
     method: &str,
     caller: Principal,
 ) -> Result<AcceptReason, &'static str> {
+    // The policies set by the owner take precedence over the checks below.
+    if check_method_policy(state, method, caller)? {
+        return Ok(AcceptReason::Valid);
+    }
+
     match method {
         // These are query methods, so no checks are needed.
         #[cfg(feature = "mint_burn")]
         "mint" if state.stats.is_test_token => Ok(AcceptReason::Valid),
         m if PUBLIC_METHODS.contains(&m) => Ok(AcceptReason::Valid),
+        "mint" if caller == state.stats.owner || state.stats.minters.contains(&caller) => {
+            Ok(AcceptReason::Valid)
+        }
+        "mint" => Err("Mint method is called not by a minter. Rejecting."),
+        #[cfg(feature = "mint_burn")]
+        "faucet" if state.stats.is_test_token => Ok(AcceptReason::Valid),
+        "faucet" => Err("Faucet is only available for test tokens. Rejecting."),
+        "freezeAccount" | "unfreezeAccount"
+            if caller == state.stats.owner || state.stats.pausers.contains(&caller) =>
+        {
+            Ok(AcceptReason::Valid)
+        }
+        "freezeAccount" | "unfreezeAccount" => {
+            Err("Freeze method is called not by a pauser. Rejecting.")
+        }
+        "addPauser" | "removePauser"
+            if caller == state.stats.owner || state.stats.admins.contains(&caller) =>
+        {
+            Ok(AcceptReason::Valid)
+        }
+        "addPauser" | "removePauser" => Err("Admin method is called not by an admin. Rejecting."),
         // Owner
         m if OWNER_METHODS.contains(&m) && caller == state.stats.owner => Ok(AcceptReason::Valid),
         // Not owner
@@ -83,7 +229,9 @@ pub fn inspect_message(
             let state = CanisterState::get();
             let state = state.borrow();
             let balances = &state.balances;
-            if !balances.0.contains_key(&caller) {
+            // The minting account never has balance, as transfers from it are mints.
+            let is_minting_account = state.stats.minting_account == Some(caller);
+            if !balances.contains(&caller) && !is_minting_account {
                 return Err("Transaction method is not called by a stakeholder. Rejecting.");
             }
 
@@ -103,21 +251,15 @@ pub fn inspect_message(
         #[cfg(feature = "transfer")]
         "transferFrom" => {
             // Check if the caller has allowance for this transfer.
-            let allowances = &state.allowances;
             let (from, _, value) =
                 ic_cdk::api::call::arg_data::<(Principal, Principal, Tokens128)>();
-            if let Some(user_allowances) = allowances.get(&caller) {
-                if let Some(allowance) = user_allowances.get(&from) {
-                    if value <= *allowance {
-                        Ok(AcceptReason::Valid)
-                    } else {
-                        Err("Allowance amount is less then the requested transfer amount. Rejecting.")
-                    }
-                } else {
-                    Err("Caller is not allowed to transfer tokens for the requested principal. Rejecting.")
-                }
-            } else {
+            let allowance = state.allowance(from, caller);
+            if allowance.is_zero() {
                 Err("Caller is not allowed to transfer tokens for the requested principal. Rejecting.")
+            } else if value <= allowance {
+                Ok(AcceptReason::Valid)
+            } else {
+                Err("Allowance amount is less then the requested transfer amount. Rejecting.")
             }
         }
         "notify" => {
@@ -126,24 +268,47 @@ pub fn inspect_message(
             let notifications = &state.ledger.notifications;
             let (tx_id,) = ic_cdk::api::call::arg_data::<(TxId,)>();
 
-            if notifications.contains_key(&tx_id) {
+            if notifications.contains_key(tx_id) {
                 Ok(AcceptReason::Valid)
             } else {
                 Err("No pending notification with the given id. Rejecting.")
             }
         }
+        "retryNotification" => {
+            // Only the failed notifications can be retried.
+            let (tx_id,) = ic_cdk::api::call::arg_data::<(TxId,)>();
+            if state.ledger.failed_notifications.contains_key(&tx_id) {
+                Ok(AcceptReason::Valid)
+            } else {
+                Err("No failed notification with the given id. Rejecting.")
+            }
+        }
+        "notifyBatch" => {
+            // Same as `notify`, all the notification ids must be pending.
+            let notifications = &state.ledger.notifications;
+            let (batch,) = ic_cdk::api::call::arg_data::<(Vec<(TxId, Principal)>,)>();
+
+            if batch
+                .iter()
+                .all(|(tx_id, _)| notifications.contains_key(*tx_id))
+            {
+                Ok(AcceptReason::Valid)
+            } else {
+                Err("No pending notification with one of the given ids. Rejecting.")
+            }
+        }
         "ConsumeNotification" => {
             // This method can only be called if the notification id is in the pending notifications
             // list and the caller is notified canister.
             let notifications = &state.ledger.notifications;
             let (tx_id,) = ic_cdk::api::call::arg_data::<(TxId,)>();
 
-            match notifications.get(&tx_id) {
-                Some(Some(x)) if *x != ic_canister::ic_kit::ic::caller() => {
+            match notifications.get(tx_id) {
+                Some(Some(x)) if x != ic_canister::ic_kit::ic::caller() => {
                     return Err("Unauthorized")
                 }
                 Some(_) => {
-                    if !state.ledger.notifications.contains_key(&tx_id) {
+                    if !state.ledger.notifications.contains_key(tx_id) {
                         return Err("Already removed");
                     }
                 }
@@ -152,6 +317,40 @@ pub fn inspect_message(
 
             Ok(AcceptReason::Valid)
         }
+        "setAccountData" => {
+            // Only the token holders can attach data to their accounts, but anyone can remove it.
+            let (_, value) = ic_cdk::api::call::arg_data::<(String, Vec<u8>)>();
+            if !value.is_empty() && state.balances.balance_of(&caller).is_zero() {
+                Err("Account data is set not by a stakeholder. Rejecting.")
+            } else {
+                Ok(AcceptReason::Valid)
+            }
+        }
+        "proposeAdminOp" | "approveAdminOp" if state.multisig.is_signer(&caller) => {
+            Ok(AcceptReason::Valid)
+        }
+        "proposeAdminOp" | "approveAdminOp" => {
+            Err("Admin operation is proposed or approved not by a multisig signer. Rejecting.")
+        }
+        "executeUpdate" if state.multisig.is_enabled() && state.multisig.is_signer(&caller) => {
+            Ok(AcceptReason::Valid)
+        }
+        "executeUpdate" if !state.multisig.is_enabled() && caller == state.stats.owner => {
+            Ok(AcceptReason::Valid)
+        }
+        "executeUpdate" => Err("Update is executed not by the owner or a signer. Rejecting."),
+        "applyOwnerChange" => {
+            // The change can be applied by the current owner or by the new owner.
+            match &state.owner_rotation.pending {
+                Some(change) if caller == change.new_owner || caller == state.stats.owner => {
+                    Ok(AcceptReason::Valid)
+                }
+                Some(_) => {
+                    Err("Owner change is applied not by the owner or the new owner. Rejecting.")
+                }
+                None => Err("No pending owner change. Rejecting."),
+            }
+        }
         "runAuction" => {
             // We allow running auction only to the owner or any of the cycle bidders.
             let state = CanisterState::get();
@@ -165,6 +364,62 @@ pub fn inspect_message(
                 Err("Auction is not due yet or auction run method is called not by owner or bidder. Rejecting.")
             }
         }
+        "disburseRewards" if state.bidding_state.disbursement.is_some() => Ok(AcceptReason::Valid),
+        "disburseRewards" => Err("No pending auction rewards. Rejecting."),
+        "processDistribution" if state.distribution.is_some() => Ok(AcceptReason::Valid),
+        "processDistribution" => Err("No pending distribution. Rejecting."),
+        "sendRewardNotifications" if !state.bidding_state.pending_notifications.is_empty() => {
+            Ok(AcceptReason::Valid)
+        }
+        "sendRewardNotifications" => Err("No pending reward notifications. Rejecting."),
+        "withdrawBid" if state.bidding_state.bids.contains_key(&caller) => Ok(AcceptReason::Valid),
+        "withdrawBid" => Err("Bid is withdrawn not by a bidder. Rejecting."),
+        "checkCycles" if state.cycles_monitor.provider.is_some() => Ok(AcceptReason::Valid),
+        "checkCycles" => Err("No top-up provider is set. Rejecting."),
+        "scheduleTransfer" if !state.balances.balance_of(&caller).is_zero() => {
+            Ok(AcceptReason::Valid)
+        }
+        "scheduleTransfer" => Err("Transfer is scheduled not by a stakeholder. Rejecting."),
+        "releaseEscrow" | "refundEscrow" => {
+            // Only the escrow parties can settle it, the exact rules are checked by the methods.
+            let is_party = state.escrows.entries.values().any(|escrow| {
+                caller == escrow.from || caller == escrow.to || caller == escrow.arbiter
+            });
+            if is_party {
+                Ok(AcceptReason::Valid)
+            } else {
+                Err("Escrow is settled not by its party. Rejecting.")
+            }
+        }
+        "withdrawFromStream" | "closeStream" => {
+            let is_party = state
+                .streams
+                .entries
+                .values()
+                .any(|stream| caller == stream.from || caller == stream.to);
+            if is_party {
+                Ok(AcceptReason::Valid)
+            } else {
+                Err("Stream is settled not by its party. Rejecting.")
+            }
+        }
+        "claimAirdrop" if state.airdrop.is_some() => Ok(AcceptReason::Valid),
+        "claimAirdrop" => Err("No airdrop is set. Rejecting."),
+        "claimVested" => {
+            let now = ic_canister::ic_kit::ic::time();
+            let has_claimable = state
+                .vesting
+                .entries
+                .values()
+                .any(|entry| entry.beneficiary == caller && !entry.claimable(now).is_zero());
+            if has_claimable {
+                Ok(AcceptReason::Valid)
+            } else {
+                Err("No vested tokens to claim. Rejecting.")
+            }
+        }
+        "bidTokens" if state.bidding_state.bid_token.is_some() => Ok(AcceptReason::Valid),
+        "bidTokens" => Err("Bids cannot be paid in tokens. Rejecting."),
         "bidCycles" => {
             // We reject this message, because a call with cycles cannot be made through ingress,
             // only from the wallet canister.
@@ -173,3 +428,92 @@ pub fn inspect_message(
         _ => Ok(AcceptReason::NotIS20Method),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob};
+
+    use crate::canister::TokenCanisterAPI;
+    use crate::mock::*;
+    use crate::types::TxError;
+
+    use super::*;
+
+    fn canister_caller() -> Principal {
+        Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1])
+    }
+
+    #[test]
+    fn method_policies() {
+        let (context, canister) = test_context();
+        canister
+            .setMethodPolicy("approveAndNotify".to_string(), Some(MethodPolicy::Deny))
+            .unwrap();
+        canister
+            .setMethodPolicy("transfer".to_string(), Some(MethodPolicy::OwnerOnly))
+            .unwrap();
+        canister
+            .setMethodPolicy("setMethodPolicy".to_string(), Some(MethodPolicy::Deny))
+            .unwrap();
+        assert_eq!(canister.getMethodPolicies().len(), 3);
+        assert_eq!(
+            canister.setMethodPolicy("getHolders".to_string(), Some(MethodPolicy::OwnerOnly)),
+            Err(TxError::InvalidMethodPolicy)
+        );
+
+        let state = canister.state();
+        let state = state.borrow();
+        assert!(inspect_message(&state, "approveAndNotify", alice()).is_err());
+        assert!(inspect_message(&state, "transfer", alice()).is_ok());
+        assert!(inspect_message(&state, "transfer", bob()).is_err());
+        assert!(inspect_message(&state, "setMethodPolicy", alice()).is_ok());
+        drop(state);
+
+        canister
+            .setMethodPolicy("transfer".to_string(), None)
+            .unwrap();
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        context.update_caller(bob());
+        assert!(inspect_message(&canister.state().borrow(), "transfer", bob()).is_ok());
+        assert!(canister
+            .transfer(alice(), Tokens128::from(10), None)
+            .is_ok());
+
+        assert_eq!(
+            canister.setMethodPolicy("transfer".to_string(), Some(MethodPolicy::Deny)),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Method is disabled by the owner")]
+    fn denied_method_called_by_canister() {
+        let (context, canister) = test_context();
+        canister
+            .transfer(canister_caller(), Tokens128::from(100), None)
+            .unwrap();
+        canister
+            .setMethodPolicy("transfer".to_string(), Some(MethodPolicy::Deny))
+            .unwrap();
+
+        context.update_caller(canister_caller());
+        let _ = canister.transfer(bob(), Tokens128::from(10), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Method is restricted to the owner")]
+    fn owner_only_method_called_by_canister() {
+        let (context, canister) = test_context();
+        canister
+            .transfer(canister_caller(), Tokens128::from(100), None)
+            .unwrap();
+        canister
+            .setMethodPolicy("transfer".to_string(), Some(MethodPolicy::OwnerOnly))
+            .unwrap();
+
+        context.update_caller(canister_caller());
+        let _ = canister.transfer(bob(), Tokens128::from(10), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Small key/value store attached to the token accounts, used by wallets and dapps to keep user
+//! preferences next to the balances.
+
+use candid::Principal;
+
+use crate::types::TxError;
+
+use super::TokenCanisterAPI;
+
+pub(crate) const MAX_ACCOUNT_DATA_KEY_LEN: usize = 64;
+/// Maximum total size of the keys and the values stored for one account.
+pub(crate) const MAX_ACCOUNT_DATA_SIZE: usize = 4096;
+
+/// Sets the `value` for the `key` of the caller account. Empty value removes the entry.
+///
+/// Only the token holders can store data, so that the storage cannot be filled by the principals
+/// created for that purpose.
+pub(crate) fn set_account_data(
+    canister: &impl TokenCanisterAPI,
+    caller: Principal,
+    key: String,
+    value: Vec<u8>,
+) -> Result<(), TxError> {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    if key.len() > MAX_ACCOUNT_DATA_KEY_LEN {
+        return Err(TxError::AccountDataTooLarge {
+            max_size: MAX_ACCOUNT_DATA_KEY_LEN as u64,
+        });
+    }
+
+    if value.is_empty() {
+        state.account_data.remove(caller, &key);
+        return Ok(());
+    }
+
+    if state.balances.balance_of(&caller).is_zero() {
+        return Err(TxError::InsufficientBalance);
+    }
+
+    let size = state.account_data.size_without(caller, &key) + key.len() + value.len();
+    if size > MAX_ACCOUNT_DATA_SIZE {
+        return Err(TxError::AccountDataTooLarge {
+            max_size: MAX_ACCOUNT_DATA_SIZE as u64,
+        });
+    }
+
+    state.account_data.insert(caller, key, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    #[test]
+    fn set_and_get() {
+        let (_, canister) = test_context();
+        canister
+            .setAccountData("label".to_string(), b"savings".to_vec())
+            .unwrap();
+
+        assert_eq!(
+            canister.getAccountData(alice(), "label".to_string()),
+            Some(b"savings".to_vec())
+        );
+        assert_eq!(canister.getAccountData(bob(), "label".to_string()), None);
+
+        canister
+            .setAccountData("label".to_string(), Vec::new())
+            .unwrap();
+        assert_eq!(canister.getAccountData(alice(), "label".to_string()), None);
+    }
+
+    #[test]
+    fn size_limit() {
+        let (_, canister) = test_context();
+        canister
+            .setAccountData("a".to_string(), vec![1; MAX_ACCOUNT_DATA_SIZE - 1])
+            .unwrap();
+        assert_eq!(
+            canister.setAccountData("b".to_string(), vec![1]),
+            Err(TxError::AccountDataTooLarge {
+                max_size: MAX_ACCOUNT_DATA_SIZE as u64
+            })
+        );
+
+        // Replacing an entry doesn't count its old value.
+        canister
+            .setAccountData("a".to_string(), vec![2; MAX_ACCOUNT_DATA_SIZE - 1])
+            .unwrap();
+    }
+
+    #[test]
+    fn not_holder() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.setAccountData("label".to_string(), b"savings".to_vec()),
+            Err(TxError::InsufficientBalance)
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Airdrop claimed with merkle proofs. The owner commits the merkle root of the `(index, account,
+//! amount)` allocations, and every account claims its allocation once, instead of the owner
+//! minting or transferring the tokens to every account.
+//!
+//! The tree is built of SHA-256 hashes:
+//! * a leaf is `sha256(0x00 || index as 8 bytes BE || principal length as 1 byte || principal ||
+//!   amount as 16 bytes BE)`;
+//! * a node is `sha256(0x01 || min(left, right) || max(left, right))`, so the proof doesn't need
+//!   to specify the side of the sibling hashes.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+use sha2::{Digest, Sha256};
+
+use crate::canister::erc20_transactions::{mint, transfer_balance};
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::Airdrop;
+use crate::types::{TxError, TxReceipt};
+
+use super::TokenCanisterAPI;
+
+pub type Hash = [u8; 32];
+
+pub fn leaf_hash(index: u64, account: Principal, amount: Tokens128) -> Hash {
+    let account = account.as_slice();
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(index.to_be_bytes());
+    hasher.update([account.len() as u8]);
+    hasher.update(account);
+    hasher.update(amount.amount.to_be_bytes());
+    hasher.finalize().into()
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let (first, second) = if left <= right {
+        (left, right)
+    } else {
+        (right, left)
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(first);
+    hasher.update(second);
+    hasher.finalize().into()
+}
+
+/// Commits the merkle `root` of a new airdrop, replacing the previous one. The allocations are
+/// minted if `mint` is set, or transferred from the owner balance otherwise.
+pub(crate) fn set_airdrop(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    root: Vec<u8>,
+    mint: bool,
+) -> Result<(), TxError> {
+    if root.len() != 32 {
+        return Err(TxError::InvalidProof);
+    }
+
+    canister.state().borrow_mut().airdrop = Some(Airdrop {
+        root,
+        mint,
+        claimed: vec![],
+    });
+    Ok(())
+}
+
+/// Mints or transfers the allocation `(index, caller, amount)` to the caller, if the `proof`
+/// leads to the airdrop root.
+pub(crate) fn claim_airdrop(
+    canister: &impl TokenCanisterAPI,
+    index: u64,
+    amount: Tokens128,
+    proof: Vec<Vec<u8>>,
+) -> TxReceipt {
+    let caller = ic::caller();
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    state.check_not_frozen(&[caller])?;
+
+    let airdrop = state.airdrop.as_ref().ok_or(TxError::NoAirdrop)?;
+    if airdrop.is_claimed(index) {
+        return Err(TxError::AlreadyActioned);
+    }
+
+    let mut hash = leaf_hash(index, caller, amount);
+    for sibling in proof {
+        let sibling: Hash = sibling.try_into().map_err(|_| TxError::InvalidProof)?;
+        hash = node_hash(&hash, &sibling);
+    }
+    if hash.as_slice() != airdrop.root {
+        return Err(TxError::InvalidProof);
+    }
+
+    let owner = state.stats.owner;
+    let id = if airdrop.mint {
+        mint(&mut state, owner, caller, amount)?
+    } else {
+        // The allocation is paid from the owner balance, which cannot be moved while frozen.
+        state.check_not_frozen(&[owner])?;
+        transfer_balance(&mut state.balances, owner, caller, amount)?;
+        state
+            .ledger
+            .transfer(owner, caller, amount, Tokens128::ZERO)
+    };
+
+    state
+        .airdrop
+        .as_mut()
+        .expect("airdrop existence checked above")
+        .set_claimed(index);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john, xtc};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    /// Tree of three allocations: `((bob, john), xtc)`.
+    fn tree() -> (Hash, [Hash; 3]) {
+        let leaves = [
+            leaf_hash(0, bob(), Tokens128::from(100)),
+            leaf_hash(1, john(), Tokens128::from(200)),
+            leaf_hash(2, xtc(), Tokens128::from(300)),
+        ];
+        let left = node_hash(&leaves[0], &leaves[1]);
+        (node_hash(&left, &leaves[2]), leaves)
+    }
+
+    #[test]
+    fn claim_once() {
+        let (context, canister) = test_context();
+        let (root, leaves) = tree();
+        canister.setAirdrop(root.to_vec(), true).unwrap();
+
+        context.update_caller(bob());
+        let proof = vec![
+            leaves[1].to_vec(),
+            node_hash(&leaves[0], &leaves[1]).to_vec(),
+        ];
+        assert_eq!(
+            canister.claimAirdrop(0, Tokens128::from(100), proof),
+            Err(TxError::InvalidProof)
+        );
+
+        let proof = vec![leaves[1].to_vec(), leaves[2].to_vec()];
+        assert_eq!(
+            canister.claimAirdrop(0, Tokens128::from(1000), proof.clone()),
+            Err(TxError::InvalidProof)
+        );
+        canister
+            .claimAirdrop(0, Tokens128::from(100), proof.clone())
+            .unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(1100));
+        assert!(canister.isAirdropClaimed(0));
+        assert!(!canister.isAirdropClaimed(1));
+        assert_eq!(
+            canister.claimAirdrop(0, Tokens128::from(100), proof),
+            Err(TxError::AlreadyActioned)
+        );
+
+        context.update_caller(xtc());
+        let left = node_hash(&leaves[0], &leaves[1]);
+        canister
+            .claimAirdrop(2, Tokens128::from(300), vec![left.to_vec()])
+            .unwrap();
+        assert_eq!(canister.balanceOf(xtc()), Tokens128::from(300));
+    }
+
+    #[test]
+    fn claim_from_owner_balance() {
+        let (context, canister) = test_context();
+        let (root, leaves) = tree();
+        canister.setAirdrop(root.to_vec(), false).unwrap();
+
+        context.update_caller(john());
+        canister
+            .claimAirdrop(
+                1,
+                Tokens128::from(200),
+                vec![leaves[0].to_vec(), leaves[2].to_vec()],
+            )
+            .unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(200));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(800));
+        assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(1000));
+    }
+
+    #[test]
+    fn claim_from_frozen_owner() {
+        let (context, canister) = test_context();
+        let (root, leaves) = tree();
+        canister.setAirdrop(root.to_vec(), false).unwrap();
+        canister
+            .state()
+            .borrow_mut()
+            .frozen_accounts
+            .insert(alice());
+
+        context.update_caller(john());
+        let proof = vec![leaves[0].to_vec(), leaves[2].to_vec()];
+        assert_eq!(
+            canister.claimAirdrop(1, Tokens128::from(200), proof),
+            Err(TxError::AccountFrozen)
+        );
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(1000));
+        assert!(!canister.isAirdropClaimed(1));
+    }
+
+    #[test]
+    fn no_airdrop() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.claimAirdrop(0, Tokens128::from(100), vec![]),
+            Err(TxError::NoAirdrop)
+        );
+        assert_eq!(
+            canister.setAirdrop(vec![0; 32], true),
+            Err(TxError::Unauthorized)
+        );
+    }
+}
@@ -2,21 +2,44 @@
 
 use std::collections::HashMap;
 
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_canister::ic_kit::ic;
+use ic_canister::{virtual_canister_call, virtual_canister_notify};
 use ic_helpers::tokens::Tokens128;
 
+use crate::amount_math::{saturating_sub, split_proportionally};
 use crate::canister::erc20_transactions::transfer_balance;
 use crate::ledger::Ledger;
-use crate::state::{AuctionHistory, Balances, BiddingState, CanisterState};
-use crate::types::{AuctionInfo, Cycles, StatsData, Timestamp};
+use crate::state::{
+    AuctionHistory, Balances, BiddingState, CanisterState, Disbursement, PendingRewardNotification,
+};
+use crate::types::{
+    Account, AuctionInfo, AuctionRewardAsset, BidToken, Cycles, PaginatedAuctions,
+    RewardNotification, StatsData, Timestamp, TransferFromArgs, TransferFromError, TxId,
+};
 
 use super::TokenCanisterAPI;
 
+/// Max number of auctions returned by the auction history queries.
+pub(crate) const MAX_AUCTION_QUERY_LEN: usize = 100;
+
+/// Max number of the rewards paid in a single execution. The rest of the rewards are paid in the
+/// following update calls, so the auctions with many bidders don't hit the instruction limit.
+pub(crate) const DISBURSEMENT_CHUNK_LEN: usize = 500;
+
+/// Max number of the reward notifications sent in a single call.
+const NOTIFICATION_BATCH_LEN: usize = 100;
+
+/// Reward notifications that failed this number of times are dropped.
+const MAX_NOTIFICATION_ATTEMPTS: u32 = 5;
+
 // Minimum bidding amount is required, for every update call costs cycles, and we want bidding
 // to add cycles rather then to decrease them. 1M is chosen as one ingress call costs 590K cycles.
 const MIN_BIDDING_AMOUNT: Cycles = 1_000_000;
 
+// The bid withdrawal penalty is set in basis points too.
+const MAX_PENALTY_BPS: u16 = 10_000;
+
 /// Current information about upcoming auction and current cycle bids.
 #[derive(CandidType, Debug, Clone, Deserialize)]
 pub struct BiddingInfo {
@@ -29,19 +52,75 @@ pub struct BiddingInfo {
     /// Timestamp of the last auction.
     last_auction: Timestamp,
 
-    /// Period of performing auctions. Auction cannot be started before `last_auction + auction_period`
-    /// IC time.
+    /// Period of performing auctions. Auction cannot be started before `next_auction` IC time.
     auction_period: Timestamp,
 
+    /// Time the upcoming auction is due: `last_auction + auction_period`, moved by the late bids.
+    next_auction: Timestamp,
+
+    /// Time before the auction is due within which a bid moves the auction, see
+    /// `setBidExtensionWindow`.
+    extension_window: Timestamp,
+
     /// Total cycles accumulated since the last auction.
     total_cycles: Cycles,
 
+    /// Total worth of the bids paid in tokens since the last auction, in cycles. These bids share
+    /// the rewards with the cycle bids, but are not included in `total_cycles`.
+    token_bids: Cycles,
+
     /// The amount of cycles the caller bid for the upcoming auction.
     caller_cycles: Cycles,
 
     /// The amount of fees accumulated since the last auction and that will be distributed on the
     /// next auction.
     accumulated_fees: Tokens128,
+
+    /// The asset the rewards of the next auction will be paid in.
+    reward_asset: AuctionRewardAsset,
+
+    /// Share of the bid that is kept by the canister when the bid is withdrawn, in basis points.
+    withdrawal_penalty_bps: u16,
+
+    /// The token the bids can be paid in besides the cycles, if any.
+    bid_token: Option<BidToken>,
+}
+
+/// Inputs of the fee ratio formula, so that the holders can check how the fee ratio is computed.
+///
+/// The fee ratio is `2 ^ log10(min_cycles / cycles_balance)`, capped at 1.0. It is recomputed
+/// after each auction and stays the same until the next one.
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct FeeRatioDetails {
+    /// Current cycles balance of the canister.
+    cycles_balance: Cycles,
+
+    /// The amount of cycles the canister is expected to keep. If it's zero, all the fees go to the
+    /// fee receivers.
+    min_cycles: Cycles,
+
+    /// Fee ratio applied to the fees until the next auction.
+    fee_ratio: f64,
+
+    /// Fee ratio that would be set by an auction held now with the current cycles balance.
+    next_fee_ratio: f64,
+
+    /// If set, the auction is disabled and no fees go to the auction pot regardless of the ratio.
+    auction_disabled: bool,
+}
+
+/// Arguments of the `swap_to_icp` call to the exchange canister.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct SwapToIcpArgs {
+    /// Amount of tokens the exchange can take from the auction principal with `transferFrom`.
+    pub amount: Tokens128,
+
+    /// The swap must fail if the exchange cannot provide at least this amount of ICP e8s.
+    pub min_icp: u64,
+
+    /// Bidders and their shares of the `amount`. The received ICP must be split in the same
+    /// proportion.
+    pub payouts: Vec<(Principal, Tokens128)>,
 }
 
 #[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
@@ -57,6 +136,35 @@ pub enum AuctionError {
 
     /// The specified period between the auctions is not passed yet.
     TooEarlyToBeginAuction,
+
+    /// The call to the management canister for the auction random seed failed.
+    RandomnessUnavailable,
+
+    /// The caller has no bid for the upcoming auction.
+    NoBid,
+
+    /// The cycles of the withdrawn bid could not be deposited to the caller. The bid is kept.
+    WithdrawalFailed,
+
+    /// The rewards of the previous auction are still being paid.
+    DisbursementInProgress,
+
+    /// The auctions are disabled by the owner.
+    AuctionDisabled,
+
+    /// The bids cannot be paid in tokens, as no bid token is set by the owner.
+    TokenBidsDisabled,
+
+    /// The bid paid in tokens is worth more cycles than a bid can hold.
+    BiddingTooLarge,
+
+    /// The bid tokens could not be taken from the caller with `icrc2_transfer_from`.
+    TokenTransferFailed,
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize)]
+struct CanisterIdRecord {
+    canister_id: Principal,
 }
 
 pub(crate) fn bid_cycles(
@@ -69,15 +177,136 @@ pub(crate) fn bid_cycles(
     }
     let state = canister.state();
     let mut state = state.borrow_mut();
+    if state.stats.auction_disabled {
+        return Err(AuctionError::AuctionDisabled);
+    }
     let bidding_state = &mut state.bidding_state;
 
     let amount_accepted = ic::msg_cycles_accept(amount);
     bidding_state.cycles_since_auction += amount_accepted;
-    *bidding_state.bids.entry(bidder).or_insert(0) += amount_accepted;
+    bidding_state.bids.add(bidder, amount_accepted);
+    bidding_state.extend_deadline();
 
     Ok(amount_accepted)
 }
 
+/// Places a bid paid in the tokens of the ledger set with `setBidToken`. The `amount` is taken
+/// from the caller with an `icrc2_transfer_from` call, so it must be approved to the token canister
+/// first, and is sent to the treasury. The bid is credited with `amount * cycles_per_unit` cycles,
+/// which are not added to the cycles collected by the auction, as the canister doesn't receive
+/// them. Returns the amount of cycles credited.
+pub(crate) async fn bid_tokens(
+    canister: &impl TokenCanisterAPI,
+    amount: Tokens128,
+) -> Result<Cycles, AuctionError> {
+    let bidder = ic::caller();
+    let (bid_token, cycles) = {
+        let state = canister.state();
+        let state = state.borrow();
+        if state.stats.auction_disabled {
+            return Err(AuctionError::AuctionDisabled);
+        }
+        let bid_token = state
+            .bidding_state
+            .bid_token
+            .clone()
+            .ok_or(AuctionError::TokenBidsDisabled)?;
+
+        let cycles = amount
+            .amount
+            .checked_mul(bid_token.cycles_per_unit as u128)
+            .and_then(|cycles| Cycles::try_from(cycles).ok())
+            .ok_or(AuctionError::BiddingTooLarge)?;
+        if cycles < MIN_BIDDING_AMOUNT {
+            return Err(AuctionError::BiddingTooSmall);
+        }
+
+        (bid_token, cycles)
+    };
+
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account::new(bidder, None),
+        to: Account::new(bid_token.treasury, None),
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let result = virtual_canister_call!(
+        bid_token.ledger,
+        "icrc2_transfer_from",
+        (args,),
+        Result<Nat, TransferFromError>
+    )
+    .await;
+    if !matches!(result, Ok(Ok(_))) {
+        return Err(AuctionError::TokenTransferFailed);
+    }
+
+    // The bid is added after the call, so if an auction was held in the meantime, it goes to the
+    // next auction.
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let bidding_state = &mut state.bidding_state;
+    bidding_state.bids.add(bidder, cycles);
+    *bidding_state.token_bids.entry(bidder).or_default() += cycles;
+    bidding_state.extend_deadline();
+
+    Ok(cycles)
+}
+
+/// Withdraws the caller bid for the upcoming auction. The bid cycles minus the withdrawal penalty
+/// are deposited to the caller canister. The part of the bid paid in tokens cannot be withdrawn
+/// and stays in the bid. Returns the amount of cycles deposited.
+pub(crate) async fn withdraw_bid(canister: &impl TokenCanisterAPI) -> Result<Cycles, AuctionError> {
+    let bidder = ic::caller();
+    let (bid, refund) = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        let bidding_state = &mut state.bidding_state;
+
+        let token_part = bidding_state.token_bids.get(&bidder).copied().unwrap_or(0);
+        let bid = match bidding_state.bids.get(&bidder) {
+            Some(bid) if bid > token_part => bid - token_part,
+            _ => return Err(AuctionError::NoBid),
+        };
+        bidding_state.bids.remove(&bidder);
+        if token_part > 0 {
+            bidding_state.bids.add(bidder, token_part);
+        }
+        bidding_state.cycles_since_auction -= bid;
+
+        let penalty_bps = bidding_state.withdrawal_penalty_bps.min(MAX_PENALTY_BPS) as u128;
+        let penalty = (bid as u128 * penalty_bps / MAX_PENALTY_BPS as u128) as Cycles;
+        (bid, bid - penalty)
+    };
+
+    let args = CanisterIdRecord {
+        canister_id: bidder,
+    };
+    let result = virtual_canister_call!(
+        Principal::management_canister(),
+        "deposit_cycles",
+        (args,),
+        (),
+        refund
+    )
+    .await;
+
+    if result.is_err() {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        let bidding_state = &mut state.bidding_state;
+        bidding_state.cycles_since_auction += bid;
+        bidding_state.bids.add(bidder, bid);
+
+        return Err(AuctionError::WithdrawalFailed);
+    }
+
+    Ok(refund)
+}
+
 pub(crate) fn bidding_info(canister: &impl TokenCanisterAPI) -> BiddingInfo {
     let state = canister.state();
     let state = state.borrow();
@@ -85,33 +314,255 @@ pub(crate) fn bidding_info(canister: &impl TokenCanisterAPI) -> BiddingInfo {
     let balances = &state.balances;
 
     BiddingInfo {
-        fee_ratio: bidding_state.fee_ratio,
+        fee_ratio: if state.stats.auction_disabled {
+            0.0
+        } else {
+            bidding_state.fee_ratio
+        },
         last_auction: bidding_state.last_auction,
         auction_period: bidding_state.auction_period,
+        next_auction: bidding_state.next_auction(),
+        extension_window: bidding_state.extension_window,
         total_cycles: bidding_state.cycles_since_auction,
-        caller_cycles: bidding_state.bids.get(&ic::caller()).cloned().unwrap_or(0),
-        accumulated_fees: accumulated_fees(balances),
+        token_bids: bidding_state.token_bids.values().sum(),
+        caller_cycles: bidding_state.bids.get(&ic::caller()).unwrap_or(0),
+        accumulated_fees: accumulated_fees(balances, bidding_state),
+        reward_asset: bidding_state.reward_asset.clone(),
+        withdrawal_penalty_bps: bidding_state.withdrawal_penalty_bps,
+        bid_token: bidding_state.bid_token.clone(),
+    }
+}
+
+pub(crate) fn fee_ratio_details(canister: &impl TokenCanisterAPI) -> FeeRatioDetails {
+    let state = canister.state();
+    let state = state.borrow();
+    let cycles_balance = ic::balance();
+
+    FeeRatioDetails {
+        cycles_balance,
+        min_cycles: state.stats.min_cycles,
+        fee_ratio: state.bidding_state.fee_ratio,
+        next_fee_ratio: get_fee_ratio(state.stats.min_cycles, cycles_balance),
+        auction_disabled: state.stats.auction_disabled,
+    }
+}
+
+/// Requests a random seed from the management canister and runs the auction with it.
+///
+/// If there are no bids, the auction is run right away, as there is nothing to split.
+pub(crate) async fn run_auction_with_randomness(
+    canister: &impl TokenCanisterAPI,
+) -> Result<AuctionInfo, AuctionError> {
+    let has_bids = {
+        let state = canister.state();
+        let state = state.borrow();
+        check_auction_ready(&state)?;
+
+        !state.bidding_state.bids.is_empty()
+    };
+
+    if !has_bids {
+        return run_auction(canister, Vec::new());
+    }
+
+    let seed = virtual_canister_call!(Principal::management_canister(), "raw_rand", (), Vec<u8>)
+        .await
+        .map_err(|_| AuctionError::RandomnessUnavailable)?;
+
+    let reward_asset = canister.state().borrow().bidding_state.reward_asset.clone();
+    let result = match reward_asset {
+        AuctionRewardAsset::Token => run_auction(canister, seed),
+        AuctionRewardAsset::Icp {
+            exchange,
+            min_icp_per_token,
+        } => run_icp_auction(canister, seed, exchange, min_icp_per_token).await,
+    };
+
+    if result.is_ok() {
+        send_reward_notifications(canister).await;
     }
+
+    result
 }
 
-pub(crate) fn run_auction(canister: &impl TokenCanisterAPI) -> Result<AuctionInfo, AuctionError> {
+pub(crate) fn run_auction(
+    canister: &impl TokenCanisterAPI,
+    seed: Vec<u8>,
+) -> Result<AuctionInfo, AuctionError> {
     let state = canister.state();
     let mut state = state.borrow_mut();
 
-    if !state.bidding_state.is_auction_due() {
+    check_auction_ready(&state)?;
+
+    hold_auction(&mut state, seed)
+}
+
+/// Runs the auction paying the rewards in ICP: the exchange canister is allowed to take the
+/// accumulated fees from the auction principal and is asked to send the ICP received for them to
+/// the bidders. If the swap fails, the rewards are paid in the token as usual.
+///
+/// The exchange is not trusted to report the amount it took, so the distributed tokens are the
+/// consumed part of the allowance, and the auction transactions are the ones of the auction
+/// principal recorded while waiting for the exchange.
+async fn run_icp_auction(
+    canister: &impl TokenCanisterAPI,
+    seed: Vec<u8>,
+    exchange: Principal,
+    min_icp_per_token: u64,
+) -> Result<AuctionInfo, AuctionError> {
+    let (args, pot, bids, token_bids, total_cycles, auction_position) = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        check_auction_ready(&state)?;
+
+        // The exchange pulls the tokens with `transferFrom`, which charges the fee from the pot.
+        // The fee never decreases with the amount, so the fee of the whole pot is enough.
+        let pot = accumulated_fees(&state.balances, &state.bidding_state);
+        let amount = match pot - state.stats.fee_for(pot) {
+            Some(amount) if !amount.is_zero() && !state.bidding_state.bids.is_empty() => amount,
+            _ => return hold_auction(&mut state, seed),
+        };
+
+        // The bids are taken out of the state, so the bids made while waiting for the exchange
+        // go to the next auction. Setting the auction time prevents other auctions from starting
+        // in the meantime.
+        let bidding_state = &mut state.bidding_state;
+        let total_bids = bidding_state.total_bids();
+        let bids = bidding_state.bids.take();
+        let token_bids = std::mem::take(&mut bidding_state.token_bids);
+        let total_cycles = std::mem::replace(&mut bidding_state.cycles_since_auction, 0);
+        bidding_state.start_bidding_period();
+
+        state.allowances.set(auction_principal(), exchange, pot);
+
+        let args = SwapToIcpArgs {
+            amount,
+            min_icp: min_icp_amount(amount, min_icp_per_token, state.stats.decimals),
+            payouts: split_rewards(amount, total_bids, &bids, &seed),
+        };
+        let auction_position = state.ledger.get_len_user_history(auction_principal()) as u64;
+
+        (args, pot, bids, token_bids, total_cycles, auction_position)
+    };
+
+    let icp_distributed = swap_to_icp(exchange, args).await;
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let tokens_consumed = saturating_sub(pot, state.allowance(auction_principal(), exchange));
+    state
+        .allowances
+        .set(auction_principal(), exchange, Tokens128::ZERO);
+
+    match icp_distributed {
+        Some(icp_distributed) => {
+            let CanisterState {
+                ref mut bidding_state,
+                ref mut auction_history,
+                ref mut auction_stats,
+                ref ledger,
+                ref stats,
+                ..
+            } = &mut *state;
+
+            // The transactions the exchange made while swapping, or an empty range at the end of
+            // the history if it took nothing.
+            let swap_len = ledger.get_len_user_history(auction_principal()) as u64;
+            let first_transaction_id = ledger
+                .get_user_transaction_id(auction_principal(), auction_position)
+                .unwrap_or_else(|| ledger.len());
+            let last_transaction_id = match swap_len.checked_sub(1) {
+                Some(last) if last >= auction_position => ledger
+                    .get_user_transaction_id(auction_principal(), last)
+                    .unwrap_or(first_transaction_id),
+                _ => first_transaction_id,
+            };
+
+            let result = AuctionInfo {
+                auction_id: auction_history.len(),
+                auction_time: ic::time(),
+                tokens_distributed: tokens_consumed,
+                cycles_collected: total_cycles,
+                fee_ratio: bidding_state.fee_ratio,
+                first_transaction_id,
+                last_transaction_id,
+                seed,
+                icp_distributed: Some(icp_distributed),
+            };
+            auction_history.insert(result.clone());
+            auction_stats.record(&result);
+
+            bidding_state.fee_ratio = get_fee_ratio(stats.min_cycles, ic::balance());
+            bidding_state.start_bidding_period();
+
+            Ok(result)
+        }
+        None => {
+            ic_cdk::println!("Swap of {pot} auction tokens to ICP failed, paying in tokens");
+
+            let bidding_state = &mut state.bidding_state;
+            for (bidder, cycles) in bids {
+                bidding_state.bids.add(bidder, cycles);
+            }
+            for (bidder, cycles) in token_bids {
+                *bidding_state.token_bids.entry(bidder).or_default() += cycles;
+            }
+            bidding_state.cycles_since_auction += total_cycles;
+
+            hold_auction(&mut state, seed)
+        }
+    }
+}
+
+/// Minimum amount of ICP e8s to receive for the `amount` of tokens, if at least
+/// `min_icp_per_token` e8s must be received for a whole token of `decimals` decimals.
+fn min_icp_amount(amount: Tokens128, min_icp_per_token: u64, decimals: u8) -> u64 {
+    let whole_token = 10u128.saturating_pow(decimals as u32);
+    let min_icp = amount.amount.saturating_mul(min_icp_per_token as u128) / whole_token;
+    min_icp.min(u64::MAX as u128) as u64
+}
+
+/// Performs the swap on the exchange. Returns the amount of ICP distributed, or `None` if the swap
+/// failed.
+async fn swap_to_icp(exchange: Principal, args: SwapToIcpArgs) -> Option<u64> {
+    match virtual_canister_call!(exchange, "swap_to_icp", (args,), Result<u64, String>).await {
+        Ok(Ok(icp_distributed)) => Some(icp_distributed),
+        _ => None,
+    }
+}
+
+fn check_auction_ready(state: &CanisterState) -> Result<(), AuctionError> {
+    let bidding_state = &state.bidding_state;
+    if state.stats.auction_disabled {
+        return Err(AuctionError::AuctionDisabled);
+    }
+
+    if !bidding_state.is_auction_due() {
         return Err(AuctionError::TooEarlyToBeginAuction);
     }
 
+    if bidding_state.disbursement.is_some() {
+        return Err(AuctionError::DisbursementInProgress);
+    }
+
+    Ok(())
+}
+
+fn hold_auction(state: &mut CanisterState, seed: Vec<u8>) -> Result<AuctionInfo, AuctionError> {
     let CanisterState {
         ref mut bidding_state,
         ref mut balances,
         ref mut auction_history,
+        ref mut auction_stats,
         ref mut ledger,
         ref stats,
         ..
-    } = &mut *state;
+    } = state;
 
-    let result = perform_auction(ledger, bidding_state, balances, auction_history);
+    let result = perform_auction(ledger, bidding_state, balances, auction_history, seed);
+    if let Ok(auction) = &result {
+        auction_stats.record(auction);
+    }
     reset_bidding_state(stats, bidding_state);
 
     result
@@ -125,61 +576,254 @@ pub(crate) fn auction_info(
         .state()
         .borrow()
         .auction_history
-        .0
         .get(id)
-        .cloned()
         .ok_or(AuctionError::AuctionNotFound)
 }
 
+pub(crate) fn auction_history(
+    canister: &impl TokenCanisterAPI,
+    offset: usize,
+    limit: usize,
+) -> PaginatedAuctions {
+    canister
+        .state()
+        .borrow()
+        .auction_history
+        .page(offset, limit.min(MAX_AUCTION_QUERY_LEN))
+}
+
+pub(crate) fn auctions_by_time(
+    canister: &impl TokenCanisterAPI,
+    from: Timestamp,
+    to: Timestamp,
+) -> PaginatedAuctions {
+    canister
+        .state()
+        .borrow()
+        .auction_history
+        .by_time(from, to, MAX_AUCTION_QUERY_LEN)
+}
+
 fn perform_auction(
     ledger: &mut Ledger,
     bidding_state: &mut BiddingState,
     balances: &mut Balances,
     auction_history: &mut AuctionHistory,
+    seed: Vec<u8>,
 ) -> Result<AuctionInfo, AuctionError> {
     if bidding_state.bids.is_empty() {
         return Err(AuctionError::NoBids);
     }
 
-    let total_amount = accumulated_fees(balances);
-    let mut transferred_amount = Tokens128::from(0u128);
+    let total_amount = accumulated_fees(balances, bidding_state);
     let total_cycles = bidding_state.cycles_since_auction;
-
     let first_id = ledger.len();
-
-    for (bidder, cycles) in &bidding_state.bids {
-        let amount = (total_amount * cycles / total_cycles)
-            .expect("total cycles is not 0 checked by bids existing")
-            .to_tokens128()
-            .expect("total cycles is smaller then single user bid cycles");
-        transfer_balance(balances, auction_principal(), *bidder, amount)
-            .expect("auction principal always have enough balance");
-        ledger.auction(*bidder, amount);
-        transferred_amount =
-            (transferred_amount + amount).expect("can never be larger than total_supply");
-    }
-
-    let last_id = ledger.len() - 1;
-    let result = AuctionInfo {
-        auction_id: auction_history.0.len(),
+    let auction_id = auction_history.len();
+
+    let payouts = split_rewards(
+        total_amount,
+        bidding_state.total_bids(),
+        &bidding_state.bids.to_map(),
+        &seed,
+    );
+    auction_history.insert(AuctionInfo {
+        auction_id,
         auction_time: ic::time(),
-        tokens_distributed: transferred_amount,
+        tokens_distributed: total_amount,
         cycles_collected: total_cycles,
         fee_ratio: bidding_state.fee_ratio,
         first_transaction_id: first_id,
-        last_transaction_id: last_id,
+        last_transaction_id: first_id + payouts.len() as TxId - 1,
+        seed,
+        icp_distributed: None,
+    });
+
+    bidding_state.disbursement = Some(Disbursement {
+        auction_id,
+        payouts,
+        next: 0,
+        remaining: total_amount,
+    });
+    disburse_chunk(ledger, bidding_state, balances, auction_history);
+
+    Ok(auction_history
+        .get(auction_id)
+        .expect("auction is added above"))
+}
+
+/// Pays the next chunk of the pending auction rewards. Returns `true` if there are no more rewards
+/// to pay.
+///
+/// If the rewards are paid in several chunks, other transactions may be recorded between them, so
+/// the `last_transaction_id` of the auction is updated when the last chunk is paid.
+fn disburse_chunk(
+    ledger: &mut Ledger,
+    bidding_state: &mut BiddingState,
+    balances: &mut Balances,
+    auction_history: &mut AuctionHistory,
+) -> bool {
+    let disbursement = match &mut bidding_state.disbursement {
+        Some(disbursement) => disbursement,
+        None => return true,
+    };
+
+    let end = (disbursement.next + DISBURSEMENT_CHUNK_LEN).min(disbursement.payouts.len());
+    for &(bidder, amount) in &disbursement.payouts[disbursement.next..end] {
+        // The payouts sum up to the auction balance, so this can only fail if the balance was
+        // changed by a bug. The unpaid reward then stays in the auction balance and goes to the
+        // next auction instead of blocking the rest of the payouts.
+        if let Err(err) = transfer_balance(balances, auction_principal(), bidder, amount) {
+            ic_cdk::println!("Failed to pay the auction reward of {amount:?} to {bidder}: {err}");
+            continue;
+        }
+        let transaction_id = ledger.auction(bidder, amount);
+        if bidding_state.notify_bidders {
+            bidding_state
+                .pending_notifications
+                .push_back(PendingRewardNotification {
+                    bidder,
+                    notification: RewardNotification {
+                        auction_id: disbursement.auction_id,
+                        transaction_id,
+                        amount,
+                    },
+                    attempts: 0,
+                });
+        }
+        disbursement.remaining = saturating_sub(disbursement.remaining, amount);
+    }
+    disbursement.next = end;
+
+    if end < disbursement.payouts.len() {
+        return false;
+    }
+
+    if let Some(mut auction) = auction_history.get(disbursement.auction_id) {
+        auction.last_transaction_id = ledger.len() - 1;
+        auction_history.insert(auction);
+    }
+    bidding_state.disbursement = None;
+
+    true
+}
+
+/// Pays the next chunk of the pending auction rewards. Returns `true` if there are no more rewards
+/// to pay.
+pub(crate) fn disburse_rewards(canister: &impl TokenCanisterAPI) -> bool {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let CanisterState {
+        ref mut bidding_state,
+        ref mut balances,
+        ref mut auction_history,
+        ref mut ledger,
+        ..
+    } = *state;
+
+    disburse_chunk(ledger, bidding_state, balances, auction_history)
+}
+
+/// Sends up to `NOTIFICATION_BATCH_LEN` pending reward notifications to the bidders with one-way
+/// `auction_reward_notification` calls. The failed notifications are put back to the queue to be
+/// retried later, unless they failed `MAX_NOTIFICATION_ATTEMPTS` times. Returns the number of the
+/// notifications sent.
+pub(crate) async fn send_reward_notifications(canister: &impl TokenCanisterAPI) -> usize {
+    let batch = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        let queue = &mut state.bidding_state.pending_notifications;
+        let len = queue.len().min(NOTIFICATION_BATCH_LEN);
+        queue.drain(..len).collect::<Vec<_>>()
     };
 
-    auction_history.0.push(result.clone());
+    let mut sent = 0;
+    for mut pending in batch {
+        let result = virtual_canister_notify!(
+            pending.bidder,
+            "auction_reward_notification",
+            (pending.notification.clone(),),
+            ()
+        )
+        .await;
+        if result.is_ok() {
+            sent += 1;
+            continue;
+        }
 
-    Ok(result)
+        pending.attempts += 1;
+        if pending.attempts < MAX_NOTIFICATION_ATTEMPTS {
+            canister
+                .state()
+                .borrow_mut()
+                .bidding_state
+                .pending_notifications
+                .push_back(pending);
+        } else {
+            ic_cdk::println!(
+                "Dropping reward notification to {} after {} attempts",
+                pending.bidder,
+                pending.attempts
+            );
+        }
+    }
+
+    sent
+}
+
+/// Splits `total_amount` between the bidders in proportion to their cycle bids.
+///
+/// The proportional shares are rounded down, and the leftover token units are then given out one
+/// per bidder starting from the largest bids. Bidders with equal bids are ordered using the random
+/// `seed`, so the outcome doesn't depend on the iteration order of the bids map and can be
+/// verified later from the seed stored in the [AuctionInfo].
+fn split_rewards(
+    total_amount: Tokens128,
+    total_cycles: Cycles,
+    bids: &HashMap<Principal, Cycles>,
+    seed: &[u8],
+) -> Vec<(Principal, Tokens128)> {
+    let mut bidders = bids.iter().map(|(&p, &c)| (p, c)).collect::<Vec<_>>();
+    bidders.sort_by(|a, b| a.0.cmp(&b.0));
+    shuffle(&mut bidders, seed);
+    // The sort is stable, so the bidders with equal bids stay in the shuffled order.
+    bidders.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let cycles = bidders
+        .iter()
+        .map(|(_, cycles)| *cycles as u128)
+        .collect::<Vec<_>>();
+    let rewards = split_proportionally(total_amount, &cycles, total_cycles as u128);
+
+    bidders
+        .into_iter()
+        .map(|(bidder, _)| bidder)
+        .zip(rewards)
+        .collect()
+}
+
+/// Fisher-Yates shuffle driven by a splitmix64 generator seeded with the given bytes.
+fn shuffle<T>(items: &mut [T], seed: &[u8]) {
+    let mut state = seed
+        .iter()
+        .fold(0u64, |acc, b| acc.wrapping_mul(31).wrapping_add(*b as u64));
+
+    for i in (1..items.len()).rev() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        items.swap(i, (z % (i as u64 + 1)) as usize);
+    }
 }
 
 fn reset_bidding_state(stats: &StatsData, bidding_state: &mut BiddingState) {
     bidding_state.fee_ratio = get_fee_ratio(stats.min_cycles, ic::balance());
     bidding_state.cycles_since_auction = 0;
-    bidding_state.last_auction = ic::time();
-    bidding_state.bids = HashMap::new();
+    bidding_state.start_bidding_period();
+    bidding_state.bids.clear();
+    bidding_state.token_bids.clear();
 }
 
 fn get_fee_ratio(min_cycles: Cycles, current_cycles: Cycles) -> f64 {
@@ -205,45 +849,42 @@ pub fn auction_principal() -> Principal {
     Principal::management_canister()
 }
 
-pub fn accumulated_fees(balances: &Balances) -> Tokens128 {
-    balances
-        .0
-        .get(&auction_principal())
-        .cloned()
-        .unwrap_or_else(|| Tokens128::from(0u128))
+/// Holder of the tokens of an IS20 feature, e.g. the escrow deposits, identified by the `tag`.
+/// Principals ending with `0x7f` belong to the reserved class, so nobody can make calls with them.
+pub fn reserved_principal(tag: &str) -> Principal {
+    let mut bytes = tag.as_bytes().to_vec();
+    bytes.push(0x7f);
+    Principal::from_slice(&bytes)
+}
+
+/// Whether the `principal` holds the tokens of an IS20 feature rather than of a user.
+pub fn is_reserved_principal(principal: &Principal) -> bool {
+    *principal == auction_principal() || principal.as_slice().last() == Some(&0x7f)
+}
+
+/// The fees accumulated for the next auction. The rewards of the previous auction that are not
+/// paid yet are not included.
+pub fn accumulated_fees(balances: &Balances, bidding_state: &BiddingState) -> Tokens128 {
+    let pending = bidding_state
+        .disbursement
+        .as_ref()
+        .map_or(Tokens128::ZERO, |disbursement| disbursement.remaining);
+    // The pending rewards are held by the auction principal.
+    saturating_sub(balances.balance_of(&auction_principal()), pending)
 }
 
 #[cfg(test)]
 mod tests {
-    use ic_canister::ic_kit::mock_principals::{alice, bob};
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
     use ic_canister::ic_kit::MockContext;
-    use ic_canister::Canister;
+    use ic_canister::{register_failing_virtual_responder, register_virtual_responder, Canister};
     use test_case::test_case;
 
     use crate::mock::*;
-    use crate::types::{Metadata, TxError};
+    use crate::types::{AuctionStats, Metadata, TxError};
 
     use super::*;
 
-    fn test_context() -> (&'static mut MockContext, TokenCanisterMock) {
-        let context = MockContext::new().with_caller(alice()).inject();
-
-        let canister = TokenCanisterMock::init_instance();
-        canister.init(Metadata {
-            logo: "".to_string(),
-            name: "".to_string(),
-            symbol: "".to_string(),
-            decimals: 8,
-            totalSupply: Tokens128::from(1000),
-            owner: alice(),
-            fee: Tokens128::from(0),
-            feeTo: alice(),
-            isTestToken: None,
-        });
-
-        (context, canister)
-    }
-
     #[test_case(0, 0, 0.0)]
     #[test_case(0, 1000, 0.0)]
     #[test_case(1000, 0, 1.0)]
@@ -271,6 +912,57 @@ mod tests {
         assert_eq!(info.caller_cycles, 0);
     }
 
+    #[tokio::test]
+    async fn disabled_auction() {
+        let (context, canister) = test_context();
+        canister.setAuctionDisabled(true).unwrap();
+
+        context.update_msg_cycles(2_000_000);
+        assert_eq!(
+            canister.bidCycles(alice()),
+            Err(AuctionError::AuctionDisabled)
+        );
+        assert_eq!(
+            canister.runAuction().await,
+            Err(AuctionError::AuctionDisabled)
+        );
+
+        {
+            let state = canister.state();
+            let mut state = state.borrow_mut();
+            state.stats.fee = Tokens128::from(10);
+            state.stats.fee_to = john();
+            state.bidding_state.fee_ratio = 1.0;
+        }
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(10));
+        assert_eq!(canister.biddingInfo().fee_ratio, 0.0);
+        assert_eq!(canister.getMetadata().auctionDisabled, Some(true));
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.setAuctionDisabled(false),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn fee_ratio_details() {
+        let (context, canister) = test_context();
+        context.update_balance(1_000_000);
+        canister.setMinCycles(100_000).unwrap();
+        canister.state().borrow_mut().bidding_state.fee_ratio = 1.0;
+
+        let details = canister.getFeeRatioDetails();
+        assert_eq!(details.cycles_balance, 1_000_000);
+        assert_eq!(details.min_cycles, 100_000);
+        assert_eq!(details.fee_ratio, 1.0);
+        assert!((details.next_fee_ratio - 0.5).abs() < 1e-9);
+        assert!(!details.auction_disabled);
+    }
+
     #[test]
     fn bidding_cycles_under_limit() {
         let (context, canister) = test_context();
@@ -293,8 +985,17 @@ mod tests {
         assert_eq!(canister.biddingInfo().caller_cycles, 4_000_000);
     }
 
-    #[test]
-    fn auction_test() {
+    fn register_raw_rand(seed: Vec<u8>) {
+        register_virtual_responder(
+            Principal::management_canister(),
+            "raw_rand",
+            move |_: ()| seed.clone(),
+        );
+    }
+
+    #[tokio::test]
+    async fn auction_test() {
+        register_raw_rand(vec![0; 32]);
         let (context, canister) = test_context();
         context.update_msg_cycles(2_000_000);
         bid_cycles(&canister, alice()).unwrap();
@@ -306,17 +1007,16 @@ mod tests {
             .state()
             .borrow_mut()
             .balances
-            .0
-            .insert(auction_principal(), Tokens128::from(6_000));
+            .set(auction_principal(), Tokens128::from(6_000));
 
-        let result = canister.runAuction().unwrap();
+        let result = canister.runAuction().await.unwrap();
         assert_eq!(result.cycles_collected, 6_000_000);
         assert_eq!(result.first_transaction_id, 1);
         assert_eq!(result.last_transaction_id, 2);
         assert_eq!(result.tokens_distributed, Tokens128::from(6_000));
 
         assert_eq!(
-            canister.state().borrow().balances.0[&bob()],
+            canister.state().borrow().balances.balance_of(&bob()),
             Tokens128::from(4_000)
         );
 
@@ -324,14 +1024,184 @@ mod tests {
         assert_eq!(retrieved_result, result);
     }
 
+    #[tokio::test]
+    async fn auction_stats() {
+        register_raw_rand(vec![0; 32]);
+        let (context, canister) = test_context();
+        assert_eq!(canister.getAuctionStats(), AuctionStats::default());
+
+        for (cycles, pot, fee_ratio) in [(2_000_000, 600, 1.0), (4_000_000, 300, 0.5)] {
+            context.update_msg_cycles(cycles);
+            bid_cycles(&canister, bob()).unwrap();
+            {
+                let state = canister.state();
+                let mut state = state.borrow_mut();
+                state
+                    .balances
+                    .set(auction_principal(), Tokens128::from(pot));
+                state.bidding_state.fee_ratio = fee_ratio;
+                state.bidding_state.last_auction = 0;
+            }
+            canister.runAuction().await.unwrap();
+        }
+
+        let stats = canister.getAuctionStats();
+        assert_eq!(stats.auctions_count, 2);
+        assert_eq!(stats.total_cycles_collected, 6_000_000);
+        assert_eq!(stats.total_tokens_distributed, Tokens128::from(900));
+        assert_eq!(stats.average_fee_ratio, 0.75);
+    }
+
+    #[tokio::test]
+    async fn auction_remainder_goes_to_the_largest_bid() {
+        register_raw_rand(vec![7; 32]);
+        let (context, canister) = test_context();
+        context.update_msg_cycles(2_000_000);
+        bid_cycles(&canister, alice()).unwrap();
+
+        context.update_msg_cycles(4_000_000);
+        bid_cycles(&canister, bob()).unwrap();
+
+        canister
+            .state()
+            .borrow_mut()
+            .balances
+            .set(auction_principal(), Tokens128::from(100));
+
+        let result = canister.runAuction().await.unwrap();
+        assert_eq!(result.tokens_distributed, Tokens128::from(100));
+        assert_eq!(result.seed, vec![7; 32]);
+        assert_eq!(
+            canister.state().borrow().balances.balance_of(&bob()),
+            Tokens128::from(67)
+        );
+    }
+
+    fn setup_icp_auction(context: &mut MockContext, canister: &TokenCanisterMock) {
+        canister
+            .setAuctionRewardAsset(AuctionRewardAsset::Icp {
+                exchange: john(),
+                min_icp_per_token: 10_000_000,
+            })
+            .unwrap();
+
+        context.update_msg_cycles(2_000_000);
+        bid_cycles(canister, alice()).unwrap();
+        context.update_msg_cycles(4_000_000);
+        bid_cycles(canister, bob()).unwrap();
+
+        canister
+            .state()
+            .borrow_mut()
+            .balances
+            .set(auction_principal(), Tokens128::from(6_000));
+    }
+
+    #[tokio::test]
+    async fn icp_auction() {
+        register_raw_rand(vec![0; 32]);
+        register_virtual_responder(john(), "swap_to_icp", |(args,): (SwapToIcpArgs,)| {
+            assert_eq!(args.amount, Tokens128::from(6_000));
+            assert_eq!(args.min_icp, 600);
+            assert_eq!(args.payouts.len(), 2);
+
+            // The exchange takes only a part of the allowed tokens.
+            let canister = TokenCanisterMock::init_instance();
+            let state = canister.state();
+            let mut state = state.borrow_mut();
+            let pulled = Tokens128::from(5_000);
+            state
+                .balances
+                .set(auction_principal(), Tokens128::from(1_000));
+            state.balances.set(john(), pulled);
+            state
+                .allowances
+                .set(auction_principal(), john(), Tokens128::from(1_000));
+            state.ledger.transfer_from(
+                john(),
+                auction_principal(),
+                john(),
+                pulled,
+                Tokens128::ZERO,
+            );
+
+            Ok::<u64, String>(995)
+        });
+
+        let (context, canister) = test_context();
+        setup_icp_auction(context, &canister);
+
+        let result = canister.runAuction().await.unwrap();
+        assert_eq!(result.icp_distributed, Some(995));
+        assert_eq!(result.tokens_distributed, Tokens128::from(5_000));
+        assert_eq!(result.first_transaction_id, canister.historySize() - 1);
+        assert_eq!(result.last_transaction_id, canister.historySize() - 1);
+        assert_eq!(result.cycles_collected, 6_000_000);
+        assert!(canister.state().borrow().bidding_state.bids.is_empty());
+        assert_eq!(
+            canister.allowance(auction_principal(), john()),
+            Tokens128::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn icp_auction_swap_failed() {
+        register_raw_rand(vec![0; 32]);
+        register_virtual_responder(john(), "swap_to_icp", |(_,): (SwapToIcpArgs,)| {
+            Err::<u64, String>("slippage".into())
+        });
+
+        let (context, canister) = test_context();
+        setup_icp_auction(context, &canister);
+
+        let result = canister.runAuction().await.unwrap();
+        assert_eq!(result.icp_distributed, None);
+        assert_eq!(result.tokens_distributed, Tokens128::from(6_000));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(4_000));
+        assert_eq!(
+            canister.allowance(auction_principal(), john()),
+            Tokens128::ZERO
+        );
+    }
+
     #[test]
-    fn auction_without_bids() {
+    fn split_rewards_with_equal_bids() {
+        let bids = HashMap::from_iter([
+            (alice(), 1_000_000),
+            (bob(), 1_000_000),
+            (john(), 1_000_000),
+        ]);
+
+        for seed in 0..16u8 {
+            let rewards = split_rewards(Tokens128::from(100), 3_000_000, &bids, &[seed; 32]);
+            let total = rewards
+                .iter()
+                .fold(0, |acc, (_, amount)| acc + amount.amount);
+            assert_eq!(total, 100);
+            assert_eq!(
+                rewards
+                    .iter()
+                    .filter(|(_, amount)| *amount == Tokens128::from(34))
+                    .count(),
+                1
+            );
+
+            // The same seed always gives the same result.
+            assert_eq!(
+                rewards,
+                split_rewards(Tokens128::from(100), 3_000_000, &bids, &[seed; 32])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn auction_without_bids() {
         let (_, canister) = test_context();
-        assert_eq!(canister.runAuction(), Err(AuctionError::NoBids));
+        assert_eq!(canister.runAuction().await, Err(AuctionError::NoBids));
     }
 
-    #[test]
-    fn auction_not_in_time() {
+    #[tokio::test]
+    async fn auction_not_in_time() {
         let (context, canister) = test_context();
         context.update_msg_cycles(2_000_000);
         canister.bidCycles(alice()).unwrap();
@@ -344,22 +1214,351 @@ mod tests {
         }
 
         assert_eq!(
-            canister.runAuction(),
+            canister.runAuction().await,
             Err(AuctionError::TooEarlyToBeginAuction)
         );
     }
 
     #[test]
-    fn fee_ratio_update() {
+    fn late_bid_extends_deadline() {
+        const SEC: u64 = 1_000_000_000;
+        let (context, canister) = test_context();
+        canister.setAuctionPeriod(100).unwrap();
+        canister.setBidExtensionWindow(10).unwrap();
+        canister
+            .state()
+            .borrow_mut()
+            .bidding_state
+            .start_bidding_period();
+        let deadline = canister.biddingInfo().next_auction;
+
+        context.add_time(50 * SEC);
+        context.update_msg_cycles(1_000_000);
+        canister.bidCycles(alice()).unwrap();
+        assert_eq!(canister.biddingInfo().next_auction, deadline);
+
+        context.add_time(45 * SEC);
+        context.update_msg_cycles(1_000_000);
+        canister.bidCycles(alice()).unwrap();
+        assert_eq!(canister.biddingInfo().next_auction, deadline + 5 * SEC);
+
+        context.add_time(6 * SEC);
+        assert_eq!(
+            run_auction(&canister, vec![0; 32]),
+            Err(AuctionError::TooEarlyToBeginAuction)
+        );
+
+        // The extension is capped at one auction period.
+        canister.setBidExtensionWindow(1000).unwrap();
+        context.update_msg_cycles(1_000_000);
+        canister.bidCycles(alice()).unwrap();
+        assert_eq!(canister.biddingInfo().next_auction, deadline + 100 * SEC);
+
+        context.add_time(100 * SEC);
+        assert!(run_auction(&canister, vec![0; 32]).is_ok());
+        assert_eq!(canister.biddingInfo().next_auction, ic::time() + 100 * SEC);
+    }
+
+    #[tokio::test]
+    async fn fee_ratio_update() {
         let (context, canister) = test_context();
         context.update_balance(1_000_000_000);
 
         canister.state().borrow_mut().stats.min_cycles = 1_000_000;
-        canister.runAuction().unwrap_err();
+        canister.runAuction().await.unwrap_err();
 
         assert_eq!(canister.state().borrow().bidding_state.fee_ratio, 0.125);
     }
 
+    #[tokio::test]
+    async fn withdrawing_bid() {
+        register_virtual_responder(
+            Principal::management_canister(),
+            "deposit_cycles",
+            |(args,): (CanisterIdRecord,)| assert_eq!(args.canister_id, bob()),
+        );
+        let (context, canister) = test_context();
+        canister.setBidWithdrawalPenalty(500).unwrap();
+
+        context.update_caller(bob());
+        context.update_msg_cycles(2_000_000);
+        canister.bidCycles(bob()).unwrap();
+        context.update_msg_cycles(0);
+
+        assert_eq!(canister.withdrawBid().await, Ok(1_900_000));
+        let info = canister.biddingInfo();
+        assert_eq!(info.total_cycles, 0);
+        assert_eq!(info.caller_cycles, 0);
+        assert_eq!(info.withdrawal_penalty_bps, 500);
+
+        assert_eq!(canister.withdrawBid().await, Err(AuctionError::NoBid));
+    }
+
+    #[tokio::test]
+    async fn bidding_with_tokens() {
+        let ledger = Principal::from_slice(&[1; 29]);
+        register_virtual_responder(
+            ledger,
+            "icrc2_transfer_from",
+            |(args,): (TransferFromArgs,)| -> Result<Nat, TransferFromError> {
+                assert_eq!(args.from, Account::new(bob(), None));
+                assert_eq!(args.to, Account::new(john(), None));
+                if args.amount > Tokens128::from(100) {
+                    return Err(TransferFromError::InsufficientAllowance {
+                        allowance: Tokens128::from(100),
+                    });
+                }
+                Ok(Nat::from(1u64))
+            },
+        );
+        let (context, canister) = test_context();
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.bidTokens(Tokens128::from(10)).await,
+            Err(AuctionError::TokenBidsDisabled)
+        );
+
+        context.update_caller(alice());
+        canister
+            .setBidToken(Some(BidToken {
+                ledger,
+                treasury: john(),
+                cycles_per_unit: 100_000,
+            }))
+            .unwrap();
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.bidTokens(Tokens128::from(1)).await,
+            Err(AuctionError::BiddingTooSmall)
+        );
+        assert_eq!(
+            canister.bidTokens(Tokens128::from(200)).await,
+            Err(AuctionError::TokenTransferFailed)
+        );
+        assert_eq!(
+            canister.bidTokens(Tokens128::from(u128::MAX)).await,
+            Err(AuctionError::BiddingTooLarge)
+        );
+        assert_eq!(canister.biddingInfo().total_cycles, 0);
+
+        assert_eq!(canister.bidTokens(Tokens128::from(20)).await, Ok(2_000_000));
+        context.update_msg_cycles(1_000_000);
+        canister.bidCycles(bob()).unwrap();
+        context.update_msg_cycles(0);
+        let info = canister.biddingInfo();
+        assert_eq!(info.total_cycles, 1_000_000);
+        assert_eq!(info.token_bids, 2_000_000);
+        assert_eq!(info.caller_cycles, 3_000_000);
+
+        // Only the part paid in cycles is withdrawn.
+        register_virtual_responder(
+            Principal::management_canister(),
+            "deposit_cycles",
+            |(args,): (CanisterIdRecord,)| assert_eq!(args.canister_id, bob()),
+        );
+        assert_eq!(canister.withdrawBid().await, Ok(1_000_000));
+        let info = canister.biddingInfo();
+        assert_eq!(info.total_cycles, 0);
+        assert_eq!(info.token_bids, 2_000_000);
+        assert_eq!(info.caller_cycles, 2_000_000);
+        assert_eq!(canister.withdrawBid().await, Err(AuctionError::NoBid));
+
+        // The token bid takes the reward, but no cycles are collected for it.
+        register_raw_rand(vec![0; 32]);
+        context.update_caller(alice());
+        canister
+            .state()
+            .borrow_mut()
+            .balances
+            .set(auction_principal(), Tokens128::from(100));
+        let result = canister.runAuction().await.unwrap();
+        assert_eq!(result.cycles_collected, 0);
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(canister.biddingInfo().token_bids, 0);
+    }
+
+    #[test]
+    fn setting_bid_withdrawal_penalty_not_authorized() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.setBidWithdrawalPenalty(500),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    fn push_auctions(canister: &TokenCanisterMock, times: &[Timestamp]) {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        for &auction_time in times {
+            let auction_id = state.auction_history.len();
+            state.auction_history.insert(AuctionInfo {
+                auction_id,
+                auction_time,
+                tokens_distributed: Tokens128::ZERO,
+                cycles_collected: 0,
+                fee_ratio: 0.0,
+                first_transaction_id: 0,
+                last_transaction_id: 0,
+                seed: vec![],
+                icp_distributed: None,
+            });
+        }
+    }
+
+    fn auction_ids(page: &PaginatedAuctions) -> Vec<usize> {
+        page.result
+            .iter()
+            .map(|auction| auction.auction_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn chunked_disbursement() {
+        register_raw_rand(vec![0; 32]);
+        let (context, canister) = test_context();
+        let bidders = (0..DISBURSEMENT_CHUNK_LEN as u32 + 100)
+            .map(|i| Principal::from_slice(&i.to_be_bytes()))
+            .collect::<Vec<_>>();
+        for bidder in &bidders {
+            context.update_msg_cycles(1_000_000);
+            bid_cycles(&canister, *bidder).unwrap();
+        }
+
+        canister
+            .state()
+            .borrow_mut()
+            .balances
+            .set(auction_principal(), Tokens128::from(600_000));
+
+        let result = canister.runAuction().await.unwrap();
+        assert_eq!(result.tokens_distributed, Tokens128::from(600_000));
+        {
+            let state = canister.state();
+            let state = state.borrow();
+            assert!(state.bidding_state.disbursement.is_some());
+            assert_eq!(state.ledger.len(), 1 + DISBURSEMENT_CHUNK_LEN as u64);
+            assert_eq!(
+                accumulated_fees(&state.balances, &state.bidding_state),
+                Tokens128::ZERO
+            );
+        }
+
+        context.update_msg_cycles(1_000_000);
+        bid_cycles(&canister, alice()).unwrap();
+        context.add_time(crate::canister::DEFAULT_AUCTION_PERIOD);
+        assert_eq!(
+            run_auction(&canister, vec![0; 32]),
+            Err(AuctionError::DisbursementInProgress)
+        );
+
+        assert!(disburse_rewards(&canister));
+        assert!(canister
+            .state()
+            .borrow()
+            .bidding_state
+            .disbursement
+            .is_none());
+        for bidder in &bidders {
+            assert_eq!(canister.balanceOf(*bidder), Tokens128::from(1_000));
+        }
+
+        let info = canister.auctionInfo(result.auction_id).unwrap();
+        assert_eq!(info.last_transaction_id, canister.historySize() - 1);
+    }
+
+    #[tokio::test]
+    async fn reward_notifications() {
+        register_raw_rand(vec![0; 32]);
+        register_virtual_responder(
+            bob(),
+            "auction_reward_notification",
+            |(notification,): (RewardNotification,)| {
+                assert_eq!(notification.amount, Tokens128::from(4_000));
+            },
+        );
+        register_failing_virtual_responder(
+            alice(),
+            "auction_reward_notification",
+            "no such method".into(),
+        );
+
+        let (context, canister) = test_context();
+        canister.setNotifyBidders(true).unwrap();
+        context.update_msg_cycles(2_000_000);
+        bid_cycles(&canister, alice()).unwrap();
+        context.update_msg_cycles(4_000_000);
+        bid_cycles(&canister, bob()).unwrap();
+        canister
+            .state()
+            .borrow_mut()
+            .balances
+            .set(auction_principal(), Tokens128::from(6_000));
+
+        canister.runAuction().await.unwrap();
+        let pending = canister
+            .state()
+            .borrow()
+            .bidding_state
+            .pending_notifications
+            .clone();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].bidder, alice());
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(pending[0].notification.amount, Tokens128::from(2_000));
+
+        for _ in 1..MAX_NOTIFICATION_ATTEMPTS {
+            assert_eq!(canister.sendRewardNotifications().await, 0);
+        }
+        assert!(canister
+            .state()
+            .borrow()
+            .bidding_state
+            .pending_notifications
+            .is_empty());
+    }
+
+    #[test]
+    fn auction_history_pages() {
+        let (_, canister) = test_context();
+        push_auctions(&canister, &[10, 20, 30, 40, 50]);
+
+        let page = canister.getAuctionHistory(1, 3);
+        assert_eq!(auction_ids(&page), vec![1, 2, 3]);
+        assert_eq!(page.next, Some(4));
+
+        let page = canister.getAuctionHistory(4, 3);
+        assert_eq!(auction_ids(&page), vec![4]);
+        assert_eq!(page.next, None);
+
+        assert!(canister.getAuctionHistory(10, 3).result.is_empty());
+    }
+
+    #[test]
+    fn auctions_by_time_range() {
+        let (_, canister) = test_context();
+        push_auctions(&canister, &[10, 20, 30, 40, 50]);
+
+        let page = canister.getAuctionsByTime(20, 40);
+        assert_eq!(auction_ids(&page), vec![1, 2, 3]);
+        assert_eq!(page.next, None);
+
+        assert!(canister.getAuctionsByTime(41, 49).result.is_empty());
+        assert!(canister.getAuctionsByTime(40, 20).result.is_empty());
+    }
+
+    #[test]
+    fn auctions_by_time_limit() {
+        let times = (0..MAX_AUCTION_QUERY_LEN as u64 + 10).collect::<Vec<_>>();
+        let (_, canister) = test_context();
+        push_auctions(&canister, &times);
+        let page = canister.getAuctionsByTime(5, 200);
+        assert_eq!(page.result.len(), MAX_AUCTION_QUERY_LEN);
+        assert_eq!(page.next, Some(MAX_AUCTION_QUERY_LEN + 5));
+    }
+
     #[test]
     fn setting_min_cycles() {
         let (_, canister) = test_context();
@@ -378,7 +1577,37 @@ mod tests {
     fn setting_auction_period() {
         let (_, canister) = test_context();
         canister.setAuctionPeriod(100500).unwrap();
-        assert_eq!(canister.biddingInfo().auction_period, 100500 * 1000000);
+        assert_eq!(
+            canister.biddingInfo().auction_period,
+            100500 * 1_000_000_000
+        );
+    }
+
+    #[test]
+    fn auction_configuration_at_creation() {
+        MockContext::new().with_caller(alice()).inject();
+        let canister = TokenCanisterMock::init_instance();
+        canister.init(Metadata {
+            auctionDisabled: Some(true),
+            auctionPeriod: Some(3600),
+            minCycles: Some(100_000),
+            ..test_metadata()
+        });
+
+        assert_eq!(canister.biddingInfo().auction_period, 3600 * 1_000_000_000);
+        assert_eq!(canister.getFeeRatioDetails().min_cycles, 100_000);
+        let metadata = canister.getMetadata();
+        assert_eq!(metadata.auctionDisabled, Some(true));
+        assert_eq!(metadata.auctionPeriod, Some(3600));
+        assert_eq!(metadata.minCycles, Some(100_000));
+
+        let standards: Vec<String> = canister
+            .icrc1_supported_standards()
+            .into_iter()
+            .map(|standard| standard.name)
+            .collect();
+        assert!(standards.contains(&"ICRC-1".to_string()));
+        assert!(!standards.contains(&"IS20-auction".to_string()));
     }
 
     #[test]
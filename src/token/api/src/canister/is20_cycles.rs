@@ -0,0 +1,184 @@
+//! Low-cycles monitoring. When the cycles balance drops below the threshold set by the owner, a
+//! `LowCycles` event is recorded in the event log, and the top-up provider canister, if set, is
+//! asked to top up the token. The balance is checked and the top-up requests are sent by the
+//! canister timer, see [crate::canister::run_timer_tasks], or by the `checkCycles` call.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_canister::virtual_canister_notify;
+
+use crate::types::{AdminEvent, Cycles, Timestamp};
+
+use super::TokenCanisterAPI;
+
+/// Method of the provider canister called with the cycles balance and the threshold of the token.
+pub const TOP_UP_METHOD: &str = "cycles_top_up_request";
+/// Minimum time between two top-up requests, in nanoseconds.
+pub const TOP_UP_REQUEST_INTERVAL: Timestamp = 60 * 60 * 1_000_000_000;
+
+/// Compares the cycles balance to the threshold. The `LowCycles` event is recorded once the
+/// balance drops below the threshold, and then again only after the balance was restored. Returns
+/// `true` if the balance is below the threshold.
+pub(crate) fn check_cycles_balance(canister: &impl TokenCanisterAPI) -> bool {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    let threshold = state.cycles_monitor.threshold;
+    let balance = ic::balance();
+    if threshold == 0 || balance >= threshold {
+        state.cycles_monitor.low_since = None;
+        return false;
+    }
+
+    if state.cycles_monitor.low_since.is_none() {
+        state.cycles_monitor.low_since = Some(ic::time());
+        state
+            .ledger
+            .record_admin_event(ic::id(), AdminEvent::LowCycles { balance, threshold });
+    }
+
+    true
+}
+
+/// Checks the cycles balance and sends a top-up request to the provider if the balance is below
+/// the threshold. Requests are sent at most once in [TOP_UP_REQUEST_INTERVAL]. Returns `true` if
+/// the request was sent.
+pub(crate) async fn request_top_up(canister: &impl TokenCanisterAPI) -> bool {
+    if !check_cycles_balance(canister) {
+        return false;
+    }
+
+    let (provider, threshold) = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        let monitor = &mut state.cycles_monitor;
+
+        let provider = match monitor.provider {
+            Some(provider) => provider,
+            None => return false,
+        };
+        let now = ic::time();
+        if let Some(last_request) = monitor.last_top_up_request {
+            if now < last_request.saturating_add(TOP_UP_REQUEST_INTERVAL) {
+                return false;
+            }
+        }
+
+        // Set before the call, so the concurrent calls don't send the request twice.
+        monitor.last_top_up_request = Some(now);
+        (provider, monitor.threshold)
+    };
+
+    send_request(provider, threshold).await
+}
+
+async fn send_request(provider: Principal, threshold: Cycles) -> bool {
+    let balance = ic::balance();
+    match virtual_canister_notify!(provider, TOP_UP_METHOD, (balance, threshold), ()).await {
+        Ok(()) => true,
+        Err(_) => {
+            ic_cdk::println!("Failed to request top-up from {provider}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ic_canister::ic_kit::mock_principals::bob;
+    use ic_canister::register_virtual_responder;
+
+    use crate::mock::*;
+    use crate::types::{EventKind, TxError};
+
+    use super::*;
+
+    fn low_cycles_events(canister: &TokenCanisterMock) -> usize {
+        canister
+            .getEvents(0, 100)
+            .into_iter()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    EventKind::Admin {
+                        event: AdminEvent::LowCycles { .. },
+                        ..
+                    }
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn alert_recorded_once() {
+        let (context, canister) = test_context();
+        context.update_balance(1_000);
+        canister.setCyclesMonitor(10_000, None).unwrap();
+
+        assert!(check_cycles_balance(&canister));
+        assert!(check_cycles_balance(&canister));
+        assert_eq!(low_cycles_events(&canister), 1);
+        assert!(canister.getCyclesMonitor().low_since.is_some());
+
+        context.update_balance(100_000);
+        assert!(!check_cycles_balance(&canister));
+        assert!(canister.getCyclesMonitor().low_since.is_none());
+
+        context.update_balance(1_000);
+        assert!(check_cycles_balance(&canister));
+        assert_eq!(low_cycles_events(&canister), 2);
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let (context, canister) = test_context();
+        context.update_balance(0);
+        assert!(!check_cycles_balance(&canister));
+        assert_eq!(low_cycles_events(&canister), 0);
+    }
+
+    #[tokio::test]
+    async fn top_up_requested() {
+        let requests = Rc::new(AtomicUsize::new(0));
+        let requests_copy = requests.clone();
+        register_virtual_responder(
+            bob(),
+            TOP_UP_METHOD,
+            move |(balance, threshold): (Cycles, Cycles)| {
+                assert_eq!(balance, 1_000);
+                assert_eq!(threshold, 10_000);
+                requests.fetch_add(1, Ordering::Relaxed);
+            },
+        );
+
+        let (context, canister) = test_context();
+        context.update_balance(1_000);
+        canister.setCyclesMonitor(10_000, Some(bob())).unwrap();
+
+        assert!(canister.checkCycles().await);
+        assert!(!canister.checkCycles().await);
+        assert_eq!(requests_copy.load(Ordering::Relaxed), 1);
+
+        context.add_time(TOP_UP_REQUEST_INTERVAL);
+        assert!(canister.checkCycles().await);
+        assert_eq!(requests_copy.load(Ordering::Relaxed), 2);
+
+        context.update_balance(100_000);
+        context.add_time(TOP_UP_REQUEST_INTERVAL);
+        assert!(!canister.checkCycles().await);
+        assert_eq!(requests_copy.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn not_owner() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.setCyclesMonitor(10_000, None),
+            Err(TxError::Unauthorized)
+        );
+    }
+}
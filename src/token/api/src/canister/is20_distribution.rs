@@ -0,0 +1,288 @@
+//! Pro-rata distribution of tokens to all the holders. The owner moves the distributed amount to
+//! the [distribution_principal], and it's paid to the holders proportionally to their balances in
+//! chunks, one chunk per run of the timer.
+//!
+//! The chunks go through the balances in the principal order, starting after the last holder of
+//! the previous chunk, so no holder list is kept in the heap. The shares are relative to the
+//! supply held by the paid holders when the distribution starts, and a holder is paid by its
+//! balance when its chunk is processed. The reserved principals holding the tokens of the other
+//! features (escrow, streams, vesting, auction) and the frozen accounts are not paid. The rounding
+//! leftovers and the unpaid shares are returned to the owner when the distribution is over.
+
+use candid::Principal;
+use ic_helpers::tokens::Tokens128;
+
+use crate::amount_math::{proportional_share, saturating_add, saturating_sub};
+use crate::canister::erc20_transactions::{transfer_balance, transfer_tokens};
+use crate::canister::is20_auction::{auction_principal, is_reserved_principal, reserved_principal};
+use crate::canister::is20_escrow::escrow_principal;
+use crate::canister::is20_streams::stream_principal;
+use crate::canister::is20_vesting::vesting_principal;
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::{CanisterState, Distribution};
+use crate::types::{TxError, TxReceipt};
+
+use super::TokenCanisterAPI;
+
+/// Number of the holders paid in one chunk.
+const DISTRIBUTION_CHUNK_LEN: usize = 100;
+
+/// Holder of the tokens that are being distributed.
+pub fn distribution_principal() -> Principal {
+    reserved_principal("distribution")
+}
+
+/// Moves the `amount` from the owner balance to the distribution principal and starts the
+/// distribution. Only one distribution can be in progress.
+pub(crate) fn distribute(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Owner>,
+    amount: Tokens128,
+) -> TxReceipt {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    if state.distribution.is_some() {
+        return Err(TxError::DistributionInProgress);
+    }
+    if amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    let id = transfer_tokens(
+        &mut state,
+        caller.inner(),
+        distribution_principal(),
+        amount,
+        None,
+    )?;
+
+    // The reserved principals and the frozen accounts are few, so the supply of the paid holders
+    // is computed without going through all the balances.
+    let reserved = [
+        auction_principal(),
+        escrow_principal(),
+        stream_principal(),
+        vesting_principal(),
+        distribution_principal(),
+    ];
+    let excluded = reserved
+        .iter()
+        .chain(
+            state
+                .frozen_accounts
+                .iter()
+                .filter(|holder| !reserved.contains(*holder)),
+        )
+        .fold(Tokens128::ZERO, |total, holder| {
+            saturating_add(total, state.balances.balance_of(holder))
+        });
+    state.distribution = Some(Distribution {
+        amount,
+        supply: saturating_sub(state.stats.total_supply, excluded),
+        remaining: amount,
+        cursor: None,
+    });
+
+    Ok(id)
+}
+
+/// Pays the next chunk of the pending distribution. Returns `true` if there is nothing more to
+/// pay.
+pub(crate) fn process_distribution(canister: &impl TokenCanisterAPI) -> bool {
+    distribute_chunk(&mut canister.state().borrow_mut(), DISTRIBUTION_CHUNK_LEN)
+}
+
+fn distribute_chunk(state: &mut CanisterState, limit: usize) -> bool {
+    let mut distribution = match state.distribution.take() {
+        Some(distribution) if !distribution.supply.is_zero() => distribution,
+        Some(distribution) => {
+            finish(state, distribution);
+            return true;
+        }
+        None => return true,
+    };
+
+    let holders = state.balances.get_accounts(distribution.cursor, limit);
+    for &(holder, balance) in &holders {
+        if is_reserved_principal(&holder) || state.frozen_accounts.contains(&holder) {
+            continue;
+        }
+
+        let share = proportional_share(
+            distribution.amount,
+            balance.amount,
+            distribution.supply.amount,
+        )
+        .min(distribution.remaining);
+        if share.is_zero() {
+            continue;
+        }
+
+        // The distributed tokens are held by the distribution principal, so the transfer fails only
+        // if the state is inconsistent. The share is then returned to the owner with the rest.
+        if transfer_balance(&mut state.balances, distribution_principal(), holder, share).is_err() {
+            continue;
+        }
+        state
+            .ledger
+            .transfer(distribution_principal(), holder, share, Tokens128::ZERO);
+        distribution.remaining = saturating_sub(distribution.remaining, share);
+    }
+
+    if holders.len() < limit || distribution.remaining.is_zero() {
+        finish(state, distribution);
+        return true;
+    }
+
+    distribution.cursor = holders.last().map(|&(holder, _)| holder);
+    state.distribution = Some(distribution);
+    false
+}
+
+/// Returns the undistributed tokens to the owner.
+fn finish(state: &mut CanisterState, distribution: Distribution) {
+    // Only the tokens the distribution principal still holds can be returned.
+    let remaining = distribution
+        .remaining
+        .min(state.balances.balance_of(&distribution_principal()));
+    if remaining.is_zero() {
+        return;
+    }
+
+    let owner = state.stats.owner;
+    if transfer_balance(
+        &mut state.balances,
+        distribution_principal(),
+        owner,
+        remaining,
+    )
+    .is_err()
+    {
+        return;
+    }
+    state
+        .ledger
+        .transfer(distribution_principal(), owner, remaining, Tokens128::ZERO);
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+    use ic_canister::ic_kit::MockContext;
+
+    use crate::canister::is20_escrow::escrow_principal;
+    use crate::mock::*;
+
+    use super::*;
+
+    fn test_context() -> (&'static mut MockContext, TokenCanisterMock) {
+        let (context, canister) = crate::mock::test_context();
+        canister
+            .transfer(bob(), Tokens128::from(200), None)
+            .unwrap();
+        canister
+            .transfer(john(), Tokens128::from(100), None)
+            .unwrap();
+
+        (context, canister)
+    }
+
+    #[test]
+    fn distribute_in_chunks() {
+        let (_, canister) = test_context();
+        canister.distribute(Tokens128::from(100)).unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(600));
+        assert_eq!(
+            canister.getDistribution().unwrap().supply,
+            Tokens128::from(900)
+        );
+
+        // Alice, Bob, John and the distribution principal hold the tokens.
+        let state = canister.state();
+        assert!(!distribute_chunk(&mut state.borrow_mut(), 3));
+        assert!(canister.getDistribution().unwrap().cursor.is_some());
+        assert!(distribute_chunk(&mut state.borrow_mut(), 3));
+
+        // The rounding leftover is returned to the owner.
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(667));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(222));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(111));
+        assert_eq!(
+            canister.balanceOf(distribution_principal()),
+            Tokens128::ZERO
+        );
+        assert!(canister.getDistribution().is_none());
+    }
+
+    #[test]
+    fn transfers_during_distribution() {
+        let (_, canister) = test_context();
+        canister
+            .transfer(escrow_principal(), Tokens128::from(120), None)
+            .unwrap();
+        canister.distribute(Tokens128::from(80)).unwrap();
+        assert_eq!(
+            canister.getDistribution().unwrap().supply,
+            Tokens128::from(800)
+        );
+
+        // The holders are paid by their balances when the chunk is processed.
+        let state = canister.state();
+        transfer_tokens(
+            &mut state.borrow_mut(),
+            bob(),
+            john(),
+            Tokens128::from(150),
+            None,
+        )
+        .unwrap();
+        assert!(distribute_chunk(&mut state.borrow_mut(), 10));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(550));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(55));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(275));
+        assert_eq!(canister.balanceOf(escrow_principal()), Tokens128::from(120));
+    }
+
+    #[test]
+    fn frozen_holder_not_paid() {
+        let (_, canister) = test_context();
+        canister.distribute(Tokens128::from(100)).unwrap();
+        canister.state().borrow_mut().frozen_accounts.insert(john());
+
+        assert!(process_distribution(&canister));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(100));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(222));
+        // The share of the frozen holder is returned to the owner.
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(678));
+    }
+
+    #[test]
+    fn one_distribution_at_a_time() {
+        let (_, canister) = test_context();
+        canister.distribute(Tokens128::from(100)).unwrap();
+        assert_eq!(
+            distribute(
+                &canister,
+                CheckedPrincipal::owner(&canister.state().borrow()).unwrap(),
+                Tokens128::from(100)
+            ),
+            Err(TxError::DistributionInProgress)
+        );
+
+        // The pending distribution is finished by the timer.
+        assert!(process_distribution(&canister));
+        canister.distribute(Tokens128::from(100)).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(222));
+    }
+
+    #[test]
+    fn not_owner() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.distribute(Tokens128::from(100)),
+            Err(TxError::Unauthorized)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Escrow for the OTC deals. The payer moves the tokens into an escrow, and they are either
+//! released to the recipient or refunded to the payer. The arbiter can do both, the payer can
+//! release the tokens, and the recipient can refund them. After the deadline the payer can take the
+//! tokens back as well.
+//!
+//! The escrowed tokens are held by the [escrow_principal], and all the movements are recorded in
+//! the ledger as transfers.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+
+use crate::canister::erc20_transactions::{transfer_balance, transfer_tokens};
+use crate::canister::is20_auction::reserved_principal;
+use crate::principal::{CheckedPrincipal, WithRecipient};
+use crate::state::{CanisterState, Escrow};
+use crate::types::{Timestamp, TxError, TxReceipt};
+
+use super::TokenCanisterAPI;
+
+/// Holder of the escrowed tokens.
+pub fn escrow_principal() -> Principal {
+    reserved_principal("escrow")
+}
+
+/// Moves the `amount` from the caller balance into a new escrow and returns its id. The transfer
+/// is charged the usual fee.
+pub(crate) fn create_escrow(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    amount: Tokens128,
+    arbiter: Principal,
+    deadline: Timestamp,
+) -> Result<u64, TxError> {
+    let now = ic::time();
+    if amount.is_zero() || deadline <= now {
+        return Err(TxError::InvalidEscrow);
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    state.check_not_frozen(&[caller.recipient()])?;
+    transfer_tokens(&mut state, caller.inner(), escrow_principal(), amount, None)?;
+
+    let escrows = &mut state.escrows;
+    let id = escrows.next_id;
+    escrows.next_id += 1;
+    escrows.entries.insert(
+        id,
+        Escrow {
+            id,
+            from: caller.inner(),
+            to: caller.recipient(),
+            amount,
+            arbiter,
+            deadline,
+            created_at: now,
+        },
+    );
+
+    Ok(id)
+}
+
+/// Transfers the escrowed tokens to the recipient. Can be called by the payer or the arbiter.
+pub(crate) fn release_escrow(canister: &impl TokenCanisterAPI, id: u64) -> TxReceipt {
+    let caller = ic::caller();
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    let escrow = state
+        .escrows
+        .entries
+        .get(&id)
+        .ok_or(TxError::EscrowDoesNotExist)?;
+    if caller != escrow.from && caller != escrow.arbiter {
+        return Err(TxError::Unauthorized);
+    }
+
+    let to = escrow.to;
+    settle(&mut state, id, to)
+}
+
+/// Returns the escrowed tokens to the payer. Can be called by the recipient or the arbiter, or by
+/// the payer after the deadline.
+pub(crate) fn refund_escrow(canister: &impl TokenCanisterAPI, id: u64) -> TxReceipt {
+    let caller = ic::caller();
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    let escrow = state
+        .escrows
+        .entries
+        .get(&id)
+        .ok_or(TxError::EscrowDoesNotExist)?;
+    let is_expired = ic::time() >= escrow.deadline;
+    if caller != escrow.to && caller != escrow.arbiter && !(caller == escrow.from && is_expired) {
+        return Err(TxError::Unauthorized);
+    }
+
+    let from = escrow.from;
+    settle(&mut state, id, from)
+}
+
+/// Removes the escrow and transfers its tokens to the `receiver`. The transfer is not charged a
+/// fee, as it was paid on the escrow creation.
+fn settle(state: &mut CanisterState, id: u64, receiver: Principal) -> TxReceipt {
+    state.check_not_frozen(&[receiver])?;
+    let amount = state
+        .escrows
+        .entries
+        .get(&id)
+        .ok_or(TxError::EscrowDoesNotExist)?
+        .amount;
+
+    transfer_balance(&mut state.balances, escrow_principal(), receiver, amount)?;
+    let escrow = state
+        .escrows
+        .entries
+        .remove(&id)
+        .expect("escrow existence checked above");
+    let id = state
+        .ledger
+        .transfer(escrow_principal(), receiver, escrow.amount, Tokens128::ZERO);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    const DEADLINE: Timestamp = 1_000_000_000;
+
+    fn create(canister: &TokenCanisterMock) -> u64 {
+        canister
+            .createEscrow(bob(), Tokens128::from(100), john(), ic::time() + DEADLINE)
+            .unwrap()
+    }
+
+    #[test]
+    fn release_by_arbiter() {
+        let (context, canister) = test_context();
+        let id = create(&canister);
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(900));
+        assert_eq!(canister.balanceOf(escrow_principal()), Tokens128::from(100));
+        assert_eq!(canister.getEscrows(john()).len(), 1);
+
+        context.update_caller(bob());
+        assert_eq!(canister.releaseEscrow(id), Err(TxError::Unauthorized));
+
+        context.update_caller(john());
+        let tx_id = canister.releaseEscrow(id).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(canister.balanceOf(escrow_principal()), Tokens128::ZERO);
+        assert_eq!(canister.getTransaction(tx_id).from, escrow_principal());
+        assert!(canister.getEscrows(john()).is_empty());
+        assert_eq!(canister.refundEscrow(id), Err(TxError::EscrowDoesNotExist));
+    }
+
+    #[test]
+    fn refund_after_deadline() {
+        let (context, canister) = test_context();
+        let id = create(&canister);
+        assert_eq!(canister.refundEscrow(id), Err(TxError::Unauthorized));
+
+        context.add_time(DEADLINE);
+        canister.refundEscrow(id).unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(1000));
+    }
+
+    #[test]
+    fn refund_by_recipient() {
+        let (context, canister) = test_context();
+        let id = create(&canister);
+
+        context.update_caller(bob());
+        canister.refundEscrow(id).unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(1000));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::ZERO);
+    }
+
+    #[test]
+    fn invalid_escrow() {
+        let (_, canister) = test_context();
+        assert_eq!(
+            canister.createEscrow(bob(), Tokens128::from(100), john(), ic::time()),
+            Err(TxError::InvalidEscrow)
+        );
+        assert_eq!(
+            canister.createEscrow(bob(), Tokens128::from(2000), john(), ic::time() + DEADLINE),
+            Err(TxError::InsufficientBalance)
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Account freezing by the owner and the pausers. Frozen accounts cannot send, receive or approve tokens until
+//! they are unfrozen. Freezing and unfreezing are recorded in the transaction history.
+
+use candid::Principal;
+
+use crate::principal::{CheckedPrincipal, Pauser};
+use crate::types::{TxError, TxReceipt};
+
+use super::TokenCanisterAPI;
+
+pub(crate) fn freeze_account(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Pauser>,
+    who: Principal,
+) -> TxReceipt {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    if !state.frozen_accounts.insert(who) {
+        return Err(TxError::AlreadyActioned);
+    }
+
+    Ok(state.ledger.freeze(caller.inner(), who))
+}
+
+pub(crate) fn unfreeze_account(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Pauser>,
+    who: Principal,
+) -> TxReceipt {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    if !state.frozen_accounts.remove(&who) {
+        return Err(TxError::AlreadyActioned);
+    }
+
+    Ok(state.ledger.unfreeze(caller.inner(), who))
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+    use ic_helpers::tokens::Tokens128;
+
+    use crate::mock::*;
+    use crate::types::Operation;
+
+    use super::*;
+
+    #[test]
+    fn frozen_account_cannot_transact() {
+        let (context, canister) = test_context();
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        canister.freezeAccount(bob()).unwrap();
+        assert!(canister.isAccountFrozen(bob()));
+
+        assert_eq!(
+            canister.transfer(bob(), Tokens128::from(100), None),
+            Err(TxError::AccountFrozen)
+        );
+        assert_eq!(
+            canister.approve(bob(), Tokens128::from(100)),
+            Err(TxError::AccountFrozen)
+        );
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.transfer(john(), Tokens128::from(10), None),
+            Err(TxError::AccountFrozen)
+        );
+        assert_eq!(
+            canister.approve(john(), Tokens128::from(10)),
+            Err(TxError::AccountFrozen)
+        );
+
+        context.update_caller(alice());
+        canister.approve(john(), Tokens128::from(100)).unwrap();
+        context.update_caller(john());
+        assert_eq!(
+            canister.transferFrom(alice(), bob(), Tokens128::from(10)),
+            Err(TxError::AccountFrozen)
+        );
+
+        context.update_caller(alice());
+        canister.unfreezeAccount(bob()).unwrap();
+        assert!(!canister.isAccountFrozen(bob()));
+
+        context.update_caller(bob());
+        canister
+            .transfer(john(), Tokens128::from(10), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(10));
+    }
+
+    #[test]
+    fn freeze_events_recorded() {
+        let (_, canister) = test_context();
+        let freeze_id = canister.freezeAccount(bob()).unwrap();
+        let unfreeze_id = canister.unfreezeAccount(bob()).unwrap();
+
+        let freeze = canister.getTransaction(freeze_id);
+        assert_eq!(freeze.operation, Operation::Freeze);
+        assert_eq!(freeze.caller, Some(alice()));
+        assert_eq!(freeze.to, bob());
+        assert_eq!(
+            canister.getTransaction(unfreeze_id).operation,
+            Operation::Unfreeze
+        );
+    }
+
+    #[test]
+    fn freeze_twice() {
+        let (_, canister) = test_context();
+        canister.freezeAccount(bob()).unwrap();
+        assert_eq!(canister.freezeAccount(bob()), Err(TxError::AlreadyActioned));
+        assert_eq!(
+            canister.unfreezeAccount(john()),
+            Err(TxError::AlreadyActioned)
+        );
+    }
+
+    #[test]
+    fn pauser() {
+        let (context, canister) = test_context();
+        canister.addAdmin(bob()).unwrap();
+
+        context.update_caller(bob());
+        assert_eq!(canister.addAdmin(john()), Err(TxError::Unauthorized));
+        canister.addPauser(john()).unwrap();
+        assert_eq!(canister.getPausers(), vec![john()]);
+
+        context.update_caller(john());
+        let id = canister.freezeAccount(bob()).unwrap();
+        assert_eq!(canister.getTransaction(id).caller, Some(john()));
+        canister.unfreezeAccount(bob()).unwrap();
+        assert_eq!(canister.addPauser(bob()), Err(TxError::Unauthorized));
+
+        context.update_caller(alice());
+        canister.removeAdmin(bob()).unwrap();
+        canister.removePauser(john()).unwrap();
+        assert!(canister.getAdmins().is_empty());
+
+        context.update_caller(john());
+        assert_eq!(canister.freezeAccount(bob()), Err(TxError::Unauthorized));
+    }
+
+    #[test]
+    fn not_owner() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(canister.freezeAccount(john()), Err(TxError::Unauthorized));
+        assert_eq!(canister.unfreezeAccount(john()), Err(TxError::Unauthorized));
+    }
+}
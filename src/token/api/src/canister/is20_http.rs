@@ -0,0 +1,381 @@
+//! HTTP interface of the token, served by the `http_request` query through the HTTP gateway, so
+//! the token can be displayed by the block explorers and the link previews without an agent.
+//!
+//! * `/metrics` - canister metrics in the Prometheus text format.
+//! * `/info` - token metadata in JSON.
+//! * `/logo` - token logo image, or a redirect if the logo is a URL.
+//! * `/holders?limit=<n>` - largest holders in JSON.
+//! * `/tx/<id>` - transaction in JSON.
+
+use std::fmt::{Display, Write};
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use serde_json::{json, Value};
+
+use crate::types::{HttpRequest, HttpResponse, TxId, TxRecord};
+
+use super::{TokenCanisterAPI, MAX_HOLDERS_QUERY_LEN};
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const DEFAULT_HOLDERS_LIMIT: usize = 100;
+const DEFAULT_LOGO_TYPE: &str = "image/png";
+
+pub(crate) fn http_request(canister: &impl TokenCanisterAPI, request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::not_found();
+    }
+
+    let (path, query) = request
+        .url
+        .split_once('?')
+        .unwrap_or((request.url.as_str(), ""));
+    match path {
+        "/metrics" => metrics(canister),
+        "/info" => info(canister),
+        "/logo" => logo(canister),
+        "/holders" => holders(canister, query),
+        _ => match path.strip_prefix("/tx/").map(str::parse::<TxId>) {
+            Some(Ok(id)) => transaction(canister, id),
+            _ => HttpResponse::not_found(),
+        },
+    }
+}
+
+fn info(canister: &impl TokenCanisterAPI) -> HttpResponse {
+    let state = canister.state();
+    let state = state.borrow();
+    let stats = &state.stats;
+    json_response(json!({
+        "name": stats.name,
+        "symbol": stats.symbol,
+        "decimals": stats.decimals,
+        "totalSupply": stats.total_supply.amount.to_string(),
+        "fee": stats.fee.amount.to_string(),
+        "owner": stats.owner.to_text(),
+        "holders": state.balances.len(),
+        "transactions": state.ledger.len(),
+        "deployTime": stats.deploy_time,
+        "logo": "/logo",
+    }))
+}
+
+/// Serves the logo stored as a base64 encoded image, e.g. `data:image/png;base64,...`. Logos set
+/// as URLs are redirected to.
+fn logo(canister: &impl TokenCanisterAPI) -> HttpResponse {
+    let logo = canister.state().borrow().stats.logo.clone();
+    if logo.starts_with("https://") || logo.starts_with("http://") {
+        return HttpResponse {
+            status_code: 302,
+            headers: vec![("Location".to_string(), logo)],
+            body: vec![],
+        };
+    }
+
+    let (header, data) = match logo.split_once("base64,") {
+        Some(parts) => parts,
+        None => return HttpResponse::not_found(),
+    };
+    let content_type = header
+        .strip_prefix("data:")
+        .map(|mime| mime.trim_end_matches(';'))
+        .filter(|mime| !mime.is_empty())
+        .unwrap_or(DEFAULT_LOGO_TYPE);
+    match base64::decode(data.trim()) {
+        Ok(image) => response(content_type, image),
+        Err(_) => HttpResponse::not_found(),
+    }
+}
+
+fn holders(canister: &impl TokenCanisterAPI, query: &str) -> HttpResponse {
+    let limit = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("limit="))
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_HOLDERS_LIMIT)
+        .min(MAX_HOLDERS_QUERY_LEN);
+    let holders = canister
+        .state()
+        .borrow()
+        .balances
+        .get_holders(None, limit)
+        .into_iter()
+        .map(|(holder, balance)| {
+            json!({
+                "account": holder.to_text(),
+                "balance": balance.amount.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json_response(Value::Array(holders))
+}
+
+fn transaction(canister: &impl TokenCanisterAPI, id: TxId) -> HttpResponse {
+    match canister.state().borrow().ledger.get(id) {
+        Some(tx) => json_response(tx_json(&tx)),
+        None => HttpResponse::not_found(),
+    }
+}
+
+fn tx_json(tx: &TxRecord) -> Value {
+    json!({
+        "index": tx.index,
+        "caller": tx.caller.as_ref().map(Principal::to_text),
+        "from": tx.from.to_text(),
+        "to": tx.to.to_text(),
+        // Amounts are given as strings, as they may not fit into the JSON numbers.
+        "amount": tx.amount.amount.to_string(),
+        "fee": tx.fee.amount.to_string(),
+        "timestamp": tx.timestamp,
+        "status": format!("{:?}", tx.status),
+        "operation": format!("{:?}", tx.operation),
+    })
+}
+
+fn json_response(value: Value) -> HttpResponse {
+    response("application/json", value.to_string().into_bytes())
+}
+
+fn response(content_type: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        body,
+    }
+}
+
+fn metrics(canister: &impl TokenCanisterAPI) -> HttpResponse {
+    let state = canister.state();
+    let state = state.borrow();
+    let mut body = String::new();
+
+    write_metric(
+        &mut body,
+        "is20_cycles_balance",
+        "gauge",
+        "Cycles balance of the canister.",
+        ic::balance(),
+    );
+    write_metric(
+        &mut body,
+        "is20_heap_memory_bytes",
+        "gauge",
+        "Size of the canister heap memory.",
+        heap_memory_size(),
+    );
+    write_metric(
+        &mut body,
+        "is20_stable_memory_bytes",
+        "gauge",
+        "Size of the canister stable memory.",
+        stable_memory_size(),
+    );
+    write_metric(
+        &mut body,
+        "is20_transactions_total",
+        "counter",
+        "Number of transactions in the history.",
+        state.ledger.len(),
+    );
+    write_metric(
+        &mut body,
+        "is20_holders",
+        "gauge",
+        "Number of accounts with non-zero balance.",
+        state.balances.len(),
+    );
+    write_metric(
+        &mut body,
+        "is20_total_supply",
+        "gauge",
+        "Total supply of the token, in the smallest units.",
+        state.stats.total_supply.amount,
+    );
+    write_metric(
+        &mut body,
+        "is20_auctions_total",
+        "counter",
+        "Number of the cycle auctions held.",
+        state.auction_stats.auctions_count,
+    );
+    write_metric(
+        &mut body,
+        "is20_auction_cycles_collected_total",
+        "counter",
+        "Cycles collected by all the auctions.",
+        state.auction_stats.total_cycles_collected,
+    );
+    write_metric(
+        &mut body,
+        "is20_auction_tokens_distributed_total",
+        "counter",
+        "Tokens distributed by all the auctions, in the smallest units.",
+        state.auction_stats.total_tokens_distributed.amount,
+    );
+    write_metric(
+        &mut body,
+        "is20_auction_cycles_bid",
+        "gauge",
+        "Cycles bid for the upcoming auction.",
+        state.bidding_state.cycles_since_auction,
+    );
+    write_metric(
+        &mut body,
+        "is20_auction_fee_ratio",
+        "gauge",
+        "Share of the fees going to the upcoming auction.",
+        state.bidding_state.fee_ratio,
+    );
+
+    response("text/plain; version=0.0.4", body.into_bytes())
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    writeln!(out, "# HELP {name} {help}").expect("writing to a string never fails");
+    writeln!(out, "# TYPE {name} {kind}").expect("writing to a string never fails");
+    writeln!(out, "{name} {value}").expect("writing to a string never fails");
+}
+
+/// Size of the heap memory in bytes. Always zero outside of the canister.
+pub(crate) fn heap_memory_size() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+/// Size of the stable memory in bytes. Always zero outside of the canister.
+pub(crate) fn stable_memory_size() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob};
+    use ic_helpers::tokens::Tokens128;
+
+    use crate::mock::*;
+
+    use super::*;
+
+    fn get(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn prometheus_metrics() {
+        let (context, canister) = test_context();
+        context.update_balance(1_000_000);
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+
+        let response = canister.http_request(get("/metrics?format=prometheus"));
+        assert_eq!(response.status_code, 200);
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.contains("# TYPE is20_transactions_total counter\n"));
+        assert!(body.contains("\nis20_transactions_total 2\n"));
+        assert!(body.contains("\nis20_holders 2\n"));
+        assert!(body.contains("\nis20_total_supply 1000\n"));
+        assert!(body.contains("\nis20_auctions_total 0\n"));
+    }
+
+    fn json(response: HttpResponse) -> Value {
+        assert_eq!(response.status_code, 200);
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn token_info() {
+        let (_, canister) = test_context();
+        canister.setName("Token".to_string()).unwrap();
+
+        let info = json(canister.http_request(get("/info")));
+        assert_eq!(info["name"], "Token");
+        assert_eq!(info["totalSupply"], "1000");
+        assert_eq!(info["owner"], alice().to_text());
+        assert_eq!(info["holders"], 1);
+    }
+
+    #[test]
+    fn logo_image() {
+        let (_, canister) = test_context();
+        assert_eq!(canister.http_request(get("/logo")).status_code, 404);
+
+        canister
+            .setLogo("data:image/svg+xml;base64,PHN2Zy8+".to_string())
+            .unwrap();
+        let response = canister.http_request(get("/logo"));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.headers,
+            vec![("Content-Type".to_string(), "image/svg+xml".to_string())]
+        );
+        assert_eq!(response.body, b"<svg/>");
+
+        canister
+            .setLogo("https://example.com/logo.png".to_string())
+            .unwrap();
+        let response = canister.http_request(get("/logo"));
+        assert_eq!(response.status_code, 302);
+        assert_eq!(
+            response.headers,
+            vec![(
+                "Location".to_string(),
+                "https://example.com/logo.png".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn holders_and_transactions() {
+        let (_, canister) = test_context();
+        canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+
+        let holders = json(canister.http_request(get("/holders?limit=1")));
+        assert_eq!(
+            holders,
+            json!([{ "account": alice().to_text(), "balance": "900" }])
+        );
+
+        let tx = json(canister.http_request(get("/tx/1")));
+        assert_eq!(tx["from"], alice().to_text());
+        assert_eq!(tx["to"], bob().to_text());
+        assert_eq!(tx["amount"], "100");
+        assert_eq!(tx["operation"], "Transfer");
+
+        assert_eq!(canister.http_request(get("/tx/2")).status_code, 404);
+        assert_eq!(canister.http_request(get("/tx/abc")).status_code, 404);
+    }
+
+    #[test]
+    fn unknown_path() {
+        let (_, canister) = test_context();
+        assert_eq!(canister.http_request(get("/unknown")).status_code, 404);
+
+        let mut request = get("/metrics");
+        request.method = "POST".to_string();
+        assert_eq!(canister.http_request(request).status_code, 404);
+    }
+}
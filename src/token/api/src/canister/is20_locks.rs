@@ -0,0 +1,91 @@
+//! Voluntary balance locks. A holder can lock a part of their balance until a timestamp, so other
+//! canisters (e.g. governance or staking) can rely on the tokens not being moved. The locked
+//! tokens stay on the holder balance, but cannot be transferred or burned until the lock expires.
+
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+
+use crate::state::BalanceLock;
+use crate::types::{Timestamp, TxError};
+
+use super::TokenCanisterAPI;
+
+/// Locks the `amount` of the caller balance until the `until` timestamp. Only the unlocked part of
+/// the balance can be locked.
+pub(crate) fn lock_balance(
+    canister: &impl TokenCanisterAPI,
+    amount: Tokens128,
+    until: Timestamp,
+) -> Result<(), TxError> {
+    let caller = ic::caller();
+    if amount.is_zero() || until <= ic::time() {
+        return Err(TxError::InvalidLock);
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    if state.balances.transferable(&caller) < amount {
+        return Err(TxError::InsufficientBalance);
+    }
+
+    state.balances.lock(caller, BalanceLock { amount, until });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    const LOCK_PERIOD: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn locked_tokens_cannot_be_transferred() {
+        let (context, canister) = test_context();
+        let until = ic::time() + LOCK_PERIOD;
+        canister.lock(Tokens128::from(600), until).unwrap();
+        assert_eq!(
+            canister.getLocks(alice()),
+            vec![BalanceLock {
+                amount: Tokens128::from(600),
+                until
+            }]
+        );
+
+        assert_eq!(
+            canister.transfer(bob(), Tokens128::from(500), None),
+            Err(TxError::InsufficientBalance)
+        );
+        canister
+            .transfer(bob(), Tokens128::from(400), None)
+            .unwrap();
+        assert_eq!(
+            canister.lock(Tokens128::from(1), until),
+            Err(TxError::InsufficientBalance)
+        );
+
+        context.add_time(LOCK_PERIOD);
+        assert!(canister.getLocks(alice()).is_empty());
+        canister
+            .transfer(bob(), Tokens128::from(600), None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(1000));
+    }
+
+    #[test]
+    fn invalid_lock() {
+        let (_, canister) = test_context();
+        let now = ic::time();
+        assert_eq!(
+            canister.lock(Tokens128::ZERO, now + LOCK_PERIOD),
+            Err(TxError::InvalidLock)
+        );
+        assert_eq!(
+            canister.lock(Tokens128::from(10), now),
+            Err(TxError::InvalidLock)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! API methods used to move the token state between canisters or data models during migrations.
+
+use candid::{Encode, Principal};
+
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::types::{AllowanceEntry, AllowancesExport, LedgerChunk, TxError, TxId};
+
+use super::TokenCanisterAPI;
+
+pub(crate) const MAX_ALLOWANCES_EXPORT_LEN: usize = 1000;
+/// Limit of the encoded records size in one ledger chunk, well below the response size limit.
+pub(crate) const MAX_LEDGER_CHUNK_BYTES: u64 = 1024 * 1024;
+
+/// Returns the allowances in the storage order, starting right after the `cursor`.
+pub(crate) fn export_allowances(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    cursor: Option<(Principal, Principal)>,
+) -> AllowancesExport {
+    let state = canister.state();
+    let state = state.borrow();
+
+    // One entry over the limit is requested to know if there is a next page.
+    let mut entries = state
+        .allowances
+        .entries_after(cursor, MAX_ALLOWANCES_EXPORT_LEN + 1);
+
+    let next = if entries.len() > MAX_ALLOWANCES_EXPORT_LEN {
+        entries.truncate(MAX_ALLOWANCES_EXPORT_LEN);
+        entries.last().map(|entry| (entry.owner, entry.spender))
+    } else {
+        None
+    };
+
+    AllowancesExport { entries, next }
+}
+
+/// Returns the transactions starting from the `offset` id, while their total candid encoded size
+/// is not over `max_bytes` (capped by [MAX_LEDGER_CHUNK_BYTES]). At least one transaction is
+/// returned if there are any, so the export always progresses.
+pub(crate) fn export_ledger(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    offset: TxId,
+    max_bytes: u64,
+) -> LedgerChunk {
+    let state = canister.state();
+    let state = state.borrow();
+    let ledger = &state.ledger;
+    let max_bytes = max_bytes.min(MAX_LEDGER_CHUNK_BYTES);
+
+    let mut records = Vec::new();
+    let mut size = 0;
+    for id in offset..ledger.len() {
+        let record = match ledger.get(id) {
+            Some(record) => record,
+            None => continue,
+        };
+
+        // The size of a single encoded record includes the type table, so it's an upper bound of
+        // the record size in the encoded chunk.
+        size += Encode!(&record)
+            .expect("failed to encode transaction record")
+            .len() as u64;
+        if size > max_bytes && !records.is_empty() {
+            return LedgerChunk {
+                records,
+                next: Some(id),
+            };
+        }
+
+        records.push(record);
+    }
+
+    LedgerChunk {
+        records,
+        next: None,
+    }
+}
+
+/// Sets the given allowances, overwriting the existing ones for the same `(owner, spender)` pair.
+/// Zero amount removes the allowance. No fees are charged and no transactions are recorded.
+///
+/// The import is only allowed right after the token creation: once any transaction is made, the
+/// owner could use the imported allowances to take the holders' tokens.
+pub(crate) fn import_allowances(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    entries: Vec<AllowanceEntry>,
+) -> Result<(), TxError> {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    if state.allowances_import != Some(state.ledger.len()) {
+        return Err(TxError::ImportClosed);
+    }
+
+    for AllowanceEntry {
+        owner,
+        spender,
+        amount,
+    } in entries
+    {
+        state.allowances.set(owner, spender, amount);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john, xtc};
+    use ic_helpers::tokens::Tokens128;
+
+    use crate::mock::*;
+
+    use super::*;
+
+    fn entry(owner: Principal, spender: Principal, amount: u128) -> AllowanceEntry {
+        AllowanceEntry {
+            owner,
+            spender,
+            amount: Tokens128::from(amount),
+        }
+    }
+
+    #[test]
+    fn import_and_export() {
+        let (_, canister) = test_context();
+        let entries = vec![
+            entry(alice(), bob(), 100),
+            entry(bob(), john(), 200),
+            entry(xtc(), alice(), 300),
+        ];
+        canister.importAllowances(entries.clone()).unwrap();
+
+        assert_eq!(canister.allowance(alice(), bob()), Tokens128::from(100));
+        assert_eq!(canister.allowance(bob(), john()), Tokens128::from(200));
+        assert_eq!(canister.getAllowanceSize(), 3);
+
+        let export = canister.exportAllowances(None).unwrap();
+        assert_eq!(export.entries.len(), 3);
+        assert_eq!(export.next, None);
+        for entry in entries {
+            assert!(export.entries.contains(&entry));
+        }
+    }
+
+    #[test]
+    fn import_zero_removes_allowance() {
+        let (_, canister) = test_context();
+        canister
+            .importAllowances(vec![entry(alice(), bob(), 100)])
+            .unwrap();
+        canister
+            .importAllowances(vec![entry(alice(), bob(), 0)])
+            .unwrap();
+
+        assert_eq!(canister.allowance(alice(), bob()), Tokens128::ZERO);
+        assert_eq!(canister.getAllowanceSize(), 0);
+    }
+
+    #[test]
+    fn import_closed_after_transaction() {
+        let (_, canister) = test_context();
+        canister.transfer(bob(), Tokens128::from(1), None).unwrap();
+        assert_eq!(
+            canister.importAllowances(vec![entry(bob(), alice(), 100)]),
+            Err(TxError::ImportClosed)
+        );
+        assert_eq!(canister.allowance(bob(), alice()), Tokens128::ZERO);
+    }
+
+    #[test]
+    fn export_with_cursor() {
+        let (_, canister) = test_context();
+        let entries = (0..MAX_ALLOWANCES_EXPORT_LEN as u32 + 10)
+            .map(|i| entry(alice(), Principal::from_slice(&i.to_be_bytes()), 1))
+            .collect();
+        canister.importAllowances(entries).unwrap();
+
+        let first = canister.exportAllowances(None).unwrap();
+        assert_eq!(first.entries.len(), MAX_ALLOWANCES_EXPORT_LEN);
+        assert!(first.next.is_some());
+
+        let second = canister.exportAllowances(first.next).unwrap();
+        assert_eq!(second.entries.len(), 10);
+        assert_eq!(second.next, None);
+        assert!(!second.entries.contains(first.entries.last().unwrap()));
+    }
+
+    #[test]
+    fn export_ledger_in_chunks() {
+        let (_, canister) = test_context();
+        for _ in 0..9 {
+            canister.transfer(bob(), Tokens128::from(1), None).unwrap();
+        }
+
+        // The mint record is a bit larger than the transfers of 1 token.
+        let mint_size = Encode!(&canister.getTransaction(0)).unwrap().len() as u64;
+        let record_size = Encode!(&canister.getTransaction(1)).unwrap().len() as u64;
+        let first = canister
+            .exportLedger(0, mint_size + record_size * 3)
+            .unwrap();
+        assert_eq!(first.records.len(), 4);
+        assert_eq!(first.next, Some(4));
+
+        let second = canister.exportLedger(4, record_size * 100).unwrap();
+        let ids = second.records.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, (4..10).collect::<Vec<_>>());
+        assert_eq!(second.next, None);
+
+        // A record is returned even if it doesn't fit.
+        let small = canister.exportLedger(0, 1).unwrap();
+        assert_eq!(small.records.len(), 1);
+        assert_eq!(small.next, Some(1));
+    }
+
+    #[test]
+    fn not_owner() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.importAllowances(vec![entry(bob(), john(), 100)]),
+            Err(TxError::Unauthorized)
+        );
+        assert!(matches!(
+            canister.exportAllowances(None),
+            Err(TxError::Unauthorized)
+        ));
+        assert!(matches!(
+            canister.exportLedger(0, 1000),
+            Err(TxError::Unauthorized)
+        ));
+    }
+}
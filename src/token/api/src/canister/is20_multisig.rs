@@ -0,0 +1,441 @@
+//! Threshold multisig for the admin operations. Once it's enabled, the fee, the owner, the
+//! minters and the minting by the owner can only be changed with proposals approved by the
+//! `threshold` number of the signers, and the owner gets `MultisigRequired` error from the methods
+//! changing them. The canister upgrades, e.g. by the factory, also need an `ApproveUpgrade`
+//! proposal. The other owner-only methods stay available to the owner. While the timelock is
+//! enabled, the parameters are changed with the `QueueUpdate` proposals instead.
+
+use ic_canister::ic_kit::ic;
+
+use crate::canister::erc20_transactions::mint;
+use crate::canister::is20_ownership::{notify_guardian, record_owner_change, GuardianNotification};
+use crate::canister::is20_timelock::{dequeue, enqueue, validate_update};
+use crate::principal::{CheckedPrincipal, Owner, Signer};
+use crate::state::{AdminOp, AdminProposal, CanisterState, MultisigConfig};
+use crate::types::{
+    valid_basis_points, valid_fee_bounds, valid_fee_distribution, AdminEvent, TxError,
+};
+
+use super::TokenCanisterAPI;
+
+/// Enables the multisig. After that the config can only be changed with a `SetMultisig` proposal.
+pub(crate) fn enable_multisig(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Owner>,
+    config: MultisigConfig,
+) -> Result<(), TxError> {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    state.multisig.check_disabled()?;
+    if !config.is_valid() {
+        return Err(TxError::InvalidMultisigConfig);
+    }
+
+    state
+        .ledger
+        .record_admin_event(caller.inner(), AdminEvent::MultisigEnabled(config.clone()));
+    state.multisig.config = Some(config);
+    Ok(())
+}
+
+/// Outcome of a proposal approval.
+enum Execution {
+    /// The proposal needs more approvals.
+    Pending,
+    /// The proposal was executed. The owner change has to be sent to the guardian.
+    Executed(Option<GuardianNotification>),
+}
+
+/// Creates a proposal approved by the proposer and returns its id. If the threshold is one, the
+/// operation is executed right away.
+pub(crate) async fn propose_admin_op(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Signer>,
+    op: AdminOp,
+) -> Result<u64, TxError> {
+    match &op {
+        AdminOp::SetMultisig(Some(config)) if !config.is_valid() => {
+            return Err(TxError::InvalidMultisigConfig)
+        }
+        AdminOp::SetFeeMode(mode) if !mode.is_valid() => return Err(TxError::InvalidFeeConfig),
+        AdminOp::SetFeeBounds { min, max } if !valid_fee_bounds(*min, *max) => {
+            return Err(TxError::InvalidFeeConfig)
+        }
+        AdminOp::SetFeeBurnRatio(ratio) if !valid_basis_points(*ratio) => {
+            return Err(TxError::InvalidFeeConfig)
+        }
+        AdminOp::SetFeeDistribution(distribution) if !valid_fee_distribution(distribution) => {
+            return Err(TxError::InvalidFeeConfig)
+        }
+        AdminOp::QueueUpdate(update) => validate_update(update)?,
+        _ => {}
+    }
+
+    let (id, execution) = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        if op.is_timelocked() {
+            state.timelock.check_disabled()?;
+        }
+
+        let id = state.multisig.next_proposal_id;
+        state.multisig.next_proposal_id += 1;
+        state.multisig.proposals.insert(
+            id,
+            AdminProposal {
+                id,
+                op,
+                proposer: caller.inner(),
+                created_at: ic::time(),
+                approvals: [caller.inner()].into_iter().collect(),
+            },
+        );
+
+        (id, execute_if_approved(&mut state, id)?)
+    };
+
+    if let Execution::Executed(notification) = execution {
+        notify_guardian(notification).await;
+    }
+    Ok(id)
+}
+
+/// Adds the caller approval to the proposal. Returns `true` if the proposal reached the threshold
+/// and was executed.
+pub(crate) async fn approve_admin_op(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Signer>,
+    id: u64,
+) -> Result<bool, TxError> {
+    let execution = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+
+        let proposal = state
+            .multisig
+            .proposals
+            .get_mut(&id)
+            .ok_or(TxError::ProposalDoesNotExist)?;
+        if !proposal.approvals.insert(caller.inner()) {
+            return Err(TxError::AlreadyActioned);
+        }
+
+        execute_if_approved(&mut state, id)?
+    };
+
+    match execution {
+        Execution::Executed(notification) => {
+            notify_guardian(notification).await;
+            Ok(true)
+        }
+        Execution::Pending => Ok(false),
+    }
+}
+
+/// Executes the proposal if it has enough approvals. The proposal is removed even if the
+/// operation fails, as the same failure would happen on the next approval. Only the executed
+/// operations are recorded in the event log.
+fn execute_if_approved(state: &mut CanisterState, id: u64) -> Result<Execution, TxError> {
+    let threshold = match &state.multisig.config {
+        Some(config) => config.threshold as usize,
+        None => return Err(TxError::ProposalDoesNotExist),
+    };
+
+    match state.multisig.proposals.get(&id) {
+        Some(proposal) if proposal.approvals.len() >= threshold => {}
+        Some(_) => return Ok(Execution::Pending),
+        None => return Err(TxError::ProposalDoesNotExist),
+    }
+
+    let proposal = state
+        .multisig
+        .proposals
+        .remove(&id)
+        .expect("proposal existence checked above");
+    let notification = apply_admin_op(state, proposal.op.clone())?;
+    state
+        .ledger
+        .record_admin_event(proposal.proposer, AdminEvent::MultisigOp(proposal.op));
+
+    Ok(Execution::Executed(notification))
+}
+
+fn apply_admin_op(
+    state: &mut CanisterState,
+    op: AdminOp,
+) -> Result<Option<GuardianNotification>, TxError> {
+    // The timelock might be enabled by a queued update after the proposal was made.
+    if op.is_timelocked() {
+        state.timelock.check_disabled()?;
+    }
+
+    match op {
+        AdminOp::SetFee(fee) => state.stats.fee = fee,
+        AdminOp::SetFeeTo(fee_to) => state.stats.fee_to = fee_to,
+        AdminOp::SetFeeMode(mode) => state.stats.fee_mode = mode,
+        AdminOp::SetFeeBounds { min, max } => {
+            state.stats.fee_min = min;
+            state.stats.fee_max = max;
+        }
+        AdminOp::SetFeeBurnRatio(ratio) => state.stats.fee_burn_ratio = ratio,
+        AdminOp::SetFeeDistribution(distribution) => state.stats.fee_distribution = distribution,
+        AdminOp::SetOwner(owner) => return Ok(record_owner_change(state, owner)),
+        AdminOp::Mint { to, amount } => {
+            let owner = state.stats.owner;
+            mint(state, owner, to, amount)?;
+        }
+        AdminOp::AddMinter(minter) => {
+            state.stats.minters.insert(minter);
+        }
+        AdminOp::RemoveMinter(minter) => {
+            state.stats.minters.remove(&minter);
+        }
+        AdminOp::QueueUpdate(update) => {
+            enqueue(state, update);
+        }
+        AdminOp::CancelUpdate(id) => dequeue(state, id)?,
+        AdminOp::SetMultisig(config) => {
+            // The pending proposals were approved by the previous signers, so they are dropped.
+            state.multisig.config = config;
+            state.multisig.proposals.clear();
+        }
+        AdminOp::ApproveUpgrade => state.multisig.upgrade_approved = true,
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john, xtc};
+    use ic_helpers::tokens::Tokens128;
+
+    use crate::canister::is20_ownership::OWNER_CHANGE_DELAY;
+    use crate::mock::*;
+
+    use super::*;
+
+    fn config(signers: &[Principal], threshold: u32) -> MultisigConfig {
+        MultisigConfig {
+            signers: signers.iter().copied().collect(),
+            threshold,
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_ops_require_proposal() {
+        let (_, canister) = test_context();
+        canister
+            .enableMultisig(config(&[alice(), bob(), john()], 2))
+            .unwrap();
+
+        assert_eq!(
+            canister.setFee(Tokens128::from(10)),
+            Err(TxError::MultisigRequired)
+        );
+        assert_eq!(
+            canister.mint(bob(), Tokens128::from(10)),
+            Err(TxError::MultisigRequired)
+        );
+        assert_eq!(canister.removeMinter(bob()), Err(TxError::MultisigRequired));
+        assert_eq!(
+            canister.setOwner(bob()).await,
+            Err(TxError::MultisigRequired)
+        );
+        assert_eq!(
+            canister.enableMultisig(config(&[alice()], 1)),
+            Err(TxError::MultisigRequired)
+        );
+    }
+
+    #[test]
+    fn owner_methods_without_proposal_reachable() {
+        let (_, canister) = test_context();
+        canister
+            .enableMultisig(config(&[alice(), bob(), john()], 2))
+            .unwrap();
+
+        assert_eq!(canister.setName("Renamed".into()), Ok(()));
+        assert_eq!(canister.setLogo("logo".into()), Ok(()));
+        assert_eq!(canister.setMintingAccount(Some(alice())), Ok(()));
+        assert_eq!(canister.addReservedAccount(xtc()), Ok(()));
+        assert_eq!(canister.importAllowances(vec![]), Ok(()));
+        assert_eq!(canister.addAdmin(bob()), Ok(()));
+        assert_eq!(canister.addPauser(bob()), Ok(()));
+        assert!(canister.freezeAccount(xtc()).is_ok());
+        assert_eq!(canister.setGuardian(Some(john())), Ok(()));
+        assert_eq!(canister.setMinCycles(1), Ok(()));
+        assert_eq!(canister.setAuctionPeriod(1000), Ok(()));
+        assert_eq!(canister.name(), "Renamed");
+    }
+
+    #[tokio::test]
+    async fn upgrade_requires_approval() {
+        let (context, canister) = test_context();
+        canister
+            .enableMultisig(config(&[alice(), bob(), john()], 2))
+            .unwrap();
+        assert_eq!(
+            canister
+                .state()
+                .borrow_mut()
+                .multisig
+                .take_upgrade_approval(),
+            Err(TxError::MultisigRequired)
+        );
+
+        let id = canister
+            .proposeAdminOp(AdminOp::ApproveUpgrade)
+            .await
+            .unwrap();
+        context.update_caller(bob());
+        assert_eq!(canister.approveAdminOp(id).await, Ok(true));
+
+        let mut state = canister.state().borrow_mut();
+        assert_eq!(state.multisig.take_upgrade_approval(), Ok(()));
+        assert_eq!(
+            state.multisig.take_upgrade_approval(),
+            Err(TxError::MultisigRequired)
+        );
+    }
+
+    #[tokio::test]
+    async fn proposal_executed_at_threshold() {
+        let (context, canister) = test_context();
+        canister
+            .enableMultisig(config(&[alice(), bob(), john()], 2))
+            .unwrap();
+
+        let id = canister
+            .proposeAdminOp(AdminOp::Mint {
+                to: xtc(),
+                amount: Tokens128::from(100),
+            })
+            .await
+            .unwrap();
+        assert_eq!(canister.getAdminProposals().len(), 1);
+        assert_eq!(canister.balanceOf(xtc()), Tokens128::ZERO);
+        assert_eq!(
+            canister.approveAdminOp(id).await,
+            Err(TxError::AlreadyActioned)
+        );
+
+        context.update_caller(bob());
+        assert_eq!(canister.approveAdminOp(id).await, Ok(true));
+        assert_eq!(canister.balanceOf(xtc()), Tokens128::from(100));
+        assert!(canister.getAdminProposals().is_empty());
+        assert_eq!(
+            canister.approveAdminOp(id).await,
+            Err(TxError::ProposalDoesNotExist)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_op_not_recorded() {
+        let (_, canister) = test_context();
+        canister.enableMultisig(config(&[alice()], 1)).unwrap();
+        let events = canister.getEvents(0, 100).len();
+
+        assert_eq!(
+            canister
+                .proposeAdminOp(AdminOp::Mint {
+                    to: xtc(),
+                    amount: Tokens128::from(u128::MAX),
+                })
+                .await,
+            Err(TxError::AmountOverflow)
+        );
+        assert!(canister.getAdminProposals().is_empty());
+        assert_eq!(canister.getEvents(0, 100).len(), events);
+    }
+
+    #[tokio::test]
+    async fn owner_change_delayed() {
+        let (context, canister) = test_context();
+        canister.enableMultisig(config(&[alice()], 1)).unwrap();
+        canister
+            .proposeAdminOp(AdminOp::SetOwner(bob()))
+            .await
+            .unwrap();
+        assert_eq!(canister.owner(), alice());
+        assert_eq!(canister.getPendingOwnerChange().unwrap().new_owner, bob());
+
+        context.add_time(OWNER_CHANGE_DELAY);
+        context.update_caller(bob());
+        canister.applyOwnerChange().await.unwrap();
+        assert_eq!(canister.owner(), bob());
+    }
+
+    #[tokio::test]
+    async fn change_signers() {
+        let (context, canister) = test_context();
+        canister
+            .enableMultisig(config(&[alice(), bob()], 2))
+            .unwrap();
+        let fee_id = canister
+            .proposeAdminOp(AdminOp::SetFee(Tokens128::from(10)))
+            .await
+            .unwrap();
+        let id = canister
+            .proposeAdminOp(AdminOp::SetMultisig(Some(config(&[bob(), john()], 1))))
+            .await
+            .unwrap();
+
+        context.update_caller(bob());
+        assert_eq!(canister.approveAdminOp(id).await, Ok(true));
+        assert_eq!(canister.getMultisig(), Some(config(&[bob(), john()], 1)));
+        assert_eq!(
+            canister.approveAdminOp(fee_id).await,
+            Err(TxError::ProposalDoesNotExist)
+        );
+
+        context.update_caller(alice());
+        assert_eq!(
+            canister
+                .proposeAdminOp(AdminOp::SetFee(Tokens128::from(10)))
+                .await,
+            Err(TxError::Unauthorized)
+        );
+
+        context.update_caller(john());
+        canister
+            .proposeAdminOp(AdminOp::SetMultisig(None))
+            .await
+            .unwrap();
+        assert_eq!(canister.getMultisig(), None);
+
+        context.update_caller(alice());
+        canister.setFee(Tokens128::from(10)).unwrap();
+    }
+
+    #[test]
+    fn invalid_config() {
+        let (_, canister) = test_context();
+        assert_eq!(
+            canister.enableMultisig(config(&[alice(), bob()], 3)),
+            Err(TxError::InvalidMultisigConfig)
+        );
+        assert_eq!(
+            canister.enableMultisig(config(&[alice()], 0)),
+            Err(TxError::InvalidMultisigConfig)
+        );
+    }
+
+    #[tokio::test]
+    async fn not_signer() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister
+                .proposeAdminOp(AdminOp::SetFee(Tokens128::from(10)))
+                .await,
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(
+            canister.enableMultisig(config(&[bob()], 1)),
+            Err(TxError::Unauthorized)
+        );
+    }
+}
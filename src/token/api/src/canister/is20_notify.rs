@@ -1,14 +1,36 @@
 //! API methods of IS20 standard related to transaction notification mechanism.
+//!
+//! Notifications that fail to be delivered are put into the retry queue. They are retried with
+//! exponentially growing delays, until they are delivered, consumed or fail
+//! `MAX_NOTIFICATION_ATTEMPTS` times.
 
 use candid::Principal;
-use ic_canister::virtual_canister_notify;
+use ic_canister::ic_kit::ic;
+use ic_canister::{virtual_canister_call, virtual_canister_notify};
 use ic_helpers::tokens::Tokens128;
 
+use crate::canister::erc20_transactions::transfer;
 use crate::principal::{CheckedPrincipal, WithRecipient};
-use crate::types::{TxError, TxId, TxReceipt};
+use crate::receiver::{
+    declares_on_received, is_canister, CANDID_INTERFACE_METHOD, ON_APPROVAL_METHOD,
+    ON_RECEIVED_METHOD, ON_TRANSFER_METHOD_PREFIX,
+};
+use crate::types::{
+    ApprovalCall, FailedNotification, Timestamp, TransactionCall, TxError, TxId, TxReceipt,
+    TxRecord,
+};
 
 use super::TokenCanisterAPI;
 
+/// Delay before the first retry of a failed notification. Each next retry doubles the delay.
+pub const NOTIFICATION_RETRY_DELAY: Timestamp = 60 * 1_000_000_000;
+/// Failed notifications are dropped from the retry queue after this number of attempts.
+pub const MAX_NOTIFICATION_ATTEMPTS: u32 = 10;
+/// Maximum number of notifications sent by one `notifyBatch` call.
+pub const MAX_NOTIFY_BATCH_LEN: usize = 100;
+/// Maximum number of notifications retried by one run of the timer.
+const NOTIFICATION_RETRY_BATCH_LEN: usize = 50;
+
 pub(crate) async fn approve_and_notify(
     canister: &impl TokenCanisterAPI,
     caller: CheckedPrincipal<WithRecipient>,
@@ -22,24 +44,67 @@ pub(crate) async fn approve_and_notify(
         })
 }
 
+/// Approves the tokens and calls [ON_APPROVAL_METHOD] of the spender with the [ApprovalCall]
+/// holding the `payload`, so the spender gets the details it needs to use the allowance in the
+/// same call. The method is fixed, so the token cannot be made to call an arbitrary method of
+/// the spender on behalf of the user.
+pub(crate) async fn approve_and_call(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    amount: Tokens128,
+    payload: Vec<u8>,
+) -> TxReceipt {
+    let user = caller.inner();
+    let spender = caller.recipient();
+    let transaction_id = canister.approve(spender, amount)?;
+    canister
+        .state()
+        .borrow_mut()
+        .ledger
+        .notifications
+        .insert(transaction_id, Some(spender));
+
+    let call = ApprovalCall {
+        transaction_id,
+        caller: user,
+        amount,
+        payload,
+    };
+    match virtual_canister_call!(spender, ON_APPROVAL_METHOD, (call,), ()).await {
+        Ok(()) => Ok(transaction_id),
+        Err(_) => Err(TxError::ApproveSucceededButNotifyFailed {
+            tx_error: Box::new(TxError::NotificationFailed { transaction_id }),
+        }),
+    }
+}
+
+/// Marks the notification of the transaction as consumed, so it cannot be sent anymore. The
+/// notification can be consumed by its receiver, or by the transaction recipient if it was not sent
+/// yet, and by the consumers set by the owner.
+///
+/// The check and the removal are done without awaiting, so of the concurrent attempts only the
+/// first one succeeds, and the others get `AlreadyActioned`.
 pub(crate) async fn consume_notification(
     canister: &impl TokenCanisterAPI,
     transaction_id: TxId,
 ) -> TxReceipt {
+    let caller = ic::caller();
     let state = canister.state();
     let mut state = state.borrow_mut();
-    match state.ledger.notifications.get(&transaction_id) {
-        Some(Some(x)) if *x != ic_canister::ic_kit::ic::caller() => {
-            return Err(TxError::Unauthorized);
-        }
-        Some(_) => {
-            if state.ledger.notifications.remove(&transaction_id).is_none() {
-                return Err(TxError::AlreadyActioned);
-            }
-        }
-        None => return Err(TxError::NotificationDoesNotExist),
+
+    let tx = state
+        .ledger
+        .get(transaction_id)
+        .ok_or(TxError::NotificationDoesNotExist)?;
+    let addressee = match state.ledger.notifications.get(transaction_id) {
+        Some(receiver) => receiver.unwrap_or(tx.to),
+        None => return Err(TxError::AlreadyActioned),
+    };
+    if caller != addressee && !state.notification_consumers.contains(&caller) {
+        return Err(TxError::Unauthorized);
     }
 
+    state.ledger.notifications.remove(transaction_id);
     Ok(transaction_id)
 }
 
@@ -60,55 +125,271 @@ pub(crate) async fn notify(
         return Err(TxError::Unauthorized);
     }
 
-    match canister
+    {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        let notifications = &mut state.ledger.notifications;
+        match notifications.get(transaction_id) {
+            Some(Some(dest)) if dest != to => return Err(TxError::Unauthorized),
+            Some(_) => notifications.insert(transaction_id, Some(to)),
+            None => return Err(TxError::AlreadyActioned),
+        }
+    }
+
+    send_notification(canister, tx, to).await
+}
+
+/// Sends the notifications given as `(transaction_id, to)` pairs one by one, with the same checks
+/// as `notify`. Returns the receipts in the order of the notifications.
+pub(crate) async fn notify_batch(
+    canister: &impl TokenCanisterAPI,
+    notifications: Vec<(TxId, Principal)>,
+) -> Result<Vec<TxReceipt>, TxError> {
+    if notifications.len() > MAX_NOTIFY_BATCH_LEN {
+        return Err(TxError::BatchTooLarge {
+            max_len: MAX_NOTIFY_BATCH_LEN as u64,
+        });
+    }
+
+    let mut receipts = Vec::with_capacity(notifications.len());
+    for (transaction_id, to) in notifications {
+        receipts.push(notify(canister, transaction_id, to).await);
+    }
+
+    Ok(receipts)
+}
+
+/// Transfers the tokens to a canister implementing [crate::receiver::Is20Receiver] and calls its
+/// `is20_on_received` method with the `memo`. The transfer is not made if the receiver doesn't
+/// declare the method in its candid interface.
+///
+/// The receiver is set as the notification receiver of the transaction, so it can consume the
+/// notification. If the hook call fails, the transfer is still completed.
+pub(crate) async fn transfer_with_hook(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    amount: Tokens128,
+    memo: Option<Vec<u8>>,
+) -> TxReceipt {
+    let from = caller.inner();
+    let to = caller.recipient();
+    check_receiver(to).await?;
+
+    let transaction_id = transfer(canister, caller, amount, None)?;
+    canister
         .state()
         .borrow_mut()
         .ledger
         .notifications
-        .get_mut(&transaction_id)
-    {
-        Some(Some(dest)) if *dest != to => return Err(TxError::Unauthorized),
-        Some(x) => *x = Some(to),
-        None => return Err(TxError::AlreadyActioned),
-    }
+        .insert(transaction_id, Some(to));
 
-    match virtual_canister_notify!(to, "transaction_notification", (tx,), ()).await {
+    match virtual_canister_notify!(
+        to,
+        ON_RECEIVED_METHOD,
+        (from, amount, transaction_id, memo),
+        ()
+    )
+    .await
+    {
         Ok(_) => Ok(transaction_id),
         Err(_) => Err(TxError::NotificationFailed { transaction_id }),
     }
 }
 
+/// Transfers the tokens, checking first that a canister recipient implements the
+/// [crate::receiver::Is20Receiver] interface, so the tokens are not stranded in a canister that
+/// cannot use them. Transfers to the user principals are made without the check.
+pub(crate) async fn safe_transfer(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    amount: Tokens128,
+    fee_limit: Option<Tokens128>,
+) -> TxReceipt {
+    let to = caller.recipient();
+    if is_canister(&to) {
+        check_receiver(to).await?;
+    }
+
+    transfer(canister, caller, amount, fee_limit)
+}
+
+/// Checks that the receiver declares `is20_on_received` in its candid interface.
+async fn check_receiver(to: Principal) -> Result<(), TxError> {
+    let interface = virtual_canister_call!(to, CANDID_INTERFACE_METHOD, (), String).await;
+    if matches!(interface, Ok(interface) if declares_on_received(&interface)) {
+        Ok(())
+    } else {
+        Err(TxError::ReceiverNotSupported)
+    }
+}
+
+/// Transfers the tokens and calls the `method` of the receiver with the [TransactionCall] holding
+/// the `payload`, so the receiver can act on the transfer in the same call from the caller's view.
+/// The method must start with [ON_TRANSFER_METHOD_PREFIX], so the token cannot be made to call an
+/// arbitrary method of the receiver on behalf of the user.
+///
+/// The receiver is set as the notification receiver of the transaction. If the call fails, the
+/// transfer is still completed, and the failed notification is put into the retry queue, from
+/// which it's delivered as the `transaction_notification` of the transaction.
+pub(crate) async fn transfer_and_call(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    amount: Tokens128,
+    method: String,
+    payload: Vec<u8>,
+) -> TxReceipt {
+    if !method.starts_with(ON_TRANSFER_METHOD_PREFIX) {
+        return Err(TxError::ReceiverNotSupported);
+    }
+
+    let from = caller.inner();
+    let to = caller.recipient();
+    let transaction_id = transfer(canister, caller, amount, None)?;
+    canister
+        .state()
+        .borrow_mut()
+        .ledger
+        .notifications
+        .insert(transaction_id, Some(to));
+
+    let call = TransactionCall {
+        transaction_id,
+        from,
+        amount,
+        payload,
+    };
+    let result = virtual_canister_call!(to, &method, (call,), ()).await;
+    record_notification_result(canister, transaction_id, to, result.is_ok())
+}
+
+/// Retries the failed notification right away, regardless of its retry time.
+pub(crate) async fn retry_notification(
+    canister: &impl TokenCanisterAPI,
+    transaction_id: TxId,
+) -> TxReceipt {
+    let (tx, to) = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        let ledger = &mut state.ledger;
+
+        let to = ledger
+            .failed_notifications
+            .get(&transaction_id)
+            .ok_or(TxError::NotificationDoesNotExist)?
+            .to;
+
+        // The notification was consumed by the receiver after the failed attempt.
+        if !ledger.notifications.contains_key(transaction_id) {
+            ledger.failed_notifications.remove(&transaction_id);
+            return Err(TxError::AlreadyActioned);
+        }
+
+        let tx = ledger
+            .get(transaction_id)
+            .ok_or(TxError::TransactionDoesNotExist)?;
+        (tx, to)
+    };
+
+    send_notification(canister, tx, to).await
+}
+
+/// Retries up to `NOTIFICATION_RETRY_BATCH_LEN` failed notifications whose retry time has come.
+/// Returns the number of the notifications delivered. Called by the timer, see
+/// [crate::canister::run_timer_tasks].
+pub(crate) async fn retry_failed_notifications(canister: &impl TokenCanisterAPI) -> usize {
+    let now = ic::time();
+    let due = canister
+        .state()
+        .borrow()
+        .ledger
+        .failed_notifications
+        .values()
+        .filter(|notification| notification.next_retry <= now)
+        .take(NOTIFICATION_RETRY_BATCH_LEN)
+        .map(|notification| notification.transaction_id)
+        .collect::<Vec<_>>();
+
+    let mut delivered = 0;
+    for transaction_id in due {
+        if retry_notification(canister, transaction_id).await.is_ok() {
+            delivered += 1;
+        }
+    }
+
+    delivered
+}
+
+async fn send_notification(
+    canister: &impl TokenCanisterAPI,
+    tx: TxRecord,
+    to: Principal,
+) -> TxReceipt {
+    let transaction_id = tx.index;
+    let result = virtual_canister_notify!(to, "transaction_notification", (tx,), ()).await;
+    record_notification_result(canister, transaction_id, to, result.is_ok())
+}
+
+/// Removes the delivered notification from the retry queue, or schedules the next retry of the
+/// failed one, dropping it after `MAX_NOTIFICATION_ATTEMPTS` attempts.
+fn record_notification_result(
+    canister: &impl TokenCanisterAPI,
+    transaction_id: TxId,
+    to: Principal,
+    delivered: bool,
+) -> TxReceipt {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let failed_notifications = &mut state.ledger.failed_notifications;
+    if delivered {
+        failed_notifications.remove(&transaction_id);
+        return Ok(transaction_id);
+    }
+
+    let attempts = failed_notifications
+        .get(&transaction_id)
+        .map_or(0, |notification| notification.attempts)
+        + 1;
+    if attempts < MAX_NOTIFICATION_ATTEMPTS {
+        failed_notifications.insert(
+            transaction_id,
+            FailedNotification {
+                transaction_id,
+                to,
+                attempts,
+                next_retry: ic::time() + retry_delay(attempts),
+            },
+        );
+    } else {
+        failed_notifications.remove(&transaction_id);
+        ic_cdk::println!(
+            "Dropping notification of transaction {transaction_id} after {attempts} attempts"
+        );
+    }
+
+    Err(TxError::NotificationFailed { transaction_id })
+}
+
+/// Delay before the next retry after the given number of failed attempts.
+fn retry_delay(attempts: u32) -> Timestamp {
+    NOTIFICATION_RETRY_DELAY.saturating_mul(1 << attempts.saturating_sub(1).min(32))
+}
+
 #[cfg(test)]
 mod tests {
     use std::rc::Rc;
     use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-    use ic_canister::ic_kit::mock_principals::{alice, bob};
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
     use ic_canister::ic_kit::MockContext;
-    use ic_canister::{register_failing_virtual_responder, register_virtual_responder, Canister};
+    use ic_canister::{register_failing_virtual_responder, register_virtual_responder};
 
     use crate::mock::*;
-    use crate::types::{Metadata, TxRecord};
+    use crate::types::TxRecord;
 
     use super::*;
 
     fn test_canister() -> TokenCanisterMock {
-        MockContext::new().with_caller(alice()).inject();
-
-        let canister = TokenCanisterMock::init_instance();
-        canister.init(Metadata {
-            logo: "".to_string(),
-            name: "".to_string(),
-            symbol: "".to_string(),
-            decimals: 8,
-            totalSupply: Tokens128::from(1000),
-            owner: alice(),
-            fee: Tokens128::from(0),
-            feeTo: alice(),
-            isTestToken: None,
-        });
-
-        canister
+        crate::mock::test_context().1
     }
 
     #[tokio::test]
@@ -136,6 +417,71 @@ mod tests {
         assert_eq!(counter_copy.load(Ordering::Relaxed), 1);
     }
 
+    #[tokio::test]
+    async fn approve_and_call() {
+        register_virtual_responder(bob(), ON_APPROVAL_METHOD, move |(call,): (ApprovalCall,)| {
+            assert_eq!(call.caller, alice());
+            assert_eq!(call.amount, Tokens128::from(100));
+            assert_eq!(call.payload, b"buy".to_vec());
+        });
+        register_failing_virtual_responder(john(), ON_APPROVAL_METHOD, "no orders".into());
+
+        let canister = test_canister();
+        canister
+            .approveAndCall(bob(), Tokens128::from(100), b"buy".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(canister.allowance(alice(), bob()), Tokens128::from(100));
+
+        let response = canister
+            .approveAndCall(john(), Tokens128::from(50), vec![])
+            .await;
+        assert!(matches!(
+            response,
+            Err(TxError::ApproveSucceededButNotifyFailed { .. })
+        ));
+        assert_eq!(canister.allowance(alice(), john()), Tokens128::from(50));
+    }
+
+    #[tokio::test]
+    async fn notify_batch() {
+        let counter = Rc::new(AtomicU32::new(0));
+        let counter_copy = counter.clone();
+        register_virtual_responder(bob(), "transaction_notification", move |_: (TxRecord,)| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        register_virtual_responder(john(), "transaction_notification", |_: (TxRecord,)| {});
+
+        let canister = test_canister();
+        let ids = canister
+            .batchTransfer(vec![
+                (bob(), Tokens128::from(10)),
+                (john(), Tokens128::from(10)),
+            ])
+            .unwrap();
+        let receipts = canister
+            .notifyBatch(vec![(ids[0], bob()), (ids[1], john()), (10, bob())])
+            .await
+            .unwrap();
+        assert_eq!(
+            receipts,
+            vec![
+                Ok(ids[0]),
+                Ok(ids[1]),
+                Err(TxError::TransactionDoesNotExist)
+            ]
+        );
+        assert_eq!(counter_copy.load(Ordering::Relaxed), 1);
+
+        let too_large = vec![(ids[0], bob()); MAX_NOTIFY_BATCH_LEN + 1];
+        assert_eq!(
+            canister.notifyBatch(too_large).await,
+            Err(TxError::BatchTooLarge {
+                max_len: MAX_NOTIFY_BATCH_LEN as u64
+            })
+        );
+    }
+
     #[tokio::test]
     async fn notify_non_existing() {
         let canister = test_canister();
@@ -165,6 +511,99 @@ mod tests {
         assert_eq!(counter_copy.load(Ordering::Relaxed), 1);
     }
 
+    #[tokio::test]
+    async fn consume_by_addressee() {
+        let canister = test_canister();
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+
+        MockContext::new().with_caller(john()).inject();
+        assert_eq!(
+            canister.consume_notification(id).await,
+            Err(TxError::Unauthorized)
+        );
+
+        MockContext::new().with_caller(bob()).inject();
+        assert_eq!(canister.consume_notification(id).await, Ok(id));
+        assert_eq!(
+            canister.consume_notification(id).await,
+            Err(TxError::AlreadyActioned)
+        );
+        assert_eq!(
+            canister.consume_notification(10).await,
+            Err(TxError::NotificationDoesNotExist)
+        );
+    }
+
+    #[tokio::test]
+    async fn consume_by_notification_receiver() {
+        register_virtual_responder(john(), "transaction_notification", |_: (TxRecord,)| {});
+        let canister = test_canister();
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        canister.notify(id, john()).await.unwrap();
+
+        MockContext::new().with_caller(bob()).inject();
+        assert_eq!(
+            canister.consume_notification(id).await,
+            Err(TxError::Unauthorized)
+        );
+
+        MockContext::new().with_caller(john()).inject();
+        assert_eq!(canister.consume_notification(id).await, Ok(id));
+    }
+
+    #[tokio::test]
+    async fn consume_by_configured_consumer() {
+        let canister = test_canister();
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        canister.addNotificationConsumer(john()).unwrap();
+        assert_eq!(canister.getNotificationConsumers(), vec![john()]);
+
+        MockContext::new().with_caller(john()).inject();
+        assert_eq!(canister.consume_notification(id).await, Ok(id));
+        assert_eq!(
+            canister.addNotificationConsumer(john()),
+            Err(TxError::Unauthorized)
+        );
+
+        MockContext::new().with_caller(alice()).inject();
+        canister.removeNotificationConsumer(john()).unwrap();
+        assert!(canister.getNotificationConsumers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_consumption() {
+        let canister = test_canister();
+        canister.addNotificationConsumer(john()).unwrap();
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+
+        MockContext::new().with_caller(bob()).inject();
+        let (first, second) = tokio::join!(
+            canister.consume_notification(id),
+            canister.consume_notification(id)
+        );
+        assert_eq!(first, Ok(id));
+        assert_eq!(second, Err(TxError::AlreadyActioned));
+
+        MockContext::new().with_caller(john()).inject();
+        assert_eq!(
+            canister.consume_notification(id).await,
+            Err(TxError::AlreadyActioned)
+        );
+        MockContext::new().with_caller(alice()).inject();
+        assert_eq!(
+            canister.notify(id, bob()).await,
+            Err(TxError::AlreadyActioned)
+        );
+    }
+
     #[tokio::test]
     async fn notification_failure() {
         register_failing_virtual_responder(
@@ -185,6 +624,203 @@ mod tests {
 
         register_virtual_responder(bob(), "transaction_notification", move |_: (TxRecord,)| {});
         let response = canister.notify(id, bob()).await;
-        assert!(response.is_ok())
+        assert!(response.is_ok());
+        assert!(canister.getFailedNotifications().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_notification_retries() {
+        register_failing_virtual_responder(
+            bob(),
+            "transaction_notification",
+            "something's wrong".into(),
+        );
+
+        let canister = test_canister();
+        let context = MockContext::new().with_caller(alice()).inject();
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert!(canister.notify(id, bob()).await.is_err());
+
+        let failed = canister.getFailedNotifications();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].to, bob());
+        assert_eq!(failed[0].attempts, 1);
+        assert_eq!(failed[0].next_retry, ic::time() + NOTIFICATION_RETRY_DELAY);
+
+        // The retry time has not come yet.
+        assert_eq!(retry_failed_notifications(&canister).await, 0);
+        assert_eq!(canister.getFailedNotifications()[0].attempts, 1);
+
+        context.add_time(NOTIFICATION_RETRY_DELAY);
+        assert_eq!(retry_failed_notifications(&canister).await, 0);
+        let failed = canister.getFailedNotifications();
+        assert_eq!(failed[0].attempts, 2);
+        assert_eq!(
+            failed[0].next_retry,
+            ic::time() + 2 * NOTIFICATION_RETRY_DELAY
+        );
+
+        register_virtual_responder(bob(), "transaction_notification", move |_: (TxRecord,)| {});
+        assert_eq!(canister.retryNotification(id).await, Ok(id));
+        assert!(canister.getFailedNotifications().is_empty());
+        assert_eq!(
+            canister.retryNotification(id).await,
+            Err(TxError::NotificationDoesNotExist)
+        );
+    }
+
+    #[tokio::test]
+    async fn transfer_with_hook() {
+        let received = Rc::new(AtomicU32::new(0));
+        let received_copy = received.clone();
+        register_virtual_responder(bob(), CANDID_INTERFACE_METHOD, |()| {
+            format!("service : {{ {} }}", crate::receiver::RECEIVER_CANDID)
+        });
+        register_virtual_responder(
+            bob(),
+            ON_RECEIVED_METHOD,
+            move |(from, amount, _, memo): (Principal, Tokens128, TxId, Option<Vec<u8>>)| {
+                assert_eq!(from, alice());
+                assert_eq!(memo, Some(vec![1, 2, 3]));
+                received.fetch_add(amount.amount as u32, Ordering::Relaxed);
+            },
+        );
+        register_virtual_responder(john(), CANDID_INTERFACE_METHOD, |()| {
+            "service : {}".to_string()
+        });
+
+        let canister = test_canister();
+        let id = canister
+            .transferWithHook(bob(), Tokens128::from(100), Some(vec![1, 2, 3]))
+            .await
+            .unwrap();
+        assert_eq!(received_copy.load(Ordering::Relaxed), 100);
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(
+            canister.state().borrow().ledger.notifications.get(id),
+            Some(Some(bob()))
+        );
+
+        assert_eq!(
+            canister
+                .transferWithHook(john(), Tokens128::from(100), None)
+                .await,
+            Err(TxError::ReceiverNotSupported)
+        );
+        assert_eq!(canister.balanceOf(john()), Tokens128::ZERO);
+    }
+
+    const DEPOSIT: &str = "is20_on_transfer_deposit";
+
+    #[tokio::test]
+    async fn transfer_and_call() {
+        let called = Rc::new(AtomicBool::new(false));
+        let called_copy = called.clone();
+        register_virtual_responder(bob(), DEPOSIT, move |(call,): (TransactionCall,)| {
+            assert_eq!(call.from, alice());
+            assert_eq!(call.amount, Tokens128::from(100));
+            assert_eq!(call.payload, vec![42]);
+            called.store(true, Ordering::Relaxed);
+        });
+        register_failing_virtual_responder(john(), DEPOSIT, "no deposits".into());
+
+        let canister = test_canister();
+        canister
+            .transferAndCall(bob(), Tokens128::from(100), DEPOSIT.to_string(), vec![42])
+            .await
+            .unwrap();
+        assert!(called_copy.load(Ordering::Relaxed));
+        assert!(canister.getFailedNotifications().is_empty());
+
+        let response = canister
+            .transferAndCall(john(), Tokens128::from(100), DEPOSIT.to_string(), vec![])
+            .await;
+        let transaction_id = match response {
+            Err(TxError::NotificationFailed { transaction_id }) => transaction_id,
+            other => panic!("unexpected response: {other:?}"),
+        };
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(100));
+        assert_eq!(
+            canister
+                .state()
+                .borrow()
+                .ledger
+                .notifications
+                .get(transaction_id),
+            Some(Some(john()))
+        );
+        let failed = canister.getFailedNotifications();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].transaction_id, transaction_id);
+        assert_eq!(failed[0].to, john());
+        assert_eq!(failed[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn transfer_and_call_other_method() {
+        let canister = test_canister();
+        for method in ["deposit", ON_RECEIVED_METHOD, ON_APPROVAL_METHOD] {
+            assert_eq!(
+                canister
+                    .transferAndCall(bob(), Tokens128::from(100), method.to_string(), vec![])
+                    .await,
+                Err(TxError::ReceiverNotSupported)
+            );
+        }
+        assert_eq!(canister.balanceOf(bob()), Tokens128::ZERO);
+    }
+
+    #[tokio::test]
+    async fn safe_transfer() {
+        let receiver = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        let other_canister = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 1, 1]);
+        let user = Principal::self_authenticating(b"public key");
+        register_virtual_responder(receiver, CANDID_INTERFACE_METHOD, |()| {
+            format!("service : {{ {} }}", crate::receiver::RECEIVER_CANDID)
+        });
+        register_virtual_responder(other_canister, CANDID_INTERFACE_METHOD, |()| {
+            "service : {}".to_string()
+        });
+
+        let canister = test_canister();
+        canister
+            .safeTransfer(receiver, Tokens128::from(10), None)
+            .await
+            .unwrap();
+        canister
+            .safeTransfer(user, Tokens128::from(10), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            canister
+                .safeTransfer(other_canister, Tokens128::from(10), None)
+                .await,
+            Err(TxError::ReceiverNotSupported)
+        );
+        assert_eq!(canister.balanceOf(receiver), Tokens128::from(10));
+        assert_eq!(canister.balanceOf(user), Tokens128::from(10));
+        assert_eq!(canister.balanceOf(other_canister), Tokens128::ZERO);
+    }
+
+    #[tokio::test]
+    async fn failed_notification_dropped() {
+        register_failing_virtual_responder(
+            bob(),
+            "transaction_notification",
+            "something's wrong".into(),
+        );
+
+        let canister = test_canister();
+        let id = canister
+            .transfer(bob(), Tokens128::from(100), None)
+            .unwrap();
+        assert!(canister.notify(id, bob()).await.is_err());
+        for _ in 1..MAX_NOTIFICATION_ATTEMPTS {
+            assert!(canister.retryNotification(id).await.is_err());
+        }
+
+        assert!(canister.getFailedNotifications().is_empty());
     }
 }
@@ -0,0 +1,252 @@
+//! API methods for the time-locked change of the token owner.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_canister::virtual_canister_notify;
+
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::{CanisterState, PendingOwnerChange};
+use crate::types::{AdminEvent, Timestamp, TxError};
+
+use super::{CanisterUpdate, TokenCanisterAPI};
+
+/// Time between the owner change is initiated and it can be applied, in nanoseconds (2 days).
+pub const OWNER_CHANGE_DELAY: Timestamp = 2 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Notification of the guardian about the initiated owner change, see [notify_guardian].
+pub(crate) struct GuardianNotification {
+    guardian: Principal,
+    change: PendingOwnerChange,
+}
+
+/// Records the pending owner change and notifies the guardian about it, if one is set.
+pub(crate) async fn initiate_owner_change(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    new_owner: Principal,
+) -> Result<(), TxError> {
+    let notification = record_owner_change(&mut canister.state().borrow_mut(), new_owner);
+    notify_guardian(notification).await;
+    Ok(())
+}
+
+/// Records the pending owner change, replacing the previous one. The owner changes made by the
+/// multisig and the timelock are recorded this way as well, so they have the same delay window.
+/// Returns the notification to be sent to the guardian once the state is updated.
+pub(crate) fn record_owner_change(
+    state: &mut CanisterState,
+    new_owner: Principal,
+) -> Option<GuardianNotification> {
+    let now = ic::time();
+    let change = PendingOwnerChange {
+        new_owner,
+        initiated_at: now,
+        effective_at: now.saturating_add(OWNER_CHANGE_DELAY),
+    };
+
+    state.owner_rotation.pending = Some(change.clone());
+    state
+        .owner_rotation
+        .guardian
+        .map(|guardian| GuardianNotification { guardian, change })
+}
+
+pub(crate) async fn notify_guardian(notification: Option<GuardianNotification>) {
+    let GuardianNotification { guardian, change } = match notification {
+        Some(notification) => notification,
+        None => return,
+    };
+
+    // The notification is best effort: the change can still be seen by the guardian with the
+    // `getPendingOwnerChange` query, so a failed call shouldn't block the rotation.
+    if virtual_canister_notify!(guardian, "owner_change_initiated", (change,), ())
+        .await
+        .is_err()
+    {
+        ic_cdk::println!("Failed to notify guardian {guardian} about the owner change");
+    }
+}
+
+/// Applies the pending owner change. Can be called by the current owner or the new owner, once
+/// the delay window is over. The registry is notified about the new owner, if one is set.
+pub(crate) async fn apply_owner_change(canister: &impl TokenCanisterAPI) -> Result<(), TxError> {
+    let (new_owner, registry) = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+
+        let change = state
+            .owner_rotation
+            .pending
+            .clone()
+            .ok_or(TxError::NoPendingOwnerChange)?;
+
+        let caller = ic::caller();
+        if caller != state.stats.owner && caller != change.new_owner {
+            return Err(TxError::Unauthorized);
+        }
+
+        if ic::time() < change.effective_at {
+            return Err(TxError::OwnerChangeTooEarly {
+                effective_at: change.effective_at,
+            });
+        }
+
+        state.ledger.record_admin_event(
+            caller,
+            AdminEvent::Update(CanisterUpdate::Owner(change.new_owner)),
+        );
+        state.stats.owner = change.new_owner;
+        state.owner_rotation.pending = None;
+        (change.new_owner, state.owner_rotation.registry)
+    };
+
+    if let Some(registry) = registry {
+        // Best effort as well: the registry can still pull the owner from the token.
+        if virtual_canister_notify!(registry, "notify_owner_changed", (new_owner,), ())
+            .await
+            .is_err()
+        {
+            ic_cdk::println!("Failed to notify registry {registry} about the owner change");
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn cancel_owner_change(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+) -> Result<(), TxError> {
+    canister
+        .state()
+        .borrow_mut()
+        .owner_rotation
+        .pending
+        .take()
+        .map(|_| ())
+        .ok_or(TxError::NoPendingOwnerChange)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+    use ic_canister::ic_kit::MockContext;
+    use ic_canister::{register_virtual_responder, Canister};
+
+    use crate::mock::*;
+    use crate::types::{EventKind, Metadata};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn owner_changed_after_delay() {
+        let (context, canister) = test_context();
+        canister.setOwner(bob()).await.unwrap();
+        assert_eq!(canister.owner(), alice());
+        assert_eq!(canister.getPendingOwnerChange().unwrap().new_owner, bob());
+
+        assert!(matches!(
+            canister.applyOwnerChange().await,
+            Err(TxError::OwnerChangeTooEarly { .. })
+        ));
+
+        context.add_time(OWNER_CHANGE_DELAY);
+        context.update_caller(bob());
+        canister.applyOwnerChange().await.unwrap();
+        assert_eq!(canister.owner(), bob());
+        assert!(canister.getPendingOwnerChange().is_none());
+
+        let event = canister.getEvents(0, 100).pop().unwrap();
+        assert!(matches!(
+            event.kind,
+            EventKind::Admin {
+                caller,
+                event: AdminEvent::Update(CanisterUpdate::Owner(owner)),
+                ..
+            } if caller == bob() && owner == bob()
+        ));
+    }
+
+    #[tokio::test]
+    async fn owner_change_cancelled() {
+        let (context, canister) = test_context();
+        canister.setOwner(bob()).await.unwrap();
+        canister.cancelOwnerChange().unwrap();
+
+        context.add_time(OWNER_CHANGE_DELAY);
+        assert_eq!(
+            canister.applyOwnerChange().await,
+            Err(TxError::NoPendingOwnerChange)
+        );
+        assert_eq!(canister.owner(), alice());
+    }
+
+    #[tokio::test]
+    async fn owner_change_not_authorized() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(canister.setOwner(bob()).await, Err(TxError::Unauthorized));
+
+        context.update_caller(alice());
+        canister.setOwner(bob()).await.unwrap();
+
+        context.add_time(OWNER_CHANGE_DELAY);
+        context.update_caller(john());
+        assert_eq!(
+            canister.applyOwnerChange().await,
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(canister.cancelOwnerChange(), Err(TxError::Unauthorized));
+    }
+
+    #[tokio::test]
+    async fn guardian_notified() {
+        let is_notified = Rc::new(AtomicBool::new(false));
+        let is_notified_clone = is_notified.clone();
+        register_virtual_responder(
+            john(),
+            "owner_change_initiated",
+            move |(change,): (PendingOwnerChange,)| {
+                is_notified.swap(true, Ordering::Relaxed);
+                assert_eq!(change.new_owner, bob());
+            },
+        );
+
+        let (_, canister) = test_context();
+        canister.setGuardian(Some(john())).unwrap();
+        canister.setOwner(bob()).await.unwrap();
+        assert!(is_notified_clone.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn registry_notified() {
+        let is_notified = Rc::new(AtomicBool::new(false));
+        let is_notified_clone = is_notified.clone();
+        register_virtual_responder(
+            john(),
+            "notify_owner_changed",
+            move |(owner,): (Principal,)| {
+                is_notified.swap(true, Ordering::Relaxed);
+                assert_eq!(owner, bob());
+            },
+        );
+
+        let context = MockContext::new().with_caller(alice()).inject();
+        let canister = TokenCanisterMock::init_instance();
+        canister.init(Metadata {
+            registry: Some(john()),
+            ..test_metadata()
+        });
+        assert_eq!(canister.getMetadata().registry, Some(john()));
+
+        canister.setOwner(bob()).await.unwrap();
+        assert!(!is_notified_clone.load(Ordering::Relaxed));
+
+        context.add_time(OWNER_CHANGE_DELAY);
+        canister.applyOwnerChange().await.unwrap();
+        assert!(is_notified_clone.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,268 @@
+//! Scheduled and recurring transfers. The users schedule the transfers from their balance, which
+//! are executed when they are due by the canister timer, or with the `executeScheduledTransfers`
+//! call. The executed transfers are recorded in the ledger as usual.
+//!
+//! Scheduling a transfer is charged the same fee as an approval, so the queue cannot be filled for
+//! free. The number of the scheduled transfers is capped per user and in total.
+
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+
+use crate::canister::erc20_transactions::{charge_service_fee, transfer_tokens};
+use crate::principal::{CheckedPrincipal, WithRecipient};
+use crate::state::ScheduledTransfer;
+use crate::types::{Timestamp, TxError};
+
+use super::TokenCanisterAPI;
+
+/// Minimum interval between the executions of a recurring transfer.
+pub const MIN_SCHEDULE_INTERVAL: Timestamp = 60 * 1_000_000_000;
+pub const MAX_SCHEDULED_TRANSFERS_PER_USER: usize = 32;
+pub const MAX_SCHEDULED_TRANSFERS: usize = 10_000;
+/// A transfer that fails this number of times in a row is removed.
+pub const MAX_SCHEDULED_TRANSFER_FAILURES: u32 = 3;
+/// Maximum number of the scheduled transfers executed at once.
+const SCHEDULED_BATCH_LEN: usize = 50;
+
+/// Schedules the transfer to be executed at the `start` time, and then every `interval` if it's
+/// set. Returns the id of the scheduled transfer.
+pub(crate) fn schedule_transfer(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    amount: Tokens128,
+    start: Timestamp,
+    interval: Option<Timestamp>,
+) -> Result<u64, TxError> {
+    if amount.is_zero() || interval.map_or(false, |interval| interval < MIN_SCHEDULE_INTERVAL) {
+        return Err(TxError::InvalidSchedule);
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let from = caller.inner();
+    state.check_not_frozen(&[from, caller.recipient()])?;
+
+    let scheduler = &state.scheduler;
+    if scheduler.len() >= MAX_SCHEDULED_TRANSFERS
+        || scheduler
+            .transfers
+            .values()
+            .filter(|scheduled| scheduled.from == from)
+            .count()
+            >= MAX_SCHEDULED_TRANSFERS_PER_USER
+    {
+        return Err(TxError::TooManyScheduledTransfers);
+    }
+
+    charge_service_fee(&mut state, from, caller.recipient())?;
+
+    let scheduler = &mut state.scheduler;
+    let id = scheduler.next_id;
+    scheduler.next_id += 1;
+    scheduler.insert(ScheduledTransfer {
+        id,
+        from,
+        to: caller.recipient(),
+        amount,
+        next_execution: start.max(ic::time()),
+        interval,
+        failures: 0,
+    });
+
+    Ok(id)
+}
+
+/// Cancels the scheduled transfer. Only the sender of the transfer can cancel it.
+pub(crate) fn cancel_scheduled_transfer(
+    canister: &impl TokenCanisterAPI,
+    id: u64,
+) -> Result<(), TxError> {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let scheduler = &mut state.scheduler;
+    match scheduler.transfers.get(&id) {
+        Some(scheduled) if scheduled.from != ic::caller() => Err(TxError::Unauthorized),
+        Some(_) => {
+            scheduler.remove(id);
+            Ok(())
+        }
+        None => Err(TxError::ScheduledTransferDoesNotExist),
+    }
+}
+
+/// Executes up to `SCHEDULED_BATCH_LEN` due transfers, the earliest first. Returns the number of
+/// the transfers executed successfully. Called by the timer, see
+/// [crate::canister::run_timer_tasks].
+///
+/// A failed transfer is not retried: a one-time transfer is removed, and a recurring transfer
+/// waits for its next execution, unless it failed `MAX_SCHEDULED_TRANSFER_FAILURES` times in a row.
+/// If the executions of a recurring transfer were missed, they are made on the next runs, one per
+/// run.
+pub(crate) fn execute_scheduled_transfers(canister: &impl TokenCanisterAPI) -> usize {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let due = state.scheduler.due(ic::time(), SCHEDULED_BATCH_LEN);
+
+    let mut executed = 0;
+    for mut scheduled in due {
+        state.scheduler.remove(scheduled.id);
+        match transfer_tokens(
+            &mut state,
+            scheduled.from,
+            scheduled.to,
+            scheduled.amount,
+            None,
+        ) {
+            Ok(_) => {
+                executed += 1;
+                scheduled.failures = 0;
+            }
+            Err(e) => {
+                ic_cdk::println!("Scheduled transfer {} failed: {e}", scheduled.id);
+                scheduled.failures += 1;
+            }
+        }
+
+        if let Some(interval) = scheduled.interval {
+            if scheduled.failures < MAX_SCHEDULED_TRANSFER_FAILURES {
+                scheduled.next_execution = scheduled.next_execution.saturating_add(interval);
+                state.scheduler.insert(scheduled);
+            }
+        }
+    }
+
+    executed
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    #[test]
+    fn recurring_transfer() {
+        let (context, canister) = test_context();
+        let id = canister
+            .scheduleTransfer(bob(), Tokens128::from(10), 0, Some(MIN_SCHEDULE_INTERVAL))
+            .unwrap();
+
+        assert_eq!(execute_scheduled_transfers(&canister), 1);
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(10));
+        assert_eq!(execute_scheduled_transfers(&canister), 0);
+
+        context.add_time(MIN_SCHEDULE_INTERVAL);
+        assert_eq!(execute_scheduled_transfers(&canister), 1);
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(20));
+
+        canister.cancelScheduledTransfer(id).unwrap();
+        assert!(canister.getScheduledTransfers(alice()).is_empty());
+        context.add_time(MIN_SCHEDULE_INTERVAL);
+        assert_eq!(execute_scheduled_transfers(&canister), 0);
+    }
+
+    #[test]
+    fn one_time_transfer() {
+        let (context, canister) = test_context();
+        let start = ic::time() + 1_000;
+        canister
+            .scheduleTransfer(john(), Tokens128::from(10), start, None)
+            .unwrap();
+
+        assert_eq!(execute_scheduled_transfers(&canister), 0);
+        assert_eq!(
+            canister.getScheduledTransfers(alice())[0].next_execution,
+            start
+        );
+
+        context.add_time(1_000);
+        assert_eq!(execute_scheduled_transfers(&canister), 1);
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(10));
+        assert!(canister.getScheduledTransfers(alice()).is_empty());
+    }
+
+    #[test]
+    fn failing_transfer_removed() {
+        let (context, canister) = test_context();
+        canister
+            .scheduleTransfer(bob(), Tokens128::from(10), 0, Some(MIN_SCHEDULE_INTERVAL))
+            .unwrap();
+        let id = canister
+            .scheduleTransfer(
+                john(),
+                Tokens128::from(2000),
+                0,
+                Some(MIN_SCHEDULE_INTERVAL),
+            )
+            .unwrap();
+
+        for failures in 1..MAX_SCHEDULED_TRANSFER_FAILURES {
+            assert_eq!(execute_scheduled_transfers(&canister), 1);
+            let scheduled = canister.getScheduledTransfers(alice());
+            assert_eq!(scheduled[1].id, id);
+            assert_eq!(scheduled[1].failures, failures);
+            context.add_time(MIN_SCHEDULE_INTERVAL);
+        }
+
+        assert_eq!(execute_scheduled_transfers(&canister), 1);
+        let scheduled = canister.getScheduledTransfers(alice());
+        assert_eq!(scheduled.len(), 1);
+        assert_eq!(scheduled[0].to, bob());
+    }
+
+    #[test]
+    fn scheduling_fee() {
+        let (_, canister) = test_context();
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        let transactions = canister.state().borrow().ledger.len();
+
+        canister
+            .scheduleTransfer(bob(), Tokens128::from(100), ic::time() + 1_000, None)
+            .unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(990));
+        assert_eq!(canister.state().borrow().ledger.len(), transactions + 1);
+    }
+
+    #[test]
+    fn too_many_scheduled_transfers() {
+        let (_, canister) = test_context();
+        for _ in 0..MAX_SCHEDULED_TRANSFERS_PER_USER {
+            canister
+                .scheduleTransfer(bob(), Tokens128::from(10), ic::time() + 1_000, None)
+                .unwrap();
+        }
+
+        assert_eq!(
+            canister.scheduleTransfer(bob(), Tokens128::from(10), ic::time() + 1_000, None),
+            Err(TxError::TooManyScheduledTransfers)
+        );
+    }
+
+    #[test]
+    fn invalid_schedule() {
+        let (context, canister) = test_context();
+        assert_eq!(
+            canister.scheduleTransfer(bob(), Tokens128::from(10), 0, Some(1)),
+            Err(TxError::InvalidSchedule)
+        );
+        assert_eq!(
+            canister.scheduleTransfer(bob(), Tokens128::ZERO, 0, None),
+            Err(TxError::InvalidSchedule)
+        );
+
+        let id = canister
+            .scheduleTransfer(bob(), Tokens128::from(10), ic::time() + 1_000, None)
+            .unwrap();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.cancelScheduledTransfer(id),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(
+            canister.cancelScheduledTransfer(id + 1),
+            Err(TxError::ScheduledTransferDoesNotExist)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Streaming payments. The payer deposits tokens into a stream, which accrues to the recipient at
+//! a constant rate per second until the deposit is exhausted. The recipient can withdraw the
+//! accrued tokens at any time, and the payer can close the stream to get back the part that has
+//! not accrued yet.
+//!
+//! The deposits are held by the [stream_principal], and the settlements are recorded in the ledger
+//! as transfers.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+
+use crate::amount_math::{credit, saturating_sub};
+use crate::canister::erc20_transactions::{transfer_balance, transfer_tokens};
+use crate::canister::is20_auction::reserved_principal;
+use crate::principal::{CheckedPrincipal, WithRecipient};
+use crate::state::{CanisterState, Stream};
+use crate::types::{TxError, TxId, TxReceipt};
+
+use super::TokenCanisterAPI;
+
+/// Holder of the stream deposits.
+pub fn stream_principal() -> Principal {
+    reserved_principal("stream")
+}
+
+/// Moves the `deposit` from the caller balance into a new stream and returns its id. The deposit
+/// is charged the usual transfer fee.
+pub(crate) fn open_stream(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<WithRecipient>,
+    rate: Tokens128,
+    deposit: Tokens128,
+) -> Result<u64, TxError> {
+    if rate.is_zero() || deposit.is_zero() {
+        return Err(TxError::InvalidStream);
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    state.check_not_frozen(&[caller.recipient()])?;
+    transfer_tokens(
+        &mut state,
+        caller.inner(),
+        stream_principal(),
+        deposit,
+        None,
+    )?;
+
+    let streams = &mut state.streams;
+    let id = streams.next_id;
+    streams.next_id += 1;
+    streams.entries.insert(
+        id,
+        Stream {
+            id,
+            from: caller.inner(),
+            to: caller.recipient(),
+            rate,
+            deposit,
+            withdrawn: Tokens128::ZERO,
+            start: ic::time(),
+        },
+    );
+
+    Ok(id)
+}
+
+/// Transfers the accrued tokens of the stream to the recipient. The stream is removed once its
+/// whole deposit is withdrawn.
+pub(crate) fn withdraw_from_stream(canister: &impl TokenCanisterAPI, id: u64) -> TxReceipt {
+    let caller = ic::caller();
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    let stream = state
+        .streams
+        .entries
+        .get_mut(&id)
+        .ok_or(TxError::StreamDoesNotExist)?;
+    if caller != stream.to {
+        return Err(TxError::Unauthorized);
+    }
+
+    let amount = stream.withdrawable(ic::time());
+    if amount.is_zero() {
+        return Err(TxError::NothingToClaim);
+    }
+
+    let withdrawn = credit(stream.withdrawn, amount)?;
+    let is_exhausted = withdrawn == stream.deposit;
+    let tx_id = pay_out(&mut state, caller, amount)?;
+
+    if is_exhausted {
+        state.streams.entries.remove(&id);
+    } else if let Some(stream) = state.streams.entries.get_mut(&id) {
+        stream.withdrawn = withdrawn;
+    }
+
+    Ok(tx_id)
+}
+
+/// Closes the stream, transferring the accrued tokens to the recipient and the rest of the
+/// deposit back to the payer. Can be called by the payer or the recipient. Returns the ids of the
+/// settlement transactions.
+pub(crate) fn close_stream(
+    canister: &impl TokenCanisterAPI,
+    id: u64,
+) -> Result<Vec<TxId>, TxError> {
+    let caller = ic::caller();
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    let stream = state
+        .streams
+        .entries
+        .get(&id)
+        .ok_or(TxError::StreamDoesNotExist)?;
+    if caller != stream.from && caller != stream.to {
+        return Err(TxError::Unauthorized);
+    }
+    state.check_not_frozen(&[stream.from, stream.to])?;
+
+    let stream = state
+        .streams
+        .entries
+        .remove(&id)
+        .expect("stream existence checked above");
+    let accrued = stream.withdrawable(ic::time());
+    let rest = saturating_sub(saturating_sub(stream.deposit, stream.withdrawn), accrued);
+
+    let mut ids = vec![];
+    for (receiver, amount) in [(stream.to, accrued), (stream.from, rest)] {
+        if !amount.is_zero() {
+            ids.push(pay_out(&mut state, receiver, amount)?);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Transfers the `amount` from the stream deposits to the `receiver`. The transfer is not charged
+/// a fee, as it was paid on the stream opening. Frozen receivers cannot be paid out.
+fn pay_out(state: &mut CanisterState, receiver: Principal, amount: Tokens128) -> TxReceipt {
+    state.check_not_frozen(&[receiver])?;
+    transfer_balance(&mut state.balances, stream_principal(), receiver, amount)?;
+    let id = state
+        .ledger
+        .transfer(stream_principal(), receiver, amount, Tokens128::ZERO);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn withdraw_accrued() {
+        let (context, canister) = test_context();
+        let id = canister
+            .openStream(bob(), Tokens128::from(10), Tokens128::from(100))
+            .unwrap();
+        assert_eq!(canister.balanceOf(stream_principal()), Tokens128::from(100));
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.withdrawFromStream(id),
+            Err(TxError::NothingToClaim)
+        );
+
+        context.add_time(3 * SECOND);
+        canister.withdrawFromStream(id).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(30));
+
+        context.add_time(100 * SECOND);
+        canister.withdrawFromStream(id).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert!(canister.getStreams(bob()).is_empty());
+
+        context.update_caller(john());
+        assert_eq!(
+            canister.withdrawFromStream(id),
+            Err(TxError::StreamDoesNotExist)
+        );
+    }
+
+    #[test]
+    fn close_by_payer() {
+        let (context, canister) = test_context();
+        let id = canister
+            .openStream(bob(), Tokens128::from(10), Tokens128::from(100))
+            .unwrap();
+
+        context.update_caller(john());
+        assert_eq!(canister.closeStream(id), Err(TxError::Unauthorized));
+
+        context.update_caller(alice());
+        context.add_time(4 * SECOND);
+        assert_eq!(canister.closeStream(id).unwrap().len(), 2);
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(40));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(960));
+        assert_eq!(canister.balanceOf(stream_principal()), Tokens128::ZERO);
+    }
+
+    #[test]
+    fn frozen_recipient_cannot_withdraw() {
+        let (context, canister) = test_context();
+        let id = canister
+            .openStream(bob(), Tokens128::from(10), Tokens128::from(100))
+            .unwrap();
+        canister.freezeAccount(bob()).unwrap();
+
+        context.update_caller(bob());
+        context.add_time(3 * SECOND);
+        assert_eq!(canister.withdrawFromStream(id), Err(TxError::AccountFrozen));
+        assert_eq!(canister.closeStream(id), Err(TxError::AccountFrozen));
+        assert_eq!(canister.balanceOf(stream_principal()), Tokens128::from(100));
+
+        context.update_caller(alice());
+        canister.unfreezeAccount(bob()).unwrap();
+        context.update_caller(bob());
+        canister.withdrawFromStream(id).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(30));
+    }
+
+    #[test]
+    fn invalid_stream() {
+        let (_, canister) = test_context();
+        assert_eq!(
+            canister.openStream(bob(), Tokens128::ZERO, Tokens128::from(100)),
+            Err(TxError::InvalidStream)
+        );
+        assert_eq!(
+            canister.openStream(bob(), Tokens128::from(10), Tokens128::from(2000)),
+            Err(TxError::InsufficientBalance)
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Push notifications of the transactions to the subscribed canisters. Each subscriber has a
+//! cursor in the transaction history, which is only moved forward once a batch of the matching
+//! transactions is sent, so a batch that cannot be sent is sent again by the next run.
+//!
+//! The batches are sent by the canister timer, see [crate::canister::run_timer_tasks], with
+//! one-way calls, so a subscriber that doesn't respond doesn't hold up the others. Only canisters
+//! can subscribe, and subscribing is charged the approval fee, so the subscriber slots cannot be
+//! taken for free.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_canister::virtual_canister_notify;
+
+use crate::canister::erc20_transactions::charge_service_fee;
+use crate::ledger::Ledger;
+use crate::receiver::is_canister;
+use crate::state::Subscription;
+use crate::types::{SubscriptionFilter, TxError, TxId, TxRecord};
+
+use super::TokenCanisterAPI;
+
+/// Method of the subscriber canister called with the batches of the matching transactions.
+pub const EVENTS_METHOD: &str = "is20_events";
+pub const MAX_SUBSCRIBERS: usize = 100;
+/// Maximum number of the transactions delivered to a subscriber in one call.
+const EVENT_BATCH_LEN: usize = 100;
+/// Maximum number of the transactions checked against the filter of a subscriber in one run.
+const EVENT_SCAN_LEN: TxId = 1000;
+
+/// Subscribes the caller to the transactions matching the `filter`, starting from the next
+/// transaction. If the caller is already subscribed, only the filter is changed.
+pub(crate) fn subscribe(
+    canister: &impl TokenCanisterAPI,
+    filter: SubscriptionFilter,
+) -> Result<(), TxError> {
+    let caller = ic::caller();
+    if !is_canister(&caller) {
+        return Err(TxError::Unauthorized);
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    if let Some(subscription) = state.subscriptions.get_mut(&caller) {
+        subscription.filter = filter;
+        return Ok(());
+    }
+
+    if state.subscriptions.len() >= MAX_SUBSCRIBERS {
+        return Err(TxError::TooManySubscribers);
+    }
+
+    charge_service_fee(&mut state, caller, ic::id())?;
+    let next_transaction = state.ledger.len();
+    state.subscriptions.insert(
+        caller,
+        Subscription {
+            filter,
+            next_transaction,
+        },
+    );
+    Ok(())
+}
+
+pub(crate) fn unsubscribe(canister: &impl TokenCanisterAPI) -> Result<(), TxError> {
+    canister
+        .state()
+        .borrow_mut()
+        .subscriptions
+        .remove(&ic::caller())
+        .map(|_| ())
+        .ok_or(TxError::AlreadyActioned)
+}
+
+/// Sends the next batch of the matching transactions to each subscriber. Returns the number of
+/// the transactions sent.
+pub(crate) async fn publish_events(canister: &impl TokenCanisterAPI) -> usize {
+    let batches = {
+        let state = canister.state();
+        let state = state.borrow();
+        state
+            .subscriptions
+            .iter()
+            .map(|(subscriber, subscription)| {
+                let (events, next) = next_batch(&state.ledger, subscription);
+                (*subscriber, subscription.next_transaction, events, next)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut sent = 0;
+    for (subscriber, start, events, next) in batches {
+        if !events.is_empty() {
+            let count = events.len();
+            let result = virtual_canister_notify!(subscriber, EVENTS_METHOD, (events,), ()).await;
+            if result.is_err() {
+                continue;
+            }
+            sent += count;
+        }
+
+        advance_cursor(canister, subscriber, start, next);
+    }
+
+    sent
+}
+
+/// Returns the matching transactions starting from the subscription cursor, and the cursor value
+/// after them.
+fn next_batch(ledger: &Ledger, subscription: &Subscription) -> (Vec<TxRecord>, TxId) {
+    let start = subscription.next_transaction;
+    let end = ledger.len().min(start.saturating_add(EVENT_SCAN_LEN));
+
+    let mut events = vec![];
+    for id in start..end {
+        let tx = match ledger.get(id) {
+            Some(tx) => tx,
+            None => continue,
+        };
+        if subscription.filter.matches(&tx) {
+            events.push(tx);
+            if events.len() == EVENT_BATCH_LEN {
+                return (events, id + 1);
+            }
+        }
+    }
+
+    (events, end)
+}
+
+/// Moves the cursor of the subscriber to `next`, unless the subscriber re-subscribed or the cursor
+/// was moved by another run while the batch was sent.
+fn advance_cursor(
+    canister: &impl TokenCanisterAPI,
+    subscriber: Principal,
+    start: TxId,
+    next: TxId,
+) {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    if let Some(subscription) = state.subscriptions.get_mut(&subscriber) {
+        if subscription.next_transaction == start {
+            subscription.next_transaction = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+    use ic_canister::{register_failing_virtual_responder, register_virtual_responder};
+    use ic_helpers::tokens::Tokens128;
+
+    use crate::mock::*;
+    use crate::types::Operation;
+
+    use super::*;
+
+    fn subscriber() -> Principal {
+        Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1])
+    }
+
+    #[tokio::test]
+    async fn events_delivered() {
+        let received = Rc::new(AtomicUsize::new(0));
+        let received_copy = received.clone();
+        register_virtual_responder(
+            subscriber(),
+            EVENTS_METHOD,
+            move |(events,): (Vec<TxRecord>,)| {
+                assert!(events.iter().all(|tx| tx.operation == Operation::Transfer));
+                received.fetch_add(events.len(), Ordering::Relaxed);
+            },
+        );
+
+        let (context, canister) = test_context();
+        context.update_caller(subscriber());
+        canister
+            .subscribe(SubscriptionFilter {
+                operations: vec![Operation::Transfer],
+                account: None,
+            })
+            .unwrap();
+
+        context.update_caller(alice());
+        canister
+            .transfer(john(), Tokens128::from(10), None)
+            .unwrap();
+        canister.approve(john(), Tokens128::from(10)).unwrap();
+        canister
+            .transfer(john(), Tokens128::from(10), None)
+            .unwrap();
+
+        assert_eq!(publish_events(&canister).await, 2);
+        assert_eq!(received_copy.load(Ordering::Relaxed), 2);
+        assert_eq!(publish_events(&canister).await, 0);
+
+        context.update_caller(subscriber());
+        canister.unsubscribe().unwrap();
+        assert_eq!(canister.unsubscribe(), Err(TxError::AlreadyActioned));
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_retried() {
+        register_failing_virtual_responder(subscriber(), EVENTS_METHOD, "unavailable".into());
+
+        let (context, canister) = test_context();
+        context.update_caller(subscriber());
+        canister
+            .subscribe(SubscriptionFilter {
+                operations: vec![],
+                account: Some(bob()),
+            })
+            .unwrap();
+        let start = canister
+            .getSubscription(subscriber())
+            .unwrap()
+            .next_transaction;
+
+        context.update_caller(alice());
+        canister.transfer(bob(), Tokens128::from(10), None).unwrap();
+        assert_eq!(publish_events(&canister).await, 0);
+        assert_eq!(
+            canister
+                .getSubscription(subscriber())
+                .unwrap()
+                .next_transaction,
+            start
+        );
+
+        register_virtual_responder(subscriber(), EVENTS_METHOD, |_: (Vec<TxRecord>,)| {});
+        assert_eq!(publish_events(&canister).await, 1);
+        assert_eq!(
+            canister
+                .getSubscription(subscriber())
+                .unwrap()
+                .next_transaction,
+            start + 1
+        );
+    }
+
+    #[test]
+    fn only_canisters_subscribe() {
+        let (context, canister) = test_context();
+        let filter = SubscriptionFilter {
+            operations: vec![],
+            account: None,
+        };
+        context.update_caller(Principal::self_authenticating(b"public key"));
+        assert_eq!(
+            canister.subscribe(filter.clone()),
+            Err(TxError::Unauthorized)
+        );
+
+        canister.state().borrow_mut().stats.fee = Tokens128::from(10);
+        context.update_caller(subscriber());
+        assert_eq!(
+            canister.subscribe(filter.clone()),
+            Err(TxError::InsufficientBalance)
+        );
+
+        context.update_caller(alice());
+        canister
+            .transfer(subscriber(), Tokens128::from(100), None)
+            .unwrap();
+        context.update_caller(subscriber());
+        canister.subscribe(filter.clone()).unwrap();
+        assert_eq!(canister.balanceOf(subscriber()), Tokens128::from(90));
+
+        // Changing the filter is not charged.
+        canister.subscribe(filter).unwrap();
+        assert_eq!(canister.balanceOf(subscriber()), Tokens128::from(90));
+    }
+}
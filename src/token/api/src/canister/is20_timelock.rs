@@ -0,0 +1,319 @@
+//! Timelock for the sensitive parameter changes. When it's enabled, the fee, the fee receiver and
+//! the owner are changed by queuing an update, which can only be executed after the delay, so the
+//! holders can see the upcoming changes and react to them. While the multisig is enabled, the
+//! updates are queued and cancelled with the multisig proposals, and executed by the signers.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+
+use crate::canister::is20_ownership::{notify_guardian, record_owner_change, GuardianNotification};
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::{CanisterState, QueuedUpdate, TimelockedUpdate};
+use crate::types::{
+    valid_basis_points, valid_fee_bounds, valid_fee_distribution, AdminEvent, Timestamp, TxError,
+};
+
+use super::TokenCanisterAPI;
+
+/// Sets the timelock delay. The delay can only be decreased with a queued `Delay` update while
+/// the timelock is enabled.
+pub(crate) fn set_timelock_delay(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Owner>,
+    delay: Timestamp,
+) -> Result<(), TxError> {
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+
+    if delay < state.timelock.delay {
+        return Err(TxError::TimelockRequired);
+    }
+
+    state
+        .ledger
+        .record_admin_event(caller.inner(), AdminEvent::TimelockDelay(delay));
+    state.timelock.delay = delay;
+    Ok(())
+}
+
+/// Queues the update and returns its id.
+pub(crate) fn queue_update(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    update: TimelockedUpdate,
+) -> Result<u64, TxError> {
+    validate_update(&update)?;
+    Ok(enqueue(&mut canister.state().borrow_mut(), update))
+}
+
+pub(crate) fn validate_update(update: &TimelockedUpdate) -> Result<(), TxError> {
+    let is_valid = match update {
+        TimelockedUpdate::FeeMode(mode) => mode.is_valid(),
+        TimelockedUpdate::FeeBounds { min, max } => valid_fee_bounds(*min, *max),
+        TimelockedUpdate::FeeBurnRatio(ratio) => valid_basis_points(*ratio),
+        TimelockedUpdate::FeeDistribution(distribution) => valid_fee_distribution(distribution),
+        _ => true,
+    };
+    if is_valid {
+        Ok(())
+    } else {
+        Err(TxError::InvalidFeeConfig)
+    }
+}
+
+/// Adds the validated update to the queue and returns its id.
+pub(crate) fn enqueue(state: &mut CanisterState, update: TimelockedUpdate) -> u64 {
+    let timelock = &mut state.timelock;
+
+    let id = timelock.next_update_id;
+    timelock.next_update_id += 1;
+
+    let now = ic::time();
+    timelock.queue.insert(
+        id,
+        QueuedUpdate {
+            id,
+            update,
+            queued_at: now,
+            executable_at: now.saturating_add(timelock.delay),
+        },
+    );
+
+    id
+}
+
+/// Executes the queued update, once its delay is over. The `caller` is the owner, or one of the
+/// signers while the multisig is enabled. The owner update initiates the owner change, which is
+/// applied after its own delay, see `apply_owner_change`.
+pub(crate) async fn execute_update(
+    canister: &impl TokenCanisterAPI,
+    caller: Principal,
+    id: u64,
+) -> Result<(), TxError> {
+    let notification = {
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        execute(&mut state, caller, id)?
+    };
+
+    notify_guardian(notification).await;
+    Ok(())
+}
+
+fn execute(
+    state: &mut CanisterState,
+    caller: Principal,
+    id: u64,
+) -> Result<Option<GuardianNotification>, TxError> {
+    let queued = state
+        .timelock
+        .queue
+        .get(&id)
+        .ok_or(TxError::UpdateDoesNotExist)?;
+    if ic::time() < queued.executable_at {
+        return Err(TxError::UpdateNotReady {
+            executable_at: queued.executable_at,
+        });
+    }
+
+    let queued = state
+        .timelock
+        .queue
+        .remove(&id)
+        .expect("update existence checked above");
+    state
+        .ledger
+        .record_admin_event(caller, AdminEvent::TimelockedUpdate(queued.update.clone()));
+    match queued.update {
+        TimelockedUpdate::Fee(fee) => state.stats.fee = fee,
+        TimelockedUpdate::FeeTo(fee_to) => state.stats.fee_to = fee_to,
+        TimelockedUpdate::FeeMode(mode) => state.stats.fee_mode = mode,
+        TimelockedUpdate::FeeBounds { min, max } => {
+            state.stats.fee_min = min;
+            state.stats.fee_max = max;
+        }
+        TimelockedUpdate::FeeBurnRatio(ratio) => state.stats.fee_burn_ratio = ratio,
+        TimelockedUpdate::FeeDistribution(distribution) => {
+            state.stats.fee_distribution = distribution
+        }
+        TimelockedUpdate::Owner(owner) => return Ok(record_owner_change(state, owner)),
+        TimelockedUpdate::Delay(delay) => state.timelock.delay = delay,
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn cancel_update(
+    canister: &impl TokenCanisterAPI,
+    _caller: CheckedPrincipal<Owner>,
+    id: u64,
+) -> Result<(), TxError> {
+    dequeue(&mut canister.state().borrow_mut(), id)
+}
+
+pub(crate) fn dequeue(state: &mut CanisterState, id: u64) -> Result<(), TxError> {
+    state
+        .timelock
+        .queue
+        .remove(&id)
+        .map(|_| ())
+        .ok_or(TxError::UpdateDoesNotExist)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+    use ic_helpers::tokens::Tokens128;
+
+    use crate::canister::is20_ownership::OWNER_CHANGE_DELAY;
+    use crate::mock::*;
+    use crate::state::{AdminOp, MultisigConfig};
+
+    use super::*;
+
+    const DELAY: Timestamp = 1_000_000_000;
+
+    #[tokio::test]
+    async fn queue_and_execute() {
+        let (context, canister) = test_context();
+        canister.setTimelockDelay(DELAY).unwrap();
+        assert_eq!(
+            canister.setFee(Tokens128::from(10)),
+            Err(TxError::TimelockRequired)
+        );
+
+        let id = canister
+            .queueUpdate(TimelockedUpdate::Fee(Tokens128::from(10)))
+            .unwrap();
+        let pending = canister.getPendingUpdates();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].executable_at, pending[0].queued_at + DELAY);
+
+        assert_eq!(
+            canister.executeUpdate(id).await,
+            Err(TxError::UpdateNotReady {
+                executable_at: pending[0].executable_at
+            })
+        );
+
+        context.add_time(DELAY);
+        canister.executeUpdate(id).await.unwrap();
+        assert_eq!(canister.getMetadata().fee, Tokens128::from(10));
+        assert!(canister.getPendingUpdates().is_empty());
+        assert_eq!(
+            canister.executeUpdate(id).await,
+            Err(TxError::UpdateDoesNotExist)
+        );
+    }
+
+    #[tokio::test]
+    async fn owner_change() {
+        let (context, canister) = test_context();
+        canister.setTimelockDelay(DELAY).unwrap();
+        let id = canister
+            .queueUpdate(TimelockedUpdate::Owner(bob()))
+            .unwrap();
+
+        context.add_time(DELAY);
+        canister.executeUpdate(id).await.unwrap();
+        assert_eq!(canister.owner(), alice());
+        assert_eq!(canister.getPendingOwnerChange().unwrap().new_owner, bob());
+
+        context.add_time(OWNER_CHANGE_DELAY);
+        canister.applyOwnerChange().await.unwrap();
+        assert_eq!(canister.owner(), bob());
+    }
+
+    #[tokio::test]
+    async fn delay_decrease_is_timelocked() {
+        let (context, canister) = test_context();
+        canister.setTimelockDelay(DELAY).unwrap();
+        canister.setTimelockDelay(DELAY * 2).unwrap();
+        assert_eq!(canister.setTimelockDelay(0), Err(TxError::TimelockRequired));
+
+        let id = canister.queueUpdate(TimelockedUpdate::Delay(0)).unwrap();
+        context.add_time(DELAY * 2);
+        canister.executeUpdate(id).await.unwrap();
+
+        canister.setFee(Tokens128::from(10)).unwrap();
+    }
+
+    #[test]
+    fn max_delay() {
+        let (_, canister) = test_context();
+        canister.setTimelockDelay(Timestamp::MAX).unwrap();
+        canister
+            .queueUpdate(TimelockedUpdate::FeeTo(bob()))
+            .unwrap();
+        assert_eq!(
+            canister.getPendingUpdates()[0].executable_at,
+            Timestamp::MAX
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_by_multisig() {
+        let (context, canister) = test_context();
+        canister.setTimelockDelay(DELAY).unwrap();
+        canister
+            .enableMultisig(MultisigConfig {
+                signers: [alice(), bob()].into_iter().collect(),
+                threshold: 2,
+            })
+            .unwrap();
+        assert_eq!(
+            canister.queueUpdate(TimelockedUpdate::Fee(Tokens128::from(10))),
+            Err(TxError::MultisigRequired)
+        );
+        assert_eq!(
+            canister
+                .proposeAdminOp(AdminOp::SetFee(Tokens128::from(10)))
+                .await,
+            Err(TxError::TimelockRequired)
+        );
+
+        let proposal_id = canister
+            .proposeAdminOp(AdminOp::QueueUpdate(TimelockedUpdate::Fee(
+                Tokens128::from(10),
+            )))
+            .await
+            .unwrap();
+        assert!(canister.getPendingUpdates().is_empty());
+
+        context.update_caller(bob());
+        canister.approveAdminOp(proposal_id).await.unwrap();
+        let id = canister.getPendingUpdates()[0].id;
+
+        context.add_time(DELAY);
+        context.update_caller(john());
+        assert_eq!(canister.executeUpdate(id).await, Err(TxError::Unauthorized));
+
+        context.update_caller(bob());
+        canister.executeUpdate(id).await.unwrap();
+        assert_eq!(canister.getMetadata().fee, Tokens128::from(10));
+    }
+
+    #[test]
+    fn cancel() {
+        let (_, canister) = test_context();
+        canister.setTimelockDelay(DELAY).unwrap();
+        let id = canister
+            .queueUpdate(TimelockedUpdate::FeeTo(bob()))
+            .unwrap();
+
+        canister.cancelUpdate(id).unwrap();
+        assert!(canister.getPendingUpdates().is_empty());
+        assert_eq!(canister.cancelUpdate(id), Err(TxError::UpdateDoesNotExist));
+    }
+
+    #[test]
+    fn not_owner() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.queueUpdate(TimelockedUpdate::Owner(bob())),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(canister.setTimelockDelay(DELAY), Err(TxError::Unauthorized));
+    }
+}
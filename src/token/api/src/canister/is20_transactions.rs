@@ -1,10 +1,12 @@
 use candid::Principal;
 use ic_helpers::tokens::Tokens128;
 
-use crate::canister::erc20_transactions::{charge_fee, transfer_balance};
+use crate::amount_math::{credit, debit};
+use crate::canister::erc20_transactions::{
+    is_mint_or_burn, mint_or_burn, plan_fee, record_burn, record_fee, record_mint, BalanceUpdate,
+};
 use crate::principal::{CheckedPrincipal, WithRecipient};
-use crate::state::CanisterState;
-use crate::types::{TxError, TxId, TxReceipt};
+use crate::types::{FeeSplit, TxError, TxId, TxReceipt};
 
 use super::TokenCanisterAPI;
 
@@ -20,112 +22,138 @@ pub fn transfer_include_fee(
 ) -> TxReceipt {
     let state = canister.state();
     let mut state = state.borrow_mut();
-    let CanisterState {
-        ref mut balances,
-        ref mut ledger,
-        ref bidding_state,
-        ref stats,
-        ..
-    } = *state;
-
-    let (fee, fee_to) = stats.fee_info();
-    let fee_ratio = bidding_state.fee_ratio;
+    state.check_not_frozen(&[caller.inner(), caller.recipient()])?;
+    if is_mint_or_burn(&state.stats, caller.inner(), caller.recipient()) {
+        return mint_or_burn(
+            &mut state,
+            caller.inner(),
+            caller.inner(),
+            caller.recipient(),
+            amount,
+        );
+    }
 
+    let fee = state.stats.fee_for(amount);
     if amount <= fee {
         return Err(TxError::AmountTooSmall);
     }
 
-    if balances.balance_of(&caller.inner()) < amount {
+    if state.balances.transferable(&caller.inner()) < amount {
         return Err(TxError::InsufficientBalance);
     }
 
-    charge_fee(balances, caller.inner(), fee_to, fee, fee_ratio)
-        .expect("never fails due to checks above");
-    transfer_balance(
-        balances,
+    let mut update = BalanceUpdate::default();
+    let split = plan_fee(&state, &mut update, caller.inner(), fee)?;
+    update.transfer(
+        &state.balances,
         caller.inner(),
         caller.recipient(),
-        (amount - fee).expect("amount > fee is checked above"),
-    )
-    .expect("never fails due to checks above");
-
-    let id = ledger.transfer(caller.inner(), caller.recipient(), amount, fee);
+        debit(amount, fee)?,
+    )?;
+
+    update.apply(&mut state.balances);
+    record_fee(&mut state, caller.inner(), &split);
+    let id = state
+        .ledger
+        .transfer(caller.inner(), caller.recipient(), amount, fee);
     Ok(id)
 }
 
+/// Transfers the tokens to several recipients. Either all the transfers are applied or none. If a
+/// transfer of the batch cannot be applied, the error tells the index of this transfer.
 pub fn batch_transfer(
     canister: &impl TokenCanisterAPI,
     transfers: Vec<(Principal, Tokens128)>,
 ) -> Result<Vec<TxId>, TxError> {
+    apply_batch(canister, transfers).map_err(|(index, error)| match index {
+        Some(index) => TxError::BatchTransferFailed {
+            index: index as u64,
+            tx_error: Box::new(error),
+        },
+        None => error,
+    })
+}
+
+/// Plans every transfer of the batch on top of the previous ones, and then applies them one by one,
+/// so that the balances after each transfer are recorded with it. If a transfer cannot be applied,
+/// nothing is changed and the index of the transfer is returned along with the error. The index is
+/// `None` if the whole batch cannot be applied. The transfers from and to the minting account are
+/// applied as mints and burns, see [mint_or_burn].
+fn apply_batch(
+    canister: &impl TokenCanisterAPI,
+    transfers: Vec<(Principal, Tokens128)>,
+) -> Result<Vec<TxId>, (Option<usize>, TxError)> {
     let from = ic_canister::ic_kit::ic::caller();
     let state = canister.state();
     let mut state = state.borrow_mut();
-
-    let mut total_value = Tokens128::from(0u128);
-    for target in transfers.iter() {
-        total_value = (total_value + target.1).ok_or(TxError::AmountOverflow)?;
-    }
-
-    let CanisterState {
-        ref mut balances,
-        ref bidding_state,
-        ref stats,
-        ..
-    } = &mut *state;
-
-    let (fee, fee_to) = stats.fee_info();
-    let fee_ratio = bidding_state.fee_ratio;
-
-    let total_fee = (fee * transfers.len())
-        .to_tokens128()
-        .ok_or(TxError::AmountOverflow)?;
-
-    if balances.balance_of(&from) < (total_value + total_fee).ok_or(TxError::AmountOverflow)? {
-        return Err(TxError::InsufficientBalance);
+    state.check_not_frozen(&[from]).map_err(|e| (None, e))?;
+
+    let mut update = BalanceUpdate::default();
+    let mut total_supply = state.stats.total_supply;
+    let mut planned = Vec::with_capacity(transfers.len());
+    for (index, (to, value)) in transfers.into_iter().enumerate() {
+        let failed = |error| (Some(index), error);
+        state.check_not_frozen(&[to]).map_err(failed)?;
+
+        let transfer = if state.stats.minting_account == Some(from) {
+            total_supply = credit(total_supply, value).map_err(failed)?;
+            if matches!(state.stats.max_supply, Some(max_supply) if total_supply > max_supply) {
+                return Err(failed(TxError::MaxSupplyExceeded));
+            }
+            update.credit(&state.balances, to, value).map_err(failed)?;
+            BatchTransfer::Mint(to, value)
+        } else if state.stats.minting_account == Some(to) {
+            total_supply = debit(total_supply, value).map_err(failed)?;
+            update.debit(&state.balances, from, value).map_err(failed)?;
+            BatchTransfer::Burn(value)
+        } else {
+            let fee = state.stats.fee_for(value);
+            let split = plan_fee(&state, &mut update, from, fee).map_err(failed)?;
+            update
+                .transfer(&state.balances, from, to, value)
+                .map_err(failed)?;
+            BatchTransfer::Transfer(to, value, split)
+        };
+        // The update holds the balances after all the transfers planned so far.
+        planned.push((transfer, update.clone()));
     }
 
-    {
-        for (to, value) in transfers.clone() {
-            charge_fee(balances, from, fee_to, fee, fee_ratio)
-                .expect("never fails due to checks above");
-            transfer_balance(balances, from, to, value).expect("never fails due to checks above");
-        }
-    }
+    let ids = planned
+        .into_iter()
+        .map(|(transfer, update)| {
+            update.apply(&mut state.balances);
+            match transfer {
+                BatchTransfer::Mint(to, value) => record_mint(&mut state, from, to, value),
+                BatchTransfer::Burn(value) => record_burn(&mut state, from, from, value),
+                BatchTransfer::Transfer(to, value, split) => {
+                    record_fee(&mut state, from, &split);
+                    state.ledger.transfer(from, to, value, split.fee)
+                }
+            }
+        })
+        .collect();
+
+    Ok(ids)
+}
 
-    let id = state.ledger.batch_transfer(from, transfers, fee);
-    Ok(id)
+/// A transfer of the batch with its balance changes already planned.
+enum BatchTransfer {
+    Mint(Principal, Tokens128),
+    Burn(Tokens128),
+    Transfer(Principal, Tokens128, FeeSplit),
 }
 
 #[cfg(test)]
 mod tests {
-    use ic_canister::{
-        ic_kit::{
-            mock_principals::{alice, bob, john, xtc},
-            MockContext,
-        },
-        Canister,
-    };
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john, xtc};
 
     use crate::mock::*;
-    use crate::types::Metadata;
+    use crate::types::{FeeMode, Operation};
 
     use super::*;
 
     fn test_canister() -> TokenCanisterMock {
-        MockContext::new().with_caller(alice()).inject();
-
-        let canister = TokenCanisterMock::init_instance();
-        canister.init(Metadata {
-            logo: "".to_string(),
-            name: "".to_string(),
-            symbol: "".to_string(),
-            decimals: 8,
-            totalSupply: Tokens128::from(1000),
-            owner: alice(),
-            fee: Tokens128::from(0),
-            feeTo: alice(),
-            isTestToken: None,
-        });
+        let (_, canister) = crate::mock::test_context();
 
         // This is to make tests that don't rely on auction state
         // pass, because since we are running auction state on each
@@ -169,6 +197,23 @@ mod tests {
         assert_eq!(canister.balanceOf(john()), Tokens128::from(100));
     }
 
+    #[test]
+    fn batch_transfer_with_bps_fee() {
+        let canister = test_canister();
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        state.stats.fee_mode = FeeMode::BasisPoints(1000);
+        state.stats.fee_to = john();
+        drop(state);
+
+        let transfers = vec![(bob(), Tokens128::from(100)), (xtc(), Tokens128::from(200))];
+        let ids = canister.batchTransfer(transfers).unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(670));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(30));
+        assert_eq!(canister.getTransaction(ids[0]).fee, Tokens128::from(10));
+        assert_eq!(canister.getTransaction(ids[1]).fee, Tokens128::from(20));
+    }
+
     #[test]
     fn batch_transfer_insufficient_balance() {
         let canister = test_canister();
@@ -176,14 +221,69 @@ mod tests {
             (bob(), Tokens128::from(500)),
             (john(), Tokens128::from(600)),
         ];
-        let receipt = canister.batchTransfer(transfers);
-        assert!(receipt.is_err());
-        assert_eq!(receipt.unwrap_err(), TxError::InsufficientBalance);
+        assert_eq!(
+            canister.batchTransfer(transfers),
+            Err(TxError::BatchTransferFailed {
+                index: 1,
+                tx_error: Box::new(TxError::InsufficientBalance)
+            })
+        );
         assert_eq!(canister.balanceOf(alice()), Tokens128::from(1000));
         assert_eq!(canister.balanceOf(bob()), Tokens128::from(0));
         assert_eq!(canister.balanceOf(john()), Tokens128::from(0));
     }
 
+    #[test]
+    fn batch_transfer_with_minting_account() {
+        let (context, canister) = crate::mock::test_context();
+        {
+            let state = canister.state();
+            let mut state = state.borrow_mut();
+            state.stats.fee = Tokens128::from(10);
+            state.stats.fee_to = xtc();
+            state.bidding_state.fee_ratio = 0.0;
+        }
+        canister.setMintingAccount(Some(john())).unwrap();
+
+        let transfers = vec![
+            (bob(), Tokens128::from(100)),
+            (john(), Tokens128::from(200)),
+        ];
+        let ids = canister.batchTransfer(transfers).unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(690));
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(100));
+        assert_eq!(canister.balanceOf(john()), Tokens128::ZERO);
+        assert_eq!(canister.totalSupply(), Tokens128::from(800));
+        assert_eq!(canister.getTransaction(ids[1]).operation, Operation::Burn);
+
+        context.update_caller(john());
+        let transfers = vec![(bob(), Tokens128::from(50)), (xtc(), Tokens128::from(50))];
+        let ids = canister.batchTransfer(transfers).unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(150));
+        assert_eq!(canister.totalSupply(), Tokens128::from(900));
+        assert_eq!(canister.getTransaction(ids[0]).operation, Operation::Mint);
+    }
+
+    #[test]
+    fn batch_transfer_frozen_recipient() {
+        let canister = test_canister();
+
+        canister.freezeAccount(xtc()).unwrap();
+        let transfers = vec![(bob(), Tokens128::from(10)), (xtc(), Tokens128::from(10))];
+        assert_eq!(
+            canister.batchTransfer(transfers),
+            Err(TxError::BatchTransferFailed {
+                index: 1,
+                tx_error: Box::new(TxError::AccountFrozen)
+            })
+        );
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(0));
+
+        let transfers = vec![(bob(), Tokens128::from(10)), (john(), Tokens128::from(20))];
+        assert_eq!(canister.batchTransfer(transfers).unwrap().len(), 2);
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(20));
+    }
+
     #[test]
     fn transfer_without_fee() {
         let canister = test_canister();
@@ -214,6 +314,23 @@ mod tests {
         assert_eq!(canister.balanceOf(john()), Tokens128::from(100));
     }
 
+    #[test]
+    fn transfer_with_bps_fee() {
+        let canister = test_canister();
+        let state = canister.state();
+        let mut state = state.borrow_mut();
+        state.stats.fee_mode = FeeMode::BasisPoints(1000);
+        state.stats.fee_to = john();
+        drop(state);
+
+        canister
+            .transferIncludeFee(bob(), Tokens128::from(200))
+            .unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(180));
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(800));
+        assert_eq!(canister.balanceOf(john()), Tokens128::from(20));
+    }
+
     #[test]
     fn transfer_insufficient_balance() {
         let canister = test_canister();
@@ -0,0 +1,178 @@
+//! Vesting of the token allocations. The owner mints or transfers the tokens into a vesting entry
+//! of a beneficiary, and the beneficiary claims the tokens as they are released. The tokens are
+//! released linearly from the vesting start till `start + duration`, and none of them can be
+//! claimed before `start + cliff`.
+//!
+//! The vested tokens are held by the [vesting_principal] until they are claimed.
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+
+use crate::canister::erc20_transactions::{mint, transfer_balance, transfer_tokens};
+use crate::canister::is20_auction::reserved_principal;
+use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::Vesting;
+use crate::types::{Timestamp, TxError, TxReceipt};
+
+use super::TokenCanisterAPI;
+
+/// Holder of the tokens that are not claimed yet.
+pub fn vesting_principal() -> Principal {
+    reserved_principal("vesting")
+}
+
+/// Creates a vesting entry for the `beneficiary`, minting the `amount` or transferring it from
+/// the owner balance. Returns the id of the entry.
+pub(crate) fn create_vesting(
+    canister: &impl TokenCanisterAPI,
+    caller: CheckedPrincipal<Owner>,
+    beneficiary: Principal,
+    amount: Tokens128,
+    cliff: Timestamp,
+    duration: Timestamp,
+    mint_tokens: bool,
+) -> Result<u64, TxError> {
+    if amount.is_zero() || cliff > duration {
+        return Err(TxError::InvalidVesting);
+    }
+
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    let owner = caller.inner();
+    if mint_tokens {
+        mint(&mut state, owner, vesting_principal(), amount)?;
+    } else {
+        transfer_tokens(&mut state, owner, vesting_principal(), amount, None)?;
+    }
+
+    let vesting = &mut state.vesting;
+    let id = vesting.next_id;
+    vesting.next_id += 1;
+    vesting.entries.insert(
+        id,
+        Vesting {
+            id,
+            beneficiary,
+            total: amount,
+            claimed: Tokens128::ZERO,
+            start: ic::time(),
+            cliff,
+            duration,
+        },
+    );
+
+    Ok(id)
+}
+
+/// Transfers all the released tokens of the caller vesting entries to the caller. The fully
+/// claimed entries are removed.
+pub(crate) fn claim_vested(canister: &impl TokenCanisterAPI) -> TxReceipt {
+    let beneficiary = ic::caller();
+    let now = ic::time();
+    let state = canister.state();
+    let mut state = state.borrow_mut();
+    state.check_not_frozen(&[beneficiary])?;
+
+    let mut amount = Tokens128::ZERO;
+    for entry in state.vesting.entries.values_mut() {
+        if entry.beneficiary != beneficiary {
+            continue;
+        }
+
+        let claimable = entry.claimable(now);
+        entry.claimed = (entry.claimed + claimable).expect("claimed amount is not over the total");
+        amount = (amount + claimable).expect("vested amounts are not over the total supply");
+    }
+    state
+        .vesting
+        .entries
+        .retain(|_, entry| entry.claimed < entry.total);
+
+    if amount.is_zero() {
+        return Err(TxError::NothingToClaim);
+    }
+
+    transfer_balance(
+        &mut state.balances,
+        vesting_principal(),
+        beneficiary,
+        amount,
+    )
+    .expect("vested tokens are held by the vesting principal");
+    let id = state
+        .ledger
+        .transfer(vesting_principal(), beneficiary, amount, Tokens128::ZERO);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob};
+
+    use crate::mock::*;
+
+    use super::*;
+
+    const CLIFF: Timestamp = 100;
+    const DURATION: Timestamp = 1_000;
+
+    #[test]
+    fn linear_release() {
+        let (context, canister) = test_context();
+        canister
+            .createVesting(bob(), Tokens128::from(500), CLIFF, DURATION, false)
+            .unwrap();
+        assert_eq!(canister.balanceOf(alice()), Tokens128::from(500));
+        assert_eq!(
+            canister.balanceOf(vesting_principal()),
+            Tokens128::from(500)
+        );
+
+        context.update_caller(bob());
+        assert_eq!(canister.claimVested(), Err(TxError::NothingToClaim));
+
+        context.add_time(DURATION / 2);
+        canister.claimVested().unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(250));
+        assert_eq!(canister.getVesting(bob())[0].claimed, Tokens128::from(250));
+
+        context.add_time(DURATION);
+        canister.claimVested().unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(500));
+        assert!(canister.getVesting(bob()).is_empty());
+        assert_eq!(canister.balanceOf(vesting_principal()), Tokens128::ZERO);
+    }
+
+    #[test]
+    fn minted_vesting() {
+        let (context, canister) = test_context();
+        canister
+            .createVesting(bob(), Tokens128::from(500), 0, 0, true)
+            .unwrap();
+        assert_eq!(canister.getMetadata().totalSupply, Tokens128::from(1500));
+
+        context.update_caller(bob());
+        canister.claimVested().unwrap();
+        assert_eq!(canister.balanceOf(bob()), Tokens128::from(500));
+    }
+
+    #[test]
+    fn invalid_vesting() {
+        let (context, canister) = test_context();
+        assert_eq!(
+            canister.createVesting(bob(), Tokens128::from(10), DURATION, CLIFF, false),
+            Err(TxError::InvalidVesting)
+        );
+        assert_eq!(
+            canister.createVesting(bob(), Tokens128::from(2000), CLIFF, DURATION, false),
+            Err(TxError::InsufficientBalance)
+        );
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.createVesting(bob(), Tokens128::from(10), CLIFF, DURATION, true),
+            Err(TxError::Unauthorized)
+        );
+    }
+}
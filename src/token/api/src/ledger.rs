@@ -1,16 +1,98 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
 use candid::{CandidType, Deserialize, Principal};
+use ic_canister::ic_kit::ic;
 use ic_helpers::tokens::Tokens128;
 
-use crate::types::{PaginatedResult, PendingNotifications, TxId, TxRecord};
+use crate::state::impl_stable_candid;
+use crate::state::stable::{
+    BalanceHistoryKey, StableLen, StablePrincipal, StableReceiver, StableTokens, StableTxId,
+    StableTxRecord, UserTxKey, BALANCE_HISTORY, HISTORY, NOTIFICATIONS, PENDING_BALANCES,
+    USER_HISTORY, USER_HISTORY_LEN,
+};
+use crate::types::{
+    AdminEvent, DailyMetrics, Event, EventKind, FailedNotification, Operation, PaginatedResult,
+    SortOrder, Timestamp, TxId, TxRecord,
+};
 
-const MAX_HISTORY_LENGTH: usize = 1_000_000;
-const HISTORY_REMOVAL_BATCH_SIZE: usize = 10_000;
+/// Notifications can only be sent for this number of the latest transactions.
+const MAX_NOTIFIED_HISTORY_LENGTH: u64 = 1_000_000;
+/// Number of days the daily metrics are kept for.
+pub const MAX_METRICS_DAYS: usize = 90;
+const DAY: Timestamp = 24 * 60 * 60 * 1_000_000_000;
 
+/// Transaction history of the token. The records are kept in the stable memory, so they are not
+/// serialized on upgrade. Notifications can only be sent for the latest
+/// `MAX_NOTIFIED_HISTORY_LENGTH` transactions.
+///
+/// The ids of the transactions of each user are indexed by their positions in the user history
+/// in the stable memory, so the user queries don't scan the history. The balances of the users
+/// changed by a transaction are stored along with its id, see [Ledger::balance_at].
 #[derive(Debug, Default, CandidType, Deserialize)]
 pub struct Ledger {
-    history: Vec<TxRecord>,
-    vec_offset: u64,
+    len: u64,
     pub notifications: PendingNotifications,
+    /// Notifications that failed to be delivered, by the transaction id.
+    #[serde(default)]
+    pub failed_notifications: BTreeMap<TxId, FailedNotification>,
+    /// Admin events of the event log. The transactions are not stored in the event log, their
+    /// sequence numbers are computed from the sequence numbers of the admin events around them.
+    #[serde(default)]
+    admin_events: Vec<AdminEventRecord>,
+    #[serde(default)]
+    metrics: Metrics,
+    /// Id of the first transaction with the recorded balances. The balances are not recorded
+    /// before the migration from the legacy layout, see [Ledger::record_legacy_balances].
+    #[serde(default)]
+    balances_recorded_from: TxId,
+}
+
+/// Daily activity counters, updated on every recorded transaction.
+#[derive(Debug, Default, CandidType, Deserialize)]
+struct Metrics {
+    /// Counters of the latest days with transactions, the oldest first.
+    days: Vec<DailyMetrics>,
+    /// Principals active in the last day of `days`.
+    active_accounts: BTreeSet<Principal>,
+}
+
+impl Metrics {
+    fn record(&mut self, tx: &TxRecord) {
+        let day = tx.timestamp / DAY;
+        if self.days.last().map(|metrics| metrics.day) != Some(day) {
+            self.active_accounts.clear();
+            self.days.push(DailyMetrics {
+                day,
+                ..Default::default()
+            });
+            if self.days.len() > MAX_METRICS_DAYS {
+                self.days.remove(0);
+            }
+        }
+
+        let metrics = self.days.last_mut().expect("current day is pushed above");
+        match tx.operation {
+            Operation::Transfer | Operation::TransferFrom => metrics.transfers += 1,
+            Operation::Mint => metrics.mints += 1,
+            Operation::Burn => metrics.burns += 1,
+            Operation::Approve => {}
+            Operation::Auction | Operation::Freeze | Operation::Unfreeze => return,
+        }
+
+        metrics.fee_volume = (metrics.fee_volume + tx.fee).unwrap_or(Tokens128::from(u128::MAX));
+        self.active_accounts.insert(tx.from);
+        self.active_accounts.insert(tx.to);
+        self.active_accounts.extend(tx.caller);
+        metrics.active_accounts = self.active_accounts.len() as u64;
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct AdminEventRecord {
+    seq: u64,
+    timestamp: Timestamp,
+    caller: Principal,
+    event: AdminEvent,
 }
 
 impl Ledger {
@@ -19,33 +101,55 @@ impl Ledger {
     }
 
     pub fn len(&self) -> u64 {
-        self.vec_offset + self.history.len() as u64
+        self.len
     }
 
     fn next_id(&self) -> TxId {
-        self.vec_offset + self.history.len() as u64
+        self.len
     }
 
     pub fn get(&self, id: TxId) -> Option<TxRecord> {
-        self.history.get(self.get_index(id)?).cloned()
+        HISTORY.with(|history| history.borrow().get(&StableTxId(id)).map(|record| record.0))
     }
 
+    /// Returns up to `count` transactions of `who` in the given `order`, starting from the
+    /// `transaction_id` inclusive.
     pub fn get_transactions(
         &self,
         who: Option<Principal>,
         count: usize,
         transaction_id: Option<TxId>,
+        order: SortOrder,
     ) -> PaginatedResult {
-        let count = count as usize;
-        let mut transactions = self
-            .history
-            .iter()
-            .rev()
-            .filter(|tx| who.map_or(true, |c| c == tx.from || c == tx.to || Some(c) == tx.caller))
-            .filter(|tx| transaction_id.map_or(true, |id| id >= tx.index))
-            .take(count + 1)
-            .cloned()
-            .collect::<Vec<_>>();
+        let history: Box<dyn Iterator<Item = TxRecord> + '_> = match (who, order) {
+            (Some(who), SortOrder::Desc) => {
+                let end = transaction_id.map_or(user_history_len(who), |id| {
+                    user_position(who, id.saturating_add(1))
+                });
+                Box::new(
+                    (0..end)
+                        .rev()
+                        .filter_map(move |position| self.get_user_tx(who, position)),
+                )
+            }
+            (Some(who), SortOrder::Asc) => {
+                let start = user_position(who, transaction_id.unwrap_or(0));
+                Box::new(
+                    (start..user_history_len(who))
+                        .filter_map(move |position| self.get_user_tx(who, position)),
+                )
+            }
+            (None, SortOrder::Desc) => {
+                let end = transaction_id.map_or(self.len, |id| id.saturating_add(1).min(self.len));
+                Box::new((0..end).rev().filter_map(move |id| self.get(id)))
+            }
+            (None, SortOrder::Asc) => {
+                let start = transaction_id.unwrap_or(0);
+                Box::new((start..self.len).filter_map(move |id| self.get(id)))
+            }
+        };
+
+        let mut transactions = history.take(count + 1).collect::<Vec<_>>();
 
         let next_id = if transactions.len() == count + 1 {
             Some(transactions.remove(count).index)
@@ -53,29 +157,208 @@ impl Ledger {
             None
         };
 
+        let total_count = match who {
+            Some(who) => self.get_len_user_history(who) as u64,
+            None => self.len(),
+        };
+
         PaginatedResult {
             result: transactions,
             next: next_id,
+            total_count,
         }
     }
 
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TxRecord> {
-        self.history.iter()
+    /// Returns up to `limit` transactions of `who`, paging over the positions of the transactions
+    /// in the user history rather than over the transaction ids. Position 0 is the oldest user
+    /// transaction, so the positions don't change when new transactions are added.
+    ///
+    /// The `start` position is inclusive. If it's not given, the pages start from the oldest
+    /// transaction for `Asc` order and from the newest one for `Desc` order. The `next` field of
+    /// the result is the position to start the next page from.
+    pub fn get_user_transactions(
+        &self,
+        who: Principal,
+        start: Option<u64>,
+        limit: usize,
+        order: SortOrder,
+    ) -> PaginatedResult {
+        let len = user_history_len(who);
+
+        let (positions, next) = match order {
+            SortOrder::Asc => {
+                let start = start.unwrap_or(0).min(len);
+                let end = start.saturating_add(limit as u64).min(len);
+                let next = if end < len { Some(end) } else { None };
+                (start..end, next)
+            }
+            SortOrder::Desc => {
+                // Positions after the last one are treated as the last one.
+                let end = start.map_or(len, |start| start.saturating_add(1).min(len));
+                let begin = end.saturating_sub(limit as u64);
+                let next = if begin > 0 { Some(begin - 1) } else { None };
+                (begin..end, next)
+            }
+        };
+
+        let mut result = positions
+            .filter_map(|position| self.get_user_tx(who, position))
+            .collect::<Vec<_>>();
+        if order == SortOrder::Desc {
+            result.reverse();
+        }
+
+        PaginatedResult {
+            result,
+            next,
+            total_count: len,
+        }
     }
 
-    fn get_index(&self, id: TxId) -> Option<usize> {
-        if id < self.vec_offset || id > usize::MAX as TxId {
-            None
-        } else {
-            Some((id - self.vec_offset) as usize)
+    /// Balance of `who` right after the transaction `id`, which is the balance stored with the
+    /// latest change of the balance of `who` up to `id`, or zero if there is no such change.
+    /// Returns `None` if the balances at `id` are not recorded.
+    pub fn balance_at(&self, who: Principal, id: TxId) -> Option<Tokens128> {
+        if id < self.balances_recorded_from || id >= self.len {
+            return None;
         }
+
+        let balance = BALANCE_HISTORY.with(|history| {
+            history
+                .borrow()
+                .range(
+                    BalanceHistoryKey::prefix(&who),
+                    Some(BalanceHistoryKey::offset(id)),
+                )
+                .next()
+                .map_or(Tokens128::ZERO, |(_, balance)| balance.0)
+        });
+
+        Some(balance)
     }
 
-    pub fn get_len_user_history(&self, user: Principal) -> usize {
-        self.history
+    /// Stores the balances changed since the latest transaction with the next transaction id, so
+    /// the changes made without a transaction, e.g. by a distribution, don't pile up until the
+    /// next one. Called by the timer.
+    pub fn record_balance_changes(&self) {
+        record_pending_balances(self.len);
+    }
+
+    /// Stores the balances imported from the legacy layout as the balances right after the
+    /// latest imported transaction. The earlier balances are not known.
+    pub(crate) fn record_legacy_balances(&mut self) {
+        if let Some(last) = self.len.checked_sub(1) {
+            record_pending_balances(last);
+            self.balances_recorded_from = last;
+        }
+    }
+
+    /// Number of the events in the event log, which is also the sequence number of the next event.
+    pub fn events_len(&self) -> u64 {
+        self.len() + self.admin_events.len() as u64
+    }
+
+    /// Records the admin event in the event log and returns its sequence number.
+    pub fn record_admin_event(&mut self, caller: Principal, event: AdminEvent) -> u64 {
+        let seq = self.events_len();
+        self.admin_events.push(AdminEventRecord {
+            seq,
+            timestamp: ic::time(),
+            caller,
+            event,
+        });
+
+        seq
+    }
+
+    /// Returns up to `limit` events of the event log starting from the `since` sequence number
+    /// inclusive. The transactions and the admin events are returned in the order they happened.
+    pub fn get_events(&self, since: u64, limit: usize) -> Vec<Event> {
+        // The sequence number of an admin event is the number of the transactions and the admin
+        // events before it, so the events before `since` split into these two parts.
+        let mut admin_id = self
+            .admin_events
+            .partition_point(|record| record.seq < since);
+        let mut tx_id = since - admin_id as u64;
+
+        let mut events = vec![];
+        while events.len() < limit {
+            let seq = tx_id + admin_id as u64;
+            match self.admin_events.get(admin_id) {
+                Some(record) if record.seq == seq => {
+                    events.push(Event {
+                        seq,
+                        kind: EventKind::Admin {
+                            timestamp: record.timestamp,
+                            caller: record.caller,
+                            event: record.event.clone(),
+                        },
+                    });
+                    admin_id += 1;
+                }
+                _ => match self.get(tx_id) {
+                    Some(tx) => {
+                        events.push(Event {
+                            seq,
+                            kind: EventKind::Transaction(tx),
+                        });
+                        tx_id += 1;
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        events
+    }
+
+    /// Activity counters of the days with transactions among the last `days` days, the newest
+    /// first.
+    pub fn metrics(&self, days: u64) -> Vec<DailyMetrics> {
+        let today = ic::time() / DAY;
+        self.metrics
+            .days
             .iter()
-            .filter(|tx| tx.to == user || tx.from == user || tx.caller == Some(user))
-            .count()
+            .rev()
+            .take_while(|metrics| metrics.day + days > today)
+            .cloned()
+            .collect()
+    }
+
+    /// Number of the transactions of `user`.
+    pub fn get_len_user_history(&self, user: Principal) -> usize {
+        user_history_len(user) as usize
+    }
+
+    /// Moves the `history` of the ledger written before the history was kept in the stable
+    /// memory into the stable memory, see [crate::state::legacy]. The records before `offset`
+    /// were removed from the legacy history.
+    pub(crate) fn import_legacy_history(
+        &mut self,
+        offset: TxId,
+        history: Vec<TxRecord>,
+        notifications: HashMap<TxId, Option<Principal>>,
+    ) {
+        self.len = offset;
+        for record in history {
+            index_user_transaction(&record);
+            store_record(record);
+            self.len += 1;
+        }
+
+        for (id, receiver) in notifications {
+            self.notifications.insert(id, receiver);
+        }
+    }
+
+    /// Id of the transaction at the `position` in the history of the user.
+    pub fn get_user_transaction_id(&self, who: Principal, position: u64) -> Option<TxId> {
+        user_tx_id(who, position)
+    }
+
+    /// Transaction at the `position` in the history of `who`.
+    fn get_user_tx(&self, who: Principal, position: u64) -> Option<TxRecord> {
+        self.get(user_tx_id(who, position)?)
     }
 
     pub fn transfer(
@@ -91,15 +374,15 @@ impl Ledger {
         id
     }
 
+    /// Records the transfers given as `(to, amount, fee)`.
     pub fn batch_transfer(
         &mut self,
         from: Principal,
-        transfers: Vec<(Principal, Tokens128)>,
-        fee: Tokens128,
+        transfers: Vec<(Principal, Tokens128, Tokens128)>,
     ) -> Vec<TxId> {
         transfers
             .into_iter()
-            .map(|(to, amount)| self.transfer(from, to, amount, fee))
+            .map(|(to, amount, fee)| self.transfer(from, to, amount, fee))
             .collect()
     }
 
@@ -144,25 +427,310 @@ impl Ledger {
         id
     }
 
-    pub fn auction(&mut self, to: Principal, amount: Tokens128) {
+    pub fn fee_burn(&mut self, from: Principal, amount: Tokens128) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::fee_burn(id, from, amount));
+
+        id
+    }
+
+    pub fn auction(&mut self, to: Principal, amount: Tokens128) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::auction(id, to, amount));
+
+        id
+    }
+
+    pub fn freeze(&mut self, caller: Principal, who: Principal) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::freeze(id, caller, who));
+
+        id
+    }
+
+    pub fn unfreeze(&mut self, caller: Principal, who: Principal) -> TxId {
         let id = self.next_id();
-        self.push(TxRecord::auction(id, to, amount))
+        self.push(TxRecord::unfreeze(id, caller, who));
+
+        id
     }
 
     fn push(&mut self, record: TxRecord) {
-        self.history.push(record.clone());
+        self.metrics.record(&record);
+        index_user_transaction(&record);
+        // A fee burn is always followed by the transaction the fee is charged for, which records
+        // the balances changed by both.
+        if !record.is_fee_burn() {
+            record_pending_balances(record.index);
+        }
         self.notifications.insert(record.index, None);
+        if let Some(expired) = record.index.checked_sub(MAX_NOTIFIED_HISTORY_LENGTH) {
+            self.expire_notification(expired);
+        }
+
+        store_record(record);
+        self.len += 1;
+    }
 
-        if self.history.len() > MAX_HISTORY_LENGTH + HISTORY_REMOVAL_BATCH_SIZE {
-            // We remove first `HISTORY_REMOVAL_BATCH_SIZE` from the history at one go, to prevent
-            // often relocation of the history vec.
-            // This removal code can later be changed to moving old history records into another
-            // storage.
-            for record in &self.history[..HISTORY_REMOVAL_BATCH_SIZE] {
-                self.notifications.remove(&record.index);
+    /// Removes the pending and the failed notifications of the transaction, so it can no longer
+    /// be notified about.
+    fn expire_notification(&mut self, id: TxId) {
+        self.notifications.remove(id);
+        self.failed_notifications.remove(&id);
+    }
+}
+
+/// Pending notifications of the transactions by their ids, stored in the stable memory. The
+/// receiver is `None` until the notification is sent for the first time.
+#[derive(Debug, Default)]
+pub struct PendingNotifications;
+
+impl PendingNotifications {
+    pub fn get(&self, id: TxId) -> Option<Option<Principal>> {
+        NOTIFICATIONS.with(|map| map.borrow().get(&StableTxId(id)).map(|receiver| receiver.0))
+    }
+
+    pub fn contains_key(&self, id: TxId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn insert(&mut self, id: TxId, receiver: Option<Principal>) {
+        NOTIFICATIONS.with(|map| {
+            map.borrow_mut()
+                .insert(StableTxId(id), StableReceiver(receiver))
+                .expect("notification key and value have fixed size");
+        })
+    }
+
+    pub fn remove(&mut self, id: TxId) {
+        NOTIFICATIONS.with(|map| map.borrow_mut().remove(&StableTxId(id)));
+    }
+}
+
+impl_stable_candid!(PendingNotifications);
+
+fn store_record(record: TxRecord) {
+    HISTORY.with(|history| {
+        history
+            .borrow_mut()
+            .insert(StableTxId(record.index), StableTxRecord(record))
+            .expect("transaction record size is limited");
+    });
+}
+
+/// Adds the transaction to the histories of its sender, recipient and caller.
+fn index_user_transaction(record: &TxRecord) {
+    let mut users = vec![record.from, record.to];
+    users.extend(record.caller);
+    users.sort();
+    users.dedup();
+
+    USER_HISTORY.with(|history| {
+        USER_HISTORY_LEN.with(|lens| {
+            let mut history = history.borrow_mut();
+            let mut lens = lens.borrow_mut();
+            for who in users {
+                let position = lens.get(&StablePrincipal(who)).map_or(0, |len| len.0);
+                history
+                    .insert(UserTxKey { who, position }, StableTxId(record.index))
+                    .expect("user history key and value have fixed size");
+                lens.insert(StablePrincipal(who), StableLen(position + 1))
+                    .expect("user history length key and value have fixed size");
             }
-            self.history = self.history[HISTORY_REMOVAL_BATCH_SIZE..].into();
-            self.vec_offset += HISTORY_REMOVAL_BATCH_SIZE as u64;
+        })
+    });
+}
+
+fn user_history_len(who: Principal) -> u64 {
+    USER_HISTORY_LEN.with(|lens| {
+        lens.borrow()
+            .get(&StablePrincipal(who))
+            .map_or(0, |len| len.0)
+    })
+}
+
+fn user_tx_id(who: Principal, position: u64) -> Option<TxId> {
+    USER_HISTORY.with(|history| {
+        history
+            .borrow()
+            .get(&UserTxKey { who, position })
+            .map(|id| id.0)
+    })
+}
+
+/// Marks the balance of `who` as changed, so it is stored in the balance history with the next
+/// transaction. Called on every balance change.
+pub(crate) fn record_balance_change(who: Principal, balance: Tokens128) {
+    PENDING_BALANCES.with(|pending| {
+        pending
+            .borrow_mut()
+            .insert(StablePrincipal(who), StableTokens(balance))
+            .expect("pending balance key and value have fixed size");
+    });
+}
+
+/// Moves the balances changed since the latest transaction to the balance history as the
+/// balances right after the transaction `id`.
+fn record_pending_balances(id: TxId) {
+    PENDING_BALANCES.with(|pending| {
+        BALANCE_HISTORY.with(|history| {
+            let mut pending = pending.borrow_mut();
+            let mut history = history.borrow_mut();
+            let changes = pending.iter().collect::<Vec<_>>();
+            for (who, balance) in changes {
+                pending.remove(&who);
+                history
+                    .insert(BalanceHistoryKey { who: who.0, id }, balance)
+                    .expect("balance history key and value have fixed size");
+            }
+        })
+    });
+}
+
+/// Number of the transactions of `who` with the ids lower than `id`, which is also the position
+/// of the first transaction of `who` starting from `id`. The ids grow with the positions, so the
+/// position is found with a binary search.
+fn user_position(who: Principal, id: TxId) -> u64 {
+    let mut low = 0;
+    let mut high = user_history_len(who);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if user_tx_id(who, middle).map_or(false, |middle_id| middle_id < id) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob, john};
+    use ic_canister::ic_kit::MockContext;
+
+    use crate::canister::CanisterUpdate;
+
+    use super::*;
+
+    #[test]
+    fn history() {
+        MockContext::new().inject();
+        let mut ledger = Ledger::default();
+        for _ in 0..10 {
+            ledger.transfer(alice(), bob(), Tokens128::from(1), Tokens128::ZERO);
+        }
+        ledger.transfer(alice(), john(), Tokens128::from(1), Tokens128::ZERO);
+        ledger.expire_notification(2);
+
+        assert_eq!(ledger.len(), 11);
+        assert_eq!(ledger.get(2).unwrap().index, 2);
+        assert_eq!(ledger.get(7).unwrap().index, 7);
+        assert!(ledger.get(11).is_none());
+        assert!(!ledger.notifications.contains_key(2));
+        assert_eq!(ledger.notifications.get(3), Some(None));
+
+        let page = ledger.get_transactions(None, 3, Some(6), SortOrder::Desc);
+        let ids = page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![6, 5, 4]);
+        assert_eq!(page.next, Some(3));
+        assert_eq!(page.total_count, 11);
+
+        let page = ledger.get_transactions(None, 3, Some(3), SortOrder::Asc);
+        let ids = page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4, 5]);
+        assert_eq!(page.next, Some(6));
+
+        let page = ledger.get_transactions(Some(john()), 3, None, SortOrder::Desc);
+        assert_eq!(page.result.len(), 1);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn user_history() {
+        MockContext::new().inject();
+        let mut ledger = Ledger::default();
+        for _ in 0..10 {
+            ledger.transfer(alice(), bob(), Tokens128::from(1), Tokens128::ZERO);
+            ledger.transfer(alice(), john(), Tokens128::from(1), Tokens128::ZERO);
         }
+        let ids = |page: PaginatedResult| page.result.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(ledger.get_len_user_history(bob()), 10);
+        assert_eq!(ledger.get_len_user_history(alice()), 20);
+
+        let page = ledger.get_user_transactions(bob(), Some(6), 3, SortOrder::Desc);
+        assert_eq!(page.total_count, 10);
+        assert_eq!(page.next, Some(3));
+        assert_eq!(ids(page), vec![12, 10, 8]);
+
+        let page = ledger.get_transactions(Some(bob()), 3, Some(15), SortOrder::Desc);
+        assert_eq!(ids(page.clone()), vec![14, 12, 10]);
+        assert_eq!(page.next, Some(8));
+        assert_eq!(page.total_count, 10);
+
+        let page = ledger.get_transactions(Some(john()), 2, Some(12), SortOrder::Asc);
+        assert_eq!(ids(page.clone()), vec![13, 15]);
+        assert_eq!(page.next, Some(17));
+    }
+
+    #[test]
+    fn daily_metrics() {
+        let context = MockContext::new().inject();
+        let mut ledger = Ledger::default();
+        ledger.mint(alice(), alice(), Tokens128::from(100));
+        ledger.transfer(alice(), bob(), Tokens128::from(10), Tokens128::from(1));
+        ledger.transfer_from(
+            john(),
+            alice(),
+            bob(),
+            Tokens128::from(10),
+            Tokens128::from(1),
+        );
+        ledger.freeze(alice(), john());
+
+        context.add_time(DAY);
+        ledger.burn(bob(), bob(), Tokens128::from(5));
+
+        let metrics = ledger.metrics(2);
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].burns, 1);
+        assert_eq!(metrics[0].active_accounts, 1);
+        assert_eq!(metrics[1].day + 1, metrics[0].day);
+        assert_eq!(metrics[1].transfers, 2);
+        assert_eq!(metrics[1].mints, 1);
+        assert_eq!(metrics[1].active_accounts, 3);
+        assert_eq!(metrics[1].fee_volume, Tokens128::from(2));
+
+        assert_eq!(ledger.metrics(1).len(), 1);
+        context.add_time(DAY);
+        assert!(ledger.metrics(1).is_empty());
+    }
+
+    #[test]
+    fn event_log() {
+        MockContext::new().inject();
+        let mut ledger = Ledger::default();
+        let fee_update = AdminEvent::Update(CanisterUpdate::Fee(Tokens128::from(10)));
+        ledger.record_admin_event(alice(), fee_update.clone());
+        ledger.transfer(alice(), bob(), Tokens128::from(1), Tokens128::ZERO);
+        ledger.transfer(alice(), bob(), Tokens128::from(2), Tokens128::ZERO);
+        assert_eq!(ledger.record_admin_event(alice(), fee_update.clone()), 3);
+        ledger.transfer(alice(), bob(), Tokens128::from(3), Tokens128::ZERO);
+        assert_eq!(ledger.events_len(), 5);
+
+        let events = ledger.get_events(0, 10);
+        let seqs = events.iter().map(|event| event.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, vec![0, 1, 2, 3, 4]);
+        assert!(matches!(&events[0].kind, EventKind::Admin { event, .. } if *event == fee_update));
+        assert!(matches!(&events[2].kind, EventKind::Transaction(tx) if tx.index == 1));
+        assert!(matches!(&events[4].kind, EventKind::Transaction(tx) if tx.index == 2));
+
+        let events = ledger.get_events(2, 2);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].seq, 2);
+        assert!(matches!(&events[1].kind, EventKind::Admin { .. }));
+
+        assert!(ledger.get_events(5, 10).is_empty());
     }
 }
@@ -1,6 +1,9 @@
+pub mod amount;
+pub mod amount_math;
 pub mod canister;
 pub mod ledger;
 pub mod principal;
+pub mod receiver;
 pub mod state;
 pub mod types;
 
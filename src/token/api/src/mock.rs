@@ -1,9 +1,14 @@
 use std::{cell::RefCell, rc::Rc};
 
 use candid::Principal;
+use ic_canister::ic_kit::mock_principals::alice;
+use ic_canister::ic_kit::MockContext;
 use ic_canister::{Canister, PreUpdate};
+use ic_helpers::tokens::Tokens128;
 
-use crate::{canister::TokenCanisterAPI, state::CanisterState, types::Metadata};
+use crate::canister::TokenCanisterAPI;
+use crate::state::CanisterState;
+use crate::types::{valid_metadata_key, Metadata};
 
 #[derive(Debug, Clone, Canister)]
 pub struct TokenCanisterMock {
@@ -15,20 +20,42 @@ pub struct TokenCanisterMock {
 
 impl TokenCanisterMock {
     pub fn init(&self, metadata: Metadata) {
-        self.state
-            .borrow_mut()
-            .balances
-            .0
-            .insert(metadata.owner, metadata.totalSupply);
+        if let Some(max_supply) = metadata.maxSupply {
+            assert!(
+                metadata.totalSupply <= max_supply,
+                "initial supply exceeds the max supply"
+            );
+        }
 
-        self.state
-            .borrow_mut()
-            .ledger
-            .mint(metadata.owner, metadata.owner, metadata.totalSupply);
+        let initial_balances = metadata
+            .initial_balances()
+            .expect("genesis allocation doesn't sum up to the total supply");
+        for (holder, amount) in initial_balances {
+            let mut state = self.state.borrow_mut();
+            let balance = (state.balances.balance_of(&holder) + amount)
+                .expect("balances sum up to the total supply");
+            state.balances.set(holder, balance);
+            state.ledger.mint(metadata.owner, holder, amount);
+        }
 
+        let custom_metadata = metadata.customMetadata.clone().unwrap_or_default();
+        assert!(
+            custom_metadata
+                .iter()
+                .all(|(key, _)| valid_metadata_key(key)),
+            "invalid custom metadata key"
+        );
+
+        let auction_period = metadata
+            .auctionPeriod
+            .map(|period_sec| period_sec.saturating_mul(1_000_000_000))
+            .unwrap_or(crate::canister::DEFAULT_AUCTION_PERIOD);
+        self.state.borrow_mut().owner_rotation.registry = metadata.registry;
         self.state.borrow_mut().stats = metadata.into();
-        self.state.borrow_mut().bidding_state.auction_period =
-            crate::canister::DEFAULT_AUCTION_PERIOD;
+        self.state.borrow_mut().bidding_state.auction_period = auction_period;
+        self.state.borrow_mut().custom_metadata = custom_metadata.into_iter().collect();
+        let transactions = self.state.borrow().ledger.len();
+        self.state.borrow_mut().allowances_import = Some(transactions);
     }
 }
 
@@ -43,3 +70,36 @@ impl TokenCanisterAPI for TokenCanisterMock {
         self.state.clone()
     }
 }
+
+/// Metadata of the token used in the tests: 1000 tokens owned by `alice`, with zero fee. The tests
+/// that depend on other values override them with the struct update syntax.
+pub fn test_metadata() -> Metadata {
+    Metadata {
+        logo: "".to_string(),
+        name: "".to_string(),
+        symbol: "".to_string(),
+        decimals: 8,
+        totalSupply: Tokens128::from(1000),
+        owner: alice(),
+        fee: Tokens128::from(0),
+        feeTo: alice(),
+        isTestToken: None,
+        maxSupply: None,
+        auctionDisabled: None,
+        auctionPeriod: None,
+        minCycles: None,
+        genesisAllocation: None,
+        customMetadata: None,
+        registry: None,
+    }
+}
+
+/// Token initialized with [test_metadata], called by `alice`.
+pub fn test_context() -> (&'static mut MockContext, TokenCanisterMock) {
+    let context = MockContext::new().with_caller(alice()).inject();
+
+    let canister = TokenCanisterMock::init_instance();
+    canister.init(test_metadata());
+
+    (context, canister)
+}
@@ -1,11 +1,32 @@
 use candid::Principal;
 
+use crate::state::{CanisterState, Multisig};
 use crate::types::{StatsData, TxError};
 use ic_canister::ic_kit::ic;
 
 /// Canister owner
 pub struct Owner;
 
+/// Canister owner or one of the minters
+pub struct Minter;
+
+/// Canister owner or one of the pausers, who can freeze and unfreeze accounts
+pub struct Pauser;
+
+/// Canister owner or one of the admins, who can manage the pausers
+pub struct Admin;
+
+/// Roles allowed to change the canister settings. The owner can make any change, while the
+/// endpoints available to the admins only make the changes of the admin role.
+pub trait SettingsRole {}
+
+impl SettingsRole for Owner {}
+
+impl SettingsRole for Admin {}
+
+/// One of the multisig signers
+pub struct Signer;
+
 /// Any principal but the canister
 /// has isTestToken set to true
 pub struct TestNet;
@@ -31,14 +52,74 @@ impl<T> CheckedPrincipal<T> {
 }
 
 impl CheckedPrincipal<Owner> {
-    pub fn owner(stats: &StatsData) -> Result<Self, TxError> {
+    /// The owner keeps the access to the methods that have no [crate::state::AdminOp]
+    /// counterpart while the multisig is enabled.
+    pub fn owner(state: &CanisterState) -> Result<Self, TxError> {
         let caller = ic::caller();
-        if caller == stats.owner {
+        if caller == state.stats.owner {
             Ok(Self(caller, Owner))
         } else {
             Err(TxError::Unauthorized)
         }
     }
+
+    /// The owner of the methods whose changes are made with the [crate::state::AdminOp]
+    /// proposals while the multisig is enabled. `MultisigRequired` error is returned for the owner
+    /// then, so the proposals cannot be bypassed.
+    pub fn owner_outside_multisig(state: &CanisterState) -> Result<Self, TxError> {
+        let owner = Self::owner(state)?;
+        state.multisig.check_disabled()?;
+        Ok(owner)
+    }
+}
+
+impl CheckedPrincipal<Minter> {
+    /// The owner can only mint with the [crate::state::AdminOp::Mint] proposals while the
+    /// multisig is enabled.
+    pub fn minter(state: &CanisterState) -> Result<Self, TxError> {
+        let caller = ic::caller();
+        if caller == state.stats.owner {
+            state.multisig.check_disabled()?;
+            Ok(Self(caller, Minter))
+        } else if state.stats.minters.contains(&caller) {
+            Ok(Self(caller, Minter))
+        } else {
+            Err(TxError::Unauthorized)
+        }
+    }
+}
+
+impl CheckedPrincipal<Pauser> {
+    pub fn pauser(state: &CanisterState) -> Result<Self, TxError> {
+        let caller = ic::caller();
+        if caller == state.stats.owner || state.stats.pausers.contains(&caller) {
+            Ok(Self(caller, Pauser))
+        } else {
+            Err(TxError::Unauthorized)
+        }
+    }
+}
+
+impl CheckedPrincipal<Admin> {
+    pub fn admin(state: &CanisterState) -> Result<Self, TxError> {
+        let caller = ic::caller();
+        if caller == state.stats.owner || state.stats.admins.contains(&caller) {
+            Ok(Self(caller, Admin))
+        } else {
+            Err(TxError::Unauthorized)
+        }
+    }
+}
+
+impl CheckedPrincipal<Signer> {
+    pub fn signer(multisig: &Multisig) -> Result<Self, TxError> {
+        let caller = ic::caller();
+        if multisig.is_signer(&caller) {
+            Ok(Self(caller, Signer))
+        } else {
+            Err(TxError::Unauthorized)
+        }
+    }
 }
 
 impl CheckedPrincipal<TestNet> {
@@ -0,0 +1,91 @@
+//! Interface of the canisters receiving IS20 tokens.
+//!
+//! A canister implementing [Is20Receiver] is notified with an `is20_on_received` call about every
+//! token transfer made to it with `transferWithHook`. The token canister checks that the receiver
+//! declares the method in its candid interface before making the transfer, so the tokens cannot
+//! be sent to a canister that will never learn about them. The same check is made by
+//! `safeTransfer` for the canister recipients.
+//!
+//! A spender approved with `approveAndCall` is called with [ON_APPROVAL_METHOD], so it can tell
+//! that the token canister makes the call on behalf of the approving user. In the same way, the
+//! receiver of `transferAndCall` can only be called with a method starting with
+//! [ON_TRANSFER_METHOD_PREFIX].
+
+use candid::Principal;
+use ic_canister::AsyncReturn;
+use ic_helpers::tokens::Tokens128;
+
+use crate::types::TxId;
+
+/// Name of the method called on the receiver canister.
+pub const ON_RECEIVED_METHOD: &str = "is20_on_received";
+
+/// Name of the method called on the spender canister by `approveAndCall`, with the
+/// [ApprovalCall](crate::types::ApprovalCall) argument.
+pub const ON_APPROVAL_METHOD: &str = "is20_on_approval";
+
+/// Prefix of the methods that can be called on the receiver canister by `transferAndCall`, with
+/// the [TransactionCall](crate::types::TransactionCall) argument, e.g. `is20_on_transfer_deposit`.
+pub const ON_TRANSFER_METHOD_PREFIX: &str = "is20_on_transfer_";
+
+/// Candid fragment of the receiver interface, to be included in the service definition of the
+/// receiver canister.
+pub const RECEIVER_CANDID: &str = "is20_on_received : (principal, nat, nat64, opt vec nat8) -> ();";
+
+/// Query method returning the candid interface of a canister, which is used to check whether the
+/// receiver implements [ON_RECEIVED_METHOD].
+pub const CANDID_INTERFACE_METHOD: &str = "__get_candid_interface_tmp_hack";
+
+/// Receiver of the IS20 token transfers.
+pub trait Is20Receiver {
+    /// Called by the token canister after `amount` of tokens were transferred from `from` to this
+    /// canister in the transaction `tx_id`. The `memo` is given by the sender as is.
+    ///
+    /// The call is made after the transfer is done, so failing it doesn't revert the transfer.
+    fn is20_on_received<'a>(
+        &'a self,
+        from: Principal,
+        amount: Tokens128,
+        tx_id: TxId,
+        memo: Option<Vec<u8>>,
+    ) -> AsyncReturn<()>;
+}
+
+/// Returns `true` if the principal is a canister id. The canister ids are opaque ids, which end
+/// with `0x01`, while the user principals are self-authenticating ids ending with `0x02`.
+pub fn is_canister(principal: &Principal) -> bool {
+    principal.as_slice().last() == Some(&0x01)
+}
+
+/// Returns `true` if the candid interface of the receiver declares [ON_RECEIVED_METHOD].
+pub fn declares_on_received(candid_interface: &str) -> bool {
+    candid_interface
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .any(|word| word == ON_RECEIVED_METHOD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canister_principals() {
+        assert!(is_canister(&Principal::from_slice(&[
+            0, 0, 0, 0, 0, 0, 0, 1, 1, 1
+        ])));
+        assert!(!is_canister(&Principal::self_authenticating(b"public key")));
+        assert!(!is_canister(&Principal::anonymous()));
+        assert!(!is_canister(&Principal::management_canister()));
+    }
+
+    #[test]
+    fn receiver_candid_declares_method() {
+        assert!(declares_on_received(&format!(
+            "service : {{ {RECEIVER_CANDID} }}"
+        )));
+        assert!(!declares_on_received(
+            "service : { is20_on_received_v2 : () -> () }"
+        ));
+        assert!(!declares_on_received(""));
+    }
+}
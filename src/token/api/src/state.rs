@@ -1,19 +1,64 @@
-use crate::ledger::Ledger;
-use crate::types::{Allowances, AuctionInfo, Cycles, Metadata, StatsData, Timestamp};
-use candid::{CandidType, Deserialize, Principal};
+use crate::amount_math::{proportional_share, saturating_add, saturating_sub};
+use crate::ledger::{record_balance_change, Ledger};
+use crate::types::{
+    AllowanceEntry, AuctionInfo, AuctionRewardAsset, AuctionStats, BidToken, Cycles, FeeMode,
+    Metadata, MethodPolicy, PaginatedAuctions, RewardNotification, StatsData, SubscriptionFilter,
+    Timestamp, TxError, TxId, Value,
+};
+use candid::types::{Serializer, Type};
+use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_helpers::tokens::Tokens128;
+use ic_stable_structures::Storable;
 use ic_storage::stable::Versioned;
 use ic_storage::IcStorage;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use self::legacy::CanisterStateV1;
+use self::stable::{
+    AccountDataKey, Empty, HolderKey, PrincipalPair, StableAuctionId, StableAuctionInfo,
+    StableBytes, StableCycles, StablePrincipal, StableTokens, ACCOUNT_DATA, ALLOWANCES,
+    AUCTION_HISTORY, BALANCES, BIDS, HOLDERS, SPENDER_INDEX,
+};
+
+pub mod legacy;
+pub mod stable;
 
 #[derive(Debug, Default, CandidType, Deserialize, IcStorage)]
 pub struct CanisterState {
     pub bidding_state: BiddingState,
     pub balances: Balances,
     pub auction_history: AuctionHistory,
+    pub auction_stats: AuctionStats,
     pub stats: StatsData,
     pub allowances: Allowances,
     pub ledger: Ledger,
+    /// Owner-defined metadata entries returned by `icrc1_metadata` along with the token stats.
+    pub custom_metadata: BTreeMap<String, Value>,
+    pub owner_rotation: OwnerRotation,
+    pub account_data: AccountData,
+    /// Accounts that are not allowed to send, receive or approve tokens.
+    pub frozen_accounts: BTreeSet<Principal>,
+    /// Principals allowed to consume any notification, see `addNotificationConsumer`.
+    pub notification_consumers: BTreeSet<Principal>,
+    pub multisig: Multisig,
+    pub timelock: Timelock,
+    /// Owner-configured overrides of the `inspect_message` checks.
+    pub method_policies: BTreeMap<String, MethodPolicy>,
+    /// Canisters notified about the new transactions, see `subscribe`.
+    pub subscriptions: BTreeMap<Principal, Subscription>,
+    pub scheduler: Scheduler,
+    pub vesting: VestingState,
+    pub escrows: Escrows,
+    pub streams: Streams,
+    pub airdrop: Option<Airdrop>,
+    pub distribution: Option<Distribution>,
+    pub cycles_monitor: CyclesMonitor,
+    pub faucet: Faucet,
+    /// ICRC-1 transfers within the deduplication window, see `icrc1_transfer`.
+    pub recent_transfers: RecentTransfers,
+    /// Number of the transactions at the token creation. The allowances of the migrated token can
+    /// only be imported before any other transaction is made, see `importAllowances`.
+    pub allowances_import: Option<TxId>,
 }
 
 impl CanisterState {
@@ -28,80 +73,1194 @@ impl CanisterState {
             fee: self.stats.fee,
             feeTo: self.stats.fee_to,
             isTestToken: Some(self.stats.is_test_token),
+            maxSupply: self.stats.max_supply,
+            auctionDisabled: Some(self.stats.auction_disabled),
+            auctionPeriod: Some(self.bidding_state.auction_period / 1_000_000_000),
+            minCycles: Some(self.stats.min_cycles),
+            genesisAllocation: None,
+            customMetadata: Some(
+                self.custom_metadata
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect(),
+            ),
+            registry: self.owner_rotation.registry,
         }
     }
 
-    pub fn allowance(&self, owner: Principal, spender: Principal) -> Tokens128 {
-        match self.allowances.get(&owner) {
-            Some(inner) => match inner.get(&spender) {
-                Some(value) => *value,
-                None => Tokens128::from(0u128),
-            },
-            None => Tokens128::from(0u128),
+    /// Token metadata in the ICRC-1 format. Custom entries with keys in the `icrc1:` namespace are
+    /// skipped, as these keys are reserved for the values taken from the token stats.
+    pub fn icrc1_metadata(&self) -> Vec<(String, Value)> {
+        let mut metadata = vec![
+            (
+                "icrc1:name".to_string(),
+                Value::Text(self.stats.name.clone()),
+            ),
+            (
+                "icrc1:symbol".to_string(),
+                Value::Text(self.stats.symbol.clone()),
+            ),
+            (
+                "icrc1:decimals".to_string(),
+                Value::Nat(Nat::from(self.stats.decimals as u64)),
+            ),
+            (
+                "icrc1:fee".to_string(),
+                Value::Nat(Nat::from(self.stats.fee.amount)),
+            ),
+            (
+                "icrc1:logo".to_string(),
+                Value::Text(self.stats.logo.clone()),
+            ),
+        ];
+
+        metadata.extend(
+            self.custom_metadata
+                .iter()
+                .filter(|(key, _)| !key.starts_with("icrc1:"))
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+
+        metadata
+    }
+
+    /// Balance of `who` right after the transaction `id`, including the fees received by `who`.
+    /// Returns `None` if the transaction doesn't exist or was imported from the legacy layout
+    /// before the latest imported transaction.
+    pub fn balance_of_at(&self, who: Principal, id: TxId) -> Option<Tokens128> {
+        self.ledger.balance_at(who, id)
+    }
+
+    /// Returns `AccountFrozen` error if any of the `accounts` is frozen.
+    pub fn check_not_frozen(&self, accounts: &[Principal]) -> Result<(), TxError> {
+        if accounts
+            .iter()
+            .any(|account| self.frozen_accounts.contains(account))
+        {
+            Err(TxError::AccountFrozen)
+        } else {
+            Ok(())
         }
     }
 
+    pub fn allowance(&self, owner: Principal, spender: Principal) -> Tokens128 {
+        self.allowances.get(owner, spender)
+    }
+
     pub fn allowance_size(&self) -> usize {
-        self.allowances
-            .iter()
-            .map(|(_, v)| v.len())
-            .reduce(|accum, v| accum + v)
-            .unwrap_or(0)
+        self.allowances.len()
     }
 
     pub fn user_approvals(&self, who: Principal) -> Vec<(Principal, Tokens128)> {
-        match self.allowances.get(&who) {
-            Some(allow) => Vec::from_iter(allow.clone().into_iter()),
-            None => Vec::new(),
-        }
+        self.allowances.owner_allowances(who)
+    }
+
+    pub fn spender_approvals(&self, spender: Principal) -> Vec<(Principal, Tokens128)> {
+        self.allowances.spender_allowances(spender)
     }
 }
 impl Versioned for CanisterState {
-    type Previous = ();
+    type Previous = CanisterStateV1;
 
-    fn upgrade((): ()) -> Self {
-        Self::default()
+    fn upgrade(legacy: CanisterStateV1) -> Self {
+        legacy.into()
     }
 }
 
+/// Balances stored in the stable memory. Accounts with zero balance are not stored.
+///
+/// The balances are not part of the serialized canister state, as the stable memory is kept as is
+/// between the upgrades. Only the balance distribution, which is updated on every balance change,
+/// is kept in the heap.
 #[derive(Debug, Default, CandidType, Deserialize)]
-pub struct Balances(pub HashMap<Principal, Tokens128>);
+pub struct Balances {
+    /// Number of holders with balance in `[10^i, 10^(i+1))` at index `i`.
+    histogram: Vec<u64>,
+    /// Balance locks of the holders, see `lock`. Expired locks are removed on the next lock of
+    /// the holder.
+    #[serde(default)]
+    locks: BTreeMap<Principal, Vec<BalanceLock>>,
+}
+
+/// Part of the holder balance that cannot be moved until the `until` timestamp.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct BalanceLock {
+    pub amount: Tokens128,
+    pub until: Timestamp,
+}
 
 impl Balances {
     pub fn balance_of(&self, who: &Principal) -> Tokens128 {
-        self.0
+        BALANCES.with(|map| {
+            map.borrow()
+                .get(&StablePrincipal(*who))
+                .map_or(Tokens128::ZERO, |amount| amount.0)
+        })
+    }
+
+    /// Sets the balance of the account. Zero amount removes the account. The changed balance is
+    /// recorded in the balance history with the next transaction, see [Ledger::balance_at].
+    pub fn set(&mut self, who: Principal, amount: Tokens128) {
+        let previous = BALANCES.with(|map| {
+            let mut map = map.borrow_mut();
+            let key = StablePrincipal(who);
+            if amount.is_zero() {
+                map.remove(&key)
+            } else {
+                map.insert(key, StableTokens(amount))
+                    .expect("balance key and value have fixed size")
+            }
+        });
+
+        HOLDERS.with(|holders| {
+            let mut holders = holders.borrow_mut();
+            if let Some(previous) = previous {
+                holders.remove(&HolderKey {
+                    amount: previous.0,
+                    who,
+                });
+            }
+            if !amount.is_zero() {
+                holders
+                    .insert(HolderKey { amount, who }, Empty)
+                    .expect("holder key has fixed size");
+            }
+        });
+
+        if previous.map_or(Tokens128::ZERO, |previous| previous.0) != amount {
+            record_balance_change(who, amount);
+        }
+
+        if let Some(previous) = previous {
+            self.histogram[histogram_bucket(previous.0)] -= 1;
+        }
+
+        if !amount.is_zero() {
+            let bucket = histogram_bucket(amount);
+            if self.histogram.len() <= bucket {
+                self.histogram.resize(bucket + 1, 0);
+            }
+            self.histogram[bucket] += 1;
+        }
+    }
+
+    /// Total amount of the `who` balance that is currently locked.
+    pub fn locked(&self, who: &Principal) -> Tokens128 {
+        self.active_locks(who).fold(Tokens128::ZERO, |total, lock| {
+            saturating_add(total, lock.amount)
+        })
+    }
+
+    /// Part of the `who` balance that is not locked.
+    pub fn transferable(&self, who: &Principal) -> Tokens128 {
+        saturating_sub(self.balance_of(who), self.locked(who))
+    }
+
+    /// Locks of `who` that are not expired yet.
+    pub fn locks(&self, who: &Principal) -> Vec<BalanceLock> {
+        self.active_locks(who).cloned().collect()
+    }
+
+    /// Adds the lock of `who`, removing the expired ones.
+    pub fn lock(&mut self, who: Principal, lock: BalanceLock) {
+        let now = ic_canister::ic_kit::ic::time();
+        let locks = self.locks.entry(who).or_default();
+        locks.retain(|lock| lock.until > now);
+        locks.push(lock);
+    }
+
+    fn active_locks<'a>(&'a self, who: &Principal) -> impl Iterator<Item = &'a BalanceLock> {
+        let now = ic_canister::ic_kit::ic::time();
+        self.locks
             .get(who)
-            .cloned()
-            .unwrap_or_else(|| Tokens128::from(0u128))
+            .into_iter()
+            .flatten()
+            .filter(move |lock| lock.until > now)
+    }
+
+    /// Number of holders with balance in `[10^i, 10^(i+1))` at index `i`. The balances are taken
+    /// in the smallest token units.
+    pub fn histogram(&self) -> &[u64] {
+        &self.histogram
+    }
+
+    pub fn contains(&self, who: &Principal) -> bool {
+        BALANCES.with(|map| map.borrow().contains_key(&StablePrincipal(*who)))
+    }
+
+    /// Number of the accounts with non-zero balance.
+    pub fn len(&self) -> usize {
+        BALANCES.with(|map| map.borrow().len() as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns up to `limit` holders ordered by balance descending and then by principal, starting
+    /// right after the `after` holder. The last returned holder is used as the cursor for the next
+    /// page.
+    pub fn get_holders(
+        &self,
+        after: Option<(Principal, Tokens128)>,
+        limit: usize,
+    ) -> Vec<(Principal, Tokens128)> {
+        let after = after.map(|(who, amount)| HolderKey { amount, who });
+        HOLDERS.with(|holders| {
+            holders
+                .borrow()
+                .range(vec![], after.map(|key| key.to_bytes().into_owned()))
+                .map(|(key, _)| key)
+                .filter(|key| Some(*key) != after)
+                .take(limit)
+                .map(|key| (key.who, key.amount))
+                .collect()
+        })
+    }
+
+    /// Returns up to `limit` holders ordered by principal, starting right after the `after`
+    /// holder. Unlike [Self::get_holders], the order doesn't change with the balances, so the
+    /// pages don't skip or repeat the holders whose balances change between the calls.
+    pub fn get_accounts(
+        &self,
+        after: Option<Principal>,
+        limit: usize,
+    ) -> Vec<(Principal, Tokens128)> {
+        let after = after.map(StablePrincipal);
+        BALANCES.with(|map| {
+            map.borrow()
+                .range(vec![], after.map(|key| key.to_bytes().into_owned()))
+                .filter(|(key, _)| Some(*key) != after)
+                .take(limit)
+                .map(|(key, amount)| (key.0, amount.0))
+                .collect()
+        })
+    }
+}
+
+fn histogram_bucket(amount: Tokens128) -> usize {
+    let mut amount = amount.amount;
+    let mut bucket = 0;
+    while amount >= 10 {
+        amount /= 10;
+        bucket += 1;
+    }
+
+    bucket
+}
+
+/// Allowances stored in the stable memory, keyed by the `(owner, spender)` pair. All the
+/// allowances of one owner are stored next to each other, so they can be listed without going
+/// through the whole map. The reverse `(spender, owner)` index is kept to list the allowances of
+/// one spender in the same way.
+///
+/// The allowances are not part of the serialized canister state, as the stable memory is kept
+/// as is between the upgrades.
+#[derive(Debug, Default)]
+pub struct Allowances;
+
+impl Allowances {
+    pub fn get(&self, owner: Principal, spender: Principal) -> Tokens128 {
+        ALLOWANCES.with(|map| {
+            map.borrow()
+                .get(&PrincipalPair(owner, spender))
+                .map_or(Tokens128::ZERO, |amount| amount.0)
+        })
+    }
+
+    /// Sets the allowance of the `spender` for the tokens of the `owner`. Zero amount removes the
+    /// allowance.
+    pub fn set(&mut self, owner: Principal, spender: Principal, amount: Tokens128) {
+        ALLOWANCES.with(|map| {
+            let mut map = map.borrow_mut();
+            let key = PrincipalPair(owner, spender);
+            if amount.is_zero() {
+                map.remove(&key);
+            } else {
+                map.insert(key, StableTokens(amount))
+                    .expect("allowance key and value have fixed size");
+            }
+        });
+
+        SPENDER_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            let key = PrincipalPair(spender, owner);
+            if amount.is_zero() {
+                index.remove(&key);
+            } else {
+                index
+                    .insert(key, Empty)
+                    .expect("spender index key has fixed size");
+            }
+        })
+    }
+
+    /// All the allowances given to the `spender` as `(owner, amount)` pairs.
+    pub fn spender_allowances(&self, spender: Principal) -> Vec<(Principal, Tokens128)> {
+        let owners: Vec<Principal> = SPENDER_INDEX.with(|index| {
+            index
+                .borrow()
+                .range(PrincipalPair::prefix(&spender), None)
+                .map(|(key, _)| key.1)
+                .collect()
+        });
+
+        owners
+            .into_iter()
+            .map(|owner| (owner, self.get(owner, spender)))
+            .collect()
+    }
+
+    /// Builds the spender index for the allowances given before the index was introduced. Does
+    /// nothing if the index is already built.
+    pub fn rebuild_spender_index(&mut self) {
+        let is_built = SPENDER_INDEX.with(|index| !index.borrow().is_empty());
+        if is_built {
+            return;
+        }
+
+        ALLOWANCES.with(|map| {
+            SPENDER_INDEX.with(|index| {
+                let mut index = index.borrow_mut();
+                for (key, _) in map.borrow().iter() {
+                    index
+                        .insert(PrincipalPair(key.1, key.0), Empty)
+                        .expect("spender index key has fixed size");
+                }
+            })
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        ALLOWANCES.with(|map| map.borrow().len() as usize)
     }
 
-    pub fn get_holders(&self, start: usize, limit: usize) -> Vec<(Principal, Tokens128)> {
-        let mut balance = self.0.iter().map(|(&k, v)| (k, *v)).collect::<Vec<_>>();
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        // Sort balance and principals by the balance
-        balance.sort_by(|a, b| b.1.cmp(&a.1));
+    /// All the allowances given by the `owner` as `(spender, amount)` pairs.
+    pub fn owner_allowances(&self, owner: Principal) -> Vec<(Principal, Tokens128)> {
+        ALLOWANCES.with(|map| {
+            map.borrow()
+                .range(PrincipalPair::prefix(&owner), None)
+                .map(|(key, amount)| (key.1, amount.0))
+                .collect()
+        })
+    }
 
-        let end = (start + limit).min(balance.len());
-        balance[start..end].to_vec()
+    /// Up to `limit` allowances in the storage order, starting right after the `cursor` pair.
+    pub fn entries_after(
+        &self,
+        cursor: Option<(Principal, Principal)>,
+        limit: usize,
+    ) -> Vec<AllowanceEntry> {
+        ALLOWANCES.with(|map| {
+            let map = map.borrow();
+            let cursor = cursor.map(|(owner, spender)| PrincipalPair(owner, spender));
+            map.range(vec![], cursor.map(|key| key.to_bytes().into_owned()))
+                .filter(|(key, _)| Some(*key) != cursor)
+                .take(limit)
+                .map(|(key, amount)| AllowanceEntry {
+                    owner: key.0,
+                    spender: key.1,
+                    amount: amount.0,
+                })
+                .collect()
+        })
     }
 }
 
+/// Implements candid serialization of a state part kept entirely in the stable memory as an empty
+/// value, so that the serialized canister state doesn't depend on the size of the stable data.
+macro_rules! impl_stable_candid {
+    ($name:ident) => {
+        impl CandidType for $name {
+            fn _ty() -> Type {
+                <() as CandidType>::ty()
+            }
+
+            fn idl_serialize<S: Serializer>(&self, serializer: S) -> Result<(), S::Error> {
+                ().idl_serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <()>::deserialize(deserializer)?;
+                Ok(Self)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_stable_candid;
+
+impl_stable_candid!(Allowances);
+
 #[derive(CandidType, Default, Debug, Clone, Deserialize)]
 pub struct BiddingState {
     pub fee_ratio: f64,
     pub last_auction: Timestamp,
     pub auction_period: Timestamp,
     pub cycles_since_auction: Cycles,
-    pub bids: HashMap<Principal, Cycles>,
+    pub bids: Bids,
+    pub reward_asset: AuctionRewardAsset,
+    /// Share of the bid that is kept by the canister when the bid is withdrawn, in basis points.
+    #[serde(default)]
+    pub withdrawal_penalty_bps: u16,
+    /// Rewards of the last auction that are being paid in chunks.
+    #[serde(default)]
+    pub disbursement: Option<Disbursement>,
+    /// If set, the bidders are notified about the paid rewards.
+    #[serde(default)]
+    pub notify_bidders: bool,
+    /// Reward notifications that are not sent yet.
+    #[serde(default)]
+    pub pending_notifications: VecDeque<PendingRewardNotification>,
+    /// If set, the bids can be paid in the tokens of this ledger besides the cycles.
+    #[serde(default)]
+    pub bid_token: Option<BidToken>,
+    /// Parts of the bids paid in tokens, in cycles. These parts cannot be withdrawn and are not
+    /// counted in `cycles_since_auction`, as the canister never receives these cycles.
+    #[serde(default)]
+    pub token_bids: HashMap<Principal, Cycles>,
+    /// A bid made within this time before the auction is due moves the auction so it's due this
+    /// time after the bid. Zero disables the extension.
+    #[serde(default)]
+    pub extension_window: Timestamp,
+    /// Time the current auction is moved by the late bids, capped at the `auction_period`.
+    #[serde(default)]
+    pub deadline_extension: Timestamp,
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct PendingRewardNotification {
+    pub bidder: Principal,
+    pub notification: RewardNotification,
+    /// Number of the failed attempts to send the notification.
+    pub attempts: u32,
+}
+
+/// Auction rewards that are paid in chunks over several executions.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct Disbursement {
+    pub auction_id: usize,
+    pub payouts: Vec<(Principal, Tokens128)>,
+    /// Index of the next payout to pay.
+    pub next: usize,
+    /// Sum of the payouts that are not paid yet. These tokens are held by the auction principal.
+    pub remaining: Tokens128,
 }
 
 impl BiddingState {
     pub fn is_auction_due(&self) -> bool {
         let curr_time = ic_canister::ic_kit::ic::time();
-        let next_auction = self.last_auction + self.auction_period;
-        curr_time >= next_auction
+        curr_time >= self.next_auction()
+    }
+
+    /// Time the upcoming auction is due, including the extension by the late bids.
+    pub fn next_auction(&self) -> Timestamp {
+        self.last_auction + self.auction_period + self.deadline_extension
+    }
+
+    /// Total worth of the bids in cycles, the paid cycles and the bids paid in tokens, which the
+    /// auction rewards are split by.
+    pub fn total_bids(&self) -> Cycles {
+        self.cycles_since_auction + self.token_bids.values().sum::<Cycles>()
+    }
+
+    /// Starts the bidding for the next auction at the current time.
+    pub fn start_bidding_period(&mut self) {
+        self.last_auction = ic_canister::ic_kit::ic::time();
+        self.deadline_extension = 0;
+    }
+
+    /// Moves the upcoming auction if a bid is made within the `extension_window` before it's due,
+    /// so a bid made at the last moment cannot take the whole reward without the other bidders
+    /// having the time to respond. The auction that is already due is not moved.
+    pub fn extend_deadline(&mut self) {
+        let curr_time = ic_canister::ic_kit::ic::time();
+        let next_auction = self.next_auction();
+        if self.extension_window == 0 || curr_time >= next_auction {
+            return;
+        }
+
+        let extended = curr_time.saturating_add(self.extension_window);
+        if extended > next_auction {
+            self.deadline_extension =
+                (self.deadline_extension + (extended - next_auction)).min(self.auction_period);
+        }
     }
 }
 
-#[derive(Debug, Default, CandidType, Deserialize)]
-pub struct AuctionHistory(pub Vec<AuctionInfo>);
+/// Cycle bids for the upcoming auction. The bids are kept in the stable memory and updated on
+/// every change, so they cannot be lost by a failed upgrade.
+///
+/// The bids are not part of the serialized canister state, as the stable memory is kept as is
+/// between the upgrades.
+#[derive(Debug, Default, Clone)]
+pub struct Bids;
+
+impl Bids {
+    pub fn get(&self, bidder: &Principal) -> Option<Cycles> {
+        BIDS.with(|map| map.borrow().get(&StablePrincipal(*bidder)).map(|c| c.0))
+    }
+
+    pub fn contains_key(&self, bidder: &Principal) -> bool {
+        self.get(bidder).is_some()
+    }
+
+    /// Adds the `cycles` to the bid of the `bidder`.
+    pub fn add(&mut self, bidder: Principal, cycles: Cycles) {
+        let total = self.get(&bidder).unwrap_or(0) + cycles;
+        BIDS.with(|map| {
+            map.borrow_mut()
+                .insert(StablePrincipal(bidder), StableCycles(total))
+                .expect("bid key and value have fixed size");
+        })
+    }
+
+    pub fn remove(&mut self, bidder: &Principal) -> Option<Cycles> {
+        BIDS.with(|map| {
+            map.borrow_mut()
+                .remove(&StablePrincipal(*bidder))
+                .map(|c| c.0)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        BIDS.with(|map| map.borrow().len() as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn to_map(&self) -> HashMap<Principal, Cycles> {
+        BIDS.with(|map| map.borrow().iter().map(|(k, v)| (k.0, v.0)).collect())
+    }
+
+    /// Removes all the bids and returns them.
+    pub fn take(&mut self) -> HashMap<Principal, Cycles> {
+        let bids = self.to_map();
+        BIDS.with(|map| {
+            let mut map = map.borrow_mut();
+            for bidder in bids.keys() {
+                map.remove(&StablePrincipal(*bidder));
+            }
+        });
+
+        bids
+    }
+
+    pub fn clear(&mut self) {
+        self.take();
+    }
+}
+
+impl_stable_candid!(Bids);
+
+/// Held auctions. The auctions are kept in the stable memory and written when they are held.
+///
+/// The history is not part of the serialized canister state, as the stable memory is kept as is
+/// between the upgrades.
+#[derive(Debug, Default)]
+pub struct AuctionHistory;
+
+impl AuctionHistory {
+    pub fn len(&self) -> usize {
+        AUCTION_HISTORY.with(|map| map.borrow().len() as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: usize) -> Option<AuctionInfo> {
+        AUCTION_HISTORY.with(|map| map.borrow().get(&StableAuctionId(id as u64)).map(|a| a.0))
+    }
+
+    /// Adds the auction to the history, or replaces the one with the same id.
+    pub fn insert(&mut self, auction: AuctionInfo) {
+        AUCTION_HISTORY.with(|map| {
+            map.borrow_mut()
+                .insert(
+                    StableAuctionId(auction.auction_id as u64),
+                    StableAuctionInfo(auction),
+                )
+                .expect("auction info size is limited");
+        })
+    }
+
+    /// Returns up to `limit` auctions starting from the `offset` id.
+    pub fn page(&self, offset: usize, limit: usize) -> PaginatedAuctions {
+        let len = self.len();
+        let start = offset.min(len);
+        let end = start.saturating_add(limit).min(len);
+        PaginatedAuctions {
+            result: (start..end).filter_map(|id| self.get(id)).collect(),
+            next: if end < len { Some(end) } else { None },
+        }
+    }
+
+    /// Returns up to `limit` auctions held between `from` and `to` inclusive. The auctions are
+    /// stored in the order they were held, so the time range is found with binary search.
+    pub fn by_time(&self, from: Timestamp, to: Timestamp, limit: usize) -> PaginatedAuctions {
+        let start = self.partition_point(|auction| auction.auction_time < from);
+        let end = self.partition_point(|auction| auction.auction_time <= to);
+        if start >= end {
+            return PaginatedAuctions {
+                result: vec![],
+                next: None,
+            };
+        }
+
+        let mut page = self.page(start, (end - start).min(limit));
+        page.next = page.next.filter(|&next| next < end);
+        page
+    }
+
+    /// Id of the first auction for which the `pred` is false, in the same way as
+    /// `slice::partition_point` does it.
+    fn partition_point(&self, pred: impl Fn(&AuctionInfo) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.get(mid) {
+                Some(auction) if pred(&auction) => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+
+        low
+    }
+}
+
+impl_stable_candid!(AuctionHistory);
+
+/// Key/value entries attached to the accounts by their owners, stored in the stable memory
+/// keyed by the `(account, key)` pair.
+#[derive(Debug, Default)]
+pub struct AccountData;
+
+impl AccountData {
+    pub fn get(&self, who: Principal, key: &str) -> Option<Vec<u8>> {
+        let key = AccountDataKey {
+            who,
+            key: key.to_string(),
+        };
+        ACCOUNT_DATA.with(|map| map.borrow().get(&key).map(|value| value.0))
+    }
+
+    pub fn insert(&mut self, who: Principal, key: String, value: Vec<u8>) {
+        ACCOUNT_DATA.with(|map| {
+            map.borrow_mut()
+                .insert(AccountDataKey { who, key }, StableBytes(value))
+                .expect("account data key and value size is limited");
+        })
+    }
+
+    pub fn remove(&mut self, who: Principal, key: &str) {
+        let key = AccountDataKey {
+            who,
+            key: key.to_string(),
+        };
+        ACCOUNT_DATA.with(|map| map.borrow_mut().remove(&key));
+    }
+
+    /// Total size of the keys and the values stored for the account, not counting the `key` entry.
+    pub fn size_without(&self, who: Principal, key: &str) -> usize {
+        ACCOUNT_DATA.with(|map| {
+            map.borrow()
+                .range(AccountDataKey::prefix(&who), None)
+                .filter(|(entry, _)| entry.key != key)
+                .map(|(entry, value)| entry.key.len() + value.0.len())
+                .sum()
+        })
+    }
+}
+
+impl_stable_candid!(AccountData);
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct OwnerRotation {
+    /// Principal notified when an owner change is initiated.
+    pub guardian: Option<Principal>,
+    pub pending: Option<PendingOwnerChange>,
+    /// Canister keeping the registry of the tokens, notified with the `notify_owner_changed` call
+    /// when an owner change is applied. It's set with the `registry` init argument.
+    pub registry: Option<Principal>,
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct PendingOwnerChange {
+    pub new_owner: Principal,
+    pub initiated_at: Timestamp,
+    /// The change cannot be applied before this time.
+    pub effective_at: Timestamp,
+}
+
+/// Admin operations that require the approval of the multisig signers when the multisig is
+/// enabled.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub enum AdminOp {
+    SetFee(Tokens128),
+    SetFeeTo(Principal),
+    SetFeeMode(FeeMode),
+    SetFeeBounds {
+        min: Option<Tokens128>,
+        max: Option<Tokens128>,
+    },
+    SetFeeBurnRatio(u16),
+    SetFeeDistribution(Vec<(Principal, u32)>),
+    SetOwner(Principal),
+    Mint {
+        to: Principal,
+        amount: Tokens128,
+    },
+    AddMinter(Principal),
+    RemoveMinter(Principal),
+    /// Queues the update while the timelock is enabled, see `queueUpdate`.
+    QueueUpdate(TimelockedUpdate),
+    /// Removes the queued update, see `cancelUpdate`.
+    CancelUpdate(u64),
+    /// Changes the signers or the threshold. `None` disables the multisig.
+    SetMultisig(Option<MultisigConfig>),
+    /// Allows the next upgrade of the canister, e.g. by the factory that created it. While the
+    /// multisig is enabled, the upgrades without the approval are refused.
+    ApproveUpgrade,
+}
+
+impl AdminOp {
+    /// Whether the operation changes the parameters that can only be changed with the queued
+    /// updates while the timelock is enabled.
+    pub fn is_timelocked(&self) -> bool {
+        matches!(
+            self,
+            AdminOp::SetFee(_)
+                | AdminOp::SetFeeTo(_)
+                | AdminOp::SetFeeMode(_)
+                | AdminOp::SetFeeBounds { .. }
+                | AdminOp::SetFeeBurnRatio(_)
+                | AdminOp::SetFeeDistribution(_)
+                | AdminOp::SetOwner(_)
+        )
+    }
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct MultisigConfig {
+    pub signers: BTreeSet<Principal>,
+    /// Number of the signer approvals needed to execute a proposal.
+    pub threshold: u32,
+}
+
+impl MultisigConfig {
+    pub fn is_valid(&self) -> bool {
+        self.threshold > 0 && self.threshold as usize <= self.signers.len()
+    }
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct AdminProposal {
+    pub id: u64,
+    pub op: AdminOp,
+    pub proposer: Principal,
+    pub created_at: Timestamp,
+    pub approvals: BTreeSet<Principal>,
+}
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct Multisig {
+    pub config: Option<MultisigConfig>,
+    pub proposals: BTreeMap<u64, AdminProposal>,
+    pub next_proposal_id: u64,
+    /// Whether the next upgrade was approved with an `ApproveUpgrade` proposal.
+    #[serde(default)]
+    pub upgrade_approved: bool,
+}
+
+impl Multisig {
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn is_signer(&self, who: &Principal) -> bool {
+        self.config
+            .as_ref()
+            .map_or(false, |config| config.signers.contains(who))
+    }
+
+    /// Uses the upgrade approval. Returns `MultisigRequired` error if the multisig is enabled and
+    /// the upgrade was not approved. Called before the upgrade.
+    pub fn take_upgrade_approval(&mut self) -> Result<(), TxError> {
+        if std::mem::take(&mut self.upgrade_approved) {
+            return Ok(());
+        }
+
+        self.check_disabled()
+    }
+
+    /// Returns `MultisigRequired` error if the admin operations can only be done with proposals.
+    pub fn check_disabled(&self) -> Result<(), TxError> {
+        if self.is_enabled() {
+            Err(TxError::MultisigRequired)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Parameter changes that have to be queued for the timelock delay when the timelock is enabled.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub enum TimelockedUpdate {
+    Fee(Tokens128),
+    FeeTo(Principal),
+    FeeMode(FeeMode),
+    FeeBounds {
+        min: Option<Tokens128>,
+        max: Option<Tokens128>,
+    },
+    FeeBurnRatio(u16),
+    FeeDistribution(Vec<(Principal, u32)>),
+    Owner(Principal),
+    /// Decreases of the delay are timelocked too, otherwise the owner could skip the delay.
+    Delay(Timestamp),
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct QueuedUpdate {
+    pub id: u64,
+    pub update: TimelockedUpdate,
+    pub queued_at: Timestamp,
+    /// The update cannot be executed before this time.
+    pub executable_at: Timestamp,
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct Subscription {
+    pub filter: SubscriptionFilter,
+    /// Id of the first transaction not delivered to the subscriber yet.
+    pub next_transaction: TxId,
+}
+
+/// Transfer scheduled by the sender to be executed later.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct ScheduledTransfer {
+    pub id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub amount: Tokens128,
+    /// Time after which the transfer is executed.
+    pub next_execution: Timestamp,
+    /// If set, the transfer is repeated with this interval until it's cancelled.
+    pub interval: Option<Timestamp>,
+    /// Number of the failed executions in a row. The transfer is removed after
+    /// `MAX_SCHEDULED_TRANSFER_FAILURES` of them.
+    #[serde(default)]
+    pub failures: u32,
+}
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct Scheduler {
+    pub transfers: BTreeMap<u64, ScheduledTransfer>,
+    pub next_id: u64,
+    /// `(next_execution, id)` pairs of the transfers, so the due transfers are taken in the order
+    /// they are due without scanning all the transfers.
+    #[serde(default)]
+    due: BTreeSet<(Timestamp, u64)>,
+}
+
+impl Scheduler {
+    pub fn len(&self) -> usize {
+        self.transfers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+
+    /// Adds the transfer, or replaces the one with the same id.
+    pub fn insert(&mut self, transfer: ScheduledTransfer) {
+        if let Some(previous) = self.transfers.get(&transfer.id) {
+            self.due.remove(&(previous.next_execution, previous.id));
+        }
+        self.due.insert((transfer.next_execution, transfer.id));
+        self.transfers.insert(transfer.id, transfer);
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<ScheduledTransfer> {
+        let transfer = self.transfers.remove(&id)?;
+        self.due.remove(&(transfer.next_execution, id));
+        Some(transfer)
+    }
+
+    /// Up to `limit` transfers due by the time `now`, the earliest first.
+    pub fn due(&self, now: Timestamp, limit: usize) -> Vec<ScheduledTransfer> {
+        self.due
+            .range(..=(now, u64::MAX))
+            .take(limit)
+            .filter_map(|(_, id)| self.transfers.get(id).cloned())
+            .collect()
+    }
+}
+
+/// Tokens of the beneficiary released linearly over time, see `createVesting`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct Vesting {
+    pub id: u64,
+    pub beneficiary: Principal,
+    pub total: Tokens128,
+    pub claimed: Tokens128,
+    pub start: Timestamp,
+    /// No tokens are released before `start + cliff`.
+    pub cliff: Timestamp,
+    /// All the tokens are released at `start + duration`.
+    pub duration: Timestamp,
+}
+
+impl Vesting {
+    /// Amount of the tokens released by the time `now`.
+    pub fn vested(&self, now: Timestamp) -> Tokens128 {
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed < self.cliff {
+            Tokens128::ZERO
+        } else if elapsed >= self.duration {
+            self.total
+        } else {
+            proportional_share(self.total, elapsed as u128, self.duration as u128)
+        }
+    }
+
+    /// Amount of the released tokens that are not claimed yet.
+    pub fn claimable(&self, now: Timestamp) -> Tokens128 {
+        (self.vested(now) - self.claimed).unwrap_or(Tokens128::ZERO)
+    }
+}
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct VestingState {
+    pub entries: BTreeMap<u64, Vesting>,
+    pub next_id: u64,
+}
+
+/// Tokens of the payer held until they are released to the recipient or refunded, see
+/// `createEscrow`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct Escrow {
+    pub id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub amount: Tokens128,
+    pub arbiter: Principal,
+    /// After the deadline the payer can refund the tokens.
+    pub deadline: Timestamp,
+    pub created_at: Timestamp,
+}
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct Escrows {
+    pub entries: BTreeMap<u64, Escrow>,
+    pub next_id: u64,
+}
+
+/// Payment accruing to the recipient at the `rate` per second until the `deposit` is exhausted,
+/// see `openStream`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct Stream {
+    pub id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub rate: Tokens128,
+    pub deposit: Tokens128,
+    pub withdrawn: Tokens128,
+    pub start: Timestamp,
+}
+
+impl Stream {
+    /// Amount of the deposit accrued to the recipient by the time `now`.
+    pub fn accrued(&self, now: Timestamp) -> Tokens128 {
+        let seconds = now.saturating_sub(self.start) / 1_000_000_000;
+        (self.rate * Tokens128::from(seconds as u128))
+            .to_tokens128()
+            .map_or(self.deposit, |accrued| accrued.min(self.deposit))
+    }
+
+    /// Amount of the accrued tokens that are not withdrawn yet.
+    pub fn withdrawable(&self, now: Timestamp) -> Tokens128 {
+        (self.accrued(now) - self.withdrawn).unwrap_or(Tokens128::ZERO)
+    }
+}
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct Streams {
+    pub entries: BTreeMap<u64, Stream>,
+    pub next_id: u64,
+}
+
+/// Airdrop claimed with merkle proofs, see `setAirdrop`.
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct Airdrop {
+    /// Merkle root of the `(index, account, amount)` allocations.
+    pub root: Vec<u8>,
+    /// Whether the allocations are minted or transferred from the owner balance.
+    pub mint: bool,
+    /// Bitmap of the claimed allocation indices.
+    pub claimed: Vec<u64>,
+}
+
+impl Airdrop {
+    pub fn is_claimed(&self, index: u64) -> bool {
+        self.claimed
+            .get((index / 64) as usize)
+            .map_or(false, |word| word & (1 << (index % 64)) != 0)
+    }
+
+    /// Marks the allocation as claimed. The index is expected to be proven to be in the tree, so
+    /// the bitmap size is limited by the number of allocations.
+    pub fn set_claimed(&mut self, index: u64) {
+        let word = (index / 64) as usize;
+        if self.claimed.len() <= word {
+            self.claimed.resize(word + 1, 0);
+        }
+        self.claimed[word] |= 1 << (index % 64);
+    }
+}
+
+/// ICRC-1 transfers made with `created_at_time`, kept for the deduplication window. A transfer is
+/// identified by the hash of the caller and all the transfer arguments.
+#[derive(CandidType, Default, Debug, Clone, Deserialize, PartialEq)]
+pub struct RecentTransfers {
+    /// Ids of the transactions by the transfer hashes.
+    by_hash: HashMap<Vec<u8>, TxId>,
+    /// Transfer hashes by `created_at_time`, used to expire them.
+    by_time: BTreeMap<(Timestamp, TxId), Vec<u8>>,
+}
+
+impl RecentTransfers {
+    /// Id of the transaction made by the transfer with the hash.
+    pub fn get(&self, hash: &[u8]) -> Option<TxId> {
+        self.by_hash.get(hash).copied()
+    }
+
+    pub fn insert(&mut self, hash: Vec<u8>, created_at: Timestamp, id: TxId) {
+        self.by_time.insert((created_at, id), hash.clone());
+        self.by_hash.insert(hash, id);
+    }
+
+    /// Removes the transfers created before `oldest`.
+    pub fn expire(&mut self, oldest: Timestamp) {
+        let kept = self.by_time.split_off(&(oldest, 0));
+        for hash in std::mem::replace(&mut self.by_time, kept).into_values() {
+            self.by_hash.remove(&hash);
+        }
+    }
+}
+
+/// Tokens distributed to all the holders in chunks, see `distribute`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct Distribution {
+    pub amount: Tokens128,
+    /// Total balance of the paid holders at the distribution start, the shares are relative to
+    /// it.
+    pub supply: Tokens128,
+    /// Amount that is not paid yet. These tokens are held by the distribution principal.
+    pub remaining: Tokens128,
+    /// The last holder the chunks went through. The holders are paid in the principal order, the
+    /// next chunk starts right after this one.
+    pub cursor: Option<Principal>,
+}
+
+/// Low-cycles monitoring, see `setCyclesMonitor`.
+#[derive(CandidType, Default, Debug, Clone, Deserialize, PartialEq)]
+pub struct CyclesMonitor {
+    /// Cycles balance below which the alert is raised. Zero disables the monitoring.
+    pub threshold: Cycles,
+    /// Canister asked to top up the token when the balance is below the threshold.
+    pub provider: Option<Principal>,
+    /// Time the balance dropped below the threshold, if it's still below.
+    pub low_since: Option<Timestamp>,
+    pub last_top_up_request: Option<Timestamp>,
+}
+
+/// Daily limits of the test token mints, see `setFaucetLimit`.
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct Faucet {
+    /// Maximum amount one principal can mint in a day. `None` means no limit.
+    pub daily_limit: Option<Tokens128>,
+    /// Day of the `claimed` amounts, as the number of days since the Unix epoch.
+    day: u64,
+    /// Amounts minted by the principals during the `day`.
+    claimed: HashMap<Principal, Tokens128>,
+}
+
+impl Faucet {
+    const DAY: Timestamp = 24 * 60 * 60 * 1_000_000_000;
+
+    /// Amount the `principal` has minted today.
+    pub fn claimed(&self, principal: &Principal, now: Timestamp) -> Tokens128 {
+        if now / Self::DAY != self.day {
+            return Tokens128::ZERO;
+        }
+
+        self.claimed
+            .get(principal)
+            .copied()
+            .unwrap_or(Tokens128::ZERO)
+    }
+
+    /// Checks that the `principal` can mint the `amount` today. Returns the amount minted today
+    /// including the `amount`, to be saved with `record_claim` after the mint.
+    pub fn check_claim(
+        &self,
+        principal: &Principal,
+        amount: Tokens128,
+        now: Timestamp,
+    ) -> Result<Tokens128, TxError> {
+        let claimed = self.claimed(principal, now);
+        let total = (claimed + amount).ok_or(TxError::AmountOverflow)?;
+        match self.daily_limit {
+            Some(limit) if total > limit => Err(TxError::FaucetLimitExceeded {
+                remaining: (limit - claimed).unwrap_or(Tokens128::ZERO),
+            }),
+            _ => Ok(total),
+        }
+    }
+
+    /// Saves the amount the `principal` has minted today, as returned by `check_claim`. The
+    /// amounts of the previous days are dropped.
+    pub fn record_claim(&mut self, principal: Principal, claimed: Tokens128, now: Timestamp) {
+        let day = now / Self::DAY;
+        if day != self.day {
+            self.day = day;
+            self.claimed.clear();
+        }
+
+        self.claimed.insert(principal, claimed);
+    }
+}
+
+#[derive(CandidType, Default, Debug, Clone, Deserialize)]
+pub struct Timelock {
+    /// Time between an update is queued and it can be executed, in nanoseconds. Zero delay
+    /// disables the timelock.
+    pub delay: Timestamp,
+    pub queue: BTreeMap<u64, QueuedUpdate>,
+    pub next_update_id: u64,
+}
+
+impl Timelock {
+    pub fn is_enabled(&self) -> bool {
+        self.delay > 0
+    }
+
+    /// Returns `TimelockRequired` error if the parameter changes have to be queued.
+    pub fn check_disabled(&self) -> Result<(), TxError> {
+        if self.is_enabled() {
+            Err(TxError::TimelockRequired)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Canister state written with `ic_storage` before the stable structures were used.
+//!
+//! The whole state was serialized into the stable memory on upgrade, including the balances,
+//! the allowances, the auctions and the transaction history, which are now kept in the stable
+//! structures. On the first upgrade from this layout the state is read with
+//! [ic_storage::stable::read] and moved into the stable structures by [CanisterState::upgrade].
+
+use std::collections::HashMap;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_helpers::tokens::Tokens128;
+use ic_storage::stable::Versioned;
+
+use crate::types::{AuctionInfo, Cycles, StatsData, Timestamp, TxId, TxRecord};
+
+use super::CanisterState;
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+pub struct CanisterStateV1 {
+    pub bidding_state: BiddingStateV1,
+    pub balances: BalancesV1,
+    pub auction_history: AuctionHistoryV1,
+    pub stats: StatsData,
+    pub allowances: HashMap<Principal, HashMap<Principal, Tokens128>>,
+    pub ledger: LedgerV1,
+}
+
+impl Versioned for CanisterStateV1 {
+    type Previous = ();
+
+    fn upgrade((): ()) -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+pub struct BiddingStateV1 {
+    pub fee_ratio: f64,
+    pub last_auction: Timestamp,
+    pub auction_period: Timestamp,
+    pub cycles_since_auction: Cycles,
+    pub bids: HashMap<Principal, Cycles>,
+}
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+pub struct LedgerV1 {
+    pub history: Vec<TxRecord>,
+    /// Number of the records removed from the beginning of the history.
+    pub vec_offset: u64,
+    pub notifications: HashMap<TxId, Option<Principal>>,
+}
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+pub struct BalancesV1(pub HashMap<Principal, Tokens128>);
+
+#[derive(Debug, Default, CandidType, Deserialize)]
+pub struct AuctionHistoryV1(pub Vec<AuctionInfoV1>);
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct AuctionInfoV1 {
+    pub auction_id: usize,
+    pub auction_time: Timestamp,
+    pub tokens_distributed: Tokens128,
+    pub cycles_collected: Cycles,
+    pub fee_ratio: f64,
+    pub first_transaction_id: TxId,
+    pub last_transaction_id: TxId,
+}
+
+impl From<AuctionInfoV1> for AuctionInfo {
+    fn from(auction: AuctionInfoV1) -> Self {
+        Self {
+            auction_id: auction.auction_id,
+            auction_time: auction.auction_time,
+            tokens_distributed: auction.tokens_distributed,
+            cycles_collected: auction.cycles_collected,
+            fee_ratio: auction.fee_ratio,
+            first_transaction_id: auction.first_transaction_id,
+            last_transaction_id: auction.last_transaction_id,
+            seed: vec![],
+            icp_distributed: None,
+        }
+    }
+}
+
+impl From<CanisterStateV1> for CanisterState {
+    fn from(legacy: CanisterStateV1) -> Self {
+        let mut state = CanisterState {
+            stats: legacy.stats,
+            ..Default::default()
+        };
+
+        state.ledger.import_legacy_history(
+            legacy.ledger.vec_offset,
+            legacy.ledger.history,
+            legacy.ledger.notifications,
+        );
+
+        // The minted amount was not tracked, the whole supply is counted as minted.
+        state.stats.total_minted = state.stats.total_supply;
+
+        state.bidding_state.fee_ratio = legacy.bidding_state.fee_ratio;
+        state.bidding_state.last_auction = legacy.bidding_state.last_auction;
+        state.bidding_state.auction_period = legacy.bidding_state.auction_period;
+        state.bidding_state.cycles_since_auction = legacy.bidding_state.cycles_since_auction;
+        for (bidder, cycles) in legacy.bidding_state.bids {
+            state.bidding_state.bids.add(bidder, cycles);
+        }
+
+        for (holder, amount) in legacy.balances.0 {
+            state.balances.set(holder, amount);
+        }
+        state.ledger.record_legacy_balances();
+
+        for (owner, allowances) in legacy.allowances {
+            for (spender, amount) in allowances {
+                state.allowances.set(owner, spender, amount);
+            }
+        }
+
+        for auction in legacy.auction_history.0 {
+            let auction = AuctionInfo::from(auction);
+            state.auction_stats.record(&auction);
+            state.auction_history.insert(auction);
+        }
+
+        state
+    }
+}
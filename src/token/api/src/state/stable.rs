@@ -0,0 +1,710 @@
+//! Layout of the token canister stable memory.
+//!
+//! The stable memory is split into virtual memories by the [MemoryManager]. The data structures
+//! stored in the virtual memories are updated on every change, so they don't need to be
+//! serialized on upgrade. The rest of the canister state is written into the
+//! [UPGRADE_MEMORY_ID] memory in the `pre_upgrade` hook.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{Decode, Encode, Principal};
+use ic_helpers::tokens::Tokens128;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory, StableBTreeMap, Storable};
+
+use crate::canister::is20_account_data::{MAX_ACCOUNT_DATA_KEY_LEN, MAX_ACCOUNT_DATA_SIZE};
+use crate::types::{AuctionInfo, Cycles, TxId, TxRecord};
+
+pub type StableMemory = VirtualMemory<DefaultMemoryImpl>;
+
+pub const UPGRADE_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const ALLOWANCES_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const HISTORY_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const HOLDERS_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const BIDS_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const AUCTION_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const SPENDER_INDEX_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const USER_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const USER_HISTORY_LEN_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const BALANCE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const ACCOUNT_DATA_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const PENDING_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(13);
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const MAX_PRINCIPAL_LEN: usize = 29;
+// Candid encoding of a transaction record with the type table is about 200 bytes.
+const MAX_TX_RECORD_SIZE: usize = 512;
+// Candid encoding of an auction info with a 32 bytes seed is about 250 bytes.
+const MAX_AUCTION_INFO_SIZE: usize = 512;
+const MEMORY_MANAGER_MAGIC: &[u8; 3] = b"MGR";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    pub(crate) static ALLOWANCES: RefCell<StableBTreeMap<StableMemory, PrincipalPair, StableTokens>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(ALLOWANCES_MEMORY_ID),
+            PrincipalPair::SIZE as u32,
+            StableTokens::SIZE as u32,
+        ));
+
+    pub(crate) static BALANCES: RefCell<StableBTreeMap<StableMemory, StablePrincipal, StableTokens>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(BALANCES_MEMORY_ID),
+            StablePrincipal::SIZE as u32,
+            StableTokens::SIZE as u32,
+        ));
+
+    /// `(spender, owner)` pairs of the allowances, used as a set to list the owners that approved
+    /// a spender.
+    pub(crate) static SPENDER_INDEX: RefCell<StableBTreeMap<StableMemory, PrincipalPair, Empty>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(SPENDER_INDEX_MEMORY_ID),
+            PrincipalPair::SIZE as u32,
+            0,
+        ));
+
+    /// Holders sorted by balance, used as a set.
+    pub(crate) static HOLDERS: RefCell<StableBTreeMap<StableMemory, HolderKey, Empty>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(HOLDERS_MEMORY_ID),
+            HolderKey::SIZE as u32,
+            0,
+        ));
+
+    /// Transaction records of the ledger history by their ids, see [crate::ledger::Ledger].
+    pub(crate) static HISTORY: RefCell<StableBTreeMap<StableMemory, StableTxId, StableTxRecord>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(HISTORY_MEMORY_ID),
+            StableTxId::SIZE as u32,
+            MAX_TX_RECORD_SIZE as u32,
+        ));
+
+    /// Cycle bids for the upcoming auction, see [crate::state::Bids].
+    pub(crate) static BIDS: RefCell<StableBTreeMap<StableMemory, StablePrincipal, StableCycles>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(BIDS_MEMORY_ID),
+            StablePrincipal::SIZE as u32,
+            StableCycles::SIZE as u32,
+        ));
+
+    /// Held auctions by their ids, see [crate::state::AuctionHistory].
+    pub(crate) static AUCTION_HISTORY: RefCell<StableBTreeMap<StableMemory, StableAuctionId, StableAuctionInfo>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(AUCTION_HISTORY_MEMORY_ID),
+            StableAuctionId::SIZE as u32,
+            MAX_AUCTION_INFO_SIZE as u32,
+        ));
+
+    /// Ids of the transactions of each user by their positions in the user history, see
+    /// [crate::ledger::Ledger].
+    pub(crate) static USER_HISTORY: RefCell<StableBTreeMap<StableMemory, UserTxKey, StableTxId>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(USER_HISTORY_MEMORY_ID),
+            UserTxKey::SIZE as u32,
+            StableTxId::SIZE as u32,
+        ));
+
+    /// Number of the transactions of each user in [USER_HISTORY].
+    pub(crate) static USER_HISTORY_LEN: RefCell<StableBTreeMap<StableMemory, StablePrincipal, StableLen>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(USER_HISTORY_LEN_MEMORY_ID),
+            StablePrincipal::SIZE as u32,
+            StableLen::SIZE as u32,
+        ));
+
+    /// Balances of the users right after the transactions that changed them, see
+    /// [crate::ledger::Ledger::balance_at].
+    pub(crate) static BALANCE_HISTORY: RefCell<StableBTreeMap<StableMemory, BalanceHistoryKey, StableTokens>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(BALANCE_HISTORY_MEMORY_ID),
+            BalanceHistoryKey::SIZE as u32,
+            StableTokens::SIZE as u32,
+        ));
+
+    /// Balances changed since the latest transaction, to be added to [BALANCE_HISTORY] with the
+    /// next one.
+    pub(crate) static PENDING_BALANCES: RefCell<StableBTreeMap<StableMemory, StablePrincipal, StableTokens>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(PENDING_BALANCES_MEMORY_ID),
+            StablePrincipal::SIZE as u32,
+            StableTokens::SIZE as u32,
+        ));
+
+    /// Pending notifications of the transactions by their ids, see
+    /// [crate::ledger::PendingNotifications].
+    pub(crate) static NOTIFICATIONS: RefCell<StableBTreeMap<StableMemory, StableTxId, StableReceiver>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(NOTIFICATIONS_MEMORY_ID),
+            StableTxId::SIZE as u32,
+            StableReceiver::SIZE as u32,
+        ));
+
+    /// Entries attached to the accounts, see [crate::state::AccountData].
+    pub(crate) static ACCOUNT_DATA: RefCell<StableBTreeMap<StableMemory, AccountDataKey, StableBytes>> =
+        RefCell::new(StableBTreeMap::init(
+            get_memory(ACCOUNT_DATA_MEMORY_ID),
+            AccountDataKey::SIZE as u32,
+            MAX_ACCOUNT_DATA_SIZE as u32,
+        ));
+}
+
+pub fn get_memory(id: MemoryId) -> StableMemory {
+    MEMORY_MANAGER.with(|mm| mm.borrow().get(id))
+}
+
+/// Whether the stable memory contains the state written with `ic_storage` before the memory
+/// manager was used, see [crate::state::legacy]. Must be checked before any stable structure is
+/// accessed, as the memory manager overwrites the beginning of the stable memory on initialization.
+pub fn has_legacy_layout() -> bool {
+    let memory = DefaultMemoryImpl::default();
+    if memory.size() == 0 {
+        return false;
+    }
+
+    let mut magic = [0u8; 3];
+    memory.read(0, &mut magic);
+    &magic != MEMORY_MANAGER_MAGIC
+}
+
+/// Writes the serialized heap state into the upgrade memory. Must be called in `pre_upgrade`.
+pub fn write_upgrade_data(data: &[u8]) {
+    write_memory_data(UPGRADE_MEMORY_ID, data);
+}
+
+/// Reads the heap state written by [write_upgrade_data]. Returns an empty vec if nothing was
+/// written.
+pub fn read_upgrade_data() -> Vec<u8> {
+    read_memory_data(UPGRADE_MEMORY_ID)
+}
+
+/// Writes the length-prefixed `data` to the beginning of the virtual memory `id`, overwriting the
+/// previous data.
+pub fn write_memory_data(id: MemoryId, data: &[u8]) {
+    let memory = get_memory(id);
+    let required_pages = (8 + data.len() as u64 + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+    let current_pages = memory.size();
+    if current_pages < required_pages && memory.grow(required_pages - current_pages) < 0 {
+        ic_cdk::trap("failed to grow stable memory for the upgrade data");
+    }
+
+    memory.write(0, &(data.len() as u64).to_le_bytes());
+    memory.write(8, data);
+}
+
+/// Reads the data written by [write_memory_data] to the virtual memory `id`. Returns an empty vec
+/// if nothing was written.
+pub fn read_memory_data(id: MemoryId) -> Vec<u8> {
+    let memory = get_memory(id);
+    if memory.size() == 0 {
+        return Vec::new();
+    }
+
+    let mut len = [0u8; 8];
+    memory.read(0, &mut len);
+    let mut data = vec![0u8; u64::from_le_bytes(len) as usize];
+    memory.read(8, &mut data);
+
+    data
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StablePrincipal(pub Principal);
+
+impl StablePrincipal {
+    const SIZE: usize = 1 + MAX_PRINCIPAL_LEN;
+}
+
+impl Storable for StablePrincipal {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_principal(&self.0))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(decode_principal(&bytes))
+    }
+}
+
+/// Two principals stored in fixed size slots, so that all the entries with the same first
+/// principal are stored next to each other and can be iterated with a prefix scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PrincipalPair(pub Principal, pub Principal);
+
+impl PrincipalPair {
+    const SIZE: usize = 2 * StablePrincipal::SIZE;
+
+    /// Key prefix shared by all the pairs with the given first principal.
+    pub fn prefix(first: &Principal) -> Vec<u8> {
+        encode_principal(first)
+    }
+}
+
+impl Storable for PrincipalPair {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = encode_principal(&self.0);
+        bytes.extend(encode_principal(&self.1));
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (first, second) = bytes.split_at(1 + MAX_PRINCIPAL_LEN);
+        Self(decode_principal(first), decode_principal(second))
+    }
+}
+
+fn encode_principal(principal: &Principal) -> Vec<u8> {
+    let slice = principal.as_slice();
+    let mut bytes = vec![0u8; 1 + MAX_PRINCIPAL_LEN];
+    bytes[0] = slice.len() as u8;
+    bytes[1..1 + slice.len()].copy_from_slice(slice);
+    bytes
+}
+
+fn decode_principal(bytes: &[u8]) -> Principal {
+    let len = bytes[0] as usize;
+    Principal::from_slice(&bytes[1..1 + len])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StableTokens(pub Tokens128);
+
+impl StableTokens {
+    const SIZE: usize = 16;
+}
+
+impl Storable for StableTokens {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.amount.to_le_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut amount = [0u8; Self::SIZE];
+        amount.copy_from_slice(&bytes);
+        Self(Tokens128::from(u128::from_le_bytes(amount)))
+    }
+}
+
+/// Holder balance and principal, ordered by balance descending and then by principal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HolderKey {
+    pub amount: Tokens128,
+    pub who: Principal,
+}
+
+impl HolderKey {
+    const SIZE: usize = StableTokens::SIZE + StablePrincipal::SIZE;
+}
+
+impl Storable for HolderKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        // Inverted big endian amount, so that the larger balances go first.
+        let mut bytes = (u128::MAX - self.amount.amount).to_be_bytes().to_vec();
+        bytes.extend(encode_principal(&self.who));
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (amount, who) = bytes.split_at(StableTokens::SIZE);
+        let mut inverted = [0u8; StableTokens::SIZE];
+        inverted.copy_from_slice(amount);
+        Self {
+            amount: Tokens128::from(u128::MAX - u128::from_be_bytes(inverted)),
+            who: decode_principal(who),
+        }
+    }
+}
+
+/// Value of the maps used as sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Empty;
+
+impl Storable for Empty {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn from_bytes(_bytes: Vec<u8>) -> Self {
+        Self
+    }
+}
+
+/// Transaction id stored in big endian, so that the records are ordered by id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StableTxId(pub TxId);
+
+impl StableTxId {
+    const SIZE: usize = 8;
+}
+
+impl Storable for StableTxId {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.to_be_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut id = [0u8; Self::SIZE];
+        id.copy_from_slice(&bytes);
+        Self(TxId::from_be_bytes(id))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StableLen(pub u64);
+
+impl StableLen {
+    const SIZE: usize = 8;
+}
+
+impl Storable for StableLen {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.to_le_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut len = [0u8; Self::SIZE];
+        len.copy_from_slice(&bytes);
+        Self(u64::from_le_bytes(len))
+    }
+}
+
+/// Position of a transaction in the history of the user, stored in big endian after the user
+/// principal, so that the transactions of a user are ordered by position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserTxKey {
+    pub who: Principal,
+    pub position: u64,
+}
+
+impl UserTxKey {
+    const SIZE: usize = StablePrincipal::SIZE + 8;
+}
+
+impl Storable for UserTxKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = encode_principal(&self.who);
+        bytes.extend(self.position.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (who, position) = bytes.split_at(StablePrincipal::SIZE);
+        let mut position_bytes = [0u8; 8];
+        position_bytes.copy_from_slice(position);
+        Self {
+            who: decode_principal(who),
+            position: u64::from_be_bytes(position_bytes),
+        }
+    }
+}
+
+/// Transaction id stored inverted in big endian after the user principal, so that the balance
+/// changes of a user are ordered from the latest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceHistoryKey {
+    pub who: Principal,
+    pub id: TxId,
+}
+
+impl BalanceHistoryKey {
+    const SIZE: usize = StablePrincipal::SIZE + StableTxId::SIZE;
+
+    /// Key prefix shared by all the balance changes of the user.
+    pub fn prefix(who: &Principal) -> Vec<u8> {
+        encode_principal(who)
+    }
+
+    /// Key suffix of the change at the transaction `id`. A range scan from it goes through the
+    /// changes at `id` and before it.
+    pub fn offset(id: TxId) -> Vec<u8> {
+        (TxId::MAX - id).to_be_bytes().to_vec()
+    }
+}
+
+impl Storable for BalanceHistoryKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = encode_principal(&self.who);
+        bytes.extend(Self::offset(self.id));
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (who, id) = bytes.split_at(StablePrincipal::SIZE);
+        let mut inverted = [0u8; StableTxId::SIZE];
+        inverted.copy_from_slice(id);
+        Self {
+            who: decode_principal(who),
+            id: TxId::MAX - TxId::from_be_bytes(inverted),
+        }
+    }
+}
+
+/// Receiver of a pending notification, `None` if it is not set yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StableReceiver(pub Option<Principal>);
+
+impl StableReceiver {
+    const SIZE: usize = 1 + StablePrincipal::SIZE;
+}
+
+impl Storable for StableReceiver {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let bytes = match &self.0 {
+            Some(receiver) => [vec![1], encode_principal(receiver)].concat(),
+            None => vec![0; Self::SIZE],
+        };
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        match bytes[0] {
+            0 => Self(None),
+            _ => Self(Some(decode_principal(&bytes[1..]))),
+        }
+    }
+}
+
+/// Account principal and the entry key stored in fixed size slots, so that all the entries of an
+/// account are stored next to each other and can be iterated with a prefix scan.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AccountDataKey {
+    pub who: Principal,
+    pub key: String,
+}
+
+impl AccountDataKey {
+    const SIZE: usize = StablePrincipal::SIZE + 1 + MAX_ACCOUNT_DATA_KEY_LEN;
+
+    /// Key prefix shared by all the entries of the account.
+    pub fn prefix(who: &Principal) -> Vec<u8> {
+        encode_principal(who)
+    }
+}
+
+impl Storable for AccountDataKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = encode_principal(&self.who);
+        let mut key = vec![0u8; 1 + MAX_ACCOUNT_DATA_KEY_LEN];
+        key[0] = self.key.len() as u8;
+        key[1..1 + self.key.len()].copy_from_slice(self.key.as_bytes());
+        bytes.extend(key);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (who, key) = bytes.split_at(StablePrincipal::SIZE);
+        let len = key[0] as usize;
+        Self {
+            who: decode_principal(who),
+            key: String::from_utf8_lossy(&key[1..1 + len]).into_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StableBytes(pub Vec<u8>);
+
+impl Storable for StableBytes {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StableTxRecord(pub TxRecord);
+
+impl Storable for StableTxRecord {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(&self.0).expect("failed to encode transaction record"))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(Decode!(&bytes, TxRecord).expect("failed to decode transaction record"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StableCycles(pub Cycles);
+
+impl StableCycles {
+    const SIZE: usize = 8;
+}
+
+impl Storable for StableCycles {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.to_le_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut cycles = [0u8; Self::SIZE];
+        cycles.copy_from_slice(&bytes);
+        Self(Cycles::from_le_bytes(cycles))
+    }
+}
+
+/// Auction id stored in big endian, so that the auctions are ordered by id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StableAuctionId(pub u64);
+
+impl StableAuctionId {
+    const SIZE: usize = 8;
+}
+
+impl Storable for StableAuctionId {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.to_be_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut id = [0u8; Self::SIZE];
+        id.copy_from_slice(&bytes);
+        Self(u64::from_be_bytes(id))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StableAuctionInfo(pub AuctionInfo);
+
+impl Storable for StableAuctionInfo {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(&self.0).expect("failed to encode auction info"))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(Decode!(&bytes, AuctionInfo).expect("failed to decode auction info"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::{alice, bob};
+    use ic_canister::ic_kit::MockContext;
+
+    use super::*;
+
+    #[test]
+    fn principal_pair_roundtrip() {
+        let pair = PrincipalPair(alice(), bob());
+        let bytes = pair.to_bytes().to_vec();
+        assert_eq!(bytes.len(), PrincipalPair::SIZE);
+        assert!(bytes.starts_with(&PrincipalPair::prefix(&alice())));
+        assert_eq!(PrincipalPair::from_bytes(bytes), pair);
+    }
+
+    #[test]
+    fn user_tx_key_order() {
+        let key = |who, position| UserTxKey { who, position }.to_bytes().to_vec();
+        assert!(key(alice(), 255) < key(alice(), 256));
+        assert_eq!(key(alice(), 1).len(), UserTxKey::SIZE);
+        assert_eq!(
+            UserTxKey::from_bytes(key(bob(), 7)),
+            UserTxKey {
+                who: bob(),
+                position: 7
+            }
+        );
+    }
+
+    #[test]
+    fn balance_history_key_order() {
+        let key = |who, id| BalanceHistoryKey { who, id }.to_bytes().to_vec();
+        assert!(key(alice(), 256) < key(alice(), 255));
+        assert_eq!(key(alice(), 1).len(), BalanceHistoryKey::SIZE);
+        assert!(key(bob(), 3).starts_with(&BalanceHistoryKey::prefix(&bob())));
+        assert_eq!(
+            BalanceHistoryKey::from_bytes(key(bob(), 7)),
+            BalanceHistoryKey { who: bob(), id: 7 }
+        );
+    }
+
+    #[test]
+    fn stable_receiver_roundtrip() {
+        for receiver in [None, Some(alice())] {
+            let bytes = StableReceiver(receiver).to_bytes().to_vec();
+            assert_eq!(bytes.len(), StableReceiver::SIZE);
+            assert_eq!(StableReceiver::from_bytes(bytes).0, receiver);
+        }
+    }
+
+    #[test]
+    fn account_data_key_roundtrip() {
+        let key = AccountDataKey {
+            who: alice(),
+            key: "k".repeat(MAX_ACCOUNT_DATA_KEY_LEN),
+        };
+        let bytes = key.to_bytes().to_vec();
+        assert_eq!(bytes.len(), AccountDataKey::SIZE);
+        assert!(bytes.starts_with(&AccountDataKey::prefix(&alice())));
+        assert_eq!(AccountDataKey::from_bytes(bytes), key);
+    }
+
+    #[test]
+    fn tx_record_fits_max_size() {
+        MockContext::new().inject();
+        let record = TxRecord::transfer_from(
+            TxId::MAX,
+            Principal::from_slice(&[0xFF; MAX_PRINCIPAL_LEN]),
+            Principal::from_slice(&[0xFF; MAX_PRINCIPAL_LEN]),
+            Principal::from_slice(&[0xFF; MAX_PRINCIPAL_LEN]),
+            Tokens128::from(u128::MAX),
+            Tokens128::from(u128::MAX),
+        );
+        let bytes = StableTxRecord(record.clone()).to_bytes().to_vec();
+        assert!(bytes.len() <= MAX_TX_RECORD_SIZE);
+        assert_eq!(StableTxRecord::from_bytes(bytes).0.index, record.index);
+    }
+
+    #[test]
+    fn auction_info_fits_max_size() {
+        let info = AuctionInfo {
+            auction_id: usize::MAX,
+            auction_time: u64::MAX,
+            tokens_distributed: Tokens128::from(u128::MAX),
+            cycles_collected: Cycles::MAX,
+            fee_ratio: 1.0,
+            first_transaction_id: TxId::MAX,
+            last_transaction_id: TxId::MAX,
+            seed: vec![0xFF; 32],
+            icp_distributed: Some(u64::MAX),
+        };
+        let bytes = StableAuctionInfo(info.clone()).to_bytes().to_vec();
+        assert!(bytes.len() <= MAX_AUCTION_INFO_SIZE);
+        assert_eq!(StableAuctionInfo::from_bytes(bytes).0, info);
+    }
+
+    #[test]
+    fn holder_key_order() {
+        let key = |amount: u128, who| {
+            HolderKey {
+                amount: Tokens128::from(amount),
+                who,
+            }
+            .to_bytes()
+            .to_vec()
+        };
+
+        assert!(key(100, bob()) < key(10, alice()));
+        assert_eq!(key(10, alice()) < key(10, bob()), alice() < bob());
+        assert_eq!(
+            HolderKey::from_bytes(key(10, alice())),
+            HolderKey {
+                amount: Tokens128::from(10),
+                who: alice()
+            }
+        );
+    }
+
+    #[test]
+    fn upgrade_data_roundtrip() {
+        let data = vec![42u8; WASM_PAGE_SIZE as usize + 1];
+        write_upgrade_data(&data);
+        assert_eq!(read_upgrade_data(), data);
+    }
+}
@@ -1,10 +1,17 @@
-use candid::{CandidType, Deserialize, Principal};
+use crate::amount_math::{proportional_share, split_proportionally};
+use crate::canister::CanisterUpdate;
+use crate::state::{AdminOp, MultisigConfig, TimelockedUpdate};
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
 use ic_helpers::tokens::Tokens128;
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 use std::error::Error;
 use std::fmt::Formatter;
 
+mod account;
+mod icrc1;
 mod tx_record;
+pub use account::*;
+pub use icrc1::*;
 pub use tx_record::*;
 
 pub type Timestamp = u64;
@@ -21,6 +28,58 @@ pub struct Metadata {
     pub fee: Tokens128,
     pub feeTo: Principal,
     pub isTestToken: Option<bool>,
+    pub maxSupply: Option<Tokens128>,
+    pub auctionDisabled: Option<bool>,
+    /// Period of the cycle auctions in seconds, one day by default.
+    pub auctionPeriod: Option<u64>,
+    /// Cycles balance below which the auctions receive the whole fee, see `setMinCycles`.
+    pub minCycles: Option<u64>,
+    /// Initial balances of the token. They must sum up to the `totalSupply`. If not set, the whole
+    /// supply is minted to the owner.
+    pub genesisAllocation: Option<Vec<(Principal, Tokens128)>>,
+    /// Custom entries returned by `icrc1_metadata` along with the token stats, e.g. website or
+    /// description of the token, see `setMetadataEntry`.
+    pub customMetadata: Option<Vec<(String, Value)>>,
+    /// Canister keeping the registry of the tokens, notified when the owner of the token changes.
+    /// The factory sets it to itself for the tokens it creates.
+    pub registry: Option<Principal>,
+}
+
+impl Metadata {
+    /// Balances minted at the token creation, or `None` if the genesis allocation doesn't sum up
+    /// to the total supply.
+    pub fn initial_balances(&self) -> Option<Vec<(Principal, Tokens128)>> {
+        let allocation = match &self.genesisAllocation {
+            Some(allocation) => allocation,
+            None => return Some(vec![(self.owner, self.totalSupply)]),
+        };
+
+        let total = allocation
+            .iter()
+            .try_fold(Tokens128::ZERO, |total, (_, amount)| total + *amount)?;
+        if total != self.totalSupply {
+            return None;
+        }
+
+        Some(allocation.clone())
+    }
+}
+
+/// Value of a metadata entry returned by the `icrc1_metadata` method.
+#[derive(Deserialize, CandidType, Clone, Debug, PartialEq)]
+pub enum Value {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// A standard or a feature set supported by the canister, as returned by
+/// `icrc1_supported_standards`.
+#[derive(Deserialize, CandidType, Clone, Debug, PartialEq)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
 }
 
 #[derive(Deserialize, CandidType, Clone, Debug)]
@@ -36,11 +95,160 @@ pub struct StatsData {
     pub deploy_time: u64,
     pub min_cycles: u64,
     pub is_test_token: bool,
+    /// Transfers from this principal are recorded as mints and transfers to it as burns, in the
+    /// same way as the ICRC-1 ledger does it.
+    pub minting_account: Option<Principal>,
+    /// Cap of the total supply. Mints that would increase the total supply over it are rejected.
+    pub max_supply: Option<Tokens128>,
+    /// Principals allowed to mint tokens in addition to the owner.
+    #[serde(default)]
+    pub minters: BTreeSet<Principal>,
+    /// Principals allowed to freeze and unfreeze accounts in addition to the owner.
+    #[serde(default)]
+    pub pausers: BTreeSet<Principal>,
+    /// Principals allowed to manage the pausers in addition to the owner.
+    #[serde(default)]
+    pub admins: BTreeSet<Principal>,
+    #[serde(default)]
+    pub fee_mode: FeeMode,
+    /// Bounds of the fee in the `BasisPoints` fee mode.
+    pub fee_min: Option<Tokens128>,
+    pub fee_max: Option<Tokens128>,
+    /// Share of every fee that is burned instead of going to the fee receiver, in basis points.
+    #[serde(default)]
+    pub fee_burn_ratio: u16,
+    /// Fee receivers with their weights. If it's empty, the whole fee goes to `fee_to`.
+    #[serde(default)]
+    pub fee_distribution: Vec<(Principal, u32)>,
+    /// If set, no cycles can be bid, no auctions are held, and the whole fee goes to the fee
+    /// receivers.
+    #[serde(default)]
+    pub auction_disabled: bool,
+    /// Amount minted and burned since the token creation. The initial supply is counted as minted,
+    /// and the burned part of the fees is included in `total_burned`.
+    #[serde(default)]
+    pub total_minted: Tokens128,
+    #[serde(default)]
+    pub total_burned: Tokens128,
+    /// Accounts whose balances are not counted in the circulating supply, e.g. the treasury or
+    /// the auction pot.
+    #[serde(default)]
+    pub reserved_accounts: BTreeSet<Principal>,
 }
 
 impl StatsData {
-    pub fn fee_info(&self) -> (Tokens128, Principal) {
-        (self.fee, self.fee_to)
+    /// The fee charged for a transaction of `amount` and its receiver.
+    pub fn fee_info(&self, amount: Tokens128) -> (Tokens128, Principal) {
+        (self.fee_for(amount), self.fee_to)
+    }
+
+    pub fn fee_for(&self, amount: Tokens128) -> Tokens128 {
+        match self.fee_mode {
+            FeeMode::Flat => self.fee,
+            FeeMode::BasisPoints(bps) => {
+                let fee = proportional_share(amount, bps as u128, BASIS_POINTS_DENOMINATOR);
+                match (self.fee_min, self.fee_max) {
+                    (Some(min), _) if fee < min => min,
+                    (_, Some(max)) if fee > max => max,
+                    _ => fee,
+                }
+            }
+        }
+    }
+
+    /// Splits the `fee` between the fee receivers by their weights. The shares are rounded down,
+    /// and the units left after rounding are given out one per receiver starting from the first
+    /// one, as in [split_proportionally].
+    pub fn fee_split(&self, fee: Tokens128) -> Vec<(Principal, Tokens128)> {
+        if self.fee_distribution.is_empty() {
+            return vec![(self.fee_to, fee)];
+        }
+
+        let weights = self
+            .fee_distribution
+            .iter()
+            .map(|(_, weight)| *weight as u128)
+            .collect::<Vec<_>>();
+        let total_weight = weights.iter().sum();
+        let shares = split_proportionally(fee, &weights, total_weight);
+
+        self.fee_distribution
+            .iter()
+            .map(|(receiver, _)| *receiver)
+            .zip(shares)
+            .collect()
+    }
+}
+
+/// The fee charged for a transaction and how it's split.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct FeeSplit {
+    pub fee: Tokens128,
+    pub burned: Tokens128,
+    /// Share of the fee that goes to the cycle auction.
+    pub auction: Tokens128,
+    /// Shares of the fee receivers.
+    pub receivers: Vec<(Principal, Tokens128)>,
+}
+
+/// Max number of the fee receivers in the fee distribution.
+pub const MAX_FEE_RECEIVERS: usize = 16;
+/// Max length of a custom metadata key in bytes.
+pub const MAX_METADATA_KEY_LEN: usize = 64;
+
+/// The distribution is valid if it's empty, or if it has no more than `MAX_FEE_RECEIVERS` unique
+/// receivers with non-zero weights.
+pub fn valid_fee_distribution(distribution: &[(Principal, u32)]) -> bool {
+    let receivers = distribution
+        .iter()
+        .map(|(receiver, _)| receiver)
+        .collect::<BTreeSet<_>>();
+    distribution.len() <= MAX_FEE_RECEIVERS
+        && receivers.len() == distribution.len()
+        && distribution.iter().all(|(_, weight)| *weight > 0)
+}
+
+/// Custom metadata keys cannot be empty or longer than `MAX_METADATA_KEY_LEN` bytes. The `icrc1:`
+/// namespace is reserved for the values taken from the token stats.
+pub fn valid_metadata_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_METADATA_KEY_LEN && !key.starts_with("icrc1:")
+}
+
+pub fn valid_basis_points(bps: u16) -> bool {
+    bps as u128 <= BASIS_POINTS_DENOMINATOR
+}
+
+pub fn valid_fee_bounds(min: Option<Tokens128>, max: Option<Tokens128>) -> bool {
+    match (min, max) {
+        (Some(min), Some(max)) => min <= max,
+        _ => true,
+    }
+}
+
+pub const BASIS_POINTS_DENOMINATOR: u128 = 10_000;
+
+/// How the transaction fee is calculated.
+#[derive(CandidType, Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum FeeMode {
+    /// `StatsData::fee` is charged for every transaction.
+    Flat,
+    /// The fee is the given number of basis points (hundredths of a percent) of the transaction
+    /// amount.
+    BasisPoints(u16),
+}
+
+impl FeeMode {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            FeeMode::Flat => true,
+            FeeMode::BasisPoints(bps) => valid_basis_points(*bps),
+        }
+    }
+}
+
+impl Default for FeeMode {
+    fn default() -> Self {
+        FeeMode::Flat
     }
 }
 
@@ -60,8 +268,22 @@ impl From<Metadata> for StatsData {
             fee: md.fee,
             fee_to: md.feeTo,
             deploy_time: ic_canister::ic_kit::ic::time(),
-            min_cycles: DEFAULT_MIN_CYCLES,
+            min_cycles: md.minCycles.unwrap_or(DEFAULT_MIN_CYCLES),
             is_test_token: md.isTestToken.unwrap_or(false),
+            minting_account: None,
+            max_supply: md.maxSupply,
+            minters: BTreeSet::new(),
+            pausers: BTreeSet::new(),
+            admins: BTreeSet::new(),
+            fee_mode: FeeMode::Flat,
+            fee_min: None,
+            fee_max: None,
+            fee_burn_ratio: 0,
+            fee_distribution: Vec::new(),
+            auction_disabled: md.auctionDisabled.unwrap_or(false),
+            total_minted: md.totalSupply,
+            total_burned: Tokens128::ZERO,
+            reserved_accounts: BTreeSet::new(),
         }
     }
 }
@@ -75,6 +297,23 @@ pub struct TokenInfo {
     pub deployTime: Timestamp,
     pub holderNumber: usize,
     pub cycles: u64,
+    pub totalMinted: Tokens128,
+    pub totalBurned: Tokens128,
+}
+
+/// Canister health returned by `getHealth`, for the monitoring dashboards.
+#[derive(Deserialize, CandidType, Clone, Debug, PartialEq)]
+pub struct HealthInfo {
+    pub cycles: Cycles,
+    /// Size of the stable memory in bytes.
+    pub stable_memory_size: u64,
+    /// Size of the heap memory in bytes.
+    pub heap_memory_size: u64,
+    pub ledger_len: u64,
+    /// Number of the auction reward notifications and the failed transaction notifications
+    /// waiting to be sent.
+    pub pending_notifications: u64,
+    pub last_auction: Timestamp,
 }
 
 impl Default for StatsData {
@@ -91,11 +330,106 @@ impl Default for StatsData {
             deploy_time: 0,
             min_cycles: 0,
             is_test_token: false,
+            minting_account: None,
+            max_supply: None,
+            minters: BTreeSet::new(),
+            pausers: BTreeSet::new(),
+            admins: BTreeSet::new(),
+            fee_mode: FeeMode::Flat,
+            fee_min: None,
+            fee_max: None,
+            fee_burn_ratio: 0,
+            fee_distribution: Vec::new(),
+            auction_disabled: false,
+            total_minted: Tokens128::ZERO,
+            total_burned: Tokens128::ZERO,
+            reserved_accounts: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct AllowanceEntry {
+    pub owner: Principal,
+    pub spender: Principal,
+    pub amount: Tokens128,
+}
+
+/// A page of allowances returned by `exportAllowances`.
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct AllowancesExport {
+    pub entries: Vec<AllowanceEntry>,
+
+    /// The `(owner, spender)` pair to be used as the cursor for the next page, if there is one.
+    pub next: Option<(Principal, Principal)>,
+}
+
+/// Distribution of the token balances returned by `getHolderStats`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct HolderStats {
+    pub holder_count: u64,
+
+    /// Total balance of the `top_holders_count` largest holders.
+    pub top_holders_balance: Tokens128,
+    pub top_holders_count: u64,
+
+    /// Share of the total supply held by the largest holders, from 0.0 to 1.0.
+    pub top_holders_share: f64,
+
+    /// Number of holders with balance in `[10^i, 10^(i+1))` at index `i`, in the smallest token
+    /// units.
+    pub histogram: Vec<u64>,
+}
+
+/// Request of the HTTP gateway to the `http_request` query.
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn not_found() -> Self {
+        Self {
+            status_code: 404,
+            headers: vec![],
+            body: b"Not found".to_vec(),
         }
     }
 }
 
-pub type Allowances = HashMap<Principal, HashMap<Principal, Tokens128>>;
+/// Activity counters of one day returned by `getMetrics`.
+#[derive(CandidType, Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct DailyMetrics {
+    /// Number of days since the Unix epoch.
+    pub day: u64,
+    /// Number of `Transfer` and `TransferFrom` transactions.
+    pub transfers: u64,
+    pub mints: u64,
+    pub burns: u64,
+    /// Number of distinct principals that sent, received, minted, burned or approved tokens.
+    pub active_accounts: u64,
+    /// Total fee charged by the transactions.
+    pub fee_volume: Tokens128,
+}
+
+/// A chunk of the transaction history returned by `exportLedger`.
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct LedgerChunk {
+    pub records: Vec<TxRecord>,
+
+    /// The id of the first transaction of the next chunk, if there is one.
+    pub next: Option<TxId>,
+}
 
 // TODO: a wrapper over `ic_helpers::TxError`, this is a most likely
 // place to make tests fail in amm.
@@ -119,6 +453,40 @@ pub enum TxError {
     TxDuplicate { duplicate_of: u64 },
     SelfTransfer,
     AmountOverflow,
+    NoPendingOwnerChange,
+    OwnerChangeTooEarly { effective_at: Timestamp },
+    AccountDataTooLarge { max_size: u64 },
+    MaxSupplyExceeded,
+    AccountFrozen,
+    MultisigRequired,
+    InvalidMultisigConfig,
+    ProposalDoesNotExist,
+    TimelockRequired,
+    UpdateDoesNotExist,
+    UpdateNotReady { executable_at: Timestamp },
+    InvalidFeeConfig,
+    ReceiverNotSupported,
+    TooManySubscribers,
+    BatchTooLarge { max_len: u64 },
+    BatchTransferFailed { index: u64, tx_error: Box<TxError> },
+    InvalidSchedule,
+    ScheduledTransferDoesNotExist,
+    InvalidVesting,
+    NothingToClaim,
+    InvalidLock,
+    InvalidEscrow,
+    EscrowDoesNotExist,
+    InvalidStream,
+    StreamDoesNotExist,
+    NoAirdrop,
+    InvalidProof,
+    DistributionInProgress,
+    InvalidMetadataKey,
+    InvalidAmount,
+    FaucetLimitExceeded { remaining: Tokens128 },
+    ImportClosed,
+    TooManyScheduledTransfers,
+    InvalidMethodPolicy,
 }
 
 impl std::fmt::Display for TxError {
@@ -150,16 +518,219 @@ impl std::fmt::Display for TxError {
             }
             TxError::SelfTransfer => write!(f, "Self transfer"),
             TxError::AmountOverflow => write!(f, "Amount overflow"),
+            TxError::NoPendingOwnerChange => write!(f, "No pending owner change"),
+            TxError::OwnerChangeTooEarly { effective_at } => {
+                write!(f, "Owner change cannot be applied before {}", effective_at)
+            }
+            TxError::AccountDataTooLarge { max_size } => {
+                write!(f, "Account data exceeds {} bytes", max_size)
+            }
+            TxError::MaxSupplyExceeded => write!(f, "Max supply exceeded"),
+            TxError::AccountFrozen => write!(f, "Account is frozen"),
+            TxError::MultisigRequired => write!(f, "Operation requires multisig approval"),
+            TxError::InvalidMultisigConfig => write!(f, "Invalid multisig config"),
+            TxError::ProposalDoesNotExist => write!(f, "Proposal does not exist"),
+            TxError::TimelockRequired => write!(f, "Update must be queued in the timelock"),
+            TxError::UpdateDoesNotExist => write!(f, "Queued update does not exist"),
+            TxError::UpdateNotReady { executable_at } => {
+                write!(f, "Update cannot be executed before {}", executable_at)
+            }
+            TxError::InvalidFeeConfig => write!(f, "Invalid fee config"),
+            TxError::TooManySubscribers => write!(f, "Maximum number of subscribers reached"),
+            TxError::InvalidSchedule => write!(f, "Invalid transfer schedule"),
+            TxError::InvalidVesting => write!(f, "Invalid vesting schedule"),
+            TxError::NothingToClaim => write!(f, "No tokens to claim"),
+            TxError::InvalidLock => write!(f, "Invalid balance lock"),
+            TxError::InvalidEscrow => write!(f, "Invalid escrow"),
+            TxError::EscrowDoesNotExist => write!(f, "Escrow does not exist"),
+            TxError::InvalidStream => write!(f, "Invalid payment stream"),
+            TxError::StreamDoesNotExist => write!(f, "Payment stream does not exist"),
+            TxError::NoAirdrop => write!(f, "No airdrop is set"),
+            TxError::InvalidProof => write!(f, "Invalid merkle proof"),
+            TxError::DistributionInProgress => write!(f, "Another distribution is in progress"),
+            TxError::InvalidMetadataKey => write!(f, "Invalid custom metadata key"),
+            TxError::InvalidAmount => write!(f, "Invalid decimal amount"),
+            TxError::FaucetLimitExceeded { remaining } => {
+                write!(
+                    f,
+                    "Daily faucet limit exceeded, {} left for today",
+                    remaining
+                )
+            }
+            TxError::ImportClosed => write!(f, "Import is only allowed before any transaction"),
+            TxError::TooManyScheduledTransfers => {
+                write!(f, "Maximum number of scheduled transfers reached")
+            }
+            TxError::InvalidMethodPolicy => {
+                write!(f, "Policies cannot be set for query methods")
+            }
+            TxError::ScheduledTransferDoesNotExist => {
+                write!(f, "Scheduled transfer does not exist")
+            }
+            TxError::BatchTransferFailed { index, tx_error } => {
+                write!(f, "Transfer {} of the batch failed: {}", index, tx_error)
+            }
+            TxError::BatchTooLarge { max_len } => {
+                write!(f, "Batch is larger than {} items", max_len)
+            }
+            TxError::ReceiverNotSupported => {
+                write!(f, "Receiver does not implement the IS20 receiver interface")
+            }
         }
     }
 }
 
 impl Error for TxError {}
 
+impl TxError {
+    /// Stable numeric code of the error, returned as the `error_code` of the ICRC-1
+    /// `GenericError`. The codes are never reused, new variants get the next free code.
+    pub fn error_code(&self) -> u64 {
+        match self {
+            TxError::InsufficientBalance => 1,
+            TxError::InsufficientAllowance => 2,
+            TxError::NoAllowance => 3,
+            TxError::Unauthorized => 4,
+            TxError::AmountTooSmall => 5,
+            TxError::FeeExceededLimit => 6,
+            TxError::ApproveSucceededButNotifyFailed { .. } => 7,
+            TxError::NotificationFailed { .. } => 8,
+            TxError::AlreadyActioned => 9,
+            TxError::NotificationDoesNotExist => 10,
+            TxError::TransactionDoesNotExist => 11,
+            TxError::BadFee { .. } => 12,
+            TxError::InsufficientFunds { .. } => 13,
+            TxError::TxTooOld { .. } => 14,
+            TxError::TxCreatedInFuture => 15,
+            TxError::TxDuplicate { .. } => 16,
+            TxError::SelfTransfer => 17,
+            TxError::AmountOverflow => 18,
+            TxError::NoPendingOwnerChange => 19,
+            TxError::OwnerChangeTooEarly { .. } => 20,
+            TxError::AccountDataTooLarge { .. } => 21,
+            TxError::MaxSupplyExceeded => 22,
+            TxError::AccountFrozen => 23,
+            TxError::MultisigRequired => 24,
+            TxError::InvalidMultisigConfig => 25,
+            TxError::ProposalDoesNotExist => 26,
+            TxError::TimelockRequired => 27,
+            TxError::UpdateDoesNotExist => 28,
+            TxError::UpdateNotReady { .. } => 29,
+            TxError::InvalidFeeConfig => 30,
+            TxError::ReceiverNotSupported => 31,
+            TxError::TooManySubscribers => 32,
+            TxError::BatchTooLarge { .. } => 33,
+            TxError::BatchTransferFailed { .. } => 34,
+            TxError::InvalidSchedule => 35,
+            TxError::ScheduledTransferDoesNotExist => 36,
+            TxError::InvalidVesting => 37,
+            TxError::NothingToClaim => 38,
+            TxError::InvalidLock => 39,
+            TxError::InvalidEscrow => 40,
+            TxError::EscrowDoesNotExist => 41,
+            TxError::InvalidStream => 42,
+            TxError::StreamDoesNotExist => 43,
+            TxError::NoAirdrop => 44,
+            TxError::InvalidProof => 45,
+            TxError::DistributionInProgress => 46,
+            TxError::InvalidMetadataKey => 47,
+            TxError::InvalidAmount => 48,
+            TxError::FaucetLimitExceeded { .. } => 49,
+            TxError::ImportClosed => 50,
+            TxError::TooManyScheduledTransfers => 51,
+            TxError::InvalidMethodPolicy => 52,
+        }
+    }
+}
+
 pub type TxReceipt = Result<u64, TxError>;
 
-// Notification receiver not set if None
-pub type PendingNotifications = HashMap<u64, Option<Principal>>;
+/// Transaction notification that failed to be delivered and is waiting to be retried.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct FailedNotification {
+    pub transaction_id: TxId,
+    pub to: Principal,
+    /// Number of the failed attempts to deliver the notification.
+    pub attempts: u32,
+    /// Time after which the notification is retried.
+    pub next_retry: Timestamp,
+}
+
+/// Parameter change recorded in the event log. These changes are not recorded in the transaction
+/// history.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub enum AdminEvent {
+    /// Change made by the owner directly.
+    Update(CanisterUpdate),
+    /// Operation executed by the multisig signers.
+    MultisigOp(AdminOp),
+    /// Update executed after the timelock delay.
+    TimelockedUpdate(TimelockedUpdate),
+    /// Multisig enabled by the owner.
+    MultisigEnabled(MultisigConfig),
+    /// Timelock delay increased by the owner.
+    TimelockDelay(Timestamp),
+    /// Cycles balance dropped below the threshold of the cycles monitor. Recorded with the token
+    /// canister as the caller.
+    LowCycles { balance: Cycles, threshold: Cycles },
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub enum EventKind {
+    Transaction(TxRecord),
+    Admin {
+        timestamp: Timestamp,
+        caller: Principal,
+        event: AdminEvent,
+    },
+}
+
+/// Entry of the event log returned by `getEvents`. The sequence numbers start from zero and have
+/// no gaps.
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct Event {
+    pub seq: u64,
+    pub kind: EventKind,
+}
+
+/// Filter of the transactions a subscriber is notified about.
+#[derive(CandidType, Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct SubscriptionFilter {
+    /// Operations to notify about. All operations match if empty.
+    pub operations: Vec<Operation>,
+    /// If set, only the transactions of this account match.
+    pub account: Option<Principal>,
+}
+
+impl SubscriptionFilter {
+    pub fn matches(&self, tx: &TxRecord) -> bool {
+        (self.operations.is_empty() || self.operations.contains(&tx.operation))
+            && self.account.map_or(true, |account| {
+                account == tx.from || account == tx.to || Some(account) == tx.caller
+            })
+    }
+}
+
+/// Argument of the call made to the receiver by `transferAndCall`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct TransactionCall {
+    pub transaction_id: TxId,
+    pub from: Principal,
+    pub amount: Tokens128,
+    /// Payload given by the caller, passed to the receiver as is.
+    pub payload: Vec<u8>,
+}
+
+/// Argument of the `is20_on_approval` call made to the spender by `approveAndCall`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct ApprovalCall {
+    pub transaction_id: TxId,
+    /// User who called `approveAndCall` and owns the approved tokens.
+    pub caller: Principal,
+    pub amount: Tokens128,
+    /// Payload given by the caller, passed to the spender as is.
+    pub payload: Vec<u8>,
+}
 
 #[derive(CandidType, Debug, Clone, Copy, Deserialize, PartialEq)]
 pub enum TransactionStatus {
@@ -175,6 +746,19 @@ pub enum Operation {
     TransferFrom,
     Burn,
     Auction,
+    Freeze,
+    Unfreeze,
+}
+
+/// Owner-configured policy of accepting the calls of an update method.
+#[derive(CandidType, Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum MethodPolicy {
+    /// Anyone can call the method, the default checks are skipped.
+    Open,
+    /// Only the owner can call the method, in addition to the default checks.
+    OwnerOnly,
+    /// The method cannot be called.
+    Deny,
 }
 
 #[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
@@ -186,6 +770,87 @@ pub struct AuctionInfo {
     pub fee_ratio: f64,
     pub first_transaction_id: TxId,
     pub last_transaction_id: TxId,
+    /// Random seed used to order equal bids when splitting the rewards.
+    pub seed: Vec<u8>,
+    /// Amount of ICP e8s sent to the bidders, if the rewards were paid in ICP.
+    pub icp_distributed: Option<u64>,
+}
+
+/// Totals of the held auctions. They are updated after each auction, so the auction history
+/// doesn't have to be walked to get them.
+#[derive(CandidType, Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct AuctionStats {
+    pub auctions_count: u64,
+    pub total_cycles_collected: u128,
+    pub total_tokens_distributed: Tokens128,
+    pub average_fee_ratio: f64,
+}
+
+impl AuctionStats {
+    pub fn record(&mut self, auction: &AuctionInfo) {
+        self.auctions_count += 1;
+        self.total_cycles_collected += auction.cycles_collected as u128;
+        self.total_tokens_distributed = (self.total_tokens_distributed
+            + auction.tokens_distributed)
+            .unwrap_or(Tokens128::from(u128::MAX));
+        self.average_fee_ratio +=
+            (auction.fee_ratio - self.average_fee_ratio) / self.auctions_count as f64;
+    }
+}
+
+/// Argument of the `auction_reward_notification` call sent to the bidders when their auction
+/// reward is paid.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct RewardNotification {
+    pub auction_id: usize,
+    pub transaction_id: TxId,
+    pub amount: Tokens128,
+}
+
+/// The asset the auction rewards are paid in.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub enum AuctionRewardAsset {
+    /// The accumulated fees are paid to the bidders as is.
+    Token,
+    /// The accumulated fees are swapped to ICP by the `exchange` canister, which sends the ICP to
+    /// the bidders. The swap fails if less than `min_icp_per_token` ICP e8s are received for a
+    /// whole token.
+    Icp {
+        exchange: Principal,
+        min_icp_per_token: u64,
+    },
+}
+
+/// Ledger of the token the auction bids can be paid in besides the cycles, see `bidTokens`.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq)]
+pub struct BidToken {
+    /// ICRC-2 ledger of the token, e.g. the ICP ledger.
+    pub ledger: Principal,
+    /// Account the paid tokens are sent to.
+    pub treasury: Principal,
+    /// Cycles the bid is credited with per smallest unit of the token.
+    pub cycles_per_unit: Cycles,
+}
+
+impl Default for AuctionRewardAsset {
+    fn default() -> Self {
+        Self::Token
+    }
+}
+
+/// Order of the transactions returned by `getTransactions`.
+#[derive(CandidType, Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Oldest transactions first.
+    Asc,
+    /// Newest transactions first.
+    Desc,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self::Desc
+    }
 }
 
 /// `PaginatedResult` is returned by paginated queries i.e `getTransactions`.
@@ -196,6 +861,19 @@ pub struct PaginatedResult {
 
     /// This is  the next `id` of the transaction. The `next` is used as offset for the next query if it exits.
     pub next: Option<TxId>,
+
+    /// Total number of the transactions of the queried user, as in `getUserTransactionCount`, or
+    /// of all the transactions if no user is given.
+    pub total_count: u64,
+}
+
+/// Page of the auction history returned by `getAuctionHistory` and `getAuctionsByTime`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq)]
+pub struct PaginatedAuctions {
+    pub result: Vec<AuctionInfo>,
+
+    /// Id of the next auction to query with `getAuctionHistory`, if there are more auctions.
+    pub next: Option<usize>,
 }
 
 pub type TxId = u64;
@@ -0,0 +1,241 @@
+use std::fmt::{Display, Formatter};
+
+use candid::{CandidType, Deserialize, Principal};
+
+pub type Subaccount = [u8; 32];
+
+const DEFAULT_SUBACCOUNT: Subaccount = [0; 32];
+
+/// An account as defined by the ICRC-1 standard: a principal with an optional subaccount. `None`
+/// subaccount is the same as the subaccount of all zeros.
+#[derive(Deserialize, CandidType, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+#[derive(Deserialize, CandidType, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountError {
+    /// The owner part of the account is not a valid principal.
+    InvalidPrincipal,
+    /// The subaccount part is not a valid hex string of at most 32 bytes.
+    InvalidSubaccount,
+    /// The checksum does not match the owner and the subaccount.
+    InvalidChecksum,
+    /// The text is a valid encoding of an account, but not the canonical one.
+    NotCanonical,
+    /// The account has a non-default subaccount, which is not supported by the method.
+    SubaccountNotSupported,
+}
+
+impl Display for AccountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountError::InvalidPrincipal => write!(f, "Invalid principal"),
+            AccountError::InvalidSubaccount => write!(f, "Invalid subaccount"),
+            AccountError::InvalidChecksum => write!(f, "Invalid checksum"),
+            AccountError::NotCanonical => write!(f, "Not a canonical account encoding"),
+            AccountError::SubaccountNotSupported => write!(f, "Subaccounts are not supported"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl Account {
+    pub fn new(owner: Principal, subaccount: Option<Subaccount>) -> Self {
+        Self { owner, subaccount }
+    }
+
+    pub fn subaccount_or_default(&self) -> Subaccount {
+        self.subaccount.unwrap_or(DEFAULT_SUBACCOUNT)
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.subaccount_or_default() == DEFAULT_SUBACCOUNT
+    }
+
+    /// Parses the account from the ICRC-1 textual encoding:
+    /// `<owner>-<checksum>.<subaccount hex without leading zeros>`, or just `<owner>` for the
+    /// default subaccount.
+    pub fn from_text(text: &str) -> Result<Self, AccountError> {
+        let (owner_and_checksum, subaccount_hex) = match text.split_once('.') {
+            Some(parts) => parts,
+            None => {
+                let owner =
+                    Principal::from_text(text).map_err(|_| AccountError::InvalidPrincipal)?;
+                return Ok(Self::new(owner, None));
+            }
+        };
+
+        let (owner, checksum) = owner_and_checksum
+            .rsplit_once('-')
+            .ok_or(AccountError::InvalidChecksum)?;
+        let owner = Principal::from_text(owner).map_err(|_| AccountError::InvalidPrincipal)?;
+
+        if subaccount_hex.starts_with('0') {
+            return Err(AccountError::NotCanonical);
+        }
+        let subaccount = decode_subaccount(subaccount_hex)?;
+        if subaccount == DEFAULT_SUBACCOUNT {
+            return Err(AccountError::NotCanonical);
+        }
+
+        if checksum != account_checksum(&owner, &subaccount) {
+            return Err(AccountError::InvalidChecksum);
+        }
+
+        Ok(Self::new(owner, Some(subaccount)))
+    }
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_default() {
+            return write!(f, "{}", self.owner.to_text());
+        }
+
+        let subaccount = self.subaccount_or_default();
+        let hex = subaccount
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        write!(
+            f,
+            "{}-{}.{}",
+            self.owner.to_text(),
+            account_checksum(&self.owner, &subaccount),
+            hex.trim_start_matches('0')
+        )
+    }
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self::new(owner, None)
+    }
+}
+
+fn decode_subaccount(hex: &str) -> Result<Subaccount, AccountError> {
+    if hex.is_empty() || hex.len() > 64 || !hex.is_ascii() {
+        return Err(AccountError::InvalidSubaccount);
+    }
+
+    let padded = format!("{hex:0>64}");
+    let mut subaccount = DEFAULT_SUBACCOUNT;
+    for (i, byte) in subaccount.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&padded[i * 2..i * 2 + 2], 16)
+            .map_err(|_| AccountError::InvalidSubaccount)?;
+    }
+
+    Ok(subaccount)
+}
+
+/// CRC32 of the owner and subaccount bytes, encoded as lowercase base32 without padding.
+fn account_checksum(owner: &Principal, subaccount: &Subaccount) -> String {
+    let mut bytes = owner.as_slice().to_vec();
+    bytes.extend_from_slice(subaccount);
+    base32(&crc32(&bytes).to_be_bytes())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    let mut result = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        result.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "k2t6j-2nvnp-4zjm3-25dtz-6xhaa-c7boj-5gayf-oj3xs-i43lp-teztq-6ae";
+
+    fn test_subaccount() -> Subaccount {
+        let mut subaccount = DEFAULT_SUBACCOUNT;
+        for (i, byte) in subaccount.iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        subaccount
+    }
+
+    #[test]
+    fn default_subaccount_encoding() {
+        let owner = Principal::from_text(OWNER).unwrap();
+        assert_eq!(Account::new(owner, None).to_string(), OWNER);
+        assert_eq!(Account::new(owner, Some([0; 32])).to_string(), OWNER);
+        assert_eq!(Account::from_text(OWNER), Ok(Account::new(owner, None)));
+    }
+
+    #[test]
+    fn subaccount_encoding() {
+        let owner = Principal::from_text(OWNER).unwrap();
+        let account = Account::new(owner, Some(test_subaccount()));
+        let text = format!(
+            "{OWNER}-dfxgiyy.102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20"
+        );
+
+        assert_eq!(account.to_string(), text);
+        assert_eq!(Account::from_text(&text), Ok(account));
+    }
+
+    #[test]
+    fn short_subaccount_encoding() {
+        let owner = Principal::from_text(OWNER).unwrap();
+        let mut subaccount = DEFAULT_SUBACCOUNT;
+        subaccount[31] = 1;
+        let account = Account::new(owner, Some(subaccount));
+
+        let text = account.to_string();
+        assert!(text.ends_with(".1"));
+        assert_eq!(Account::from_text(&text), Ok(account));
+    }
+
+    #[test]
+    fn invalid_encodings() {
+        assert_eq!(
+            Account::from_text(&format!("{OWNER}-aaaaaaa.1")),
+            Err(AccountError::InvalidChecksum)
+        );
+        assert_eq!(
+            Account::from_text(&format!("{OWNER}-dfxgiyy.01")),
+            Err(AccountError::NotCanonical)
+        );
+        assert_eq!(
+            Account::from_text(&format!("{OWNER}-dfxgiyy.xyz")),
+            Err(AccountError::InvalidSubaccount)
+        );
+        assert_eq!(
+            Account::from_text("not a principal"),
+            Err(AccountError::InvalidPrincipal)
+        );
+    }
+}
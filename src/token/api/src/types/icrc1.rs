@@ -0,0 +1,121 @@
+use candid::{CandidType, Deserialize, Nat};
+use ic_canister::ic_kit::ic;
+use ic_helpers::tokens::Tokens128;
+
+use crate::types::{Account, Subaccount, Timestamp, TxError};
+
+/// Arguments of the ICRC-1 `icrc1_transfer` method. The amounts are taken as `Nat` of any size and
+/// converted to [Tokens128] by the method, so the amounts over 128 bits are rejected with the
+/// `AmountOverflow` error.
+#[derive(Deserialize, CandidType, Debug, Clone, PartialEq)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Nat,
+    /// Fee the caller expects to pay. The transfer is rejected with `BadFee` if it differs from the
+    /// token fee.
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<Timestamp>,
+}
+
+/// Errors of the ICRC-1 `icrc1_transfer` method. The IS20 errors that have no counterpart in the
+/// standard are returned as `GenericError` with the [TxError::error_code] and the error message.
+#[derive(Deserialize, CandidType, Debug, Clone, PartialEq)]
+pub enum TransferError {
+    BadFee { expected_fee: Tokens128 },
+    BadBurn { min_burn_amount: Tokens128 },
+    InsufficientFunds { balance: Tokens128 },
+    TooOld,
+    CreatedInFuture { ledger_time: Timestamp },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+impl From<TxError> for TransferError {
+    fn from(error: TxError) -> Self {
+        match error {
+            TxError::BadFee { expected_fee } => TransferError::BadFee { expected_fee },
+            TxError::InsufficientFunds { balance } => TransferError::InsufficientFunds { balance },
+            TxError::TxTooOld { .. } => TransferError::TooOld,
+            TxError::TxCreatedInFuture => TransferError::CreatedInFuture {
+                ledger_time: ic::time(),
+            },
+            TxError::TxDuplicate { duplicate_of } => TransferError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            error => TransferError::GenericError {
+                error_code: Nat::from(error.error_code()),
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Arguments of the ICRC-2 `icrc2_transfer_from` method, used to take the auction bids paid in
+/// the tokens of another ledger.
+#[derive(Deserialize, CandidType, Debug, Clone, PartialEq)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Tokens128,
+    pub fee: Option<Tokens128>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<Timestamp>,
+}
+
+/// Errors of the ICRC-2 `icrc2_transfer_from` method.
+#[derive(Deserialize, CandidType, Debug, Clone, PartialEq)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Tokens128 },
+    BadBurn { min_burn_amount: Tokens128 },
+    InsufficientFunds { balance: Tokens128 },
+    InsufficientAllowance { allowance: Tokens128 },
+    TooOld,
+    CreatedInFuture { ledger_time: Timestamp },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structured_errors() {
+        assert_eq!(
+            TransferError::from(TxError::BadFee {
+                expected_fee: Tokens128::from(10)
+            }),
+            TransferError::BadFee {
+                expected_fee: Tokens128::from(10)
+            }
+        );
+        assert_eq!(
+            TransferError::from(TxError::TxTooOld {
+                allowed_window_nanos: 0
+            }),
+            TransferError::TooOld
+        );
+        assert_eq!(
+            TransferError::from(TxError::TxDuplicate { duplicate_of: 3 }),
+            TransferError::Duplicate {
+                duplicate_of: Nat::from(3u64)
+            }
+        );
+    }
+
+    #[test]
+    fn generic_error() {
+        assert_eq!(
+            TransferError::from(TxError::AccountFrozen),
+            TransferError::GenericError {
+                error_code: Nat::from(23u64),
+                message: "Account is frozen".to_string(),
+            }
+        );
+    }
+}
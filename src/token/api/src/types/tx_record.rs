@@ -106,6 +106,20 @@ impl TxRecord {
         }
     }
 
+    /// The burned share of the fee paid by `from`. The record has no caller, as the burn is made by
+    /// the canister as a part of the `from` transaction, whose record already includes the whole fee.
+    pub fn fee_burn(index: TxId, from: Principal, amount: Tokens128) -> Self {
+        Self {
+            caller: None,
+            ..Self::burn(index, from, from, amount)
+        }
+    }
+
+    /// Whether the record is a burn of a fee share, see [Self::fee_burn].
+    pub fn is_fee_burn(&self) -> bool {
+        self.operation == Operation::Burn && self.caller.is_none()
+    }
+
     pub fn auction(index: TxId, to: Principal, amount: Tokens128) -> Self {
         Self {
             caller: Some(to),
@@ -119,4 +133,25 @@ impl TxRecord {
             operation: Operation::Auction,
         }
     }
+
+    pub fn freeze(index: TxId, caller: Principal, who: Principal) -> Self {
+        Self {
+            caller: Some(caller),
+            index,
+            from: caller,
+            to: who,
+            amount: Tokens128::from(0u128),
+            fee: Tokens128::from(0u128),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Freeze,
+        }
+    }
+
+    pub fn unfreeze(index: TxId, caller: Principal, who: Principal) -> Self {
+        Self {
+            operation: Operation::Unfreeze,
+            ..Self::freeze(index, caller, who)
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use candid::Principal;
-use ic_canister::{init, Canister, PreUpdate};
+use ic_canister::{init, post_upgrade, pre_upgrade, Canister, PreUpdate};
 
 #[cfg(not(feature = "no_api"))]
 use ic_cdk_macros::inspect_message;
@@ -9,11 +9,12 @@ use ic_helpers::candid_header::{candid_header, CandidHeader};
 use std::{cell::RefCell, rc::Rc};
 use token_api::{
     canister::{TokenCanisterAPI, DEFAULT_AUCTION_PERIOD},
-    state::CanisterState,
-    types::Metadata,
+    state::{stable, CanisterState},
+    types::{valid_metadata_key, Metadata},
 };
 
 #[derive(Debug, Clone, Canister)]
+#[canister_no_upgrade_methods]
 pub struct TokenCanister {
     #[id]
     principal: Principal,
@@ -24,19 +25,98 @@ pub struct TokenCanister {
 impl TokenCanister {
     #[init]
     pub fn init(&self, metadata: Metadata) {
-        self.state
-            .borrow_mut()
-            .balances
-            .0
-            .insert(metadata.owner, metadata.totalSupply);
+        if let Some(max_supply) = metadata.maxSupply {
+            assert!(
+                metadata.totalSupply <= max_supply,
+                "initial supply exceeds the max supply"
+            );
+        }
 
-        self.state
-            .borrow_mut()
-            .ledger
-            .mint(metadata.owner, metadata.owner, metadata.totalSupply);
+        let initial_balances = metadata
+            .initial_balances()
+            .expect("genesis allocation doesn't sum up to the total supply");
+        for (holder, amount) in initial_balances {
+            let mut state = self.state.borrow_mut();
+            let balance = (state.balances.balance_of(&holder) + amount)
+                .expect("balances sum up to the total supply");
+            state.balances.set(holder, balance);
+            state.ledger.mint(metadata.owner, holder, amount);
+        }
+
+        let custom_metadata = metadata.customMetadata.clone().unwrap_or_default();
+        assert!(
+            custom_metadata
+                .iter()
+                .all(|(key, _)| valid_metadata_key(key)),
+            "invalid custom metadata key"
+        );
 
+        let registry = metadata.registry;
+        let auction_period = metadata
+            .auctionPeriod
+            .map(|period_sec| period_sec.saturating_mul(1_000_000_000))
+            .unwrap_or(DEFAULT_AUCTION_PERIOD);
         self.state.borrow_mut().stats = metadata.into();
-        self.state.borrow_mut().bidding_state.auction_period = DEFAULT_AUCTION_PERIOD;
+        self.state.borrow_mut().bidding_state.auction_period = auction_period;
+        self.state.borrow_mut().custom_metadata = custom_metadata.into_iter().collect();
+        let transactions = self.state.borrow().ledger.len();
+        self.state.borrow_mut().allowances_import = Some(transactions);
+        self.state.borrow_mut().owner_rotation.registry = registry;
+        self.set_timers();
+    }
+
+    // Balances and allowances are kept in the stable structures, which are updated on every change,
+    // so only the heap part of the state is serialized here. It is written into its own virtual
+    // memory, as the beginning of the stable memory is used by the memory manager.
+    #[pre_upgrade]
+    pub fn pre_upgrade(&self) {
+        // Trapping here makes the upgrade fail and keeps the canister as it is.
+        if let Err(err) = self.state.borrow_mut().multisig.take_upgrade_approval() {
+            ic_cdk::trap(&format!("upgrade refused: {err}"));
+        }
+
+        let data = candid::encode_one(&*self.state.borrow())
+            .expect("failed to serialize state to the stable storage");
+        stable::write_upgrade_data(&data);
+    }
+
+    #[post_upgrade]
+    pub fn post_upgrade(&self) {
+        self.set_timers();
+        if stable::has_legacy_layout() {
+            self.upgrade_from_legacy_layout();
+            return;
+        }
+
+        let data = stable::read_upgrade_data();
+        if data.is_empty() {
+            return;
+        }
+
+        let state = candid::decode_one::<CanisterState>(&data)
+            .expect("failed to read stable state from the stable storage");
+        self.state.replace(state);
+        self.state.borrow_mut().allowances.rebuild_spender_index();
+    }
+
+    /// Reads the state written with `ic_storage` before the stable structures were used, and moves
+    /// the balances, allowances, bids and auctions into the stable structures.
+    fn upgrade_from_legacy_layout(&self) {
+        let state = ic_storage::stable::read::<CanisterState>()
+            .expect("failed to read stable state from the stable storage");
+        self.state.replace(state);
+    }
+
+    // The timers are not kept across the upgrades, so they are set up in both `init` and
+    // `post_upgrade`. The mock environment of the tests has no timers, the tests run the tasks
+    // directly.
+    fn set_timers(&self) {
+        #[cfg(target_arch = "wasm32")]
+        ic_cdk_timers::set_timer_interval(token_api::canister::TIMER_INTERVAL, || {
+            ic_cdk::spawn(async {
+                token_api::canister::run_timer_tasks(&TokenCanister::init_instance()).await
+            })
+        });
     }
 
     #[query]
@@ -45,6 +125,19 @@ impl TokenCanister {
     }
 }
 
+/// Candid interface of the token generated by `main.rs`. The build script generates it before
+/// building the wasm, so it's only included in the wasm build.
+#[cfg(target_arch = "wasm32")]
+const CANDID_INTERFACE: &str = include_str!("../../../candid/token.did");
+
+/// Returns the candid interface of the token, so the tools like Candid UI can introspect it. The
+/// same interface is stored in the `candid:service` metadata section of the wasm.
+#[cfg(target_arch = "wasm32")]
+#[ic_cdk_macros::query]
+fn __get_candid_interface_tmp_hack() -> String {
+    CANDID_INTERFACE.to_string()
+}
+
 #[cfg(not(feature = "no_api"))]
 #[inspect_message]
 fn inspect_message() {
@@ -93,7 +186,64 @@ mod test {
 
         write(&()).unwrap();
         let canister = TokenCanister::init_instance();
-        canister.__post_upgrade_inst();
+        canister.upgrade_from_legacy_layout();
+    }
+
+    #[test]
+    fn test_upgrade_from_legacy_layout() {
+        use ic_canister::ic_kit::mock_principals::{alice, bob};
+        use ic_helpers::tokens::Tokens128;
+        use ic_storage::stable::write;
+        use token_api::state::legacy::CanisterStateV1;
+        use token_api::types::TxRecord;
+
+        MockContext::new().inject();
+
+        let mut legacy = CanisterStateV1::default();
+        legacy.stats.total_supply = Tokens128::from(1000);
+        legacy.balances.0.insert(alice(), Tokens128::from(600));
+        legacy.balances.0.insert(bob(), Tokens128::from(400));
+        legacy.allowances.insert(
+            alice(),
+            [(bob(), Tokens128::from(50))].into_iter().collect(),
+        );
+        legacy
+            .ledger
+            .history
+            .push(TxRecord::mint(0, alice(), alice(), Tokens128::from(1000)));
+        legacy.ledger.notifications.insert(0, None);
+        legacy.bidding_state.fee_ratio = 0.5;
+        legacy.bidding_state.bids.insert(bob(), 2_000);
+        write(&legacy).unwrap();
+
+        let canister = TokenCanister::init_instance();
+        canister.upgrade_from_legacy_layout();
+
+        let state = canister.state.borrow();
+        assert_eq!(state.balances.balance_of(&alice()), Tokens128::from(600));
+        assert_eq!(state.balances.balance_of(&bob()), Tokens128::from(400));
+        assert_eq!(state.allowances.get(alice(), bob()), Tokens128::from(50));
+        assert_eq!(
+            state.spender_approvals(bob()),
+            vec![(alice(), Tokens128::from(50))]
+        );
+        assert_eq!(state.ledger.len(), 1);
+        assert_eq!(state.ledger.get(0).unwrap().to, alice());
+        assert_eq!(state.ledger.get_len_user_history(alice()), 1);
+        assert!(state.ledger.notifications.contains_key(0));
+        assert_eq!(state.balance_of_at(alice(), 0), Some(Tokens128::from(600)));
+        assert_eq!(state.stats.total_minted, Tokens128::from(1000));
+        assert_eq!(state.bidding_state.fee_ratio, 0.5);
+        assert_eq!(state.bidding_state.bids.get(&bob()), Some(2_000));
+    }
+
+    #[test]
+    fn test_upgrade_without_stored_state() {
+        MockContext::new().inject();
+
+        let canister = TokenCanister::init_instance();
+        canister.post_upgrade();
+        assert_eq!(canister.state.borrow().bidding_state.fee_ratio, 0.0);
     }
 
     #[test]
@@ -106,7 +256,7 @@ mod test {
         state.bidding_state.fee_ratio = 12345.0;
         drop(state);
         // ... write the state to stable storage
-        canister.__pre_upgrade_inst();
+        canister.pre_upgrade();
 
         // Update the value without writing it to stable storage
         let mut state = canister.state.borrow_mut();
@@ -115,8 +265,29 @@ mod test {
 
         // Upgrade the canister should have the state
         // written before pre_upgrade
-        canister.__post_upgrade_inst();
+        canister.post_upgrade();
         let state = canister.state.borrow();
         assert_eq!(state.bidding_state.fee_ratio, 12345.0);
     }
+
+    #[test]
+    fn test_upgrade_keeps_stable_balances() {
+        use ic_canister::ic_kit::mock_principals::alice;
+        use ic_helpers::tokens::Tokens128;
+
+        MockContext::new().inject();
+
+        let canister = TokenCanister::init_instance();
+        canister
+            .state
+            .borrow_mut()
+            .balances
+            .set(alice(), Tokens128::from(100));
+        canister.pre_upgrade();
+        canister.post_upgrade();
+
+        let state = canister.state.borrow();
+        assert_eq!(state.balances.balance_of(&alice()), Tokens128::from(100));
+        assert_eq!(state.balances.len(), 1);
+    }
 }
@@ -0,0 +1,102 @@
+//! Wasm instruction counts of the paths that get slower as the token grows: the transaction
+//! history query, the holders query and the transfer. The state is grown with `bench_fill` calls,
+//! and the counts are measured with the `bench_instructions` query, e.g.:
+//!
+//! ```shell
+//! TOKEN_TEST_FEATURES=bench dfx deploy token_test --argument '(record { ... })'
+//! for i in $(seq 10); do dfx canister call token_test bench_fill '(10_000)'; done
+//! dfx canister call token_test bench_instructions
+//! ```
+//!
+//! `bench_fill` mints tokens to the generated principals, so it's only available when the canister
+//! is built with the `bench` feature.
+
+use candid::Principal;
+use ic_helpers::tokens::Tokens128;
+use token_api::canister::erc20_transactions::{mint, transfer_tokens};
+use token_api::canister::TokenCanisterAPI;
+use token_api::types::SortOrder;
+
+/// Maximum number of the holders added by one `bench_fill` call, so the call stays within the
+/// instruction limit.
+pub const MAX_FILL_COUNT: u64 = 10_000;
+
+/// Transaction history is queried in pages of this size, same as in the native benchmarks.
+const PAGE_SIZE: usize = 100;
+
+fn holder(index: u64) -> Principal {
+    Principal::from_slice(&index.to_be_bytes())
+}
+
+/// Mints to up to `count` new generated holders, adding a transaction per holder. Returns the
+/// number of the holders.
+pub fn fill(token: &impl TokenCanisterAPI, count: u64) -> Result<u64, String> {
+    let state = token.state();
+    let mut state = state.borrow_mut();
+    let start = state.balances.len() as u64;
+    let end = start + count.min(MAX_FILL_COUNT);
+    let caller = state.stats.owner;
+    for index in start..end {
+        mint(
+            &mut state,
+            caller,
+            holder(index),
+            Tokens128::from(index as u128 + 1),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(end)
+}
+
+/// Returns the number of the wasm instructions executed by each of the measured paths.
+pub fn instruction_counts(token: &impl TokenCanisterAPI) -> Vec<(String, u64)> {
+    let mut counts = vec![];
+    let mut measure = |name: &str, path: &mut dyn FnMut()| {
+        let start = instructions();
+        path();
+        counts.push((name.to_string(), instructions() - start));
+    };
+
+    let state = token.state();
+    measure("get_transactions", &mut || {
+        state
+            .borrow()
+            .ledger
+            .get_transactions(None, PAGE_SIZE, None, SortOrder::Desc);
+    });
+    measure("get_user_transactions", &mut || {
+        state
+            .borrow()
+            .ledger
+            .get_transactions(Some(holder(2)), PAGE_SIZE, None, SortOrder::Desc);
+    });
+    measure("get_holders", &mut || {
+        state.borrow().balances.get_holders(None, PAGE_SIZE);
+    });
+    // The changes made by the transfer are discarded, as it's measured in a query call.
+    measure("transfer", &mut || {
+        let _ = transfer_tokens(
+            &mut state.borrow_mut(),
+            holder(1),
+            holder(2),
+            Tokens128::from(1),
+            None,
+        );
+    });
+
+    counts
+}
+
+/// Number of the instructions executed in the current call. Always zero outside of the canister.
+fn instructions() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::performance_counter(0)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
@@ -0,0 +1,32 @@
+//! Token canister with scripted integration scenarios.
+//!
+//! The canister exports the whole token API along with the `run_scenario` method, which runs a
+//! named scenario against the canister state and checks the results. Deploying it to a replica
+//! allows running the integration suites against the real wasm, e.g.:
+//!
+//! ```shell
+//! dfx canister call token_test run_scenario '("upgrade_prepare")'
+//! dfx canister install token_test --mode upgrade
+//! dfx canister call token_test run_scenario '("upgrade_check")'
+//! ```
+
+#![allow(dead_code)]
+
+mod bench;
+mod scenarios;
+
+#[cfg(any(target_arch = "wasm32", test))]
+fn main() {}
+
+#[cfg(not(any(target_arch = "wasm32", test)))]
+fn main() {
+    use crate::scenarios::ScenarioCanister;
+    use token_api::canister::TokenCanisterAPI;
+
+    let canister_idl = ic_canister::generate_idl!();
+    let mut trait_idl = <ScenarioCanister as TokenCanisterAPI>::get_idl();
+    trait_idl.merge(&canister_idl);
+
+    let result = candid::bindings::candid::compile(&trait_idl.env.env, &Some(trait_idl.actor));
+    print!("{result}");
+}
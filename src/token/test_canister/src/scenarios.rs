@@ -0,0 +1,336 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use candid::Principal;
+use ic_canister::ic_kit::ic;
+use ic_canister::{init, post_upgrade, pre_upgrade, query, update, Canister, PreUpdate};
+use ic_helpers::tokens::Tokens128;
+use ic_stable_structures::memory_manager::MemoryId;
+use token_api::canister::TokenCanisterAPI;
+use token_api::state::{stable, CanisterState};
+use token_api::types::{Metadata, TxId, TxRecord};
+use token_canister::canister::TokenCanister;
+
+const SCENARIOS: &[&str] = &[
+    "transfer",
+    "auction",
+    "notify",
+    "notify_check",
+    "upgrade_prepare",
+    "upgrade_check",
+];
+
+// Virtual memory keeping the state snapshot over the upgrade. The ids from the end of the range are
+// not used by the token.
+const UPGRADE_SNAPSHOT_MEMORY_ID: MemoryId = MemoryId::new(254);
+
+thread_local! {
+    static UPGRADE_SNAPSHOT: RefCell<Option<String>> = RefCell::new(None);
+    static RECEIVED_NOTIFICATIONS: RefCell<Vec<TxId>> = RefCell::new(Vec::new());
+    static SENT_NOTIFICATION: RefCell<Option<TxId>> = RefCell::new(None);
+}
+
+/// Runs the scenarios on the token canister state. The scenarios are run on behalf of the caller,
+/// so the caller must hold enough tokens for the transfers.
+#[derive(Debug, Clone, Canister)]
+#[canister_no_upgrade_methods]
+pub struct ScenarioCanister {
+    #[id]
+    principal: Principal,
+    #[state]
+    state: Rc<RefCell<CanisterState>>,
+}
+
+impl PreUpdate for ScenarioCanister {}
+
+impl TokenCanisterAPI for ScenarioCanister {
+    fn state(&self) -> Rc<RefCell<CanisterState>> {
+        self.state.clone()
+    }
+}
+
+impl ScenarioCanister {
+    // Initialization and upgrades are the same as of the token canister.
+    #[init]
+    fn init(&self, metadata: Metadata) {
+        TokenCanister::from_principal(self.principal).init(metadata);
+    }
+
+    #[pre_upgrade]
+    fn pre_upgrade(&self) {
+        TokenCanister::from_principal(self.principal).pre_upgrade();
+        let snapshot = UPGRADE_SNAPSHOT.with(|snapshot| snapshot.borrow().clone());
+        stable::write_memory_data(
+            UPGRADE_SNAPSHOT_MEMORY_ID,
+            snapshot.unwrap_or_default().as_bytes(),
+        );
+    }
+
+    #[post_upgrade]
+    fn post_upgrade(&self) {
+        TokenCanister::from_principal(self.principal).post_upgrade();
+        let snapshot = String::from_utf8(stable::read_memory_data(UPGRADE_SNAPSHOT_MEMORY_ID))
+            .ok()
+            .filter(|snapshot| !snapshot.is_empty());
+        UPGRADE_SNAPSHOT.with(|stored| *stored.borrow_mut() = snapshot);
+    }
+
+    /// Runs the scenario with the given name, returning the first failed check if any.
+    #[update]
+    async fn run_scenario(&self, name: String) -> Result<(), String> {
+        match name.as_str() {
+            "transfer" => transfer(self),
+            "auction" => auction(self).await,
+            "notify" => notify(self).await,
+            "notify_check" => notify_check(),
+            "upgrade_prepare" => upgrade_prepare(self),
+            "upgrade_check" => upgrade_check(self),
+            _ => Err(format!("Unknown scenario {name}")),
+        }
+    }
+
+    #[query]
+    fn scenario_names(&self) -> Vec<String> {
+        SCENARIOS.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Adds up to `count` generated holders with a transaction each, see the [crate::bench]
+    /// module. Returns the number of the holders. Only available with the `bench` feature.
+    #[update]
+    fn bench_fill(&self, count: u64) -> Result<u64, String> {
+        if !cfg!(feature = "bench") {
+            return Err(
+                "bench_fill requires the canister built with the bench feature".to_string(),
+            );
+        }
+
+        crate::bench::fill(self, count)
+    }
+
+    /// Returns the number of the wasm instructions of the benchmarked paths on the current state.
+    #[query]
+    fn bench_instructions(&self) -> Vec<(String, u64)> {
+        crate::bench::instruction_counts(self)
+    }
+
+    /// Receives the notifications sent by the `notify` scenario.
+    #[update]
+    fn transaction_notification(&self, tx: TxRecord) {
+        RECEIVED_NOTIFICATIONS.with(|received| received.borrow_mut().push(tx.index));
+    }
+}
+
+fn ensure(condition: bool, message: &str) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(message.to_string())
+    }
+}
+
+fn scenario_principal() -> Principal {
+    Principal::from_slice(&[0xAB; 29])
+}
+
+/// Transfers tokens from the caller and checks the balances, the fee and the recorded
+/// transaction.
+fn transfer(token: &impl TokenCanisterAPI) -> Result<(), String> {
+    let caller = ic::caller();
+    let recipient = scenario_principal();
+    let amount = Tokens128::from(1000);
+    let fee = token.getMetadata().fee;
+    let total_supply = token.totalSupply();
+    let caller_balance = token.balanceOf(caller);
+    let recipient_balance = token.balanceOf(recipient);
+
+    let id = token
+        .transfer(recipient, amount, None)
+        .map_err(|e| e.to_string())?;
+
+    // The fee may go back to the caller if it's the fee receiver, so only the bounds are checked.
+    let spent = caller_balance.amount - token.balanceOf(caller).amount;
+    ensure(
+        spent >= amount.amount && spent <= amount.amount + fee.amount,
+        "wrong caller balance",
+    )?;
+    ensure(
+        token.balanceOf(recipient) == (recipient_balance + amount).ok_or("amount overflow")?,
+        "wrong recipient balance",
+    )?;
+    ensure(token.totalSupply() == total_supply, "total supply changed")?;
+
+    let tx = token.getTransaction(id);
+    ensure(
+        tx.from == caller && tx.to == recipient && tx.amount == amount,
+        "wrong transaction record",
+    )
+}
+
+/// Bids the cycles attached to the call and runs the auction right away, without waiting for the
+/// auction period. The call must be made with at least 1M cycles attached.
+async fn auction(token: &impl TokenCanisterAPI) -> Result<(), String> {
+    let caller = ic::caller();
+    let history_len = token.getTokenInfo().historySize;
+
+    token.bidCycles(caller).map_err(|e| format!("{e:?}"))?;
+    let period = std::mem::replace(
+        &mut token.state().borrow_mut().bidding_state.auction_period,
+        0,
+    );
+    let result = token.runAuction().await;
+    token.state().borrow_mut().bidding_state.auction_period = period;
+
+    let info = result.map_err(|e| format!("{e:?}"))?;
+    ensure(
+        token.auctionInfo(info.auction_id).ok() == Some(info.clone()),
+        "auction is not recorded",
+    )?;
+    ensure(
+        token.getTokenInfo().historySize >= history_len,
+        "history is truncated",
+    )
+}
+
+/// Transfers tokens to this canister and notifies it. The notification is checked by the
+/// `notify_check` scenario, as it is delivered after the call.
+async fn notify(token: &impl TokenCanisterAPI) -> Result<(), String> {
+    let id = token
+        .transfer(ic::id(), Tokens128::from(1), None)
+        .map_err(|e| e.to_string())?;
+    token
+        .notify(id, ic::id())
+        .await
+        .map_err(|e| e.to_string())?;
+    SENT_NOTIFICATION.with(|sent| *sent.borrow_mut() = Some(id));
+
+    Ok(())
+}
+
+fn notify_check() -> Result<(), String> {
+    let sent = SENT_NOTIFICATION
+        .with(|sent| *sent.borrow())
+        .ok_or("notify scenario was not run")?;
+    let received = RECEIVED_NOTIFICATIONS.with(|received| received.borrow().contains(&sent));
+    ensure(received, "notification was not received")
+}
+
+fn state_snapshot(token: &impl TokenCanisterAPI) -> String {
+    let info = token.getTokenInfo();
+    format!(
+        "{}:{}:{}:{}",
+        token.totalSupply(),
+        info.holderNumber,
+        info.historySize,
+        token.getAllowanceSize(),
+    )
+}
+
+/// Saves the state snapshot to be checked by `upgrade_check` after the canister upgrade.
+fn upgrade_prepare(token: &impl TokenCanisterAPI) -> Result<(), String> {
+    let snapshot = state_snapshot(token);
+    UPGRADE_SNAPSHOT.with(|stored| *stored.borrow_mut() = Some(snapshot));
+
+    Ok(())
+}
+
+fn upgrade_check(token: &impl TokenCanisterAPI) -> Result<(), String> {
+    let expected = UPGRADE_SNAPSHOT
+        .with(|stored| stored.borrow_mut().take())
+        .ok_or("upgrade_prepare scenario was not run")?;
+
+    ensure(
+        state_snapshot(token) == expected,
+        "state changed over the upgrade",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_canister::ic_kit::mock_principals::alice;
+    use ic_canister::ic_kit::MockContext;
+
+    use super::*;
+
+    fn test_canister() -> ScenarioCanister {
+        MockContext::new().with_caller(alice()).inject();
+
+        let canister = ScenarioCanister::init_instance();
+        let mut state = canister.state.borrow_mut();
+        state.balances.set(alice(), Tokens128::from(10_000));
+        state.ledger.mint(alice(), alice(), Tokens128::from(10_000));
+        state.stats = Metadata {
+            logo: "".to_string(),
+            name: "".to_string(),
+            symbol: "".to_string(),
+            decimals: 8,
+            totalSupply: Tokens128::from(10_000),
+            owner: alice(),
+            fee: Tokens128::from(10),
+            feeTo: alice(),
+            isTestToken: None,
+            maxSupply: None,
+            auctionDisabled: None,
+            auctionPeriod: None,
+            minCycles: None,
+            genesisAllocation: None,
+            customMetadata: None,
+            registry: None,
+        }
+        .into();
+        drop(state);
+
+        canister
+    }
+
+    #[tokio::test]
+    async fn transfer_scenario() {
+        let canister = test_canister();
+        assert_eq!(canister.run_scenario("transfer".to_string()).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn upgrade_scenarios() {
+        let canister = test_canister();
+        assert_eq!(
+            canister.run_scenario("upgrade_check".to_string()).await,
+            Err("upgrade_prepare scenario was not run".to_string())
+        );
+        assert_eq!(
+            canister.run_scenario("upgrade_prepare".to_string()).await,
+            Ok(())
+        );
+        assert_eq!(canister.getMetadata().customMetadata, Some(vec![]));
+
+        canister.pre_upgrade();
+        UPGRADE_SNAPSHOT.with(|stored| *stored.borrow_mut() = None);
+        canister.post_upgrade();
+        assert_eq!(
+            canister.run_scenario("upgrade_check".to_string()).await,
+            Ok(())
+        );
+    }
+
+    #[cfg(not(feature = "bench"))]
+    #[test]
+    fn bench_fill_disabled() {
+        let canister = test_canister();
+        assert!(canister.bench_fill(10).is_err());
+        assert_eq!(canister.getTokenInfo().holderNumber, 1);
+    }
+
+    #[cfg(feature = "bench")]
+    #[test]
+    fn bench_fill() {
+        let canister = test_canister();
+        assert_eq!(canister.bench_fill(10), Ok(11));
+        assert_eq!(canister.getTokenInfo().holderNumber, 11);
+        assert_eq!(canister.totalSupply(), Tokens128::from(10_000 + 65));
+        assert_eq!(canister.bench_instructions().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn unknown_scenario() {
+        let canister = test_canister();
+        assert!(canister.run_scenario("unknown".to_string()).await.is_err());
+    }
+}